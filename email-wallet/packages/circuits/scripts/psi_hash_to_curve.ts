@@ -0,0 +1,30 @@
+const emailWalletUtils = require("@zk-email/relayer-utils");
+import { hash_to_curve } from "circom-grumpkin";
+import { program } from "commander";
+import fs from "fs";
+
+program
+  .requiredOption("--email-addr <string>", "User's email address")
+  .requiredOption("--output <string>", "Path of a ouput file to write the hashed point");
+
+program.parse();
+const args = program.opts();
+
+async function exec() {
+  const paddedEmailAddr = emailWalletUtils.padEmailAddr(args.emailAddr);
+  const hashedPoint = hash_to_curve(paddedEmailAddr);
+  const output = {
+    x: hashedPoint.x.toString(),
+    y: hashedPoint.y.toString(),
+  };
+  fs.writeFileSync(args.output, JSON.stringify(output));
+}
+
+exec()
+  .then(() => {
+    process.exit(0);
+  })
+  .catch((err) => {
+    console.log("Error: ", err);
+    process.exit(1);
+  });
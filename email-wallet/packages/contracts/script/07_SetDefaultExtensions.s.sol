@@ -5,6 +5,7 @@ import {ERC1967Proxy} from "@openzeppelin/contracts/proxy/ERC1967/ERC1967Proxy.s
 import "../src/EmailWalletCore.sol";
 import "../src/extensions/UniswapExtension.sol";
 import "../src/extensions/NFTExtension.sol";
+import "../src/extensions/BatchTransferExtension.sol";
 import "forge-std/Script.sol";
 import "forge-std/console.sol";
 
@@ -16,6 +17,7 @@ contract Deploy is Script {
 
     string[][] nftExtTemplates = new string[][](3);
     string[][] uniswapExtTemplates = new string[][](4);
+    string[][] batchExtTemplates = new string[][](1);
 
     function run() external {
         uint256 deployerPrivateKey = vm.envUint("PRIVATE_KEY");
@@ -39,7 +41,7 @@ contract Deploy is Script {
 
         vm.startBroadcast(deployerPrivateKey);
 
-        bytes[] memory defaultExtensions = new bytes[](2);
+        bytes[] memory defaultExtensions = new bytes[](3);
 
         NFTExtension nftExt;
         {
@@ -107,6 +109,22 @@ contract Deploy is Script {
 
         defaultExtensions[1] = abi.encode("UniswapExtension", address(uniExt), uniswapExtTemplates, 0.001 ether); // TODO: Check max exec gas
 
+        BatchTransferExtension batchExt;
+        {
+            BatchTransferExtension batchExtImpl = new BatchTransferExtension();
+            bytes memory data = abi.encodeWithSelector(
+                BatchTransferExtension(batchExtImpl).initialize.selector,
+                    address(core)
+            );
+            ERC1967Proxy proxy = new ERC1967Proxy(address(batchExtImpl), data);
+            batchExt = BatchTransferExtension(address(proxy));
+
+            console.log("BatchTransferExtension proxy deployed at: %s", address(batchExt));
+            console.log("BatchTransferExtension implementation deployed at: %s", address(batchExtImpl));
+        }
+        batchExtTemplates[0] = ["Batch", "{uint}", "sends", "{tokenAmount}"];
+        defaultExtensions[2] = abi.encode("BatchTransferExtension", address(batchExt), batchExtTemplates, 0.001 ether); // TODO: Check max exec gas
+
         core.initializeExtension(defaultExtensions);
 
         console.log("---- DONE ----");
@@ -12,6 +12,7 @@ import "../src/extensions/UniswapExtension.sol";
 import "../src/extensions/Safe2FAExtension.sol";
 import "../src/extensions/OauthSignupExtension.sol";
 import "../src/extensions/OauthSigninExtension.sol";
+import "../src/extensions/BatchTransferExtension.sol";
 import "../src/EmailWalletCore.sol";
 
 contract TestERC20 is ERC20 {
@@ -78,6 +79,9 @@ contract Deploy is Script {
     OauthSigninExtension oauthInExt;
     OauthSigninExtension oauthInExtImpl;
 
+    BatchTransferExtension batchExt;
+    BatchTransferExtension batchExtImpl;
+
     uint256 constant emailValidityDuration = 14 days;
     uint256 constant unclaimedFundClaimGas = 450000;
     uint256 constant unclaimedStateClaimGas = 500000;
@@ -89,6 +93,7 @@ contract Deploy is Script {
     string[][] safeExtTemplates = new string[][](1);
     string[][] oauthUpExtTemplates = new string[][](9);
     string[][] oauthInExtTemplates = new string[][](8);
+    string[][] batchExtTemplates = new string[][](1);
 
     function run() external {
         uint256 deployerPrivateKey = vm.envUint("PRIVATE_KEY");
@@ -224,7 +229,7 @@ contract Deploy is Script {
         tokenRegistry.setChainId(chainName, chainId);
         tokenRegistry.setTokenAddress(chainId, tokenName, address(testToken));
 
-        bytes[] memory defaultExtensions = new bytes[](5);
+        bytes[] memory defaultExtensions = new bytes[](6);
 
         {
             nftExtImpl = new NFTExtension();
@@ -469,6 +474,18 @@ contract Deploy is Script {
             0.001 ether
         ); // TODO: Check max exec gas
 
+        {
+            batchExtImpl = new BatchTransferExtension();
+            bytes memory data = abi.encodeWithSelector(
+                BatchTransferExtension(batchExtImpl).initialize.selector,
+                address(core)
+            );
+            ERC1967Proxy proxy = new ERC1967Proxy(address(batchExtImpl), data);
+            batchExt = BatchTransferExtension(address(proxy));
+        }
+        batchExtTemplates[0] = ["Batch", "{uint}", "sends", "{tokenAmount}"];
+        defaultExtensions[5] = abi.encode("BatchTransferExtension", address(batchExt), batchExtTemplates, 0.001 ether); // TODO: Check max exec gas
+
         core.initializeExtension(defaultExtensions);
 
         vm.stopBroadcast();
@@ -501,6 +518,8 @@ contract Deploy is Script {
         console.log("OauthSignupExtension implementation deployed at: %s", address(oauthUpExtImpl));
         console.log("OauthSigninExtension proxy deployed at: %s", address(oauthInExt));
         console.log("OauthSigninExtension implementation deployed at: %s", address(oauthInExtImpl));
+        console.log("BatchTransferExtension proxy deployed at: %s", address(batchExt));
+        console.log("BatchTransferExtension implementation deployed at: %s", address(batchExtImpl));
         console.log("---- DONE ----");
     }
 }
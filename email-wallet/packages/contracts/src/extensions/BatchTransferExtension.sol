@@ -0,0 +1,134 @@
+// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.12;
+
+import {Initializable} from "@openzeppelin/contracts-upgradeable/proxy/utils/Initializable.sol";
+import {UUPSUpgradeable} from "@openzeppelin/contracts/proxy/utils/UUPSUpgradeable.sol";
+import {OwnableUpgradeable} from "@openzeppelin/contracts-upgradeable/access/OwnableUpgradeable.sol";
+import {IERC20} from "@openzeppelin/contracts/token/ERC20/IERC20.sol";
+import {SafeERC20} from "@openzeppelin/contracts/token/ERC20/utils/SafeERC20.sol";
+import {Extension} from "../interfaces/Extension.sol";
+import {EmailWalletCore} from "../EmailWalletCore.sol";
+import "../interfaces/Types.sol";
+
+/// Sends a token from a wallet to many ETH addresses with one email.
+/// A list of transfers is registered for a wallet first, then the wallet owner confirms it with `Batch {id} sends {total}`.
+contract BatchTransferExtension is Extension, Initializable, UUPSUpgradeable, OwnableUpgradeable {
+    using SafeERC20 for IERC20;
+
+    struct Batch {
+        address wallet;
+        address tokenAddr;
+        address[] recipients;
+        uint256[] amounts;
+        bool executed;
+    }
+
+    uint256 public constant MAX_BATCH_TRANSFERS = 50;
+
+    EmailWalletCore public core;
+
+    uint256 public numBatches;
+
+    mapping(uint256 => Batch) internal batchOfId;
+
+    string[][] public templates;
+
+    event BatchRegistered(uint256 indexed id, address indexed wallet, address tokenAddr, uint256 total);
+
+    event BatchExecuted(uint256 indexed id, address indexed wallet);
+
+    modifier onlyCore() {
+        require(msg.sender == address(core), "invalid sender");
+        _;
+    }
+
+    constructor() {
+        _disableInitializers();
+    }
+
+    function initialize(address coreAddr) public initializer {
+        __Ownable_init();
+        core = EmailWalletCore(payable(coreAddr));
+        templates = new string[][](1);
+        templates[0] = ["Batch", "{uint}", "sends", "{tokenAmount}"];
+    }
+
+    function _authorizeUpgrade(address newImplementation) internal override onlyOwner {}
+
+    /// @notice Register the transfers of a batch. Anyone can register, as nothing moves until the wallet confirms it.
+    /// @dev A registered batch cannot be changed, so the id in the confirmed subject pins the recipients and amounts.
+    /// @param wallet Wallet that will send the tokens
+    /// @param tokenAddr Address of the token sent
+    /// @param recipients ETH addresses of the recipients
+    /// @param amounts Amount sent to each recipient
+    /// @return id The id to confirm in the subject
+    function registerBatch(
+        address wallet,
+        address tokenAddr,
+        address[] calldata recipients,
+        uint256[] calldata amounts
+    ) public returns (uint256 id) {
+        require(wallet != address(0), "invalid wallet");
+        require(tokenAddr != address(0), "invalid token");
+        require(recipients.length > 0, "empty batch");
+        require(recipients.length <= MAX_BATCH_TRANSFERS, "too many transfers");
+        require(recipients.length == amounts.length, "length mismatch");
+
+        uint256 total = 0;
+        for (uint256 i = 0; i < recipients.length; i++) {
+            require(recipients[i] != address(0), "invalid recipient");
+            require(amounts[i] > 0, "invalid amount");
+            total += amounts[i];
+        }
+
+        id = numBatches++;
+        batchOfId[id] = Batch({
+            wallet: wallet,
+            tokenAddr: tokenAddr,
+            recipients: recipients,
+            amounts: amounts,
+            executed: false
+        });
+
+        emit BatchRegistered(id, wallet, tokenAddr, total);
+    }
+
+    function getBatch(uint256 id) public view returns (Batch memory) {
+        return batchOfId[id];
+    }
+
+    function execute(
+        uint8 templateIndex,
+        bytes[] memory subjectParams,
+        address wallet,
+        bool,
+        address,
+        bytes32
+    ) external override onlyCore {
+        require(templateIndex == 0, "invalid templateIndex");
+
+        uint256 id = abi.decode(subjectParams[0], (uint256));
+        (uint256 total, string memory tokenName) = abi.decode(subjectParams[1], (uint256, string));
+
+        require(id < numBatches, "invalid batch id");
+        Batch storage batch = batchOfId[id];
+        require(batch.wallet == wallet, "batch of another wallet");
+        require(!batch.executed, "batch already executed");
+        require(core.tokenRegistry().getTokenAddress(tokenName) == batch.tokenAddr, "token mismatch");
+
+        uint256 sum = 0;
+        for (uint256 i = 0; i < batch.amounts.length; i++) {
+            sum += batch.amounts[i];
+        }
+        require(sum == total, "total mismatch");
+
+        batch.executed = true;
+
+        core.requestTokenAsExtension(batch.tokenAddr, total);
+        for (uint256 i = 0; i < batch.recipients.length; i++) {
+            IERC20(batch.tokenAddr).safeTransfer(batch.recipients[i], batch.amounts[i]);
+        }
+
+        emit BatchExecuted(id, wallet);
+    }
+}
@@ -251,6 +251,28 @@ contract UnclaimsHandler is ReentrancyGuard, Initializable, UUPSUpgradeable, Own
         emit EmailWalletEvents.UnclaimedFundVoided(id, fund.emailAddrCommit, fund.tokenAddr, fund.amount, fund.sender);
     }
 
+    /// @notice Return unclaimed fund to the sender before expiry time
+    /// @param id The id of the unclaimed fund to cancel.
+    /// @dev Only the sender can cancel, so the whole claim fee is returned to them.
+    function cancelUnclaimedFund(uint256 id) public nonReentrant {
+        require(id < numUnclaimedFunds, "invalid id");
+
+        UnclaimedFund memory fund = unclaimedFundOfId[id];
+
+        require(fund.amount > 0, "unclaimed fund not registered");
+        require(fund.sender == msg.sender, "caller is not the sender");
+        require(fund.expiryTime > block.timestamp, "unclaimed fund expired");
+
+        delete unclaimedFundOfId[id];
+
+        IERC20(fund.tokenAddr).safeTransfer(fund.sender, fund.amount);
+
+        (bool success, ) = payable(fund.sender).call{value: unclaimedFundClaimGas * maxFeePerGas}("");
+        require(success, "ETH transfer to fund.sender failed");
+
+        emit EmailWalletEvents.UnclaimedFundVoided(id, fund.emailAddrCommit, fund.tokenAddr, fund.amount, fund.sender);
+    }
+
     /// Register unclaimed state of an extension for the recipient email address commitment
     /// @param emailAddrCommit Email address commitment of the recipient
     /// @param extensionAddr Address of the extension contract
@@ -457,6 +479,28 @@ contract UnclaimsHandler is ReentrancyGuard, Initializable, UUPSUpgradeable, Own
         emit EmailWalletEvents.UnclaimedStateVoided(id, us.emailAddrCommit, us.sender);
     }
 
+    /// @notice Return unclaimed state to the sender before expiry time
+    /// @param id The id of the unclaimed state to cancel.
+    /// @dev Unlike `voidUnclaimedState`, this reverts if the extension fails to return the state.
+    function cancelUnclaimedState(uint256 id) public nonReentrant {
+        require(id < numUnclaimedStates, "invalid id");
+
+        UnclaimedState memory us = unclaimedStateOfId[id];
+
+        require(us.sender != address(0), "unclaimed state not registered");
+        require(us.sender == msg.sender, "caller is not the sender");
+        require(us.expiryTime > block.timestamp, "unclaimed state expired");
+
+        delete unclaimedStateOfId[id];
+
+        Extension(us.extensionAddr).voidUnclaimedState(us);
+
+        (bool success, ) = payable(us.sender).call{value: unclaimedStateClaimGas * maxFeePerGas}("");
+        require(success, "ETH transfer to us.sender failed");
+
+        emit EmailWalletEvents.UnclaimedStateVoided(id, us.emailAddrCommit, us.sender);
+    }
+
     function getUnclaimedFund(uint256 id) public view returns (UnclaimedFund memory) {
         return unclaimedFundOfId[id];
     }
@@ -137,9 +137,15 @@ library SubjectUtils {
             require(target != address(0), "invalid execute target");
             require(Address.isContract(target), "target is not a contract");
 
+            // The sender can cancel their own unclaimed funds and states, but nothing else on the handlers
+            bytes4 selector = bytes4(data);
+            bool isCancel = target == address(core.unclaimsHandler()) &&
+                (selector == UnclaimsHandler.cancelUnclaimedFund.selector ||
+                    selector == UnclaimsHandler.cancelUnclaimedState.selector);
+
             require(
                 target != address(core) &&
-                    target != address(core.unclaimsHandler()) &&
+                    (target != address(core.unclaimsHandler()) || isCancel) &&
                     target != address(core.accountHandler()) &&
                     target != address(core.relayerHandler()) &&
                     target != address(core.extensionHandler()),
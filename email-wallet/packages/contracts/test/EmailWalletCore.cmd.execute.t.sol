@@ -140,4 +140,92 @@ contract ExecuteCommandTest is EmailWalletCoreTestHelper {
         core.handleEmailOp(emailOp);
         vm.stopPrank();
     }
+
+    function test_ExecuteCancelUnclaimedFund() public {
+        uint256 registeredUnclaimId = _registerUnclaimedFundFrom(walletAddr);
+
+        bytes memory targetCalldata = abi.encodeCall(UnclaimsHandler.cancelUnclaimedFund, (registeredUnclaimId));
+        bytes memory emailOpCalldata = abi.encode(address(unclaimsHandler), 0, targetCalldata);
+        string memory subject = string.concat("Execute 0x", SubjectUtils.bytesToHexString(emailOpCalldata));
+
+        EmailOp memory emailOp = _getBaseEmailOp();
+        emailOp.command = Commands.EXECUTE;
+        emailOp.executeCallData = emailOpCalldata;
+        emailOp.maskedSubject = subject;
+
+        vm.startPrank(relayer);
+        (bool success, , , ) = core.handleEmailOp(emailOp);
+        vm.stopPrank();
+
+        assertTrue(success, "handleEmailOp failed");
+        assertEq(daiToken.balanceOf(walletAddr), 100 ether, "wallet didnt receive tokens");
+        assertEq(walletAddr.balance, unclaimedFundClaimGas * maxFeePerGas, "claim fee not returned to wallet");
+    }
+
+    function test_ExecuteCancelUnclaimedFundOfAnotherSenderShouldFail() public {
+        uint256 registeredUnclaimId = _registerUnclaimedFundFrom(vm.addr(7));
+
+        bytes memory targetCalldata = abi.encodeCall(UnclaimsHandler.cancelUnclaimedFund, (registeredUnclaimId));
+        bytes memory emailOpCalldata = abi.encode(address(unclaimsHandler), 0, targetCalldata);
+        string memory subject = string.concat("Execute 0x", SubjectUtils.bytesToHexString(emailOpCalldata));
+
+        EmailOp memory emailOp = _getBaseEmailOp();
+        emailOp.command = Commands.EXECUTE;
+        emailOp.executeCallData = emailOpCalldata;
+        emailOp.maskedSubject = subject;
+
+        vm.startPrank(relayer);
+        (bool success, , , ) = core.handleEmailOp(emailOp);
+        vm.stopPrank();
+
+        assertTrue(!success, "handleEmailOp succeded");
+        assertEq(daiToken.balanceOf(address(unclaimsHandler)), 100 ether, "unclaimed fund was moved");
+    }
+
+    function test_RevertIf_ExecuteOtherFunctionOfUnclaimsHandler() public {
+        uint256 registeredUnclaimId = _registerUnclaimedFundFrom(walletAddr);
+
+        bytes memory targetCalldata = abi.encodeCall(UnclaimsHandler.voidUnclaimedFund, (registeredUnclaimId));
+        bytes memory emailOpCalldata = abi.encode(address(unclaimsHandler), 0, targetCalldata);
+        string memory subject = string.concat("Execute 0x", SubjectUtils.bytesToHexString(emailOpCalldata));
+
+        EmailOp memory emailOp = _getBaseEmailOp();
+        emailOp.command = Commands.EXECUTE;
+        emailOp.executeCallData = emailOpCalldata;
+        emailOp.maskedSubject = subject;
+
+        vm.startPrank(relayer);
+        vm.expectRevert("cannot execute on core or handlers");
+        core.handleEmailOp(emailOp);
+        vm.stopPrank();
+    }
+
+    function test_RevertIf_ExecuteCancelSelectorOnAnotherHandler() public {
+        bytes memory targetCalldata = abi.encodeCall(UnclaimsHandler.cancelUnclaimedFund, (0));
+        bytes memory emailOpCalldata = abi.encode(address(accountHandler), 0, targetCalldata);
+        string memory subject = string.concat("Execute 0x", SubjectUtils.bytesToHexString(emailOpCalldata));
+
+        EmailOp memory emailOp = _getBaseEmailOp();
+        emailOp.command = Commands.EXECUTE;
+        emailOp.executeCallData = emailOpCalldata;
+        emailOp.maskedSubject = subject;
+
+        vm.startPrank(relayer);
+        vm.expectRevert("cannot execute on core or handlers");
+        core.handleEmailOp(emailOp);
+        vm.stopPrank();
+    }
+
+    function _registerUnclaimedFundFrom(address sender) internal returns (uint256) {
+        vm.deal(sender, unclaimedFundClaimGas * maxFeePerGas);
+        daiToken.freeMint(sender, 100 ether);
+
+        vm.startPrank(sender);
+        daiToken.approve(address(unclaimsHandler), 100 ether);
+        uint256 registeredUnclaimId = unclaimsHandler.registerUnclaimedFund{
+            value: unclaimedFundClaimGas * maxFeePerGas
+        }(bytes32(uint256(32333)), address(daiToken), 100 ether, 0, 0, "");
+        vm.stopPrank();
+        return registeredUnclaimId;
+    }
 }
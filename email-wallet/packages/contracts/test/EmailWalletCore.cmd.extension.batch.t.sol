@@ -0,0 +1,150 @@
+// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.12;
+
+import "./helpers/EmailWalletCoreTestHelper.sol";
+import "../src/extensions/BatchTransferExtension.sol";
+
+contract BatchTransferExtensionCommandTest is EmailWalletCoreTestHelper {
+    BatchTransferExtension batchExtension;
+    string[][] public batchExtTemplates = new string[][](1);
+
+    event BatchExecuted(uint256 indexed id, address indexed wallet);
+
+    function setUp() public override {
+        super.setUp();
+        _registerRelayer();
+        _createTestAccount();
+
+        {
+            BatchTransferExtension batchExtensionImpl = new BatchTransferExtension();
+            ERC1967Proxy proxy = new ERC1967Proxy(
+                address(batchExtensionImpl),
+                abi.encodeCall(batchExtensionImpl.initialize, (address(core)))
+            );
+            batchExtension = BatchTransferExtension(address(proxy));
+        }
+        batchExtTemplates[0] = ["Batch", "{uint}", "sends", "{tokenAmount}"];
+        extensionHandler.publishExtension("Batch Transfer", address(batchExtension), batchExtTemplates, 0.1 ether);
+
+        EmailOp memory emailOp = _getBaseEmailOp();
+        emailOp.command = Commands.INSTALL_EXTENSION;
+        emailOp.extensionName = "Batch Transfer";
+        emailOp.maskedSubject = "Install extension Batch Transfer";
+        emailOp.emailNullifier = bytes32(uint256(93845));
+
+        vm.startPrank(relayer);
+        core.handleEmailOp(emailOp);
+        vm.stopPrank();
+    }
+
+    function _registerBatch(address wallet) internal returns (uint256) {
+        address[] memory recipients = new address[](2);
+        recipients[0] = vm.addr(3);
+        recipients[1] = vm.addr(4);
+        uint256[] memory amounts = new uint256[](2);
+        amounts[0] = 10 ether;
+        amounts[1] = 20 ether;
+        return batchExtension.registerBatch(wallet, address(usdcToken), recipients, amounts);
+    }
+
+    function _getBatchEmailOp(uint256 id, string memory subject, uint256 total) internal view returns (EmailOp memory) {
+        EmailOp memory emailOp = _getBaseEmailOp();
+        emailOp.command = "Batch";
+        emailOp.maskedSubject = subject;
+        emailOp.emailNullifier = bytes32(uint256(2938));
+        emailOp.extensionParams.subjectTemplateIndex = 0;
+        emailOp.extensionParams.subjectParams = new bytes[](2);
+        emailOp.extensionParams.subjectParams[0] = abi.encode(id);
+        emailOp.extensionParams.subjectParams[1] = abi.encode(total, "USDC");
+        return emailOp;
+    }
+
+    function test_ExecuteBatch() public {
+        uint256 id = _registerBatch(walletAddr);
+        usdcToken.freeMint(walletAddr, 30 ether);
+
+        EmailOp memory emailOp = _getBatchEmailOp(id, "Batch 0 sends 30 USDC", 30 ether);
+
+        vm.startPrank(relayer);
+        vm.expectEmit(true, true, true, true);
+        emit BatchExecuted(id, walletAddr);
+        (bool success, , , ) = core.handleEmailOp(emailOp);
+        vm.stopPrank();
+
+        assertTrue(success, "handleEmailOp failed");
+        assertEq(usdcToken.balanceOf(walletAddr), 0, "USDC still with user");
+        assertEq(usdcToken.balanceOf(vm.addr(3)), 10 ether, "first recipient didnt receive USDC");
+        assertEq(usdcToken.balanceOf(vm.addr(4)), 20 ether, "second recipient didnt receive USDC");
+        assertEq(usdcToken.balanceOf(address(batchExtension)), 0, "extension kept USDC");
+        assertTrue(batchExtension.getBatch(id).executed, "batch not marked executed");
+    }
+
+    function test_RevertIf_ExecuteBatchTwice() public {
+        uint256 id = _registerBatch(walletAddr);
+        usdcToken.freeMint(walletAddr, 60 ether);
+
+        vm.startPrank(relayer);
+        (bool success, , , ) = core.handleEmailOp(_getBatchEmailOp(id, "Batch 0 sends 30 USDC", 30 ether));
+        assertTrue(success, "first handleEmailOp failed");
+
+        EmailOp memory emailOp = _getBatchEmailOp(id, "Batch 0 sends 30 USDC", 30 ether);
+        emailOp.emailNullifier = bytes32(uint256(2939));
+        bytes memory reason;
+        (success, reason, , ) = core.handleEmailOp(emailOp);
+        vm.stopPrank();
+
+        assertTrue(!success, "second handleEmailOp should have failed");
+        assertEq(string(reason), "batch already executed", "wrong revert reason");
+        assertEq(usdcToken.balanceOf(walletAddr), 30 ether, "batch sent twice");
+    }
+
+    function test_RevertIf_ExecuteBatchOfAnotherWallet() public {
+        uint256 id = _registerBatch(vm.addr(9));
+        usdcToken.freeMint(walletAddr, 30 ether);
+
+        vm.startPrank(relayer);
+        (bool success, bytes memory reason, , ) = core.handleEmailOp(
+            _getBatchEmailOp(id, "Batch 0 sends 30 USDC", 30 ether)
+        );
+        vm.stopPrank();
+
+        assertTrue(!success, "handleEmailOp should have failed");
+        assertEq(string(reason), "batch of another wallet", "wrong revert reason");
+        assertEq(usdcToken.balanceOf(walletAddr), 30 ether, "USDC left the wallet");
+    }
+
+    function test_RevertIf_ExecuteBatchWithWrongTotal() public {
+        uint256 id = _registerBatch(walletAddr);
+        usdcToken.freeMint(walletAddr, 30 ether);
+
+        vm.startPrank(relayer);
+        (bool success, bytes memory reason, , ) = core.handleEmailOp(
+            _getBatchEmailOp(id, "Batch 0 sends 25 USDC", 25 ether)
+        );
+        vm.stopPrank();
+
+        assertTrue(!success, "handleEmailOp should have failed");
+        assertEq(string(reason), "total mismatch", "wrong revert reason");
+        assertEq(usdcToken.balanceOf(walletAddr), 30 ether, "USDC left the wallet");
+    }
+
+    function test_RevertIf_RegisterBatchWithZeroRecipient() public {
+        address[] memory recipients = new address[](1);
+        uint256[] memory amounts = new uint256[](1);
+        amounts[0] = 1 ether;
+
+        vm.expectRevert("invalid recipient");
+        batchExtension.registerBatch(walletAddr, address(usdcToken), recipients, amounts);
+    }
+
+    function test_RevertIf_RegisterBatchWithLengthMismatch() public {
+        address[] memory recipients = new address[](2);
+        recipients[0] = vm.addr(3);
+        recipients[1] = vm.addr(4);
+        uint256[] memory amounts = new uint256[](1);
+        amounts[0] = 1 ether;
+
+        vm.expectRevert("length mismatch");
+        batchExtension.registerBatch(walletAddr, address(usdcToken), recipients, amounts);
+    }
+}
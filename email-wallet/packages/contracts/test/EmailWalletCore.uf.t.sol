@@ -603,4 +603,130 @@ contract UnclaimedFundTest is EmailWalletCoreTestHelper {
         unclaimsHandler.voidUnclaimedFund(registeredUnclaimId);
         vm.stopPrank();
     }
+
+    function test_CancelUnclaimedFund() public {
+        address sender = vm.addr(7);
+        bytes32 recipientEmailAddrCommit = bytes32(uint256(32333));
+
+        vm.deal(sender, unclaimedFundClaimGas * maxFeePerGas);
+        daiToken.freeMint(sender, 100 ether);
+
+        vm.startPrank(sender);
+        daiToken.approve(address(core.unclaimsHandler()), 100 ether);
+        uint256 registeredUnclaimId = unclaimsHandler.registerUnclaimedFund{
+            value: unclaimedFundClaimGas * maxFeePerGas
+        }(recipientEmailAddrCommit, address(daiToken), 100 ether, 0, 0, "");
+
+        vm.expectEmit(true, true, true, true);
+        emit EmailWalletEvents.UnclaimedFundVoided(
+            registeredUnclaimId,
+            recipientEmailAddrCommit,
+            address(daiToken),
+            100 ether,
+            sender
+        );
+        unclaimsHandler.cancelUnclaimedFund(registeredUnclaimId);
+        vm.stopPrank();
+
+        assertEq(daiToken.balanceOf(address(unclaimsHandler)), 0, "core contract still have tokens");
+        assertEq(daiToken.balanceOf(sender), 100 ether, "sender didnt receive tokens");
+        assertEq(sender.balance, unclaimedFundClaimGas * maxFeePerGas, "claim fee not returned to sender");
+
+        (, , , , uint256 amt, ) = unclaimsHandler.unclaimedFundOfId(registeredUnclaimId);
+        assertEq(amt, 0, "unclaimed fund not cleared");
+    }
+
+    function test_RevertIf_CancelUnclaimedFund_NotSender() public {
+        address sender = vm.addr(7);
+        address otherUser = vm.addr(4);
+        bytes32 recipientEmailAddrCommit = bytes32(uint256(32333));
+
+        vm.deal(sender, unclaimedFundClaimGas * maxFeePerGas);
+        daiToken.freeMint(sender, 100 ether);
+
+        vm.startPrank(sender);
+        daiToken.approve(address(core.unclaimsHandler()), 100 ether);
+        uint256 registeredUnclaimId = unclaimsHandler.registerUnclaimedFund{
+            value: unclaimedFundClaimGas * maxFeePerGas
+        }(recipientEmailAddrCommit, address(daiToken), 100 ether, 0, 0, "");
+        vm.stopPrank();
+
+        vm.startPrank(otherUser);
+        vm.expectRevert("caller is not the sender");
+        unclaimsHandler.cancelUnclaimedFund(registeredUnclaimId);
+        vm.stopPrank();
+    }
+
+    function test_RevertIf_CancelUnclaimedFund_Expired() public {
+        address sender = vm.addr(7);
+        bytes32 recipientEmailAddrCommit = bytes32(uint256(32333));
+
+        vm.deal(sender, unclaimedFundClaimGas * maxFeePerGas);
+        daiToken.freeMint(sender, 100 ether);
+
+        vm.startPrank(sender);
+        daiToken.approve(address(core.unclaimsHandler()), 100 ether);
+        uint256 registeredUnclaimId = unclaimsHandler.registerUnclaimedFund{
+            value: unclaimedFundClaimGas * maxFeePerGas
+        }(recipientEmailAddrCommit, address(daiToken), 100 ether, 0, 0, "");
+
+        vm.warp(block.timestamp + 31 days);
+
+        vm.expectRevert("unclaimed fund expired");
+        unclaimsHandler.cancelUnclaimedFund(registeredUnclaimId);
+        vm.stopPrank();
+    }
+
+    function test_RevertIf_CancelUnclaimedFund_AlreadyClaimed() public {
+        address sender = vm.addr(7);
+        bytes32 recipientEmailAddrCommit = bytes32(uint256(32333));
+
+        vm.deal(sender, unclaimedFundClaimGas * maxFeePerGas);
+        daiToken.freeMint(sender, 100 ether);
+
+        vm.startPrank(sender);
+        daiToken.approve(address(core.unclaimsHandler()), 100 ether);
+        uint256 registeredUnclaimId = unclaimsHandler.registerUnclaimedFund{
+            value: unclaimedFundClaimGas * maxFeePerGas
+        }(recipientEmailAddrCommit, address(daiToken), 100 ether, 0, 0, "");
+        vm.stopPrank();
+
+        vm.startPrank(relayer);
+        unclaimsHandler.claimUnclaimedFund(registeredUnclaimId, accountSalt, mockProof);
+        vm.stopPrank();
+
+        vm.startPrank(sender);
+        vm.expectRevert("unclaimed fund not registered");
+        unclaimsHandler.cancelUnclaimedFund(registeredUnclaimId);
+        vm.stopPrank();
+
+        assertEq(daiToken.balanceOf(walletAddr), 100 ether, "recipient lost the claimed tokens");
+        assertEq(sender.balance, 0, "sender got the claim fee back");
+    }
+
+    function test_RevertIf_CancelUnclaimedFund_AlreadyCancelled() public {
+        address sender = vm.addr(7);
+        bytes32 recipientEmailAddrCommit = bytes32(uint256(32333));
+
+        vm.deal(sender, unclaimedFundClaimGas * maxFeePerGas);
+        daiToken.freeMint(sender, 100 ether);
+
+        vm.startPrank(sender);
+        daiToken.approve(address(core.unclaimsHandler()), 100 ether);
+        uint256 registeredUnclaimId = unclaimsHandler.registerUnclaimedFund{
+            value: unclaimedFundClaimGas * maxFeePerGas
+        }(recipientEmailAddrCommit, address(daiToken), 100 ether, 0, 0, "");
+        unclaimsHandler.cancelUnclaimedFund(registeredUnclaimId);
+
+        vm.expectRevert("unclaimed fund not registered");
+        unclaimsHandler.cancelUnclaimedFund(registeredUnclaimId);
+        vm.stopPrank();
+    }
+
+    function test_RevertIf_CancelUnclaimedFund_InvalidId() public {
+        vm.startPrank(vm.addr(7));
+        vm.expectRevert("invalid id");
+        unclaimsHandler.cancelUnclaimedFund(0);
+        vm.stopPrank();
+    }
 }
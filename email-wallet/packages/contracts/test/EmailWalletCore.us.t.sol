@@ -914,4 +914,113 @@ contract UnclaimedStateTest is EmailWalletCoreTestHelper {
         unclaimsHandler.voidUnclaimedState(registeredUnclaimId);
         vm.stopPrank();
     }
+
+    function test_CancelUnclaimedState() public {
+        address sender = vm.addr(7);
+        bytes32 recipientEmailAddrCommit = bytes32(uint256(32333));
+        bytes memory state = abi.encode(address(dummyNFT), 23);
+
+        vm.startPrank(sender);
+        dummyNFT.freeMint(sender, 23); // Mint a NFT with tokenId 23 to walletAddr
+        dummyNFT.approve(address(nftExtension), 23);
+        vm.stopPrank();
+
+        vm.deal(sender, unclaimedStateClaimGas * maxFeePerGas);
+
+        vm.startPrank(sender);
+        uint256 registeredUnclaimId = unclaimsHandler.registerUnclaimedState{
+            value: unclaimedStateClaimGas * maxFeePerGas
+        }(recipientEmailAddrCommit, address(nftExtension), state, 0, 0, "");
+
+        vm.expectEmit(true, true, true, true);
+        emit EmailWalletEvents.UnclaimedStateVoided(registeredUnclaimId, recipientEmailAddrCommit, sender);
+        unclaimsHandler.cancelUnclaimedState(registeredUnclaimId);
+        vm.stopPrank();
+
+        assertEq(dummyNFT.ownerOf(23), sender, "NFT not returned to sender");
+        assertEq(sender.balance, unclaimedStateClaimGas * maxFeePerGas, "claim fee not returned to sender");
+        assertEq(address(unclaimsHandler).balance, 0, "unclaimsHandler still have ETH");
+
+        (, , , , bytes memory st, ) = unclaimsHandler.unclaimedStateOfId(registeredUnclaimId);
+        assertEq(st.length, 0, "unclaimed state not cleared");
+    }
+
+    function test_RevertIf_CancelUnclaimedState_NotSender() public {
+        address sender = vm.addr(7);
+        address otherUser = vm.addr(4);
+        bytes32 recipientEmailAddrCommit = bytes32(uint256(32333));
+        bytes memory state = abi.encode(address(dummyNFT), 23);
+
+        vm.startPrank(sender);
+        dummyNFT.freeMint(sender, 23); // Mint a NFT with tokenId 23 to walletAddr
+        dummyNFT.approve(address(nftExtension), 23);
+        vm.stopPrank();
+
+        vm.deal(sender, unclaimedStateClaimGas * maxFeePerGas);
+
+        vm.startPrank(sender);
+        uint256 registeredUnclaimId = unclaimsHandler.registerUnclaimedState{
+            value: unclaimedStateClaimGas * maxFeePerGas
+        }(recipientEmailAddrCommit, address(nftExtension), state, 0, 0, "");
+        vm.stopPrank();
+
+        vm.startPrank(otherUser);
+        vm.expectRevert("caller is not the sender");
+        unclaimsHandler.cancelUnclaimedState(registeredUnclaimId);
+        vm.stopPrank();
+    }
+
+    function test_RevertIf_CancelUnclaimedState_Expired() public {
+        address sender = vm.addr(7);
+        bytes32 recipientEmailAddrCommit = bytes32(uint256(32333));
+        bytes memory state = abi.encode(address(dummyNFT), 23);
+
+        vm.startPrank(sender);
+        dummyNFT.freeMint(sender, 23); // Mint a NFT with tokenId 23 to walletAddr
+        dummyNFT.approve(address(nftExtension), 23);
+        vm.stopPrank();
+
+        vm.deal(sender, unclaimedStateClaimGas * maxFeePerGas);
+
+        vm.startPrank(sender);
+        uint256 registeredUnclaimId = unclaimsHandler.registerUnclaimedState{
+            value: unclaimedStateClaimGas * maxFeePerGas
+        }(recipientEmailAddrCommit, address(nftExtension), state, 0, 0, "");
+
+        vm.warp(block.timestamp + 31 days); // Expiry time is 30 days (set in EmailWalletCoreTestHelper)
+
+        vm.expectRevert("unclaimed state expired");
+        unclaimsHandler.cancelUnclaimedState(registeredUnclaimId);
+        vm.stopPrank();
+    }
+
+    function test_RevertIf_CancelUnclaimedState_AlreadyClaimed() public {
+        address sender = vm.addr(7);
+        bytes32 recipientEmailAddrCommit = bytes32(uint256(32333));
+        bytes memory state = abi.encode(address(dummyNFT), 23);
+
+        vm.startPrank(sender);
+        dummyNFT.freeMint(sender, 23); // Mint a NFT with tokenId 23 to walletAddr
+        dummyNFT.approve(address(nftExtension), 23);
+        vm.stopPrank();
+
+        vm.deal(sender, unclaimedStateClaimGas * maxFeePerGas);
+
+        vm.startPrank(sender);
+        uint256 registeredUnclaimId = unclaimsHandler.registerUnclaimedState{
+            value: unclaimedStateClaimGas * maxFeePerGas
+        }(recipientEmailAddrCommit, address(nftExtension), state, 0, 0, "");
+        vm.stopPrank();
+
+        vm.startPrank(relayer);
+        unclaimsHandler.claimUnclaimedState(registeredUnclaimId, accountSalt, mockProof);
+        vm.stopPrank();
+
+        vm.startPrank(sender);
+        vm.expectRevert("unclaimed state not registered");
+        unclaimsHandler.cancelUnclaimedState(registeredUnclaimId);
+        vm.stopPrank();
+
+        assertEq(dummyNFT.ownerOf(23), walletAddr, "recipient lost the claimed NFT");
+    }
 }
@@ -1,16 +1,39 @@
 use std::str::FromStr;
 
 use crate::*;
-use ethers::abi::RawLog;
+use ethers::abi::{Detokenize, RawLog};
 use ethers::middleware::Middleware;
 use ethers::prelude::*;
 use ethers::signers::Signer;
-use futures::future::BoxFuture;
+use ethers::types::transaction::eip2718::TypedTransaction;
 
 use self::wallet::EphemeralTx;
 
 const CONFIRMATIONS: usize = 1;
 
+#[derive(Debug, Clone)]
+pub enum UnclaimRegistration {
+    Fund(email_wallet_events::UnclaimedFundRegisteredFilter),
+    State(email_wallet_events::UnclaimedStateRegisteredFilter),
+}
+
+/// Decodes a log matched by `unclaim_registration_filter`. Logs removed by a reorg are skipped.
+pub fn decode_unclaim_registration(log: &Log) -> Option<(UnclaimRegistration, LogMeta)> {
+    if log.removed == Some(true) {
+        return None;
+    }
+    let registration = match EmailWalletEventsEvents::decode_log(&RawLog::from(log.clone())).ok()? {
+        EmailWalletEventsEvents::UnclaimedFundRegisteredFilter(event) => {
+            UnclaimRegistration::Fund(event)
+        }
+        EmailWalletEventsEvents::UnclaimedStateRegisteredFilter(event) => {
+            UnclaimRegistration::State(event)
+        }
+        _ => return None,
+    };
+    Some((registration, LogMeta::from(log)))
+}
+
 #[derive(Default, Debug)]
 pub struct AccountCreationInput {
     pub account_salt: [u8; 32],
@@ -53,15 +76,15 @@ pub struct ChainClient {
 }
 
 impl ChainClient {
-    pub async fn setup() -> Result<Self> {
+    pub async fn setup(profile: &ChainProfile) -> Result<Self> {
         let wallet: LocalWallet = PRIVATE_KEY.get().unwrap().parse()?;
-        let provider = Provider::<Http>::try_from(CHAIN_RPC_PROVIDER.get().unwrap())?;
+        let provider = Provider::<Http>::try_from(profile.rpc_provider.as_str())?;
         let client = Arc::new(SignerMiddleware::new(
             provider,
-            wallet.with_chain_id(*CHAIN_ID.get().unwrap()),
+            wallet.with_chain_id(profile.chain_id),
         ));
         let core = EmailWalletCore::new(
-            CORE_CONTRACT_ADDRESS.get().unwrap().parse::<Address>()?,
+            profile.core_contract_address.parse::<Address>()?,
             client.clone(),
         );
         let token_registry_addr = core.token_registry().call().await.unwrap();
@@ -110,12 +133,34 @@ impl ChainClient {
         self.client.address()
     }
 
+    /// Sets the EIP-1559 fees of the fee strategy on a relayer transaction, keeping the ethers defaults
+    /// if they cannot be estimated.
+    #[named]
+    async fn with_fees<D: Detokenize>(
+        &self,
+        mut call: ContractCall<SignerM, D>,
+    ) -> ContractCall<SignerM, D> {
+        match self.estimate_gas_prices().await {
+            Ok(prices) => {
+                if let TypedTransaction::Eip1559(tx) = &mut call.tx {
+                    tx.max_fee_per_gas = Some(prices.max_fee_per_gas);
+                    tx.max_priority_fee_per_gas = Some(prices.max_priority_fee_per_gas);
+                }
+            }
+            Err(e) => {
+                warn!(LOG, "Failed to estimate gas prices: {}", e; "func" => function_name!());
+            }
+        }
+        call
+    }
+
     pub async fn register_relayer(&self, email_addr: String, hostname: String) -> Result<String> {
         // Mutex is used to prevent nonce conflicts.
         let mut mutex = SHARED_MUTEX.lock().await;
         *mutex += 1;
 
         let call = self.relayer_handler.register_relayer(email_addr, hostname);
+        let call = self.with_fees(call).await;
         let tx = call.send().await?;
         let receipt = tx
             .log()
@@ -127,6 +172,48 @@ impl ChainClient {
         Ok(tx_hash)
     }
 
+    /// Registers transfers of a wallet on the batch transfer extension and returns the id its `Batch` subject confirms.
+    pub async fn register_transfer_batch(
+        &self,
+        extension_addr: Address,
+        wallet_addr: Address,
+        token_addr: Address,
+        recipients: Vec<Address>,
+        amounts: Vec<U256>,
+    ) -> Result<U256> {
+        let abi = ethers::abi::parse_abi(&[
+            "function registerBatch(address wallet, address tokenAddr, address[] recipients, uint256[] amounts) returns (uint256)",
+            "event BatchRegistered(uint256 indexed id, address indexed wallet, address tokenAddr, uint256 total)",
+        ])?;
+        let registered_topic = abi.event("BatchRegistered")?.signature();
+        let extension = Contract::new(extension_addr, abi, self.client.clone());
+
+        // Mutex is used to prevent nonce conflicts.
+        let mut mutex = SHARED_MUTEX.lock().await;
+        *mutex += 1;
+
+        let call = extension.method::<_, U256>(
+            "registerBatch",
+            (wallet_addr, token_addr, recipients, amounts),
+        )?;
+        let call = self.with_fees(call).await;
+        let tx = call.send().await?;
+        let receipt = tx
+            .log()
+            .confirmations(CONFIRMATIONS)
+            .await?
+            .ok_or(anyhow!("No receipt"))?;
+        receipt
+            .logs
+            .iter()
+            .find(|log| {
+                log.address == extension_addr && log.topics.first() == Some(&registered_topic)
+            })
+            .and_then(|log| log.topics.get(1))
+            .map(|id| U256::from_big_endian(id.as_bytes()))
+            .ok_or(anyhow!("No BatchRegistered event in the receipt"))
+    }
+
     pub async fn register_psi_point(
         &self,
         point: &Point,
@@ -143,6 +230,7 @@ impl ChainClient {
             ),
             fr_to_bytes32(&account_salt.0)?,
         );
+        let call = self.with_fees(call).await;
         let tx = call.send().await?;
         let receipt = tx
             .log()
@@ -162,6 +250,7 @@ impl ChainClient {
         let call =
             self.account_handler
                 .create_account(data.account_salt, data.psi_point, data.proof);
+        let call = self.with_fees(call).await;
         let tx = call.send().await?;
         let receipt = tx
             .log()
@@ -184,6 +273,7 @@ impl ChainClient {
                 data.recipient_account_salt,
                 data.proof,
             );
+            let call = self.with_fees(call).await;
             let tx = call.send().await?;
             let receipt = tx
                 .log()
@@ -199,6 +289,7 @@ impl ChainClient {
                 data.recipient_account_salt,
                 data.proof,
             );
+            let call = self.with_fees(call).await;
             let tx = call.send().await?;
             let receipt = tx
                 .log()
@@ -218,6 +309,7 @@ impl ChainClient {
 
         if is_fund {
             let call = self.unclaims_handler.void_unclaimed_fund(id);
+            let call = self.with_fees(call).await;
             let tx = call.send().await?;
             let receipt = tx
                 .log()
@@ -229,6 +321,7 @@ impl ChainClient {
             Ok(tx_hash)
         } else {
             let call = self.unclaims_handler.void_unclaimed_state(id);
+            let call = self.with_fees(call).await;
             let tx = call.send().await?;
             let receipt = tx
                 .log()
@@ -268,6 +361,7 @@ impl ChainClient {
             gas * fee
         };
         let call = call.value(fee);
+        let call = self.with_fees(call).await;
         let tx = call.send().await?;
         let receipt = tx
             .log()
@@ -306,6 +400,7 @@ impl ChainClient {
             gas * fee
         };
         let call = call.value(fee);
+        let call = self.with_fees(call).await;
         let tx = call.send().await?;
         let receipt = tx
             .log()
@@ -323,25 +418,19 @@ impl ChainClient {
         let mut mutex = SHARED_MUTEX.lock().await;
         *mutex += 1;
 
-        let value = if !email_op.has_email_recipient {
-            U256::zero()
-        } else if email_op.command == SEND_COMMAND {
-            let gas = self.unclaims_handler.unclaimed_fund_claim_gas().await?;
-            let fee = self.unclaims_handler.max_fee_per_gas().await?;
-            gas * fee
-        } else {
-            let gas = self.unclaims_handler.unclaimed_state_claim_gas().await?;
-            let fee = self.unclaims_handler.max_fee_per_gas().await?;
-            gas * fee
-        };
-        let call = self.core.handle_email_op(email_op);
+        let value = self.email_op_value(&email_op).await?;
+        let call = self.core.handle_email_op(email_op.clone());
         let call = call.value(value);
+        let call = self.with_fees(call).await;
         let tx = call.send().await?;
         let receipt = tx
             .log()
             .confirmations(CONFIRMATIONS)
             .await?
             .ok_or(anyhow!("No receipt"))?;
+        if let Err(e) = record_email_op_fee(&email_op, &receipt).await {
+            error!(LOG, "Failed to record the fee of the email op: {}", e; "func" => function_name!());
+        }
         let tx_hash = receipt.transaction_hash;
         let tx_hash = format!("0x{}", hex::encode(tx_hash.as_bytes()));
         for log in receipt.logs.into_iter() {
@@ -360,6 +449,30 @@ impl ChainClient {
         Err(anyhow!("no EmailOpHandled event found in the receipt"))
     }
 
+    /// Estimates the gas of handling the email op. It does not include the claim gas the core contract charges on top.
+    pub async fn estimate_email_op_gas(&self, email_op: EmailOp) -> Result<U256> {
+        let value = self.email_op_value(&email_op).await?;
+        let gas = self
+            .core
+            .handle_email_op(email_op)
+            .value(value)
+            .estimate_gas()
+            .await?;
+        Ok(gas)
+    }
+
+    /// ETH the relayer sends with an email op to pay the claim of the unclaimed fund or state it registers.
+    async fn email_op_value(&self, email_op: &EmailOp) -> Result<U256> {
+        if !email_op.has_email_recipient {
+            return Ok(U256::zero());
+        }
+        let gas = self
+            .query_unclaim_claim_gas(email_op.command == SEND_COMMAND)
+            .await?;
+        let fee = self.unclaims_handler.max_fee_per_gas().await?;
+        Ok(gas * fee)
+    }
+
     pub async fn set_dkim_public_key_hash(
         &self,
         selector: String,
@@ -374,6 +487,7 @@ impl ChainClient {
         let current_registry_addr = self.account_handler.default_dkim_registry().call().await?;
         let registry = ECDSAOwnedDKIMRegistry::new(current_registry_addr, self.client.clone());
         let call = registry.set_dkim_public_key_hash(selector, domain_name, public_key_hash, signature);
+        let call = self.with_fees(call).await;
         let tx = call.send().await?;
         let receipt = tx
             .log()
@@ -391,6 +505,7 @@ impl ChainClient {
         *mutex += 1;
 
         let call = self.test_erc20.free_mint_with_to(wallet_addr, amount);
+        let call = self.with_fees(call).await;
         let tx = call.send().await?;
         let receipt = tx
             .log()
@@ -415,6 +530,7 @@ impl ChainClient {
             wallet_addr,
             ONBOARDING_TOKEN_AMOUNT.get().unwrap().to_owned(),
         );
+        let call = self.with_fees(call).await;
         let tx = call.send().await?;
 
         let receipt = tx
@@ -440,6 +556,7 @@ impl ChainClient {
 
         let erc721 = ERC721::new(token_addr, self.client.clone());
         let call = erc721.approve(to, token_id);
+        let call = self.with_fees(call).await;
         let tx = call.send().await?;
 
         let receipt = tx
@@ -493,6 +610,31 @@ impl ChainClient {
         Ok(decimals)
     }
 
+    /// Returns how many of the token, in its smallest unit, the price oracle of the core contract gives for 1 ETH.
+    pub async fn query_oracle_price_in_eth(&self, token_name: &str) -> Result<U256> {
+        let token_addr = self.query_erc20_address(token_name).await?;
+        let oracle_addr = self.core.price_oracle().call().await?;
+        let abi = ethers::abi::parse_abi(&[
+            "function getRecentPriceInETH(address token) external view returns (uint256)",
+        ])?;
+        let oracle = Contract::new(oracle_addr, abi, self.client.clone());
+        let price = oracle
+            .method::<_, U256>("getRecentPriceInETH", token_addr)?
+            .call()
+            .await?;
+        Ok(price)
+    }
+
+    /// Gas the core contract charges on top of the email op for the claim of an unclaimed fund or state.
+    pub async fn query_unclaim_claim_gas(&self, is_fund: bool) -> Result<U256> {
+        let gas = if is_fund {
+            self.unclaims_handler.unclaimed_fund_claim_gas().await?
+        } else {
+            self.unclaims_handler.unclaimed_state_claim_gas().await?
+        };
+        Ok(gas)
+    }
+
     pub async fn query_token_name(&self, token_addr: Address) -> Result<String> {
         let name = self
             .token_registry
@@ -632,54 +774,35 @@ impl ChainClient {
         Ok(())
     }
 
-    pub async fn stream_unclaim_fund_registration<
-        F: FnMut(
-            email_wallet_events::UnclaimedFundRegisteredFilter,
-            LogMeta,
-        ) -> BoxFuture<'static, Result<()>>, // Specify that the closure returns a BoxFuture
-    >(
+    /// Unclaimed funds and states registered in the given block range, in the order they were logged.
+    pub async fn query_unclaim_registrations(
         &self,
         from_block: U64,
-        mut f: F,
-    ) -> Result<U64> {
-        let ev = self
-            .unclaims_handler
-            .event_for_name::<email_wallet_events::UnclaimedFundRegisteredFilter>(
-                "UnclaimedFundRegistered",
-            )?
-            .from_block(from_block);
-        let mut stream = ev.stream_with_meta().await?;
-        let mut last_block = from_block;
-        while let Some(Ok((event, meta))) = stream.next().await {
-            last_block = meta.block_number;
-            f(event, meta).await?; // Now you can directly await the closure
-        }
-        Ok(last_block)
+        to_block: U64,
+    ) -> Result<Vec<(UnclaimRegistration, LogMeta)>> {
+        let logs = self
+            .client
+            .get_logs(
+                &self
+                    .unclaim_registration_filter()
+                    .from_block(from_block)
+                    .to_block(to_block),
+            )
+            .await?;
+        Ok(logs
+            .iter()
+            .filter_map(decode_unclaim_registration)
+            .collect())
     }
 
-    pub async fn stream_unclaim_state_registration<
-        F: FnMut(
-            email_wallet_events::UnclaimedStateRegisteredFilter,
-            LogMeta,
-        ) -> BoxFuture<'static, Result<()>>, // Specify that the closure returns a BoxFuture
-    >(
-        &self,
-        from_block: U64,
-        mut f: F,
-    ) -> Result<U64> {
-        let ev = self
-            .unclaims_handler
-            .event_for_name::<email_wallet_events::UnclaimedStateRegisteredFilter>(
-                "UnclaimedStateRegistered",
-            )?
-            .from_block(from_block);
-        let mut stream = ev.stream_with_meta().await?;
-        let mut last_block = from_block;
-        while let Some(Ok((event, meta))) = stream.next().await {
-            last_block = meta.block_number;
-            f(event, meta).await?;
-        }
-        Ok(last_block)
+    /// Logs of unclaimed fund and state registrations, for `get_logs` and `eth_subscribe`.
+    pub fn unclaim_registration_filter(&self) -> Filter {
+        Filter::new()
+            .address(self.unclaims_handler.address())
+            .topic0(vec![
+                email_wallet_events::UnclaimedFundRegisteredFilter::signature(),
+                email_wallet_events::UnclaimedStateRegisteredFilter::signature(),
+            ])
     }
 
     pub async fn check_if_point_registered(&self, point: Point) -> Result<bool> {
@@ -750,8 +873,41 @@ impl ChainClient {
         Ok(is_valid)
     }
 
-    pub async fn get_latest_block_number(&self) -> U64 {
-        self.client.get_block_number().await.unwrap()
+    /// Checks the DKIM public key hash against the DKIM registry of the account, which the core contract uses.
+    pub async fn check_if_dkim_public_key_hash_valid_for_account(
+        &self,
+        account_salt: &AccountSalt,
+        domain_name: String,
+        public_key_hash: [u8; 32],
+    ) -> Result<bool> {
+        let is_valid = self
+            .account_handler
+            .is_dkim_public_key_hash_valid(
+                fr_to_bytes32(&account_salt.0)?,
+                domain_name,
+                public_key_hash,
+            )
+            .call()
+            .await?;
+        Ok(is_valid)
+    }
+
+    pub async fn resolve_ens_name(&self, ens_name: &str) -> Result<Address> {
+        let eth_addr = self.client.resolve_name(ens_name).await?;
+        Ok(eth_addr)
+    }
+
+    pub async fn query_extension_addr_of_name(&self, extension_name: &str) -> Result<Address> {
+        let extension_addr = self
+            .extension_handler
+            .address_of_extension_name(extension_name.to_string())
+            .call()
+            .await?;
+        Ok(extension_addr)
+    }
+
+    pub async fn get_latest_block_number(&self) -> Result<U64> {
+        Ok(self.client.get_block_number().await?)
     }
 
     pub async fn get_username_from_wallet(&self, account_salt: &AccountSalt) -> Result<String> {
@@ -789,6 +945,7 @@ impl ChainClient {
         let wallet = WalletContract::new(wallet_impl, self.client.clone());
         let oauth = IOauth::new(wallet.get_oauth().await?, self.client.clone());
         let call = oauth.register_ephe_addr(wallet_addr, ephe_addr);
+        let call = self.with_fees(call).await;
         let tx = call.send().await?;
 
         let receipt = tx
@@ -845,6 +1002,7 @@ impl ChainClient {
         let wallet_addr = tx.wallet_addr;
         let wallet = WalletContract::new(wallet_addr, self.client.clone());
         let call = wallet.execute_ephemeral_tx(tx);
+        let call = self.with_fees(call).await;
         let tx = call.send().await?;
 
         let receipt = tx
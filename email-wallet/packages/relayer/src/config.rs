@@ -27,6 +27,33 @@ pub struct RelayerConfig {
     pub onboarding_token_distribution_limit: u32,
     pub onboarding_reply_msg: String,
     pub safe_api_endpoint: String,
+    pub subject_aliases_path: Option<String>,
+    pub balance_tokens: Vec<String>,
+    pub fee_tokens: Vec<String>,
+    pub price_source: String,
+    pub claim_concurrency: usize,
+    pub claim_retry_base_secs: i64,
+    pub claim_retry_max_secs: i64,
+    pub claim_max_retries: i32,
+    pub expiry_reminder_offsets: String,
+    pub claim_link_base_url: Option<String>,
+    pub trusted_authserv_id: Option<String>,
+    pub psi_enabled: bool,
+    pub psi_peers: Option<String>,
+    pub chain_name: Option<String>,
+    pub chain_profiles_path: Option<String>,
+    pub chain_subscription_provider: Option<String>,
+    pub event_poll_interval_secs: u64,
+    pub fee_strategy: String,
+    pub fee_margin_percent: u64,
+    pub max_fee_per_gas: Option<U256>,
+    pub fee_history_blocks: u64,
+    pub min_relayer_eth_balance: U256,
+    pub min_onboarding_token_balance: Option<U256>,
+    pub monitor_interval_secs: u64,
+    pub alert_task_failure_threshold: u32,
+    pub alert_dedup_secs: i64,
+    pub alert_digest_secs: Option<u64>,
 }
 
 impl RelayerConfig {
@@ -178,6 +205,116 @@ impl RelayerConfig {
                     SAFE_API_ENDPOINT_KEY
                 )
             }),
+            subject_aliases_path: env::var(SUBJECT_ALIASES_PATH_KEY).ok(),
+            balance_tokens: env::var(BALANCE_TOKENS_KEY)
+                .unwrap_or_else(|_| "ETH,DAI,USDC,TEST".to_string())
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .collect(),
+            fee_tokens: env::var(FEE_TOKENS_KEY)
+                .unwrap_or_else(|_| "ETH,DAI,USDC".to_string())
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .collect(),
+            price_source: env::var(PRICE_SOURCE_KEY).unwrap_or_else(|_| "oracle".to_string()),
+            claim_concurrency: env::var(CLAIM_CONCURRENCY_KEY)
+                .map(|s| {
+                    s.parse()
+                        .unwrap_or_else(|_| panic!("Failed to parse claim_concurrency"))
+                })
+                .unwrap_or(4),
+            claim_retry_base_secs: env::var(CLAIM_RETRY_BASE_SECS_KEY)
+                .map(|s| {
+                    s.parse()
+                        .unwrap_or_else(|_| panic!("Failed to parse claim_retry_base_secs"))
+                })
+                .unwrap_or(120),
+            claim_retry_max_secs: env::var(CLAIM_RETRY_MAX_SECS_KEY)
+                .map(|s| {
+                    s.parse()
+                        .unwrap_or_else(|_| panic!("Failed to parse claim_retry_max_secs"))
+                })
+                .unwrap_or(6 * 60 * 60),
+            claim_max_retries: env::var(CLAIM_MAX_RETRIES_KEY)
+                .map(|s| {
+                    s.parse()
+                        .unwrap_or_else(|_| panic!("Failed to parse claim_max_retries"))
+                })
+                .unwrap_or(12),
+            expiry_reminder_offsets: env::var(EXPIRY_REMINDER_OFFSETS_KEY)
+                .unwrap_or_else(|_| "7d,1d".to_string()),
+            claim_link_base_url: env::var(CLAIM_LINK_BASE_URL_KEY).ok(),
+            trusted_authserv_id: env::var(TRUSTED_AUTHSERV_ID_KEY).ok(),
+            psi_enabled: env::var(PSI_ENABLED_KEY)
+                .map(|s| {
+                    s.parse()
+                        .unwrap_or_else(|_| panic!("Failed to parse psi_enabled"))
+                })
+                .unwrap_or(false),
+            psi_peers: env::var(PSI_PEERS_KEY).ok(),
+            chain_name: env::var(CHAIN_NAME_KEY).ok(),
+            chain_profiles_path: env::var(CHAIN_PROFILES_PATH_KEY).ok(),
+            chain_subscription_provider: env::var(CHAIN_SUBSCRIPTION_PROVIDER_KEY).ok(),
+            event_poll_interval_secs: env::var(EVENT_POLL_INTERVAL_SECS_KEY)
+                .map(|s| {
+                    s.parse()
+                        .unwrap_or_else(|_| panic!("Failed to parse event_poll_interval_secs"))
+                })
+                .unwrap_or(120),
+            fee_strategy: env::var(FEE_STRATEGY_KEY)
+                .unwrap_or_else(|_| FEE_STRATEGY_EIP1559.to_string()),
+            fee_margin_percent: env::var(FEE_MARGIN_PERCENT_KEY)
+                .map(|s| {
+                    s.parse()
+                        .unwrap_or_else(|_| panic!("Failed to parse fee_margin_percent"))
+                })
+                .unwrap_or(20),
+            max_fee_per_gas: env::var(MAX_FEE_PER_GAS_KEY).ok().map(|s| {
+                U256::from_dec_str(&s)
+                    .unwrap_or_else(|_| panic!("Failed to parse max_fee_per_gas: {}", s))
+            }),
+            fee_history_blocks: env::var(FEE_HISTORY_BLOCKS_KEY)
+                .map(|s| {
+                    s.parse()
+                        .unwrap_or_else(|_| panic!("Failed to parse fee_history_blocks"))
+                })
+                .unwrap_or(10),
+            min_relayer_eth_balance: env::var(MIN_RELAYER_ETH_BALANCE_KEY)
+                .map(|s| {
+                    U256::from_dec_str(&s).unwrap_or_else(|_| {
+                        panic!("Failed to parse min_relayer_eth_balance: {}", s)
+                    })
+                })
+                .unwrap_or(U256::exp10(16)),
+            min_onboarding_token_balance: env::var(MIN_ONBOARDING_TOKEN_BALANCE_KEY).ok().map(
+                |s| {
+                    U256::from_dec_str(&s).unwrap_or_else(|_| {
+                        panic!("Failed to parse min_onboarding_token_balance: {}", s)
+                    })
+                },
+            ),
+            monitor_interval_secs: env::var(MONITOR_INTERVAL_SECS_KEY)
+                .map(|s| {
+                    s.parse()
+                        .unwrap_or_else(|_| panic!("Failed to parse monitor_interval_secs"))
+                })
+                .unwrap_or(300),
+            alert_task_failure_threshold: env::var(ALERT_TASK_FAILURE_THRESHOLD_KEY)
+                .map(|s| {
+                    s.parse()
+                        .unwrap_or_else(|_| panic!("Failed to parse alert_task_failure_threshold"))
+                })
+                .unwrap_or(3),
+            alert_dedup_secs: env::var(ALERT_DEDUP_SECS_KEY)
+                .map(|s| {
+                    s.parse()
+                        .unwrap_or_else(|_| panic!("Failed to parse alert_dedup_secs"))
+                })
+                .unwrap_or(6 * 60 * 60),
+            alert_digest_secs: env::var(ALERT_DIGEST_SECS_KEY).ok().map(|s| {
+                s.parse()
+                    .unwrap_or_else(|_| panic!("Failed to parse alert_digest_secs"))
+            }),
         }
     }
 }
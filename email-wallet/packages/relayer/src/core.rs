@@ -7,6 +7,7 @@ use relayer_utils::*;
 
 use ethers::types::{Address, Bytes, U256};
 use ethers::utils::hex::FromHex;
+use mailparse::MailHeaderMap;
 
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
@@ -82,8 +83,12 @@ pub async fn handle_email(email: String) -> Result<(EmailWalletEvent, bool)> {
                 trace!(LOG, "User inserted"; "func" => function_name!());
             }
             info!(LOG, "Sender wallet address: {}", wallet_addr; "func" => function_name!());
+            DB.mark_claim_link_converted(&from_addr, now()).await?;
             let claims = DB.get_claims_by_email_addr(&from_addr).await?;
-            for claim in claims {
+            for mut claim in claims {
+                if claim.status != ClaimStatus::AccountPending {
+                    transition_claim(&mut claim, ClaimStatus::AccountPending, None).await?;
+                }
                 match claim_unclaims(claim.clone()).await {
                     Ok(value) => {
                         if let Err(e) = handle_email_event(value).await {
@@ -134,15 +139,189 @@ pub async fn handle_email(email: String) -> Result<(EmailWalletEvent, bool)> {
     trace!(LOG, "Original Subject: {}", original_subject; "func" => function_name!());
     let code_masked_subject = get_code_masked_subject(&original_subject)?;
     trace!(LOG, "Code Masked Subject: {}", code_masked_subject; "func" => function_name!());
-    let (command, skip_subject_prefix) =
-        subject_templates::extract_command_from_subject(&code_masked_subject, &account_salt)
-            .await?;
+    let (command, skip_subject_prefix) = match subject_templates::extract_command_from_subject(
+        &code_masked_subject,
+        &account_salt,
+    )
+    .await
+    {
+        Ok(res) => res,
+        Err(err) => {
+            // A localized subject cannot be proven as is, so ask the user to reply with the canonical one.
+            if let Some(canonical_subject) = canonicalize_subject_alias(&code_masked_subject) {
+                info!(LOG, "Subject alias resolved to: {}", canonical_subject; "func" => function_name!());
+                return Ok((
+                    EmailWalletEvent::ConfirmationRequest {
+                        email_addr: from_addr,
+                        subject: canonical_subject,
+                        wallet_addr,
+                        resolved_recipient: None,
+                    },
+                    false,
+                ));
+            }
+            return Err(err);
+        }
+    };
     let subject = code_masked_subject[skip_subject_prefix..].to_string();
     trace!(LOG, "Command: {}", command; "func" => function_name!());
     trace!(LOG, "Skip Subject Prefix: {}", skip_subject_prefix; "func" => function_name!());
     trace!(LOG, "Prefix Skipped Subject: {}", subject; "func" => function_name!());
-    let fee_token_name = select_fee_token(&account_salt).await?;
-    trace!(LOG, "Fee token name: {}", fee_token_name; "func" => function_name!());
+    // A nickname or an ENS name cannot be proven, so the user confirms the subject with the resolved address.
+    if let Some((resolved_subject, resolved_recipient)) =
+        resolve_subject_recipient(&from_addr, &command, &subject).await?
+    {
+        info!(LOG, "Recipient resolved: {}", resolved_recipient; "func" => function_name!());
+        return Ok((
+            EmailWalletEvent::ConfirmationRequest {
+                email_addr: from_addr,
+                subject: resolved_subject,
+                wallet_addr,
+                resolved_recipient: Some(resolved_recipient),
+            },
+            false,
+        ));
+    }
+    // Amounts with thousands separators or full-width digits cannot be proven as written either.
+    if let Some(canonical_subject) = canonicalize_amount_notation(&command, &subject) {
+        info!(LOG, "Amount notation normalized: {}", canonical_subject; "func" => function_name!());
+        return Ok((
+            EmailWalletEvent::ConfirmationRequest {
+                email_addr: from_addr,
+                subject: canonical_subject,
+                wallet_addr,
+                resolved_recipient: None,
+            },
+            false,
+        ));
+    }
+    if [CONTACT_COMMAND, CONTACTS_COMMAND].contains(&command.as_str()) {
+        check_dkim_authentication_results(&email, &from_addr)?;
+        let message = handle_contact_command(&command, &subject, &from_addr).await?;
+        return Ok((
+            EmailWalletEvent::Contacts {
+                contacts: DB.get_contacts(&from_addr).await?,
+                email_addr: from_addr,
+                message,
+                original_subject,
+                message_id: parsed_email.get_message_id()?,
+            },
+            false,
+        ));
+    }
+    if command == BALANCE_COMMAND {
+        extract_template_vals_balance(&subject)?;
+        // No proof is generated for this read-only command, so the DKIM signature is checked here.
+        check_dkim_authentication_results(&email, &from_addr)?;
+        let summary = get_balance_summary(&from_addr, &account_salt, wallet_addr).await?;
+        return Ok((
+            EmailWalletEvent::Balance {
+                email_addr: from_addr,
+                wallet_addr,
+                summary,
+                original_subject,
+                message_id: parsed_email.get_message_id()?,
+            },
+            false,
+        ));
+    }
+    if command == CANCEL_COMMAND {
+        let recipient_email_addr = match extract_template_vals_cancel(&subject)?.pop() {
+            Some(TemplateValue::Recipient {
+                email_addr: Some(email_addr),
+                ..
+            }) => email_addr,
+            _ => bail!("Only transfers to email addresses can be cancelled"),
+        };
+        check_dkim_authentication_results(&email, &from_addr)?;
+        return Ok((
+            request_cancellation(from_addr, wallet_addr, &recipient_email_addr).await?,
+            false,
+        ));
+    }
+    if [SCHEDULE_COMMAND, SCHEDULES_COMMAND, UNSCHEDULE_COMMAND].contains(&command.as_str()) {
+        check_dkim_authentication_results(&email, &from_addr)?;
+        let message = handle_schedule_command(&command, &subject, &from_addr, wallet_addr).await?;
+        return Ok((
+            EmailWalletEvent::Schedules {
+                schedules: DB.get_schedules(&from_addr).await?,
+                email_addr: from_addr,
+                message,
+                original_subject,
+                message_id: parsed_email.get_message_id()?,
+            },
+            false,
+        ));
+    }
+    if command == REQUEST_COMMAND {
+        check_dkim_authentication_results(&email, &from_addr)?;
+        let request = create_payment_request(&from_addr, &subject).await?;
+        if let Err(e) = handle_email_event(EmailWalletEvent::PaymentRequested {
+            request: request.clone(),
+            is_reminder: false,
+        })
+        .await
+        {
+            error!(LOG, "Error handling email event: {}", e; "func" => function_name!());
+        }
+        return Ok((
+            EmailWalletEvent::PaymentRequestCreated {
+                request,
+                original_subject,
+                message_id: parsed_email.get_message_id()?,
+            },
+            false,
+        ));
+    }
+    if command == NOTIFICATIONS_COMMAND {
+        check_dkim_authentication_results(&email, &from_addr)?;
+        let message = handle_notifications_command(&from_addr, &subject).await?;
+        return Ok((
+            EmailWalletEvent::NotificationPreference {
+                email_addr: from_addr,
+                message,
+                original_subject,
+                message_id: parsed_email.get_message_id()?,
+            },
+            false,
+        ));
+    }
+    if command == FEE_COMMAND {
+        check_dkim_authentication_results(&email, &from_addr)?;
+        let message = handle_fee_command(&from_addr, &subject).await?;
+        return Ok((
+            EmailWalletEvent::FeeTokenPreference {
+                email_addr: from_addr,
+                message,
+                original_subject,
+                message_id: parsed_email.get_message_id()?,
+            },
+            false,
+        ));
+    }
+    if command == SEND_COMMAND {
+        // A subject listing several transfers, or a CSV attachment when the subject is not a single transfer.
+        let batch_subjects = match split_batch_subject(&subject) {
+            Some(subjects) => Some(subjects),
+            None if extract_template_vals_send(&subject).is_err() => read_batch_csv(&email)?,
+            None => None,
+        };
+        if let Some(subjects) = batch_subjects {
+            check_dkim_authentication_results(&email, &from_addr)?;
+            return Ok((
+                request_batch_send(
+                    from_addr,
+                    wallet_addr,
+                    &account_salt,
+                    &subjects,
+                    original_subject,
+                    parsed_email.get_message_id()?,
+                )
+                .await?,
+                false,
+            ));
+        }
+    }
     let (template_idx, template_vals) = match command.as_str() {
         SEND_COMMAND => (0, extract_template_vals_send(&subject)?),
         EXECUTE_COMMAND => (0, extract_template_vals_execute(&subject)?),
@@ -157,11 +336,7 @@ pub async fn handle_email(email: String) -> Result<(EmailWalletEvent, bool)> {
             let subject_templates = CLIENT
                 .query_subject_templates_of_extension(extension_addr)
                 .await?;
-            let (idx, vals) = extract_template_vals_and_idx(&subject, subject_templates)?;
-            if idx.is_none() {
-                bail!(WRONG_SUBJECT_FORMAT);
-            }
-            (idx.unwrap(), vals)
+            extract_template_vals_and_idx(&subject, subject_templates)?
         }
     };
     let execute_call_data = if command == EXECUTE_COMMAND {
@@ -250,7 +425,7 @@ pub async fn handle_email(email: String) -> Result<(EmailWalletEvent, bool)> {
                 info!(LOG, "decimal size: {}", decimal_size; "func" => function_name!());
                 WalletParams {
                     token_name: token_name.clone().to_string(),
-                    amount: TemplateValue::amount_to_uint(amount, decimal_size),
+                    amount: TemplateValue::amount_to_uint(amount, decimal_size)?,
                 }
             } else {
                 bail!(WRONG_SUBJECT_FORMAT)
@@ -296,6 +471,24 @@ pub async fn handle_email(email: String) -> Result<(EmailWalletEvent, bool)> {
         }
     };
     trace!(LOG, "parameter constructed"; "func" => function_name!());
+    // Everything that can fail without a proof is checked before the slow proof generation.
+    let fee_subject_prefix = &code_masked_subject[..skip_subject_prefix];
+    let fee_quote = preflight_email_op(
+        &parsed_email,
+        &PreflightParams {
+            from_addr: &from_addr,
+            account_salt: &account_salt,
+            wallet_addr,
+            command: &command,
+            template_vals: &template_vals,
+            extension_name: &extension_name,
+            recipient_email_addr: recipient_email_addr.as_deref(),
+            recipient_eth_addr,
+            subject_prefix: fee_subject_prefix,
+        },
+    )
+    .await?;
+    trace!(LOG, "Fee token name: {}", fee_quote.token_name; "func" => function_name!());
     let input = generate_email_sender_input(&email, &account_code_str).await?;
     trace!(LOG, "input generated"; "func" => function_name!());
     let (email_proof, pub_signals) =
@@ -332,8 +525,8 @@ pub async fn handle_email(email: String) -> Result<(EmailWalletEvent, bool)> {
         timestamp,
         masked_subject,
         skip_subject_prefix: U256::from(skip_subject_prefix),
-        fee_token_name,
-        fee_per_gas: *FEE_PER_GAS.get().unwrap(),
+        fee_token_name: fee_quote.token_name,
+        fee_per_gas: fee_quote.fee_per_gas,
         execute_call_data,
         extension_name,
         new_wallet_owner,
@@ -344,8 +537,28 @@ pub async fn handle_email(email: String) -> Result<(EmailWalletEvent, bool)> {
     };
     trace!(LOG, "email_op constructed: {:?}", email_op; "func" => function_name!());
     CLIENT.validate_email_op(email_op.clone()).await?;
+    let cancelled_claim = if command == EXECUTE_COMMAND {
+        get_claim_cancelled_by(&email_op.execute_call_data).await?
+    } else {
+        None
+    };
     let (tx_hash, registered_unclaim_id) = CLIENT.handle_email_op(email_op.clone()).await?;
     info!(LOG, "email_op broadcased to chain: {}", tx_hash; "func" => function_name!());
+    if let Some((mut claim, asset)) = cancelled_claim {
+        transition_claim(&mut claim, ClaimStatus::Voided, None).await?;
+        info!(LOG, "claim {} cancelled by the sender", claim.id; "func" => function_name!());
+        return Ok((
+            EmailWalletEvent::Cancelled {
+                claim,
+                sender_email_addr: from_addr,
+                asset,
+                original_subject,
+                message_id: parsed_email.get_message_id()?,
+                tx_hash,
+            },
+            false,
+        ));
+    }
     if let Some(email_addr) = recipient_email_addr.as_ref() {
         info!(LOG, "recipient email address: {}", email_addr; "func" => function_name!());
         let commit_rand = extract_rand_from_signature(&parsed_email.signature)?;
@@ -369,7 +582,9 @@ pub async fn handle_email(email: String) -> Result<(EmailWalletEvent, bool)> {
             expiry_time,
             is_fund,
             is_announced: false,
-            is_seen: false,
+            status: ClaimStatus::Registered,
+            attempts: 0,
+            sender: wallet_addr,
         };
         match claim_unclaims(claim.clone()).await {
             Ok(value) => {
@@ -381,6 +596,21 @@ pub async fn handle_email(email: String) -> Result<(EmailWalletEvent, bool)> {
         }
         trace!(LOG, "Added claim"; "func" => function_name!());
     }
+    if command == SEND_COMMAND || command == BATCH_EXTENSION_COMMAND {
+        // The batch tag is in the subject prefix.
+        if let Err(e) =
+            record_batch_result(&from_addr, &code_masked_subject, BATCH_ITEM_SENT, &tx_hash).await
+        {
+            error!(LOG, "Error recording batch result: {}", e; "func" => function_name!());
+        }
+    }
+    if command == SEND_COMMAND {
+        if let Err(e) =
+            record_payment_request_paid(&from_addr, &code_masked_subject, &tx_hash).await
+        {
+            error!(LOG, "Error recording payment request: {}", e; "func" => function_name!());
+        }
+    }
     let message_id = parsed_email.get_message_id()?;
 
     Ok((
@@ -463,28 +693,48 @@ pub fn calculate_default_hash(input: &str) -> String {
     hash_code.to_string()
 }
 
-pub async fn select_fee_token(account_salt: &AccountSalt) -> Result<String> {
-    let eth_balance = match CLIENT.query_user_erc20_balance(account_salt, "ETH").await {
-        Ok(balance) => balance,
-        Err(_) => U256::from(0),
-    };
-    let dai_balance = match CLIENT.query_user_erc20_balance(account_salt, "DAI").await {
-        Ok(balance) => balance,
-        Err(_) => U256::from(0),
-    };
-    let usdc_balance = match CLIENT.query_user_erc20_balance(account_salt, "USDC").await {
-        Ok(balance) => balance,
-        Err(_) => U256::from(0),
+/// Checks the DKIM result that our own mail server recorded in the topmost `Authentication-Results` header.
+/// Commands answered without an `email_sender` proof rely on it to authenticate the sender.
+pub fn check_dkim_authentication_results(email: &str, from_addr: &str) -> Result<()> {
+    let Some(authserv_id) = TRUSTED_AUTHSERV_ID.get().unwrap() else {
+        bail!("This relayer cannot authenticate commands without a proof");
     };
-    let usdc_balance = usdc_balance * (10u64.pow(18 - 6));
-    let max = eth_balance.max(dai_balance).max(usdc_balance);
-    if max == eth_balance {
-        Ok("ETH".to_string())
-    } else if max == dai_balance {
-        Ok("DAI".to_string())
-    } else {
-        Ok("USDC".to_string())
+    let parsed = mailparse::parse_mail(email.as_bytes())?;
+    let results = parsed
+        .headers
+        .get_first_value("Authentication-Results")
+        .ok_or(anyhow!("The email has no DKIM verification result"))?;
+    if !is_trusted_dkim_pass(&results, authserv_id, from_addr) {
+        bail!("The DKIM signature of your email could not be verified");
     }
+    Ok(())
+}
+
+// Our mail server adds its header on top, so a header with another authserv-id on top means it did not
+// check the email, and headers further down may have been written by anyone.
+fn is_trusted_dkim_pass(results: &str, authserv_id: &str, from_addr: &str) -> bool {
+    let mut results = results.split(';');
+    let header_authserv_id = results
+        .next()
+        .and_then(|id| id.split_whitespace().next())
+        .unwrap_or_default();
+    if !header_authserv_id.eq_ignore_ascii_case(authserv_id) {
+        return false;
+    }
+    let domain = from_addr
+        .rsplit('@')
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    results.any(|result| {
+        let words: Vec<String> = result
+            .split_whitespace()
+            .map(|word| word.to_lowercase())
+            .collect();
+        words.first().map(|w| w.as_str()) == Some("dkim=pass")
+            && (words.contains(&format!("header.d={}", domain))
+                || words.contains(&format!("header.i=@{}", domain)))
+    })
 }
 
 #[named]
@@ -549,3 +799,48 @@ pub async fn check_and_update_dkim(email: &str, parsed_email: &ParsedEmail) -> R
     info!(LOG, "DKIM registry updated {:?}", tx_hash; "func" => function_name!());
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trusts_dkim_results_of_our_mail_server() {
+        let results = "mx.relayer.example; dkim=pass header.d=example.com header.s=s1; spf=pass";
+        assert!(is_trusted_dkim_pass(
+            results,
+            "MX.relayer.example",
+            "alice@example.com"
+        ));
+        assert!(is_trusted_dkim_pass(
+            "mx.relayer.example 1; dkim=pass header.i=@example.com",
+            "mx.relayer.example",
+            "alice@Example.com"
+        ));
+    }
+
+    #[test]
+    fn rejects_foreign_or_failed_dkim_results() {
+        // Written by the sender or a server on the way, not by ours.
+        assert!(!is_trusted_dkim_pass(
+            "mx.attacker.example; dkim=pass header.d=example.com",
+            "mx.relayer.example",
+            "alice@example.com"
+        ));
+        assert!(!is_trusted_dkim_pass(
+            "mx.relayer.example; dkim=fail header.d=example.com",
+            "mx.relayer.example",
+            "alice@example.com"
+        ));
+        assert!(!is_trusted_dkim_pass(
+            "mx.relayer.example; dkim=pass header.d=attacker.example",
+            "mx.relayer.example",
+            "alice@example.com"
+        ));
+        assert!(!is_trusted_dkim_pass(
+            "dkim=pass header.d=example.com",
+            "mx.relayer.example",
+            "alice@example.com"
+        ));
+    }
+}
@@ -2,83 +2,219 @@ use crate::*;
 
 use sqlx::{postgres::PgPool, Row};
 
+/// Tables whose rows belong to a chain, see `chain_id`.
+const CHAIN_SCOPED_TABLES: [&str; 10] = [
+    "users",
+    "claims",
+    "batches",
+    "schedules",
+    "payment_requests",
+    "fee_token_preferences",
+    "contacts",
+    "fee_caps",
+    "notification_preferences",
+    "claim_links",
+];
+
+/// The statements that create and migrate the schema, in the order they run.
+/// Rows from before multi-chain support are given `default_chain_id`.
+fn schema_statements(default_chain_id: u32) -> Vec<String> {
+    let mut statements: Vec<String> = [
+        "CREATE TABLE IF NOT EXISTS users (
+            email_address TEXT PRIMARY KEY,
+            account_code TEXT NOT NULL,
+            tx_hash TEXT NOT NULL,
+            is_onboarded BOOLEAN NOT NULL DEFAULT FALSE,
+            wallet_addr TEXT NOT NULL
+        );",
+        "CREATE TABLE IF NOT EXISTS claims (
+            tx_hash TEXT PRIMARY KEY,
+            id TEXT NOT NULL,
+            email_address TEXT NOT NULL,
+            random TEXT NOT NULL,
+            email_addr_commit TEXT NOT NULL,
+            expiry_time BIGINT NOT NULL,
+            is_fund BOOLEAN NOT NULL,
+            is_announced BOOLEAN NOT NULL,
+            is_deleted BOOLEAN NOT NULL DEFAULT FALSE,
+            is_seen BOOLEAN NOT NULL DEFAULT FALSE
+        );",
+        "ALTER TABLE claims ADD COLUMN IF NOT EXISTS is_undeliverable BOOLEAN NOT NULL DEFAULT FALSE;",
+        // Claims used to keep their state in the is_deleted, is_seen and is_undeliverable flags.
+        // A deleted claim may also have been voided, but nothing recorded which, so it keeps the meaning
+        // deletion had, claimed. Both are final.
+        "ALTER TABLE claims ADD COLUMN IF NOT EXISTS status TEXT;",
+        "ALTER TABLE claims ADD COLUMN IF NOT EXISTS attempts INTEGER NOT NULL DEFAULT 0;",
+        "ALTER TABLE claims ADD COLUMN IF NOT EXISTS last_error TEXT;",
+        "ALTER TABLE claims ADD COLUMN IF NOT EXISTS created_at BIGINT NOT NULL DEFAULT 0;",
+        "ALTER TABLE claims ADD COLUMN IF NOT EXISTS updated_at BIGINT NOT NULL DEFAULT 0;",
+        "UPDATE claims SET status = CASE
+            WHEN is_deleted THEN 'claimed'
+            WHEN is_undeliverable THEN 'failed'
+            WHEN is_seen THEN 'invited'
+            ELSE 'registered'
+        END WHERE status IS NULL;",
+        "ALTER TABLE claims ALTER COLUMN status SET DEFAULT 'registered';",
+        "ALTER TABLE claims ALTER COLUMN status SET NOT NULL;",
+        "CREATE INDEX IF NOT EXISTS claims_status_idx ON claims (status, expiry_time);",
+        "ALTER TABLE claims ADD COLUMN IF NOT EXISTS retries INTEGER NOT NULL DEFAULT 0;",
+        "ALTER TABLE claims ADD COLUMN IF NOT EXISTS next_attempt_at BIGINT NOT NULL DEFAULT 0;",
+        "ALTER TABLE claims ADD COLUMN IF NOT EXISTS expiry_reminders_sent INTEGER NOT NULL DEFAULT 0;",
+        "ALTER TABLE claims ADD COLUMN IF NOT EXISTS sender_wallet_addr TEXT NOT NULL DEFAULT '';",
+        "CREATE TABLE IF NOT EXISTS safe (
+            wallet_addr TEXT PRIMARY KEY,
+            safe_addr TEXT[] NOT NULL
+        );",
+        "CREATE TABLE IF NOT EXISTS safe_txs (
+            tx_hash TEXT NOT NULL,
+            wallet_addr TEXT NOT NULL,
+            PRIMARY KEY (tx_hash, wallet_addr)
+        );",
+        "CREATE TABLE IF NOT EXISTS ephe_addr_info (
+            request_id TEXT PRIMARY KEY,
+            wallet_addr TEXT NOT NULL,
+            ephe_addr TEXT NOT NULL,
+            nonce TEXT NOT NULL
+        );",
+        "CREATE TABLE IF NOT EXISTS batches (
+            id TEXT PRIMARY KEY,
+            email_address TEXT NOT NULL,
+            original_subject TEXT NOT NULL,
+            message_id TEXT NOT NULL,
+            created_at BIGINT NOT NULL,
+            is_completed BOOLEAN NOT NULL DEFAULT FALSE
+        );",
+        "CREATE TABLE IF NOT EXISTS batch_items (
+            batch_id TEXT NOT NULL,
+            idx INTEGER NOT NULL,
+            subject TEXT NOT NULL,
+            recipient TEXT NOT NULL,
+            status TEXT NOT NULL,
+            detail TEXT,
+            PRIMARY KEY (batch_id, idx)
+        );",
+        "ALTER TABLE batch_items ADD COLUMN IF NOT EXISTS confirmation_subject TEXT NOT NULL DEFAULT '';",
+        "CREATE TABLE IF NOT EXISTS schedules (
+            id BIGSERIAL PRIMARY KEY,
+            email_address TEXT NOT NULL,
+            wallet_addr TEXT NOT NULL,
+            subject TEXT NOT NULL,
+            frequency TEXT NOT NULL,
+            day INTEGER NOT NULL,
+            next_due_at BIGINT NOT NULL,
+            is_cancelled BOOLEAN NOT NULL DEFAULT FALSE
+        );",
+        "CREATE TABLE IF NOT EXISTS payment_requests (
+            id BIGSERIAL PRIMARY KEY,
+            requester_email TEXT NOT NULL,
+            payer_email TEXT NOT NULL,
+            asset TEXT NOT NULL,
+            subject TEXT NOT NULL,
+            status TEXT NOT NULL,
+            tx_hash TEXT,
+            reminders_sent INTEGER NOT NULL DEFAULT 0,
+            last_notified_at BIGINT NOT NULL
+        );",
+        "CREATE TABLE IF NOT EXISTS fee_token_preferences (
+            email_address TEXT PRIMARY KEY,
+            token_name TEXT NOT NULL
+        );",
+        "CREATE TABLE IF NOT EXISTS contacts (
+            email_address TEXT NOT NULL,
+            nickname TEXT NOT NULL,
+            recipient TEXT NOT NULL,
+            PRIMARY KEY (email_address, nickname)
+        );",
+        "CREATE TABLE IF NOT EXISTS fee_caps (
+            email_address TEXT PRIMARY KEY,
+            amount TEXT NOT NULL,
+            token_name TEXT NOT NULL
+        );",
+        "CREATE TABLE IF NOT EXISTS notification_preferences (
+            email_address TEXT PRIMARY KEY,
+            sender_notifications BOOLEAN NOT NULL
+        );",
+        "CREATE TABLE IF NOT EXISTS claim_links (
+            email_address TEXT PRIMARY KEY,
+            clicks INTEGER NOT NULL DEFAULT 0,
+            first_clicked_at BIGINT NOT NULL,
+            last_emailed_at BIGINT,
+            converted_at BIGINT
+        );",
+    ]
+    .iter()
+    .map(|statement| statement.to_string())
+    .collect();
+    // Rows from before multi-chain support belong to the default chain.
+    // An email address has a separate account, and so a separate users row, on every chain.
+    for table in CHAIN_SCOPED_TABLES {
+        statements.push(format!(
+            "ALTER TABLE {} ADD COLUMN IF NOT EXISTS chain_id BIGINT NOT NULL DEFAULT {};",
+            table, default_chain_id
+        ));
+    }
+    statements.extend(
+        [
+            "ALTER TABLE users DROP CONSTRAINT IF EXISTS users_pkey;",
+            "CREATE UNIQUE INDEX IF NOT EXISTS users_email_chain_idx ON users (email_address, chain_id);",
+            "CREATE INDEX IF NOT EXISTS claims_chain_idx ON claims (chain_id, id, is_fund);",
+            "CREATE INDEX IF NOT EXISTS claims_sender_idx ON claims (sender_wallet_addr, chain_id);",
+            // Settings, contacts and claim links are kept per chain like the accounts they belong to.
+            "ALTER TABLE fee_token_preferences DROP CONSTRAINT IF EXISTS fee_token_preferences_pkey;",
+            "CREATE UNIQUE INDEX IF NOT EXISTS fee_token_preferences_email_chain_idx ON fee_token_preferences (email_address, chain_id);",
+            "ALTER TABLE contacts DROP CONSTRAINT IF EXISTS contacts_pkey;",
+            "CREATE UNIQUE INDEX IF NOT EXISTS contacts_email_chain_nickname_idx ON contacts (email_address, chain_id, nickname);",
+            "ALTER TABLE fee_caps DROP CONSTRAINT IF EXISTS fee_caps_pkey;",
+            "CREATE UNIQUE INDEX IF NOT EXISTS fee_caps_email_chain_idx ON fee_caps (email_address, chain_id);",
+            "ALTER TABLE notification_preferences DROP CONSTRAINT IF EXISTS notification_preferences_pkey;",
+            "CREATE UNIQUE INDEX IF NOT EXISTS notification_preferences_email_chain_idx ON notification_preferences (email_address, chain_id);",
+            "ALTER TABLE claim_links DROP CONSTRAINT IF EXISTS claim_links_pkey;",
+            "CREATE UNIQUE INDEX IF NOT EXISTS claim_links_email_chain_idx ON claim_links (email_address, chain_id);",
+            "CREATE TABLE IF NOT EXISTS event_checkpoints (
+                chain_id BIGINT PRIMARY KEY,
+                block_number BIGINT NOT NULL
+            );",
+            "CREATE TABLE IF NOT EXISTS email_op_fees (
+                tx_hash TEXT PRIMARY KEY,
+                chain_id BIGINT NOT NULL,
+                command TEXT NOT NULL,
+                fee_token TEXT NOT NULL,
+                fee_per_gas_charged TEXT NOT NULL,
+                gas_used TEXT NOT NULL,
+                effective_gas_price TEXT NOT NULL,
+                fee_charged TEXT NOT NULL,
+                fee_charged_wei TEXT NOT NULL,
+                fee_paid_wei TEXT NOT NULL,
+                created_at BIGINT NOT NULL
+            );",
+        ]
+        .iter()
+        .map(|statement| statement.to_string()),
+    );
+    statements
+}
+
 pub struct Database {
     db: PgPool,
 }
 
 impl Database {
-    pub async fn open(path: &str) -> Result<Self> {
+    pub async fn open(path: &str, default_chain_id: u32) -> Result<Self> {
         let res = Self {
             db: PgPool::connect(path)
                 .await
                 .map_err(|e| anyhow::anyhow!(e))?,
         };
 
-        res.setup_database().await?;
+        res.setup_database(default_chain_id).await?;
 
         Ok(res)
     }
 
-    pub async fn setup_database(&self) -> Result<()> {
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS users (
-                email_address TEXT PRIMARY KEY,
-                account_code TEXT NOT NULL,
-                tx_hash TEXT NOT NULL,
-                is_onboarded BOOLEAN NOT NULL DEFAULT FALSE,
-                wallet_addr TEXT NOT NULL
-            );",
-        )
-        .execute(&self.db)
-        .await?;
-
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS claims (
-                tx_hash TEXT PRIMARY KEY,
-                id TEXT NOT NULL,
-                email_address TEXT NOT NULL,
-                random TEXT NOT NULL,
-                email_addr_commit TEXT NOT NULL,
-                expiry_time BIGINT NOT NULL,
-                is_fund BOOLEAN NOT NULL,
-                is_announced BOOLEAN NOT NULL,
-                is_deleted BOOLEAN NOT NULL DEFAULT FALSE,
-                is_seen BOOLEAN NOT NULL DEFAULT FALSE
-            );",
-        )
-        .execute(&self.db)
-        .await?;
-
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS safe (
-                wallet_addr TEXT PRIMARY KEY,
-                safe_addr TEXT[] NOT NULL
-            );",
-        )
-        .execute(&self.db)
-        .await?;
-
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS safe_txs (
-                tx_hash TEXT NOT NULL,
-                wallet_addr TEXT NOT NULL,
-                PRIMARY KEY (tx_hash, wallet_addr)
-            );",
-        )
-        .execute(&self.db)
-        .await?;
-
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS ephe_addr_info (
-                request_id TEXT PRIMARY KEY,
-                wallet_addr TEXT NOT NULL,
-                ephe_addr TEXT NOT NULL,
-                nonce TEXT NOT NULL
-            );",
-        )
-        .execute(&self.db)
-        .await?;
-
+    pub async fn setup_database(&self, default_chain_id: u32) -> Result<()> {
+        for statement in schema_statements(default_chain_id) {
+            sqlx::query(&statement).execute(&self.db).await?;
+        }
         Ok(())
     }
 
@@ -92,13 +228,14 @@ impl Database {
         wallet_addr: &str,
     ) -> Result<()> {
         let row = sqlx::query(
-            "INSERT INTO users (email_address, account_code, tx_hash, is_onboarded, wallet_addr) VALUES ($1, $2, $3, $4, $5) RETURNING *",
+            "INSERT INTO users (email_address, account_code, tx_hash, is_onboarded, wallet_addr, chain_id) VALUES ($1, $2, $3, $4, $5, $6) RETURNING *",
         )
         .bind(email_address)
         .bind(account_code)
         .bind(tx_hash)
         .bind(is_onboarded)
         .bind(wallet_addr)
+        .bind(chain_id())
         .fetch_one(&self.db)
         .await?;
         info!(
@@ -113,10 +250,11 @@ impl Database {
     pub async fn user_onborded(&self, email_address: &str, tx_hash: &str) -> Result<()> {
         info!(LOG, "email_address {}", email_address; "func" => function_name!());
         let res = sqlx::query(
-            "UPDATE users SET is_onboarded = TRUE, tx_hash = $1 WHERE email_address = $2",
+            "UPDATE users SET is_onboarded = TRUE, tx_hash = $1 WHERE email_address = $2 AND chain_id = $3",
         )
         .bind(tx_hash)
         .bind(email_address)
+        .bind(chain_id())
         .execute(&self.db)
         .await?;
         info!(
@@ -127,143 +265,80 @@ impl Database {
         Ok(())
     }
 
-    pub async fn get_claims_by_id(&self, id: &U256) -> Result<Vec<Claim>> {
-        let mut vec = Vec::new();
-
-        let rows = sqlx::query("SELECT * FROM claims WHERE id = $1 AND is_deleted = FALSE")
-            .bind(u256_to_hex(id))
-            .fetch_all(&self.db)
-            .await?;
-
-        for row in rows {
-            let tx_hash: String = row.get("tx_hash");
-            let commit: String = row.get("email_addr_commit");
-            let email_address: String = row.get("email_address");
-            let random: String = row.get("random");
-            let expiry_time: i64 = row.get("expiry_time");
-            let is_fund: bool = row.get("is_fund");
-            let is_announced: bool = row.get("is_announced");
-            let is_seen: bool = row.get("is_seen");
-            vec.push(Claim {
-                tx_hash,
-                id: *id,
-                email_address,
-                random,
-                commit,
-                expiry_time,
-                is_fund,
-                is_announced,
-                is_seen,
-            })
-        }
-        Ok(vec)
+    pub async fn get_claim(&self, id: &U256, is_fund: bool) -> Result<Option<Claim>> {
+        let row =
+            sqlx::query("SELECT * FROM claims WHERE id = $1 AND is_fund = $2 AND chain_id = $3")
+                .bind(u256_to_hex(id))
+                .bind(is_fund)
+                .bind(chain_id())
+                .fetch_optional(&self.db)
+                .await?;
+        row.as_ref().map(claim_from_row).transpose()
     }
 
+    /// Claims to the email address that can still be claimed.
     pub async fn get_claims_by_email_addr(&self, email_addr: &str) -> Result<Vec<Claim>> {
-        let mut vec = Vec::new();
-
-        let rows =
-            sqlx::query("SELECT * FROM claims WHERE email_address = $1 AND is_deleted = FALSE")
-                .bind(email_addr)
-                .fetch_all(&self.db)
-                .await?;
-
-        for row in rows {
-            let tx_hash: String = row.get("tx_hash");
-            let id: String = row.get("id");
-            let commit: String = row.get("email_addr_commit");
-            let email_address: String = row.get("email_address");
-            let random: String = row.get("random");
-            let expiry_time: i64 = row.get("expiry_time");
-            let is_fund: bool = row.get("is_fund");
-            let is_announced: bool = row.get("is_announced");
-            let is_seen: bool = row.get("is_seen");
-            vec.push(Claim {
-                tx_hash,
-                id: hex_to_u256(&id)?,
-                email_address,
-                random,
-                commit,
-                expiry_time,
-                is_fund,
-                is_announced,
-                is_seen,
-            })
-        }
-        Ok(vec)
+        let rows = sqlx::query(
+            "SELECT * FROM claims WHERE email_address = $1 AND status = ANY($2) AND chain_id = $3",
+        )
+        .bind(email_addr)
+        .bind(active_claim_statuses())
+        .bind(chain_id())
+        .fetch_all(&self.db)
+        .await?;
+        rows.iter().map(claim_from_row).collect()
     }
 
     pub async fn get_claims_unexpired(&self, now: i64) -> Result<Vec<Claim>> {
-        let mut vec = Vec::new();
-        let rows =
-            sqlx::query("SELECT * FROM claims WHERE expiry_time > $1 AND is_deleted = FALSE")
-                .bind(now)
-                .fetch_all(&self.db)
-                .await?;
+        let rows = sqlx::query(
+            "SELECT * FROM claims WHERE expiry_time > $1 AND status = ANY($2) AND chain_id = $3",
+        )
+        .bind(now)
+        .bind(active_claim_statuses())
+        .bind(chain_id())
+        .fetch_all(&self.db)
+        .await?;
+        rows.iter().map(claim_from_row).collect()
+    }
 
-        for row in rows {
-            let tx_hash: String = row.get("tx_hash");
-            let id: String = row.get("id");
-            let commit: String = row.get("email_addr_commit");
-            let email_address: String = row.get("email_address");
-            let random: String = row.get("random");
-            let expiry_time: i64 = row.get("expiry_time");
-            let is_fund: bool = row.get("is_fund");
-            let is_announced: bool = row.get("is_announced");
-            let is_seen: bool = row.get("is_seen");
-            vec.push(Claim {
-                tx_hash,
-                id: hex_to_u256(&id)?,
-                email_address,
-                random,
-                commit,
-                expiry_time,
-                is_fund,
-                is_announced,
-                is_seen,
-            })
-        }
-        Ok(vec)
+    /// Unexpired unclaimed funds sent by the wallet `sender`.
+    pub async fn get_outgoing_fund_claims_unexpired(
+        &self,
+        sender: &Address,
+        now: i64,
+    ) -> Result<Vec<Claim>> {
+        let rows = sqlx::query(
+            "SELECT * FROM claims WHERE sender_wallet_addr = $1 AND is_fund = TRUE AND expiry_time > $2 AND status = ANY($3) AND chain_id = $4",
+        )
+        .bind(address_to_hex(sender))
+        .bind(now)
+        .bind(active_claim_statuses())
+        .bind(chain_id())
+        .fetch_all(&self.db)
+        .await?;
+        rows.iter().map(claim_from_row).collect()
     }
 
+    /// Expired claims that still have to be voided, including failed ones that have attempts left.
     pub async fn get_claims_expired(&self, now: i64) -> Result<Vec<Claim>> {
-        let mut vec = Vec::new();
-        let rows =
-            sqlx::query("SELECT * FROM claims WHERE expiry_time < $1 AND is_deleted = FALSE")
-                .bind(now)
-                .fetch_all(&self.db)
-                .await?;
-
-        for row in rows {
-            let tx_hash: String = row.get("tx_hash");
-            let id: String = row.get("id");
-            let commit: String = row.get("email_addr_commit");
-            let email_address: String = row.get("email_address");
-            let random: String = row.get("random");
-            let expiry_time: i64 = row.get("expiry_time");
-            let is_fund: bool = row.get("is_fund");
-            let is_announced: bool = row.get("is_announced");
-            let is_seen: bool = row.get("is_seen");
-            vec.push(Claim {
-                tx_hash,
-                id: hex_to_u256(&id)?,
-                email_address,
-                random,
-                commit,
-                expiry_time,
-                is_fund,
-                is_announced,
-                is_seen,
-            })
-        }
-        Ok(vec)
+        let rows = sqlx::query(
+            "SELECT * FROM claims WHERE expiry_time < $1 AND (status = ANY($2) OR (status = $3 AND attempts < $4)) AND chain_id = $5",
+        )
+        .bind(now)
+        .bind(active_claim_statuses())
+        .bind(ClaimStatus::Failed.as_str())
+        .bind(MAX_CLAIM_ATTEMPTS)
+        .bind(chain_id())
+        .fetch_all(&self.db)
+        .await?;
+        rows.iter().map(claim_from_row).collect()
     }
 
     #[named]
     pub async fn insert_claim(&self, claim: &Claim) -> Result<()> {
         info!(LOG, "expiry_time {}", claim.expiry_time; "func" => function_name!());
         let row = sqlx::query(
-            "INSERT INTO claims (tx_hash, id, email_address, random, email_addr_commit, expiry_time, is_fund, is_announced, is_seen) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *",
+            "INSERT INTO claims (tx_hash, id, email_address, random, email_addr_commit, expiry_time, is_fund, is_announced, status, attempts, created_at, updated_at, chain_id, sender_wallet_addr) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $11, $12, $13) RETURNING *",
         )
         .bind(claim.tx_hash.clone())
         .bind(u256_to_hex(&claim.id))
@@ -273,7 +348,11 @@ impl Database {
         .bind(claim.expiry_time)
         .bind(claim.is_fund)
         .bind(claim.is_announced)
-        .bind(claim.is_seen)
+        .bind(claim.status.as_str())
+        .bind(claim.attempts)
+        .bind(now())
+        .bind(chain_id())
+        .bind(address_to_hex(&claim.sender))
         .fetch_one(&self.db)
         .await?;
         info!(
@@ -284,18 +363,141 @@ impl Database {
         Ok(())
     }
 
-    pub async fn delete_claim(&self, id: &U256, is_fund: bool) -> Result<()> {
-        sqlx::query("UPDATE claims SET is_deleted=TRUE WHERE id = $1 AND is_fund = $2 AND is_deleted = FALSE")
-            .bind(u256_to_hex(id))
-            .bind(is_fund)
-            .execute(&self.db)
-            .await?;
-        Ok(())
+    /// Sets the status of the claim if it is still `claim.status`. Returns false if it was changed in the meantime.
+    /// Use `transition_claim`, which also checks that the transition is allowed.
+    pub async fn update_claim_status(
+        &self,
+        claim: &Claim,
+        status: ClaimStatus,
+        is_attempt: bool,
+        error: Option<&str>,
+    ) -> Result<bool> {
+        let res = sqlx::query(
+            "UPDATE claims SET status = $1, attempts = attempts + $2, last_error = COALESCE($3, last_error), updated_at = $4 WHERE id = $5 AND is_fund = $6 AND status = $7 AND chain_id = $8",
+        )
+        .bind(status.as_str())
+        .bind(i32::from(is_attempt))
+        .bind(error)
+        .bind(now())
+        .bind(u256_to_hex(&claim.id))
+        .bind(claim.is_fund)
+        .bind(claim.status.as_str())
+        .bind(chain_id())
+        .execute(&self.db)
+        .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Claims the claim loop should try now. Claims whose recipient was invited wait for the account creation,
+    /// which claims them directly.
+    pub async fn get_claims_due(&self, now: i64) -> Result<Vec<Claim>> {
+        let rows = sqlx::query(
+            "SELECT * FROM claims WHERE expiry_time > $1 AND next_attempt_at <= $1 AND status = ANY($2) AND chain_id = $3 ORDER BY next_attempt_at",
+        )
+        .bind(now)
+        .bind(due_claim_statuses())
+        .bind(chain_id())
+        .fetch_all(&self.db)
+        .await?;
+        rows.iter().map(claim_from_row).collect()
+    }
+
+    /// Pushes back the next try of a claim that is still waiting and returns how many times it has been retried,
+    /// or None if the claim has moved on.
+    pub async fn schedule_claim_retry(
+        &self,
+        id: &U256,
+        is_fund: bool,
+        now: i64,
+        policy: &ClaimRetryPolicy,
+        jitter: f64,
+    ) -> Result<Option<i32>> {
+        let row = sqlx::query(
+            "UPDATE claims SET retries = retries + 1 WHERE id = $1 AND is_fund = $2 AND status = ANY($3) AND chain_id = $4 RETURNING retries",
+        )
+        .bind(u256_to_hex(id))
+        .bind(is_fund)
+        .bind(due_claim_statuses())
+        .bind(chain_id())
+        .fetch_optional(&self.db)
+        .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let retries: i32 = row.get("retries");
+        sqlx::query(
+            "UPDATE claims SET next_attempt_at = $1 WHERE id = $2 AND is_fund = $3 AND chain_id = $4",
+        )
+        .bind(now + policy.backoff_secs(retries, jitter))
+        .bind(u256_to_hex(id))
+        .bind(is_fund)
+        .bind(chain_id())
+        .execute(&self.db)
+        .await?;
+        Ok(Some(retries))
+    }
+
+    /// Unclaimed claims expiring between `now` and `until`, with the number of expiry reminders sent for each.
+    /// Claims whose recipient has an account are claimed by the claim loop and need no reminder.
+    pub async fn get_claims_to_remind(&self, now: i64, until: i64) -> Result<Vec<(Claim, i32)>> {
+        let rows = sqlx::query(
+            "SELECT * FROM claims WHERE expiry_time > $1 AND expiry_time <= $2 AND status = ANY($3) AND chain_id = $4 ORDER BY expiry_time",
+        )
+        .bind(now)
+        .bind(until)
+        .bind(vec![
+            ClaimStatus::Registered.as_str().to_string(),
+            ClaimStatus::Invited.as_str().to_string(),
+        ])
+        .bind(chain_id())
+        .fetch_all(&self.db)
+        .await?;
+        rows.iter()
+            .map(|row| Ok((claim_from_row(row)?, row.get("expiry_reminders_sent"))))
+            .collect()
+    }
+
+    /// Returns false if another task already recorded the reminder.
+    pub async fn update_claim_reminders_sent(
+        &self,
+        claim: &Claim,
+        from: i32,
+        to: i32,
+    ) -> Result<bool> {
+        let res = sqlx::query(
+            "UPDATE claims SET expiry_reminders_sent = $1 WHERE id = $2 AND is_fund = $3 AND expiry_reminders_sent = $4 AND chain_id = $5",
+        )
+        .bind(to)
+        .bind(u256_to_hex(&claim.id))
+        .bind(claim.is_fund)
+        .bind(from)
+        .bind(chain_id())
+        .execute(&self.db)
+        .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Moves a claim whose invitation bounced to `failed`. Returns false if it was not active.
+    pub async fn mark_claim_undeliverable(&self, id: &U256, is_fund: bool) -> Result<bool> {
+        let res = sqlx::query(
+            "UPDATE claims SET status = $1, last_error = $2, updated_at = $3 WHERE id = $4 AND is_fund = $5 AND status = ANY($6) AND chain_id = $7",
+        )
+        .bind(ClaimStatus::Failed.as_str())
+        .bind("The invitation email bounced")
+        .bind(now())
+        .bind(u256_to_hex(id))
+        .bind(is_fund)
+        .bind(active_claim_statuses())
+        .bind(chain_id())
+        .execute(&self.db)
+        .await?;
+        Ok(res.rows_affected() > 0)
     }
 
     pub async fn contains_user(&self, email_address: &str) -> Result<bool> {
-        let result = sqlx::query("SELECT 1 FROM users WHERE email_address = $1")
+        let result = sqlx::query("SELECT 1 FROM users WHERE email_address = $1 AND chain_id = $2")
             .bind(email_address)
+            .bind(chain_id())
             .fetch_optional(&self.db)
             .await?;
 
@@ -303,18 +505,24 @@ impl Database {
     }
 
     pub async fn is_user_onborded(&self, email_address: &str) -> Result<bool> {
-        let result = sqlx::query("SELECT is_onboarded FROM users WHERE email_address = $1")
-            .bind(email_address)
-            .fetch_one(&self.db)
-            .await?;
+        let result = sqlx::query(
+            "SELECT is_onboarded FROM users WHERE email_address = $1 AND chain_id = $2",
+        )
+        .bind(email_address)
+        .bind(chain_id())
+        .fetch_one(&self.db)
+        .await?;
         Ok(result.get("is_onboarded"))
     }
 
     pub async fn get_account_code(&self, email_address: &str) -> Result<Option<String>> {
-        let row_result = sqlx::query("SELECT account_code FROM users WHERE email_address = $1")
-            .bind(email_address)
-            .fetch_one(&self.db)
-            .await;
+        let row_result = sqlx::query(
+            "SELECT account_code FROM users WHERE email_address = $1 AND chain_id = $2",
+        )
+        .bind(email_address)
+        .bind(chain_id())
+        .fetch_one(&self.db)
+        .await;
 
         match row_result {
             Ok(row) => {
@@ -327,10 +535,12 @@ impl Database {
     }
 
     pub async fn get_creation_tx_hash(&self, email_address: &str) -> Result<Option<String>> {
-        let row_result = sqlx::query("SELECT tx_hash FROM users WHERE email_address = $1")
-            .bind(email_address)
-            .fetch_one(&self.db)
-            .await;
+        let row_result =
+            sqlx::query("SELECT tx_hash FROM users WHERE email_address = $1 AND chain_id = $2")
+                .bind(email_address)
+                .bind(chain_id())
+                .fetch_one(&self.db)
+                .await;
 
         match row_result {
             Ok(row) => {
@@ -343,10 +553,13 @@ impl Database {
     }
 
     pub async fn is_wallet_addr_exist(&self, wallet_addr: &str) -> Result<bool> {
-        let result = sqlx::query("SELECT 1 FROM users WHERE LOWER(wallet_addr) = LOWER($1)")
-            .bind(wallet_addr)
-            .fetch_optional(&self.db)
-            .await?;
+        let result = sqlx::query(
+            "SELECT 1 FROM users WHERE LOWER(wallet_addr) = LOWER($1) AND chain_id = $2",
+        )
+        .bind(wallet_addr)
+        .bind(chain_id())
+        .fetch_optional(&self.db)
+        .await?;
 
         Ok(result.is_some())
     }
@@ -418,8 +631,9 @@ impl Database {
 
     pub async fn get_email_by_wallet(&self, wallet_addr: &str) -> Result<String> {
         let row =
-            sqlx::query("SELECT email_address FROM users WHERE LOWER(wallet_addr) = LOWER($1)")
+            sqlx::query("SELECT email_address FROM users WHERE LOWER(wallet_addr) = LOWER($1) AND chain_id = $2")
                 .bind(wallet_addr)
+                .bind(chain_id())
                 .fetch_one(&self.db)
                 .await?;
         Ok(row.get("email_address"))
@@ -484,4 +698,626 @@ impl Database {
         .await?;
         Ok(())
     }
+
+    pub async fn insert_batch(&self, batch: &Batch) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO batches (id, email_address, original_subject, message_id, created_at, chain_id) VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(&batch.id)
+        .bind(&batch.email_address)
+        .bind(&batch.original_subject)
+        .bind(&batch.message_id)
+        .bind(now())
+        .bind(chain_id())
+        .execute(&self.db)
+        .await?;
+        for item in batch.items.iter() {
+            sqlx::query(
+                "INSERT INTO batch_items (batch_id, idx, subject, recipient, status, detail, confirmation_subject) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            )
+            .bind(&batch.id)
+            .bind(item.idx)
+            .bind(&item.subject)
+            .bind(&item.recipient)
+            .bind(&item.status)
+            .bind(&item.detail)
+            .bind(&item.confirmation_subject)
+            .execute(&self.db)
+            .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn get_batch(&self, id: &str) -> Result<Option<Batch>> {
+        let row = sqlx::query("SELECT * FROM batches WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.db)
+            .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let rows = sqlx::query("SELECT * FROM batch_items WHERE batch_id = $1 ORDER BY idx")
+            .bind(id)
+            .fetch_all(&self.db)
+            .await?;
+        Ok(Some(Batch {
+            id: id.to_string(),
+            email_address: row.get("email_address"),
+            original_subject: row.get("original_subject"),
+            message_id: row.get("message_id"),
+            items: rows.iter().map(batch_item_from_row).collect(),
+        }))
+    }
+
+    pub async fn update_batch_item(
+        &self,
+        batch_id: &str,
+        idx: i32,
+        status: &str,
+        detail: &str,
+    ) -> Result<()> {
+        sqlx::query(
+            "UPDATE batch_items SET status = $3, detail = $4 WHERE batch_id = $1 AND idx = $2",
+        )
+        .bind(batch_id)
+        .bind(idx)
+        .bind(status)
+        .bind(detail)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Returns false if the batch was already completed.
+    pub async fn complete_batch(&self, id: &str) -> Result<bool> {
+        let res = sqlx::query(
+            "UPDATE batches SET is_completed = TRUE WHERE id = $1 AND is_completed = FALSE",
+        )
+        .bind(id)
+        .execute(&self.db)
+        .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    pub async fn insert_schedule(&self, schedule: &Schedule) -> Result<i64> {
+        let row = sqlx::query(
+            "INSERT INTO schedules (email_address, wallet_addr, subject, frequency, day, next_due_at, chain_id) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id",
+        )
+        .bind(&schedule.email_address)
+        .bind(&schedule.wallet_addr)
+        .bind(&schedule.subject)
+        .bind(&schedule.frequency)
+        .bind(schedule.day)
+        .bind(schedule.next_due_at)
+        .bind(chain_id())
+        .fetch_one(&self.db)
+        .await?;
+        Ok(row.get("id"))
+    }
+
+    pub async fn get_schedules(&self, email_address: &str) -> Result<Vec<Schedule>> {
+        let rows = sqlx::query(
+            "SELECT * FROM schedules WHERE email_address = $1 AND is_cancelled = FALSE AND chain_id = $2 ORDER BY id",
+        )
+        .bind(email_address)
+        .bind(chain_id())
+        .fetch_all(&self.db)
+        .await?;
+        Ok(rows.iter().map(schedule_from_row).collect())
+    }
+
+    pub async fn get_due_schedules(&self, now: i64) -> Result<Vec<Schedule>> {
+        let rows = sqlx::query(
+            "SELECT * FROM schedules WHERE next_due_at <= $1 AND is_cancelled = FALSE AND chain_id = $2 ORDER BY next_due_at",
+        )
+        .bind(now)
+        .bind(chain_id())
+        .fetch_all(&self.db)
+        .await?;
+        Ok(rows.iter().map(schedule_from_row).collect())
+    }
+
+    pub async fn update_schedule_due_time(&self, id: i64, next_due_at: i64) -> Result<()> {
+        sqlx::query("UPDATE schedules SET next_due_at = $2 WHERE id = $1")
+            .bind(id)
+            .bind(next_due_at)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Returns false if the user has no active schedule with the id.
+    pub async fn cancel_schedule(&self, id: i64, email_address: &str) -> Result<bool> {
+        let res = sqlx::query(
+            "UPDATE schedules SET is_cancelled = TRUE WHERE id = $1 AND email_address = $2 AND is_cancelled = FALSE",
+        )
+        .bind(id)
+        .bind(email_address)
+        .execute(&self.db)
+        .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    pub async fn insert_payment_request(&self, request: &PaymentRequest, now: i64) -> Result<i64> {
+        let row = sqlx::query(
+            "INSERT INTO payment_requests (requester_email, payer_email, asset, subject, status, last_notified_at, chain_id) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id",
+        )
+        .bind(&request.requester_email)
+        .bind(&request.payer_email)
+        .bind(&request.asset)
+        .bind(&request.subject)
+        .bind(&request.status)
+        .bind(now)
+        .bind(chain_id())
+        .fetch_one(&self.db)
+        .await?;
+        Ok(row.get("id"))
+    }
+
+    pub async fn get_payment_request(&self, id: i64) -> Result<Option<PaymentRequest>> {
+        let row = sqlx::query("SELECT * FROM payment_requests WHERE id = $1 AND chain_id = $2")
+            .bind(id)
+            .bind(chain_id())
+            .fetch_optional(&self.db)
+            .await?;
+        Ok(row.as_ref().map(payment_request_from_row))
+    }
+
+    /// Pending requests last notified before `notified_before` that have reminders left.
+    pub async fn get_payment_requests_to_remind(
+        &self,
+        notified_before: i64,
+        max_reminders: i32,
+    ) -> Result<Vec<PaymentRequest>> {
+        let rows = sqlx::query(
+            "SELECT * FROM payment_requests WHERE status = $1 AND last_notified_at <= $2 AND reminders_sent < $3 AND chain_id = $4 ORDER BY id",
+        )
+        .bind(PAYMENT_REQUEST_PENDING)
+        .bind(notified_before)
+        .bind(max_reminders)
+        .bind(chain_id())
+        .fetch_all(&self.db)
+        .await?;
+        Ok(rows.iter().map(payment_request_from_row).collect())
+    }
+
+    pub async fn update_payment_request_reminded(&self, id: i64, now: i64) -> Result<()> {
+        sqlx::query(
+            "UPDATE payment_requests SET reminders_sent = reminders_sent + 1, last_notified_at = $2 WHERE id = $1 AND chain_id = $3",
+        )
+        .bind(id)
+        .bind(now)
+        .bind(chain_id())
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Returns false if the request was not pending.
+    pub async fn mark_payment_request_paid(&self, id: i64, tx_hash: &str) -> Result<bool> {
+        let res = sqlx::query(
+            "UPDATE payment_requests SET status = $2, tx_hash = $3 WHERE id = $1 AND status = $4 AND chain_id = $5",
+        )
+        .bind(id)
+        .bind(PAYMENT_REQUEST_PAID)
+        .bind(tx_hash)
+        .bind(PAYMENT_REQUEST_PENDING)
+        .bind(chain_id())
+        .execute(&self.db)
+        .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Returns the maximum fee of the user as its amount and token name.
+    pub async fn get_fee_cap(&self, email_address: &str) -> Result<Option<(String, String)>> {
+        let row = sqlx::query(
+            "SELECT amount, token_name FROM fee_caps WHERE email_address = $1 AND chain_id = $2",
+        )
+        .bind(email_address)
+        .bind(chain_id())
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(row.map(|row| (row.get("amount"), row.get("token_name"))))
+    }
+
+    /// Stores the maximum fee of the user, or removes it if None.
+    pub async fn set_fee_cap(&self, email_address: &str, cap: Option<(&str, &str)>) -> Result<()> {
+        match cap {
+            Some((amount, token_name)) => {
+                sqlx::query(
+                    "INSERT INTO fee_caps (email_address, amount, token_name, chain_id) VALUES ($1, $2, $3, $4) ON CONFLICT (email_address, chain_id) DO UPDATE SET amount = $2, token_name = $3",
+                )
+                .bind(email_address)
+                .bind(amount)
+                .bind(token_name)
+                .bind(chain_id())
+                .execute(&self.db)
+                .await?;
+            }
+            None => {
+                sqlx::query("DELETE FROM fee_caps WHERE email_address = $1 AND chain_id = $2")
+                    .bind(email_address)
+                    .bind(chain_id())
+                    .execute(&self.db)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn get_fee_token_preference(&self, email_address: &str) -> Result<Option<String>> {
+        let row = sqlx::query(
+            "SELECT token_name FROM fee_token_preferences WHERE email_address = $1 AND chain_id = $2",
+        )
+        .bind(email_address)
+        .bind(chain_id())
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(row.map(|row| row.get("token_name")))
+    }
+
+    /// Stores the fee token the user prefers, or removes the preference if None.
+    pub async fn set_fee_token_preference(
+        &self,
+        email_address: &str,
+        token_name: Option<&str>,
+    ) -> Result<()> {
+        match token_name {
+            Some(token_name) => {
+                sqlx::query(
+                    "INSERT INTO fee_token_preferences (email_address, token_name, chain_id) VALUES ($1, $2, $3) ON CONFLICT (email_address, chain_id) DO UPDATE SET token_name = $2",
+                )
+                .bind(email_address)
+                .bind(token_name)
+                .bind(chain_id())
+                .execute(&self.db)
+                .await?;
+            }
+            None => {
+                sqlx::query(
+                    "DELETE FROM fee_token_preferences WHERE email_address = $1 AND chain_id = $2",
+                )
+                .bind(email_address)
+                .bind(chain_id())
+                .execute(&self.db)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn get_contacts(&self, email_address: &str) -> Result<Vec<Contact>> {
+        let rows = sqlx::query(
+            "SELECT * FROM contacts WHERE email_address = $1 AND chain_id = $2 ORDER BY nickname",
+        )
+        .bind(email_address)
+        .bind(chain_id())
+        .fetch_all(&self.db)
+        .await?;
+        Ok(rows.iter().map(contact_from_row).collect())
+    }
+
+    pub async fn get_contact(
+        &self,
+        email_address: &str,
+        nickname: &str,
+    ) -> Result<Option<Contact>> {
+        let row = sqlx::query(
+            "SELECT * FROM contacts WHERE email_address = $1 AND nickname = $2 AND chain_id = $3",
+        )
+        .bind(email_address)
+        .bind(nickname)
+        .bind(chain_id())
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(row.as_ref().map(contact_from_row))
+    }
+
+    pub async fn upsert_contact(&self, email_address: &str, contact: &Contact) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO contacts (email_address, nickname, recipient, chain_id) VALUES ($1, $2, $3, $4) ON CONFLICT (email_address, chain_id, nickname) DO UPDATE SET recipient = $3",
+        )
+        .bind(email_address)
+        .bind(&contact.nickname)
+        .bind(&contact.recipient)
+        .bind(chain_id())
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Returns false if the user has no contact with the nickname.
+    pub async fn delete_contact(&self, email_address: &str, nickname: &str) -> Result<bool> {
+        let res = sqlx::query(
+            "DELETE FROM contacts WHERE email_address = $1 AND nickname = $2 AND chain_id = $3",
+        )
+        .bind(email_address)
+        .bind(nickname)
+        .bind(chain_id())
+        .execute(&self.db)
+        .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Whether the user wants an email when a transfer they sent is claimed or voided. On unless turned off.
+    pub async fn get_sender_notifications(&self, email_address: &str) -> Result<bool> {
+        let row = sqlx::query(
+            "SELECT sender_notifications FROM notification_preferences WHERE email_address = $1 AND chain_id = $2",
+        )
+        .bind(email_address)
+        .bind(chain_id())
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(row
+            .map(|row| row.get("sender_notifications"))
+            .unwrap_or(true))
+    }
+
+    pub async fn set_sender_notifications(&self, email_address: &str, enabled: bool) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO notification_preferences (email_address, sender_notifications, chain_id) VALUES ($1, $2, $3) ON CONFLICT (email_address, chain_id) DO UPDATE SET sender_notifications = $2",
+        )
+        .bind(email_address)
+        .bind(enabled)
+        .bind(chain_id())
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn record_claim_link_click(&self, email_address: &str, now: i64) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO claim_links (email_address, clicks, first_clicked_at, chain_id) VALUES ($1, 1, $2, $3) ON CONFLICT (email_address, chain_id) DO UPDATE SET clicks = claim_links.clicks + 1",
+        )
+        .bind(email_address)
+        .bind(now)
+        .bind(chain_id())
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Returns true and records the email if no email was sent for a click in the last `interval` seconds.
+    pub async fn take_claim_link_email_slot(
+        &self,
+        email_address: &str,
+        now: i64,
+        interval: i64,
+    ) -> Result<bool> {
+        let res = sqlx::query(
+            "UPDATE claim_links SET last_emailed_at = $2 WHERE email_address = $1 AND chain_id = $4 AND (last_emailed_at IS NULL OR last_emailed_at <= $3)",
+        )
+        .bind(email_address)
+        .bind(now)
+        .bind(now - interval)
+        .bind(chain_id())
+        .execute(&self.db)
+        .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Counts the account creation as a conversion if the recipient clicked a claim link before.
+    pub async fn mark_claim_link_converted(&self, email_address: &str, now: i64) -> Result<()> {
+        sqlx::query(
+            "UPDATE claim_links SET converted_at = $2 WHERE email_address = $1 AND chain_id = $3 AND converted_at IS NULL",
+        )
+        .bind(email_address)
+        .bind(now)
+        .bind(chain_id())
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_claim_link_stats(&self) -> Result<ClaimLinkStats> {
+        let row = sqlx::query(
+            "SELECT COALESCE(SUM(clicks), 0)::BIGINT AS clicks, COUNT(*) AS recipients_clicked, COUNT(converted_at) AS recipients_converted FROM claim_links WHERE chain_id = $1",
+        )
+        .bind(chain_id())
+        .fetch_one(&self.db)
+        .await?;
+        Ok(ClaimLinkStats {
+            clicks: row.get("clicks"),
+            recipients_clicked: row.get("recipients_clicked"),
+            recipients_converted: row.get("recipients_converted"),
+        })
+    }
+
+    /// The last block whose unclaim registrations were handled on the current chain.
+    pub async fn get_event_checkpoint(&self) -> Result<Option<u64>> {
+        let row = sqlx::query("SELECT block_number FROM event_checkpoints WHERE chain_id = $1")
+            .bind(chain_id())
+            .fetch_optional(&self.db)
+            .await?;
+        Ok(row.map(|row| row.get::<i64, _>("block_number") as u64))
+    }
+
+    pub async fn set_event_checkpoint(&self, block_number: u64) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO event_checkpoints (chain_id, block_number) VALUES ($1, $2)
+            ON CONFLICT (chain_id) DO UPDATE SET block_number = EXCLUDED.block_number",
+        )
+        .bind(chain_id())
+        .bind(block_number as i64)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn insert_email_op_fee(&self, fee: &EmailOpFee) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO email_op_fees (tx_hash, chain_id, command, fee_token, fee_per_gas_charged, gas_used, effective_gas_price, fee_charged, fee_charged_wei, fee_paid_wei, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) ON CONFLICT (tx_hash) DO NOTHING",
+        )
+        .bind(&fee.tx_hash)
+        .bind(chain_id())
+        .bind(&fee.command)
+        .bind(&fee.fee_token)
+        .bind(fee.fee_per_gas_charged.to_string())
+        .bind(fee.gas_used.to_string())
+        .bind(fee.effective_gas_price.to_string())
+        .bind(fee.fee_charged.to_string())
+        .bind(fee.fee_charged_wei.to_string())
+        .bind(fee.fee_paid_wei.to_string())
+        .bind(now())
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+}
+
+fn batch_item_from_row(row: &sqlx::postgres::PgRow) -> BatchItem {
+    let subject: String = row.get("subject");
+    // Items from before the confirmation subject was stored are confirmed by their own `Send` subject.
+    let confirmation_subject: String = row.get("confirmation_subject");
+    BatchItem {
+        idx: row.get("idx"),
+        confirmation_subject: if confirmation_subject.is_empty() {
+            subject.clone()
+        } else {
+            confirmation_subject
+        },
+        subject,
+        recipient: row.get("recipient"),
+        status: row.get("status"),
+        detail: row.get("detail"),
+    }
+}
+
+fn schedule_from_row(row: &sqlx::postgres::PgRow) -> Schedule {
+    Schedule {
+        id: row.get("id"),
+        email_address: row.get("email_address"),
+        wallet_addr: row.get("wallet_addr"),
+        subject: row.get("subject"),
+        frequency: row.get("frequency"),
+        day: row.get("day"),
+        next_due_at: row.get("next_due_at"),
+    }
+}
+
+fn payment_request_from_row(row: &sqlx::postgres::PgRow) -> PaymentRequest {
+    PaymentRequest {
+        id: row.get("id"),
+        requester_email: row.get("requester_email"),
+        payer_email: row.get("payer_email"),
+        asset: row.get("asset"),
+        subject: row.get("subject"),
+        status: row.get("status"),
+        reminders_sent: row.get("reminders_sent"),
+        tx_hash: row.get("tx_hash"),
+    }
+}
+
+fn contact_from_row(row: &sqlx::postgres::PgRow) -> Contact {
+    Contact {
+        nickname: row.get("nickname"),
+        recipient: row.get("recipient"),
+    }
+}
+
+fn active_claim_statuses() -> Vec<String> {
+    ClaimStatus::ACTIVE
+        .iter()
+        .map(|status| status.as_str().to_string())
+        .collect()
+}
+
+// Claims in these statuses are retried by the claim loop.
+fn due_claim_statuses() -> Vec<String> {
+    vec![
+        ClaimStatus::Registered.as_str().to_string(),
+        ClaimStatus::AccountPending.as_str().to_string(),
+    ]
+}
+
+fn claim_from_row(row: &sqlx::postgres::PgRow) -> Result<Claim> {
+    let id: String = row.get("id");
+    let status: String = row.get("status");
+    let sender: String = row.get("sender_wallet_addr");
+    Ok(Claim {
+        tx_hash: row.get("tx_hash"),
+        id: hex_to_u256(&id)?,
+        email_address: row.get("email_address"),
+        random: row.get("random"),
+        commit: row.get("email_addr_commit"),
+        expiry_time: row.get("expiry_time"),
+        is_fund: row.get("is_fund"),
+        is_announced: row.get("is_announced"),
+        status: ClaimStatus::parse(&status)?,
+        attempts: row.get("attempts"),
+        // Claims registered before the sender was recorded have none.
+        sender: sender.parse().unwrap_or_default(),
+    })
+}
+
+fn address_to_hex(addr: &Address) -> String {
+    format!("0x{}", hex::encode(addr.as_bytes()))
+}
+
+// Users, claims, batches, schedules, payment requests, settings, contacts and claim links belong to the
+// chain the current task is scoped to.
+fn chain_id() -> i64 {
+    i64::from(current_chain_id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The table a statement reads or changes, or None for statements on a single table.
+    fn table_of(statement: &str) -> Option<&str> {
+        let words: Vec<&str> = statement.split_whitespace().collect();
+        let idx = match words.as_slice() {
+            ["CREATE", "TABLE", ..] => words.iter().position(|w| *w == "EXISTS")? + 1,
+            ["ALTER", "TABLE", ..] => 2,
+            ["UPDATE", ..] => 1,
+            ["CREATE", ..] => words.iter().position(|w| *w == "ON")? + 1,
+            _ => return None,
+        };
+        words.get(idx).copied()
+    }
+
+    #[test]
+    fn adds_chain_id_before_using_it() {
+        let statements = schema_statements(11155111);
+        for (idx, statement) in statements.iter().enumerate() {
+            if !statement.contains("chain_id") {
+                continue;
+            }
+            let table = table_of(statement).unwrap();
+            let has_chain_id = |s: &String| {
+                table_of(s) == Some(table)
+                    && (s.contains("ADD COLUMN IF NOT EXISTS chain_id")
+                        || (s.starts_with("CREATE TABLE") && s.contains("chain_id")))
+            };
+            assert!(
+                statements[..=idx].iter().any(has_chain_id),
+                "`{}` runs before {} has a chain_id column",
+                statement,
+                table
+            );
+        }
+    }
+
+    #[test]
+    fn gives_old_rows_the_default_chain() {
+        let statements = schema_statements(84532);
+        for table in CHAIN_SCOPED_TABLES {
+            let create = statements
+                .iter()
+                .position(|s| s.starts_with(&format!("CREATE TABLE IF NOT EXISTS {} (", table)))
+                .unwrap();
+            let alter = statements
+                .iter()
+                .position(|s| {
+                    *s == format!(
+                        "ALTER TABLE {} ADD COLUMN IF NOT EXISTS chain_id BIGINT NOT NULL DEFAULT 84532;",
+                        table
+                    )
+                })
+                .unwrap();
+            assert!(create < alter, "{}", table);
+        }
+    }
 }
@@ -17,10 +17,10 @@ pub use core::*;
 pub use database::*;
 pub use modules::*;
 use std::future::Future;
-use std::pin::Pin;
 pub use utils::*;
 
 use ::function_name::named;
+use futures::StreamExt;
 use rand::rngs::OsRng;
 use tokio::sync::Mutex;
 
@@ -29,7 +29,7 @@ use dotenv::dotenv;
 use ethers::prelude::*;
 use lazy_static::lazy_static;
 use relayer_utils::{converters::*, cryptos::*, Fr, LOG};
-use slog::{error, info, trace};
+use slog::{error, info, trace, warn};
 use std::env;
 use std::path::PathBuf;
 use std::sync::atomic::AtomicU32;
@@ -39,6 +39,7 @@ use tokio::time::{sleep, Duration};
 pub static CIRCUITS_DIR_PATH: OnceLock<PathBuf> = OnceLock::new();
 pub static WEB_SERVER_ADDRESS: OnceLock<String> = OnceLock::new();
 pub static RELAYER_RAND: OnceLock<String> = OnceLock::new();
+pub static CLAIM_LINK_KEY: OnceLock<Vec<u8>> = OnceLock::new();
 pub static PROVER_ADDRESS: OnceLock<String> = OnceLock::new();
 pub static PRIVATE_KEY: OnceLock<String> = OnceLock::new();
 pub static CHAIN_ID: OnceLock<u32> = OnceLock::new();
@@ -58,28 +59,48 @@ pub static RELAYER_EMAIL_ADDRESS: OnceLock<String> = OnceLock::new();
 pub static SAFE_API_ENDPOINT: OnceLock<String> = OnceLock::new();
 pub static SMTP_SERVER: OnceLock<String> = OnceLock::new();
 pub static ERROR_EMAIL_ADDRESSES: OnceLock<Vec<String>> = OnceLock::new();
+pub static SUBJECT_ALIASES: OnceLock<Vec<SubjectAlias>> = OnceLock::new();
+pub static BALANCE_TOKENS: OnceLock<Vec<String>> = OnceLock::new();
+pub static FEE_TOKENS: OnceLock<Vec<String>> = OnceLock::new();
+pub static PRICE_SOURCE: OnceLock<Box<dyn PriceSource>> = OnceLock::new();
+pub static CLAIM_RETRY_POLICY: OnceLock<ClaimRetryPolicy> = OnceLock::new();
+pub static EXPIRY_REMINDER_OFFSETS: OnceLock<Vec<i64>> = OnceLock::new();
+pub static CLAIM_LINK_BASE_URL: OnceLock<Option<String>> = OnceLock::new();
+pub static TRUSTED_AUTHSERV_ID: OnceLock<Option<String>> = OnceLock::new();
+pub static PSI_ENABLED: OnceLock<bool> = OnceLock::new();
+pub static PSI_PEERS: OnceLock<Option<Vec<(Address, String)>>> = OnceLock::new();
+pub static CHAIN_PROFILES: OnceLock<Vec<ChainProfile>> = OnceLock::new();
+pub static EVENT_POLL_INTERVAL_SECS: OnceLock<u64> = OnceLock::new();
+pub static FEE_STRATEGY: OnceLock<FeeStrategy> = OnceLock::new();
+pub static ALERT_POLICY: OnceLock<AlertPolicy> = OnceLock::new();
 
 lazy_static! {
     pub static ref DB: Arc<Database> = {
         dotenv().ok();
+        // The database may be opened before `setup()` sets CHAIN_ID.
+        let default_chain_id = match CHAIN_ID.get() {
+            Some(chain_id) => *chain_id,
+            None => env::var(CHAIN_ID_KEY).unwrap().parse().unwrap(),
+        };
         let db = tokio::task::block_in_place(|| {
-            tokio::runtime::Runtime::new()
-                .unwrap()
-                .block_on(Database::open(&env::var(DATABASE_PATH_KEY).unwrap()))
+            tokio::runtime::Runtime::new().unwrap().block_on(Database::open(
+                &env::var(DATABASE_PATH_KEY).unwrap(),
+                default_chain_id,
+            ))
         })
         .unwrap();
         Arc::new(db)
     };
-    pub static ref CLIENT: Arc<ChainClient> = {
+    pub static ref CHAIN_CLIENTS: ChainClients = {
         dotenv().ok();
-        let client = tokio::task::block_in_place(|| {
+        tokio::task::block_in_place(|| {
             tokio::runtime::Runtime::new()
                 .unwrap()
-                .block_on(ChainClient::setup())
+                .block_on(ChainClients::setup())
         })
-        .unwrap();
-        Arc::new(client)
+        .unwrap()
     };
+    pub static ref CLIENT: CurrentChainClient = CurrentChainClient;
     pub static ref SHARED_MUTEX: Arc<Mutex<i32>> = Arc::new(Mutex::new(0));
 }
 
@@ -98,18 +119,29 @@ pub async fn setup() -> Result<()> {
     CORE_CONTRACT_ADDRESS
         .set(env::var(CORE_CONTRACT_ADDRESS_KEY).unwrap())
         .unwrap();
+    CHAIN_PROFILES
+        .set(load_chain_profiles(
+            default_chain_profile(
+                env::var(CHAIN_NAME_KEY).ok(),
+                env::var(CHAIN_SUBSCRIPTION_PROVIDER_KEY).ok(),
+            ),
+            env::var(CHAIN_PROFILES_PATH_KEY).ok().as_deref(),
+        )?)
+        .unwrap();
 
     let rng = OsRng;
     let relayer_rand = derive_relayer_rand(PRIVATE_KEY.get().unwrap())?;
 
-    let client = ChainClient::setup().await?;
-    let tx_hash = client
-        .register_relayer(
-            env::var(RELAYER_EMAIL_ADDR_KEY).unwrap(),
-            env::var(RELAYER_HOSTNAME_KEY).unwrap(),
-        )
-        .await?;
-    println!("Register relayer in {}", tx_hash);
+    for profile in CHAIN_PROFILES.get().unwrap() {
+        let client = ChainClient::setup(profile).await?;
+        let tx_hash = client
+            .register_relayer(
+                env::var(RELAYER_EMAIL_ADDR_KEY).unwrap(),
+                env::var(RELAYER_HOSTNAME_KEY).unwrap(),
+            )
+            .await?;
+        println!("Register relayer on {} in {}", profile.name, tx_hash);
+    }
     Ok(())
 }
 
@@ -127,7 +159,24 @@ pub async fn run(config: RelayerConfig) -> Result<()> {
     CORE_CONTRACT_ADDRESS
         .set(config.core_contract_address)
         .unwrap();
+    CHAIN_PROFILES
+        .set(load_chain_profiles(
+            default_chain_profile(config.chain_name, config.chain_subscription_provider),
+            config.chain_profiles_path.as_deref(),
+        )?)
+        .unwrap();
+    EVENT_POLL_INTERVAL_SECS
+        .set(config.event_poll_interval_secs.max(1))
+        .unwrap();
     FEE_PER_GAS.set(config.fee_per_gas).unwrap();
+    FEE_STRATEGY
+        .set(FeeStrategy::new(
+            config.fee_strategy,
+            config.fee_margin_percent,
+            config.max_fee_per_gas,
+            config.fee_history_blocks,
+        )?)
+        .unwrap();
     INPUT_FILES_DIR.set(config.input_files_dir).unwrap();
     EMAIL_TEMPLATES.set(config.email_templates).unwrap();
     SUBGRAPH_URL.set(config.subgraph_url).unwrap();
@@ -151,17 +200,59 @@ pub async fn run(config: RelayerConfig) -> Result<()> {
     ERROR_EMAIL_ADDRESSES
         .set(config.error_email_addresses)
         .unwrap();
+    ALERT_POLICY
+        .set(AlertPolicy {
+            min_eth_balance: config.min_relayer_eth_balance,
+            min_onboarding_token_balance: config
+                .min_onboarding_token_balance
+                .unwrap_or(*ONBOARDING_TOKEN_AMOUNT.get().unwrap()),
+            check_interval_secs: config.monitor_interval_secs.max(1),
+            task_failure_threshold: config.alert_task_failure_threshold.max(1),
+            dedup_secs: config.alert_dedup_secs,
+            digest_secs: config.alert_digest_secs.map(|secs| secs.max(1)),
+        })
+        .unwrap();
+    SUBJECT_ALIASES
+        .set(load_subject_aliases(config.subject_aliases_path.as_deref())?)
+        .unwrap();
+    BALANCE_TOKENS.set(config.balance_tokens).unwrap();
+    FEE_TOKENS.set(config.fee_tokens).unwrap();
+    PRICE_SOURCE
+        .set(load_price_source(&config.price_source)?)
+        .map_err(|_| anyhow!("Price source is already set"))?;
+    CLAIM_RETRY_POLICY
+        .set(ClaimRetryPolicy {
+            concurrency: config.claim_concurrency.max(1),
+            base_secs: config.claim_retry_base_secs,
+            max_secs: config.claim_retry_max_secs,
+            max_retries: config.claim_max_retries,
+        })
+        .unwrap();
+    EXPIRY_REMINDER_OFFSETS
+        .set(parse_expiry_reminder_offsets(&config.expiry_reminder_offsets)?)
+        .unwrap();
+    CLAIM_LINK_BASE_URL.set(config.claim_link_base_url).unwrap();
+    TRUSTED_AUTHSERV_ID.set(config.trusted_authserv_id).unwrap();
+    PSI_ENABLED.set(config.psi_enabled).unwrap();
+    PSI_PEERS
+        .set(config.psi_peers.as_deref().map(parse_psi_peers).transpose()?)
+        .unwrap();
 
     let relayer_rand = derive_relayer_rand(PRIVATE_KEY.get().unwrap())?;
     RELAYER_RAND.set(field2hex(&relayer_rand.0)).unwrap();
+    CLAIM_LINK_KEY
+        .set(derive_claim_link_key(PRIVATE_KEY.get().unwrap())?)
+        .unwrap();
 
     let safe_task = tokio::task::spawn(async move {
         loop {
-            match safe_fn().await {
-                Ok(()) => {}
-                Err(e) => {
-                    error!(LOG, "Error at safe: {}", e; "func" => function_name!())
-                }
+            // The Safe transaction service is only configured for the default chain.
+            let result = with_chain(*CHAIN_ID.get().unwrap(), safe_fn())
+                .await
+                .and_then(|result| result);
+            report_task_result("safe", &result).await;
+            if let Err(e) = result {
+                error!(LOG, "Error at safe: {}", e; "func" => function_name!())
             }
         }
         anyhow::Ok(())
@@ -183,141 +274,79 @@ pub async fn run(config: RelayerConfig) -> Result<()> {
         }
     });
 
+    // Every chain has its own listener, so that a slow RPC does not hold up the other chains.
     let event_listener_task = tokio::task::spawn(async move {
-        // Get latest block
-        let mut from_block_fund = CLIENT.get_latest_block_number().await;
-        let mut from_block_state = CLIENT.get_latest_block_number().await;
-        let fund_f = |event: email_wallet_events::UnclaimedFundRegisteredFilter, meta: LogMeta| {
-            Box::pin(async move {
-                if event.email_addr.is_empty() {
-                    return Ok(());
-                }
-                let random = field2hex(&bytes32_to_fr(&u256_to_bytes32(
-                    &event.commitment_randomness,
-                ))?);
-                let commit = field2hex(&bytes32_to_fr(&event.email_addr_commit)?);
-                let claim = Claim {
-                    tx_hash: meta.transaction_hash.to_string(),
-                    id: event.id,
-                    email_address: event.email_addr,
-                    random,
-                    commit,
-                    expiry_time: i64::try_from(event.expiry_time.as_u64()).unwrap(),
-                    is_fund: true,
-                    is_announced: true,
-                    is_seen: false,
-                };
-                match claim_unclaims(claim.clone()).await {
-                    Ok(value) => {
-                        if let Err(e) = handle_email_event(value).await {
-                            error!(LOG, "Error handling email event: {}", e; "func" => function_name!());
-                        }
-                    }
-                    Err(e) => error!(LOG, "Error claiming: {}", e; "func" => function_name!()),
-                }
-                Ok(())
-            }) as Pin<Box<dyn Future<Output = Result<()>> + Send>> // Add + Send here
-        };
-        let state_f = |event: email_wallet_events::UnclaimedStateRegisteredFilter,
-                       meta: LogMeta| {
-            Box::pin(async move {
-                if event.email_addr.is_empty() {
-                    return Ok(());
-                }
-                let random = field2hex(&bytes32_to_fr(&u256_to_bytes32(
-                    &event.commitment_randomness,
-                ))?);
-                let commit = field2hex(&bytes32_to_fr(&event.email_addr_commit)?);
-                let claim = Claim {
-                    tx_hash: meta.transaction_hash.to_string(),
-                    id: event.id,
-                    email_address: event.email_addr,
-                    random,
-                    commit,
-                    expiry_time: i64::try_from(event.expiry_time.as_u64()).unwrap(),
-                    is_fund: false,
-                    is_announced: true,
-                    is_seen: false,
-                };
-                match claim_unclaims(claim.clone()).await {
-                    Ok(value) => {
-                        if let Err(e) = handle_email_event(value).await {
-                            error!(LOG, "Error handling email event: {}", e; "func" => function_name!());
-                        }
-                    }
-                    Err(e) => error!(LOG, "Error claiming: {}", e; "func" => function_name!()),
-                }
-                Ok(())
-            }) as Pin<Box<dyn Future<Output = Result<()>> + Send>> // Add + Send here
-        };
+        futures::future::try_join_all(
+            chain_ids()
+                .into_iter()
+                .map(|chain_id| with_chain(chain_id, listen_unclaim_events())),
+        )
+        .await?;
+        anyhow::Ok(())
+    });
+
+    let voider_task = tokio::task::spawn(async move {
         loop {
-            match event_listener_fn(from_block_fund, fund_f, from_block_state, state_f).await {
-                Ok((last_block_f, last_block_s)) => {
-                    from_block_fund = last_block_f;
-                    from_block_state = last_block_s;
-                }
-                Err(e) => {
-                    error!(LOG, "Error at event_listener: {}", e; "func" => function_name!())
+            for chain_id in chain_ids() {
+                let result = with_chain(chain_id, catch_claims_in_db_fn())
+                    .await
+                    .and_then(|result| result);
+                report_task_result(&format!("catch claims on chain {}", chain_id), &result).await;
+                if let Err(e) = result {
+                    error!(LOG, "Error at catch claims on chain {}: {}", chain_id, e; "func" => function_name!())
                 }
             }
+            sleep(Duration::from_secs(120)).await;
         }
         anyhow::Ok(())
     });
 
-    let voider_task = tokio::task::spawn(async move {
+    let scheduler_task = tokio::task::spawn(async move {
         loop {
-            match catch_claims_in_db_fn().await {
-                Ok(()) => {}
-                Err(e) => {
-                    error!(LOG, "Error at catch claims: {}", e; "func" => function_name!())
+            for chain_id in chain_ids() {
+                let result = with_chain(chain_id, send_due_schedules_fn())
+                    .await
+                    .and_then(|result| result);
+                report_task_result(&format!("scheduled payments on chain {}", chain_id), &result)
+                    .await;
+                if let Err(e) = result {
+                    error!(LOG, "Error at scheduled payments on chain {}: {}", chain_id, e; "func" => function_name!())
                 }
             }
+            sleep(Duration::from_secs(120)).await;
         }
         anyhow::Ok(())
     });
 
-    let _ = tokio::join!(api_server_task, event_listener_task, voider_task, safe_task);
+    let monitor_task = tokio::task::spawn(async move {
+        tokio::join!(monitor_relayer(), send_alert_digests());
+    });
 
-    Ok(())
-}
+    let _ = tokio::join!(
+        api_server_task,
+        event_listener_task,
+        voider_task,
+        scheduler_task,
+        safe_task,
+        monitor_task
+    );
 
-async fn event_listener_fn(
-    from_block_fund: U64,
-    fund_f: impl FnMut(
-        email_wallet_events::UnclaimedFundRegisteredFilter,
-        LogMeta,
-    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send>>, // Add + Send here
-    from_block_state: U64,
-    state_f: impl FnMut(
-        email_wallet_events::UnclaimedStateRegisteredFilter,
-        LogMeta,
-    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send>>, // And here, if necessary
-) -> Result<(U64, U64)> {
-    let last_block_f = CLIENT
-        .stream_unclaim_fund_registration(from_block_fund, fund_f)
-        .await?;
-    let last_block_s = CLIENT
-        .stream_unclaim_state_registration(from_block_state, state_f)
-        .await?;
-    sleep(Duration::from_secs(120)).await;
-    Ok((last_block_f + 1, last_block_s + 1))
+    Ok(())
 }
 
 #[named]
 async fn catch_claims_in_db_fn() -> Result<()> {
     let now = now();
-    let claims = DB.get_claims_unexpired(now).await?;
-    for claim in claims {
-        info!(LOG, "Claiming claim for : {}", claim.email_address; "func" => function_name!());
-        match claim_unclaims(claim.clone()).await {
-            Ok(value) => {
-                if let Err(e) = handle_email_event(value).await {
-                    error!(LOG, "Error handling email event: {}", e; "func" => function_name!());
-                }
+    let claims = DB.get_claims_due(now).await?;
+    let concurrency = CLAIM_RETRY_POLICY.get().unwrap().concurrency;
+    futures::stream::iter(claims)
+        .for_each_concurrent(concurrency, |claim| async move {
+            info!(LOG, "Claiming claim for : {}", claim.email_address; "func" => function_name!());
+            if let Err(e) = retry_claim(claim, now).await {
+                error!(LOG, "Error retrying claim: {}", e; "func" => function_name!());
             }
-            Err(e) => trace!(LOG, "Error claiming: {}", e; "func" => function_name!()),
-        }
-    }
+        })
+        .await;
     let claims = DB.get_claims_expired(now).await?;
     for claim in claims {
         let email_addr = claim.email_address.clone();
@@ -335,6 +364,12 @@ async fn catch_claims_in_db_fn() -> Result<()> {
         };
         handle_email_event(event).await?;
     }
-    sleep(Duration::from_secs(120)).await;
+    Ok(())
+}
+
+async fn send_due_schedules_fn() -> Result<()> {
+    send_due_schedules(now()).await?;
+    send_payment_request_reminders(now()).await?;
+    send_expiry_reminders(now()).await?;
     Ok(())
 }
@@ -0,0 +1,76 @@
+use crate::*;
+
+use relayer_utils::AccountSalt;
+
+/// Token balances and pending unclaimed funds of a wallet, formatted for the balance email.
+#[derive(Debug, Clone, Default)]
+pub struct BalanceSummary {
+    pub balances: Vec<String>,
+    pub pending_incoming: Vec<String>,
+    pub pending_outgoing: Vec<String>,
+}
+
+#[named]
+pub async fn get_balance_summary(
+    email_addr: &str,
+    account_salt: &AccountSalt,
+    wallet_addr: Address,
+) -> Result<BalanceSummary> {
+    let mut summary = BalanceSummary::default();
+    for token_name in BALANCE_TOKENS.get().unwrap() {
+        // A token missing in the registry of this chain is skipped rather than failing the whole reply.
+        let balance = match CLIENT
+            .query_user_erc20_balance(account_salt, token_name)
+            .await
+        {
+            Ok(balance) => balance,
+            Err(e) => {
+                info!(LOG, "Failed to query balance of {}: {}", token_name, e; "func" => function_name!());
+                continue;
+            }
+        };
+        let decimals = CLIENT.query_decimals_of_erc20(token_name).await?;
+        summary.balances.push(format!(
+            "{} {}",
+            uint_to_decimal_string(balance, decimals as usize),
+            token_name
+        ));
+    }
+
+    for asset in search_user_assets(email_addr).await? {
+        match asset {
+            Asset::ERC20 {
+                token_name,
+                amount_str,
+                ..
+            } => summary
+                .pending_incoming
+                .push(format!("{} {}", amount_str, token_name)),
+            Asset::ERC721 {
+                token_name,
+                token_id,
+                ..
+            } => summary
+                .pending_incoming
+                .push(format!("NFT: ID {} of {}", token_id, token_name)),
+        }
+    }
+
+    for claim in DB
+        .get_outgoing_fund_claims_unexpired(&wallet_addr, now())
+        .await?
+    {
+        let unclaimed_fund = CLIENT.query_unclaimed_fund(claim.id).await?;
+        let decimals = CLIENT
+            .query_decimals_of_erc20_address(unclaimed_fund.token_addr)
+            .await?;
+        let token_name = CLIENT.query_token_name(unclaimed_fund.token_addr).await?;
+        summary.pending_outgoing.push(format!(
+            "{} {} to {}",
+            uint_to_decimal_string(unclaimed_fund.amount, decimals as usize),
+            token_name,
+            claim.email_address
+        ));
+    }
+    Ok(summary)
+}
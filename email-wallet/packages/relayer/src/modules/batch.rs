@@ -0,0 +1,547 @@
+use crate::*;
+
+use mailparse::{parse_mail, ParsedMail};
+use rand::Rng;
+use regex::Regex;
+use relayer_utils::AccountSalt;
+use std::collections::BTreeMap;
+
+pub const BATCH_MAX_ITEMS: usize = 20;
+pub const BATCH_ITEM_PENDING: &str = "pending";
+pub const BATCH_ITEM_SENT: &str = "sent";
+pub const BATCH_ITEM_FAILED: &str = "failed";
+/// Command of the batch transfer extension, which sends a token to many ETH addresses with one email op.
+pub const BATCH_EXTENSION_COMMAND: &str = "Batch";
+
+/// A send to multiple recipients requested in one email.
+/// Transfers of a token to ETH addresses are registered on the batch transfer extension and confirmed by one `Batch` email,
+/// if the wallet has the extension. Every other transfer is confirmed by its own `Send` email.
+#[derive(Debug, Clone)]
+pub struct Batch {
+    pub id: String,
+    pub email_address: String,
+    pub original_subject: String,
+    pub message_id: String,
+    pub items: Vec<BatchItem>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchItem {
+    pub idx: i32,
+    /// Canonical `Send` subject of the transfer.
+    pub subject: String,
+    pub recipient: String,
+    pub status: String,
+    /// Transaction hash of a sent item or the error of a failed one.
+    pub detail: Option<String>,
+    /// Subject the sender confirms the transfer with: `subject` itself,
+    /// or the `Batch` subject shared by the transfers registered on the batch transfer extension together.
+    pub confirmation_subject: String,
+}
+
+impl BatchItem {
+    pub fn is_grouped(&self) -> bool {
+        self.confirmation_subject != self.subject
+    }
+}
+
+impl Batch {
+    /// The items the sender replies to, one per confirmation subject.
+    pub fn confirmations(&self) -> Vec<&BatchItem> {
+        let mut confirmations: Vec<&BatchItem> = vec![];
+        for item in self.items.iter() {
+            if !item.is_grouped()
+                || confirmations
+                    .iter()
+                    .all(|lead| lead.confirmation_subject != item.confirmation_subject)
+            {
+                confirmations.push(item);
+            }
+        }
+        confirmations
+    }
+
+    /// Indexes of the items a reply to the confirmation of item `idx` confirms.
+    pub fn confirmed_idxs(&self, idx: i32) -> Vec<i32> {
+        let Some(lead) = self.items.iter().find(|item| item.idx == idx) else {
+            return vec![];
+        };
+        if !lead.is_grouped() {
+            return vec![idx];
+        }
+        self.items
+            .iter()
+            .filter(|item| item.confirmation_subject == lead.confirmation_subject)
+            .map(|item| item.idx)
+            .collect()
+    }
+}
+
+/// The `[batch <id>#<idx>]` tag in front of a confirmation subject. The proof skips it as part of the subject prefix.
+pub fn batch_tag(batch_id: &str, idx: i32) -> String {
+    format!("[batch {}#{}]", batch_id, idx)
+}
+
+pub fn parse_batch_tag(subject: &str) -> Option<(String, i32)> {
+    let captures = Regex::new(r"\[batch ([0-9a-f]{16})#([0-9]+)\]")
+        .unwrap()
+        .captures(subject)?;
+    Some((captures[1].to_string(), captures[2].parse().ok()?))
+}
+
+/// The subject of the email that confirms `item`.
+pub fn batch_reply_subject(batch_id: &str, item: &BatchItem) -> String {
+    format!(
+        "{}{} {}",
+        chain_subject_prefix(),
+        batch_tag(batch_id, item.idx),
+        item.confirmation_subject
+    )
+}
+
+/// A transfer of a batch as parsed by `validate_batch`.
+#[derive(Debug, Clone)]
+pub struct BatchTransfer {
+    pub item: BatchItem,
+    pub token_name: String,
+    pub decimals: u8,
+    pub amount: U256,
+    /// Recipient ETH address, or None for an email recipient.
+    pub eth_addr: Option<Address>,
+}
+
+/// Groups the transfers to ETH addresses by token. Only tokens with at least two such transfers are worth a `Batch` email.
+pub fn group_address_transfers(transfers: &[BatchTransfer]) -> BTreeMap<String, Vec<usize>> {
+    let mut groups: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    for (i, transfer) in transfers.iter().enumerate() {
+        if transfer.eth_addr.is_some() {
+            groups
+                .entry(transfer.token_name.clone())
+                .or_default()
+                .push(i);
+        }
+    }
+    groups.retain(|_, idxs| idxs.len() >= 2);
+    groups
+}
+
+/// Splits a subject like `Send 10 USDC to a@x.com, 20 USDC to 0xabc...` into one `Send` subject per transfer.
+/// Returns None if the subject holds a single transfer.
+pub fn split_batch_subject(subject: &str) -> Option<Vec<String>> {
+    let transfers = subject.trim().strip_prefix(SEND_COMMAND)?;
+    // A comma directly followed by a digit is a thousands separator, not a list separator.
+    let parts: Vec<&str> = Regex::new(r"[;\n]|,\s+")
+        .unwrap()
+        .split(transfers)
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .collect();
+    if parts.len() < 2 {
+        return None;
+    }
+    Some(
+        parts
+            .iter()
+            .map(|part| format!("{} {}", SEND_COMMAND, part))
+            .collect(),
+    )
+}
+
+/// Reads `recipient,amount,token` rows of a CSV attachment into one `Send` subject per row.
+/// A header row is skipped. Returns None if the email has no CSV attachment.
+pub fn read_batch_csv(raw_email: &str) -> Result<Option<Vec<String>>> {
+    let parsed = parse_mail(raw_email.as_bytes())?;
+    let Some(body) = find_csv_body(&parsed)? else {
+        return Ok(None);
+    };
+    let mut subjects = vec![];
+    for (line_idx, line) in body.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let columns: Vec<&str> = line
+            .split(',')
+            .map(|column| column.trim().trim_matches('"'))
+            .collect();
+        if columns.len() != 3 {
+            bail!(
+                "Line {} of the CSV file must be `recipient,amount,token`",
+                line_idx + 1
+            );
+        }
+        if line_idx == 0 && normalize_amount(columns[1]).is_none() {
+            continue;
+        }
+        subjects.push(format!(
+            "{} {} {} to {}",
+            SEND_COMMAND, columns[1], columns[2], columns[0]
+        ));
+    }
+    Ok(Some(subjects))
+}
+
+fn find_csv_body(part: &ParsedMail) -> Result<Option<String>> {
+    let is_csv = part.ctype.mimetype.eq_ignore_ascii_case("text/csv")
+        || part
+            .get_content_disposition()
+            .params
+            .get("filename")
+            .map(|name| name.to_lowercase().ends_with(".csv"))
+            .unwrap_or(false);
+    if is_csv {
+        return Ok(Some(part.get_body()?));
+    }
+    for subpart in part.subparts.iter() {
+        if let Some(body) = find_csv_body(subpart)? {
+            return Ok(Some(body));
+        }
+    }
+    Ok(None)
+}
+
+/// Parses every transfer and checks that the wallet holds enough of each token for all of them together.
+/// All problems are reported at once so that nothing is sent from a batch that cannot complete.
+pub async fn validate_batch(
+    subjects: &[String],
+    account_salt: &AccountSalt,
+) -> Result<Vec<BatchTransfer>> {
+    if subjects.is_empty() {
+        bail!("The batch has no transfer");
+    }
+    if subjects.len() > BATCH_MAX_ITEMS {
+        bail!("A batch can have at most {} transfers", BATCH_MAX_ITEMS);
+    }
+    let templates = builtin_subject_templates(SEND_COMMAND).unwrap();
+    let mut items = vec![];
+    let mut errors = vec![];
+    // token name => (decimals, total amount)
+    let mut totals: BTreeMap<String, (u8, U256)> = BTreeMap::new();
+    for (idx, subject) in subjects.iter().enumerate() {
+        let (vals, canonical) = match parse_template(&tokenize_subject(subject), &templates) {
+            Ok(res) => res,
+            Err(e) => {
+                errors.push(format!("Transfer {} ({}): {}", idx + 1, subject, e));
+                continue;
+            }
+        };
+        let (
+            Some(TemplateValue::TokenAmount { token_name, amount }),
+            Some(TemplateValue::Recipient { eth_addr, .. }),
+        ) = (vals.first(), vals.get(1))
+        else {
+            bail!(WRONG_SUBJECT_FORMAT);
+        };
+        let decimals = match CLIENT.query_decimals_of_erc20(token_name).await {
+            Ok(decimals) => decimals,
+            Err(_) => {
+                errors.push(format!(
+                    "Transfer {} ({}): unknown token {}",
+                    idx + 1,
+                    subject,
+                    token_name
+                ));
+                continue;
+            }
+        };
+        let amount = match TemplateValue::amount_to_uint(amount, decimals) {
+            Ok(amount) if amount.is_zero() => {
+                errors.push(format!(
+                    "Transfer {} ({}): the amount must be greater than zero",
+                    idx + 1,
+                    subject
+                ));
+                continue;
+            }
+            Ok(amount) => amount,
+            Err(e) => {
+                errors.push(format!("Transfer {} ({}): {}", idx + 1, subject, e));
+                continue;
+            }
+        };
+        let total = totals
+            .entry(token_name.clone())
+            .or_insert((decimals, U256::zero()));
+        total.1 = total.1.saturating_add(amount);
+        items.push(BatchTransfer {
+            item: BatchItem {
+                idx: idx as i32,
+                recipient: canonical.rsplit(' ').next().unwrap_or_default().to_string(),
+                confirmation_subject: canonical.clone(),
+                subject: canonical,
+                status: BATCH_ITEM_PENDING.to_string(),
+                detail: None,
+            },
+            token_name: token_name.clone(),
+            decimals,
+            amount,
+            eth_addr: *eth_addr,
+        });
+    }
+    for (token_name, (decimals, total)) in totals.iter() {
+        let balance = CLIENT
+            .query_user_erc20_balance(account_salt, token_name)
+            .await?;
+        if balance < *total {
+            errors.push(format!(
+                "{}: the batch sends {} {} in total but the wallet holds {} {}",
+                INSUFFICIENT_BALANCE,
+                uint_to_decimal_string(*total, *decimals as usize),
+                token_name,
+                uint_to_decimal_string(balance, *decimals as usize),
+                token_name
+            ));
+        }
+    }
+    if !errors.is_empty() {
+        bail!(errors.join("; "));
+    }
+    Ok(items)
+}
+
+#[named]
+pub async fn request_batch_send(
+    email_addr: String,
+    wallet_addr: Address,
+    account_salt: &AccountSalt,
+    subjects: &[String],
+    original_subject: String,
+    message_id: String,
+) -> Result<EmailWalletEvent> {
+    let mut transfers = validate_batch(subjects, account_salt).await?;
+    let batch_id = format!("{:016x}", rand::thread_rng().gen::<u64>());
+
+    let extension_addr = CLIENT
+        .query_user_extension_for_command(account_salt, BATCH_EXTENSION_COMMAND)
+        .await?;
+    let groups = if extension_addr.is_zero() {
+        BTreeMap::new()
+    } else {
+        group_address_transfers(&transfers)
+    };
+
+    // The fees of all the confirmations must be covered on top of the transfers before anything is registered or emailed.
+    let mut gas = U256::zero();
+    let mut spent: BTreeMap<&str, U256> = BTreeMap::new();
+    for (i, transfer) in transfers.iter().enumerate() {
+        let total = spent.entry(transfer.token_name.as_str()).or_default();
+        *total = total.saturating_add(transfer.amount);
+        let is_grouped = groups.values().any(|idxs| idxs.contains(&i));
+        if !is_grouped {
+            gas += estimate_email_op_gas(transfer.eth_addr.is_none().then_some(true)).await?;
+        }
+    }
+    if !groups.is_empty() {
+        gas += estimate_email_op_gas(None).await? * U256::from(groups.len());
+    }
+    let spent: Vec<(&str, U256)> = spent.into_iter().collect();
+    let quote = select_fee_token(account_salt, &email_addr, gas, &spent).await?;
+
+    for (token_name, idxs) in groups.iter() {
+        let group: Vec<&BatchTransfer> = idxs.iter().map(|i| &transfers[*i]).collect();
+        let total = group
+            .iter()
+            .fold(U256::zero(), |total, transfer| total + transfer.amount);
+        let onchain_id = CLIENT
+            .register_transfer_batch(
+                extension_addr,
+                wallet_addr,
+                CLIENT.query_erc20_address(token_name).await?,
+                group
+                    .iter()
+                    .filter_map(|transfer| transfer.eth_addr)
+                    .collect(),
+                group.iter().map(|transfer| transfer.amount).collect(),
+            )
+            .await?;
+        let confirmation_subject = format!(
+            "{} {} sends {} {}",
+            BATCH_EXTENSION_COMMAND,
+            onchain_id,
+            uint_to_decimal_string(total, group[0].decimals as usize),
+            token_name
+        );
+        for i in idxs.iter() {
+            transfers[*i].item.confirmation_subject = confirmation_subject.clone();
+        }
+    }
+
+    let batch = Batch {
+        id: batch_id,
+        email_address: email_addr,
+        original_subject,
+        message_id,
+        items: transfers
+            .into_iter()
+            .map(|transfer| transfer.item)
+            .collect(),
+    };
+    DB.insert_batch(&batch).await?;
+    info!(LOG, "batch {} with {} transfers in {} confirmations registered, fees {}", batch.id, batch.items.len(), batch.confirmations().len(), quote.describe(); "func" => function_name!());
+    Ok(EmailWalletEvent::BatchRequest { batch, wallet_addr })
+}
+
+/// Records the result of a confirmation of a batch, identified by the batch tag in its subject,
+/// and sends the summary once every transfer of the batch has a result.
+#[named]
+pub async fn record_batch_result(
+    email_addr: &str,
+    subject: &str,
+    status: &str,
+    detail: &str,
+) -> Result<()> {
+    let Some((batch_id, idx)) = parse_batch_tag(subject) else {
+        return Ok(());
+    };
+    let Some(batch) = DB.get_batch(&batch_id).await? else {
+        return Ok(());
+    };
+    if batch.email_address != email_addr {
+        return Ok(());
+    }
+    // A subject edited after the tag confirms something else than the transfers of the batch.
+    let Some(lead) = batch.items.iter().find(|item| item.idx == idx) else {
+        return Ok(());
+    };
+    if !subject.trim().ends_with(&lead.confirmation_subject) {
+        return Ok(());
+    }
+    for item_idx in batch.confirmed_idxs(idx) {
+        DB.update_batch_item(&batch_id, item_idx, status, detail)
+            .await?;
+    }
+    info!(LOG, "confirmation {} of batch {} {}", idx, batch_id, status; "func" => function_name!());
+    let batch = DB
+        .get_batch(&batch_id)
+        .await?
+        .ok_or(anyhow!("Batch {} not found", batch_id))?;
+    if batch
+        .items
+        .iter()
+        .all(|item| item.status != BATCH_ITEM_PENDING)
+        && DB.complete_batch(&batch_id).await?
+    {
+        handle_email_event(EmailWalletEvent::BatchCompleted { batch }).await?;
+    }
+    Ok(())
+}
+
+/// A `mailto:` link that opens a new email to the relayer with the given subject.
+pub fn mailto_link(subject: &str) -> String {
+    let mut encoded = String::new();
+    for byte in subject.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'@' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    format!(
+        "mailto:{}?subject={}",
+        RELAYER_EMAIL_ADDRESS.get().unwrap(),
+        encoded
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(idx: i32, subject: &str, confirmation_subject: &str) -> BatchItem {
+        BatchItem {
+            idx,
+            subject: subject.to_string(),
+            recipient: subject.rsplit(' ').next().unwrap().to_string(),
+            status: BATCH_ITEM_PENDING.to_string(),
+            detail: None,
+            confirmation_subject: confirmation_subject.to_string(),
+        }
+    }
+
+    fn transfer(idx: i32, token_name: &str, eth_addr: Option<Address>) -> BatchTransfer {
+        let subject = format!("Send 1 {} to {:?}", token_name, eth_addr);
+        BatchTransfer {
+            item: item(idx, &subject, &subject),
+            token_name: token_name.to_string(),
+            decimals: 18,
+            amount: U256::exp10(18),
+            eth_addr,
+        }
+    }
+
+    fn batch(items: Vec<BatchItem>) -> Batch {
+        Batch {
+            id: "00000000000000ff".to_string(),
+            email_address: "alice@example.com".to_string(),
+            original_subject: String::new(),
+            message_id: String::new(),
+            items,
+        }
+    }
+
+    #[test]
+    fn parses_batch_tags() {
+        let tag = batch_tag("0123456789abcdef", 3);
+        assert_eq!(tag, "[batch 0123456789abcdef#3]");
+        assert_eq!(
+            parse_batch_tag(&format!("Re: [sepolia] {} Send 1 ETH to 0xabc", tag)),
+            Some(("0123456789abcdef".to_string(), 3))
+        );
+        assert_eq!(parse_batch_tag("Send 1 ETH to 0xabc"), None);
+        assert_eq!(parse_batch_tag("[batch 0123#3] Send 1 ETH to 0xabc"), None);
+        assert_eq!(
+            parse_batch_tag("[batch 0123456789abcdef] Send 1 ETH to 0xabc"),
+            None
+        );
+    }
+
+    #[test]
+    fn splits_batch_subjects() {
+        assert_eq!(
+            split_batch_subject("Send 10 USDC to a@x.com, 1,000 USDC to 0xabc; 2 ETH to b@y.com"),
+            Some(vec![
+                "Send 10 USDC to a@x.com".to_string(),
+                "Send 1,000 USDC to 0xabc".to_string(),
+                "Send 2 ETH to b@y.com".to_string(),
+            ])
+        );
+        assert_eq!(split_batch_subject("Send 1,000 USDC to a@x.com"), None);
+        assert_eq!(
+            split_batch_subject("Exit Email Wallet. Set the new wallet owner to 0xabc"),
+            None
+        );
+    }
+
+    #[test]
+    fn groups_address_transfers_by_token() {
+        let transfers = vec![
+            transfer(0, "USDC", Some(Address::repeat_byte(1))),
+            transfer(1, "USDC", None),
+            transfer(2, "ETH", Some(Address::repeat_byte(2))),
+            transfer(3, "USDC", Some(Address::repeat_byte(3))),
+            transfer(4, "DAI", Some(Address::repeat_byte(4))),
+            transfer(5, "DAI", None),
+        ];
+        let groups = group_address_transfers(&transfers);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups.get("USDC"), Some(&vec![0, 3]));
+    }
+
+    #[test]
+    fn lists_one_confirmation_per_group() {
+        let batch = batch(vec![
+            item(0, "Send 1 USDC to 0x01", "Batch 7 sends 2 USDC"),
+            item(1, "Send 1 USDC to a@x.com", "Send 1 USDC to a@x.com"),
+            item(2, "Send 1 USDC to 0x02", "Batch 7 sends 2 USDC"),
+            item(3, "Send 1 USDC to a@x.com", "Send 1 USDC to a@x.com"),
+        ]);
+        let leads: Vec<i32> = batch.confirmations().iter().map(|item| item.idx).collect();
+        assert_eq!(leads, vec![0, 1, 3]);
+        assert_eq!(batch.confirmed_idxs(0), vec![0, 2]);
+        // Two identical sends are still confirmed one by one.
+        assert_eq!(batch.confirmed_idxs(1), vec![1]);
+        assert_eq!(batch.confirmed_idxs(3), vec![3]);
+        assert!(batch.confirmed_idxs(9).is_empty());
+    }
+}
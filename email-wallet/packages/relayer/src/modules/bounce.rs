@@ -0,0 +1,282 @@
+use crate::*;
+
+use mailparse::{parse_headers, parse_mail, MailHeaderMap, ParsedMail};
+use regex::Regex;
+
+/// A delivery status notification (RFC 3464) returned by a remote MTA.
+#[derive(Debug, Clone, Default)]
+pub struct DeliveryStatusReport {
+    /// Recipients whose `Action` is `failed`.
+    pub failed_recipients: Vec<String>,
+    /// Enhanced status code, e.g. `5.1.1`.
+    pub status: Option<String>,
+    /// Human readable reason given by the remote server.
+    pub diagnostic: Option<String>,
+    /// Subject of the message that bounced, if the report returned its headers.
+    pub original_subject: Option<String>,
+}
+
+/// Returns true if the raw email is a `multipart/report` delivery status notification.
+pub fn is_delivery_status_notification(raw_email: &str) -> bool {
+    match parse_mail(raw_email.as_bytes()) {
+        Ok(parsed) => is_report(&parsed),
+        Err(_) => false,
+    }
+}
+
+fn is_report(parsed: &ParsedMail) -> bool {
+    parsed
+        .ctype
+        .mimetype
+        .eq_ignore_ascii_case("multipart/report")
+        && parsed
+            .ctype
+            .params
+            .get("report-type")
+            .map(|t| t.eq_ignore_ascii_case("delivery-status"))
+            .unwrap_or(false)
+}
+
+pub fn parse_delivery_status(raw_email: &str) -> Result<DeliveryStatusReport> {
+    let parsed = parse_mail(raw_email.as_bytes())?;
+    if !is_report(&parsed) {
+        bail!("The email is not a delivery status notification");
+    }
+    let mut report = DeliveryStatusReport::default();
+    for part in parsed.subparts.iter() {
+        match part.ctype.mimetype.to_ascii_lowercase().as_str() {
+            "message/delivery-status" => {
+                parse_status_fields(&part.get_body()?, &mut report);
+            }
+            "message/rfc822" | "text/rfc822-headers" => {
+                let raw = part.get_body_raw()?;
+                let (headers, _) = parse_headers(&raw)?;
+                report.original_subject = headers.get_first_value("Subject");
+            }
+            _ => {}
+        }
+    }
+    if report.failed_recipients.is_empty() {
+        bail!("No failed recipient found in the delivery status notification");
+    }
+    Ok(report)
+}
+
+// The delivery-status body is a list of header-like field groups separated by blank lines:
+// one per-message group followed by one group per recipient.
+fn parse_status_fields(body: &str, report: &mut DeliveryStatusReport) {
+    let body = body.replace("\r\n", "\n");
+    for group in body.split("\n\n") {
+        let mut recipient = None;
+        let mut is_failed = false;
+        for line in group.lines() {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "final-recipient" | "original-recipient" => {
+                    if recipient.is_none() {
+                        // e.g. "rfc822; bob@example.com"
+                        let addr = value.rsplit(';').next().unwrap_or(value).trim();
+                        recipient = Some(addr.trim_matches(|c| c == '<' || c == '>').to_string());
+                    }
+                }
+                "action" => is_failed = value.eq_ignore_ascii_case("failed"),
+                "status" => report.status = Some(value.to_string()),
+                "diagnostic-code" => {
+                    let diagnostic = value.split_once(';').map(|(_, d)| d).unwrap_or(value);
+                    report.diagnostic = Some(diagnostic.trim().to_string());
+                }
+                _ => {}
+            }
+        }
+        if let (Some(recipient), true) = (recipient, is_failed) {
+            if !report.failed_recipients.contains(&recipient) {
+                report.failed_recipients.push(recipient);
+            }
+        }
+    }
+}
+
+/// The account code in the subject of the bounced email, if it was one of our invitations.
+pub fn bounced_invitation_code(report: &DeliveryStatusReport) -> Option<String> {
+    report.original_subject.as_ref().and_then(|subject| {
+        Regex::new("Code (?:0x)?([0-9a-fA-F]{64})")
+            .unwrap()
+            .captures(subject)
+            .map(|c| c[1].to_lowercase())
+    })
+}
+
+/// Anyone can send us a delivery status notification, so it is only trusted if the bounced subject
+/// carries the account code stored for the recipient, which only the invitation email contains.
+pub fn is_bounced_invitation(
+    invitation_code: Option<&str>,
+    stored_account_code: Option<&str>,
+) -> bool {
+    match (invitation_code, stored_account_code) {
+        (Some(code), Some(account_code)) => account_code
+            .trim_start_matches("0x")
+            .eq_ignore_ascii_case(code),
+        _ => false,
+    }
+}
+
+/// Marks the claims of bounced invitations on the current chain as undeliverable and builds the
+/// notifications for their senders.
+#[named]
+pub async fn handle_delivery_status(raw_email: &str) -> Result<Vec<EmailWalletEvent>> {
+    let report = parse_delivery_status(raw_email)?;
+    info!(LOG, "delivery status report {:?}", report; "func" => function_name!());
+    let invitation_code = bounced_invitation_code(&report);
+    let diagnostic = report
+        .diagnostic
+        .clone()
+        .or(report.status.clone())
+        .unwrap_or_else(|| "The recipient's mail server rejected the email.".to_string());
+
+    let mut events = vec![];
+    for email_addr in report.failed_recipients.iter() {
+        let account_code = DB.get_account_code(email_addr).await?;
+        if !is_bounced_invitation(invitation_code.as_deref(), account_code.as_deref()) {
+            info!(LOG, "bounced email for {} is not our invitation on chain {}", email_addr, current_chain_id(); "func" => function_name!());
+            continue;
+        }
+        let claims = DB.get_claims_by_email_addr(email_addr).await?;
+        for claim in claims {
+            if !DB
+                .mark_claim_undeliverable(&claim.id, claim.is_fund)
+                .await?
+            {
+                continue;
+            }
+            info!(LOG, "claim {} for {} marked undeliverable", claim.id, email_addr; "func" => function_name!());
+            let (sender, asset) = describe_claim(&claim).await?;
+            let sender_wallet_addr = format!("0x{}", hex::encode(sender.as_bytes()));
+            let sender_email_addr = match DB.get_email_by_wallet(&sender_wallet_addr).await {
+                Ok(email_addr) => email_addr,
+                Err(_) => {
+                    info!(LOG, "sender {} is not our user", sender_wallet_addr; "func" => function_name!());
+                    continue;
+                }
+            };
+            events.push(EmailWalletEvent::Undeliverable {
+                claim,
+                sender_email_addr,
+                sender_wallet_addr,
+                asset,
+                diagnostic: diagnostic.clone(),
+            });
+        }
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODE: &str = "1f3a5c7e9b2d4f6a8c0e1f3a5c7e9b2d4f6a8c0e1f3a5c7e9b2d4f6a8c0e1f3a";
+
+    fn dsn(original_headers: Option<&str>) -> String {
+        let mut email = "From: MAILER-DAEMON@mx.example.com\r\n\
+             To: relayer@example.com\r\n\
+             Subject: Undelivered Mail Returned to Sender\r\n\
+             MIME-Version: 1.0\r\n\
+             Content-Type: multipart/report; report-type=delivery-status; boundary=\"b\"\r\n\
+             \r\n\
+             --b\r\n\
+             Content-Type: text/plain\r\n\
+             \r\n\
+             Delivery failed.\r\n\
+             --b\r\n\
+             Content-Type: message/delivery-status\r\n\
+             \r\n\
+             Reporting-MTA: dns; mx.example.com\r\n\
+             \r\n\
+             Final-Recipient: rfc822; bob@example.com\r\n\
+             Action: failed\r\n\
+             Status: 5.1.1\r\n\
+             Diagnostic-Code: smtp; 550 5.1.1 User unknown\r\n\
+             \r\n"
+            .to_string();
+        if let Some(headers) = original_headers {
+            email.push_str("--b\r\nContent-Type: text/rfc822-headers\r\n\r\n");
+            email.push_str(headers);
+            email.push_str("\r\n");
+        }
+        email.push_str("--b--\r\n");
+        email
+    }
+
+    #[test]
+    fn parses_failed_recipients() {
+        let report = parse_delivery_status(&dsn(None)).unwrap();
+        assert_eq!(
+            report.failed_recipients,
+            vec!["bob@example.com".to_string()]
+        );
+        assert_eq!(report.status.as_deref(), Some("5.1.1"));
+        assert_eq!(report.diagnostic.as_deref(), Some("550 5.1.1 User unknown"));
+    }
+
+    #[test]
+    fn accepts_bounced_invitation() {
+        let headers = format!(
+            "Subject: Your Email Wallet Account is ready to be deployed. Code {}\r\n",
+            CODE
+        );
+        let report = parse_delivery_status(&dsn(Some(&headers))).unwrap();
+        let code = bounced_invitation_code(&report);
+        assert_eq!(code.as_deref(), Some(CODE));
+        assert!(is_bounced_invitation(
+            code.as_deref(),
+            Some(&format!("0x{}", CODE.to_uppercase()))
+        ));
+    }
+
+    #[test]
+    fn rejects_forged_dsn() {
+        let forged = "ab".repeat(32);
+        let headers = format!(
+            "Subject: Your Email Wallet Account is ready to be deployed. Code {}\r\n",
+            forged
+        );
+        let report = parse_delivery_status(&dsn(Some(&headers))).unwrap();
+        let code = bounced_invitation_code(&report);
+        assert!(!is_bounced_invitation(
+            code.as_deref(),
+            Some(&format!("0x{}", CODE))
+        ));
+        assert!(!is_bounced_invitation(code.as_deref(), None));
+    }
+
+    #[test]
+    fn rejects_dsn_without_subject() {
+        let report = parse_delivery_status(&dsn(None)).unwrap();
+        let code = bounced_invitation_code(&report);
+        assert_eq!(code, None);
+        assert!(!is_bounced_invitation(
+            code.as_deref(),
+            Some(&format!("0x{}", CODE))
+        ));
+
+        let report = parse_delivery_status(&dsn(Some(
+            "Subject: Hello\r\nFrom: relayer@example.com\r\n",
+        )))
+        .unwrap();
+        assert!(!is_bounced_invitation(
+            bounced_invitation_code(&report).as_deref(),
+            Some(&format!("0x{}", CODE))
+        ));
+    }
+
+    #[test]
+    fn ignores_non_reports() {
+        assert!(!is_delivery_status_notification(
+            "From: a@example.com\r\nSubject: Send 1 ETH to b@example.com\r\n\r\nhi\r\n"
+        ));
+        assert!(is_delivery_status_notification(&dsn(None)));
+    }
+}
@@ -0,0 +1,246 @@
+use crate::*;
+
+use ethers::abi::{decode, encode, ParamType, Token};
+use ethers::utils::id;
+
+const CANCEL_UNCLAIMED_FUND_SIGNATURE: &str = "cancelUnclaimedFund(uint256)";
+const CANCEL_UNCLAIMED_STATE_SIGNATURE: &str = "cancelUnclaimedState(uint256)";
+
+/// Returns the sender's wallet address and a human readable description of what the claim holds.
+pub async fn describe_claim(claim: &Claim) -> Result<(Address, String)> {
+    if claim.is_fund {
+        let unclaimed_fund = CLIENT.query_unclaimed_fund(claim.id).await?;
+        Ok((
+            unclaimed_fund.sender,
+            describe_unclaimed_fund(&unclaimed_fund).await?,
+        ))
+    } else {
+        let unclaimed_state = CLIENT.query_unclaimed_state(claim.id).await?;
+        Ok((
+            unclaimed_state.sender,
+            describe_unclaimed_state(&unclaimed_state),
+        ))
+    }
+}
+
+/// The on-chain record is deleted by a claim or a void, so describe it before either.
+pub async fn describe_unclaimed_fund(unclaimed_fund: &UnclaimedFund) -> Result<String> {
+    let decimals = CLIENT
+        .query_decimals_of_erc20_address(unclaimed_fund.token_addr)
+        .await?;
+    let token_name = CLIENT.query_token_name(unclaimed_fund.token_addr).await?;
+    Ok(format!(
+        "{} {}",
+        uint_to_decimal_string(unclaimed_fund.amount, decimals as usize),
+        token_name
+    ))
+}
+
+pub fn describe_unclaimed_state(unclaimed_state: &UnclaimedState) -> String {
+    format!("data of extension {}", unclaimed_state.extension_addr)
+}
+
+/// Unexpired claims that the wallet sent to the email address, newest first.
+pub async fn get_cancellable_claims(
+    sender_wallet_addr: Address,
+    recipient_email_addr: &str,
+) -> Result<Vec<Claim>> {
+    let now = now();
+    let mut claims = DB.get_claims_by_email_addr(recipient_email_addr).await?;
+    // Claims stored before their sender was recorded have none, so look it up on-chain.
+    for claim in claims
+        .iter_mut()
+        .filter(|claim| claim.sender.is_zero() && claim.expiry_time > now)
+    {
+        claim.sender = if claim.is_fund {
+            CLIENT.query_unclaimed_fund(claim.id).await?.sender
+        } else {
+            CLIENT.query_unclaimed_state(claim.id).await?.sender
+        };
+    }
+    Ok(select_cancellable_claims(claims, sender_wallet_addr, now))
+}
+
+fn select_cancellable_claims(
+    claims: Vec<Claim>,
+    sender_wallet_addr: Address,
+    now: i64,
+) -> Vec<Claim> {
+    let mut claims: Vec<Claim> = claims
+        .into_iter()
+        .filter(|claim| {
+            claim.expiry_time > now
+                && claim.status.is_active()
+                && claim.sender == sender_wallet_addr
+        })
+        .collect();
+    claims.sort_by(|a, b| b.expiry_time.cmp(&a.expiry_time));
+    claims
+}
+
+/// Call data of an `Execute` email op that makes the sender's wallet cancel the claim.
+pub fn cancel_execute_call_data(claim: &Claim) -> Bytes {
+    encode_cancel_call_data(CLIENT.unclaims_handler.address(), claim)
+}
+
+fn encode_cancel_call_data(unclaims_handler: Address, claim: &Claim) -> Bytes {
+    let signature = if claim.is_fund {
+        CANCEL_UNCLAIMED_FUND_SIGNATURE
+    } else {
+        CANCEL_UNCLAIMED_STATE_SIGNATURE
+    };
+    let mut data = id(signature).to_vec();
+    data.extend(encode(&[Token::Uint(claim.id)]));
+    Bytes::from(encode(&[
+        Token::Address(unclaims_handler),
+        Token::Uint(U256::zero()),
+        Token::Bytes(data),
+    ]))
+}
+
+/// The subject the sender has to reply with to cancel the claim.
+pub fn cancel_subject(claim: &Claim) -> String {
+    format!(
+        "{} 0x{}",
+        EXECUTE_COMMAND,
+        hex::encode(cancel_execute_call_data(claim))
+    )
+}
+
+/// Returns the claim id and whether it is a fund if the `Execute` call data cancels an unclaimed fund or state.
+pub fn parse_cancel_execute_call_data(call_data: &[u8]) -> Option<(U256, bool)> {
+    decode_cancel_call_data(CLIENT.unclaims_handler.address(), call_data)
+}
+
+fn decode_cancel_call_data(unclaims_handler: Address, call_data: &[u8]) -> Option<(U256, bool)> {
+    let tokens = decode(
+        &[ParamType::Address, ParamType::Uint(256), ParamType::Bytes],
+        call_data,
+    )
+    .ok()?;
+    let [Token::Address(target), _, Token::Bytes(data)] = tokens.as_slice() else {
+        return None;
+    };
+    if *target != unclaims_handler || data.len() != 36 {
+        return None;
+    }
+    let is_fund = if data[..4] == id(CANCEL_UNCLAIMED_FUND_SIGNATURE) {
+        true
+    } else if data[..4] == id(CANCEL_UNCLAIMED_STATE_SIGNATURE) {
+        false
+    } else {
+        return None;
+    };
+    Some((U256::from_big_endian(&data[4..]), is_fund))
+}
+
+/// Finds the stored claim that the `Execute` call data cancels, together with its description.
+/// The description has to be queried before the email op is executed since the claim is deleted on-chain.
+pub async fn get_claim_cancelled_by(call_data: &[u8]) -> Result<Option<(Claim, String)>> {
+    let Some((id, is_fund)) = parse_cancel_execute_call_data(call_data) else {
+        return Ok(None);
+    };
+    match DB.get_claim(&id, is_fund).await? {
+        Some(claim) if claim.status.is_active() => {
+            let (_, asset) = describe_claim(&claim).await?;
+            Ok(Some((claim, asset)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Builds the confirmation email for cancelling the newest unexpired claim from the wallet to the recipient.
+pub async fn request_cancellation(
+    email_addr: String,
+    wallet_addr: Address,
+    recipient_email_addr: &str,
+) -> Result<EmailWalletEvent> {
+    let claims = get_cancellable_claims(wallet_addr, recipient_email_addr).await?;
+    let Some(claim) = claims.into_iter().next() else {
+        bail!(
+            "You have no unclaimed transfer to {} that can be cancelled",
+            recipient_email_addr
+        );
+    };
+    let (_, asset) = describe_claim(&claim).await?;
+    Ok(EmailWalletEvent::CancelRequest {
+        email_addr,
+        wallet_addr,
+        subject: cancel_subject(&claim),
+        claim,
+        asset,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claim(id: u64, is_fund: bool, sender: Address, expiry_time: i64) -> Claim {
+        Claim {
+            tx_hash: String::new(),
+            id: U256::from(id),
+            email_address: "bob@example.com".to_string(),
+            random: String::new(),
+            commit: String::new(),
+            expiry_time,
+            is_fund,
+            is_announced: false,
+            status: ClaimStatus::Invited,
+            attempts: 0,
+            sender,
+        }
+    }
+
+    #[test]
+    fn round_trips_cancel_call_data() {
+        let handler = Address::from_low_u64_be(0xaa);
+        for is_fund in [true, false] {
+            let data = encode_cancel_call_data(handler, &claim(42, is_fund, handler, 0));
+            assert_eq!(
+                decode_cancel_call_data(handler, &data),
+                Some((U256::from(42), is_fund))
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_other_execute_call_data() {
+        let handler = Address::from_low_u64_be(0xaa);
+        let data = encode_cancel_call_data(handler, &claim(42, true, handler, 0));
+        assert_eq!(
+            decode_cancel_call_data(Address::from_low_u64_be(0xbb), &data),
+            None
+        );
+
+        let mut call = id("voidUnclaimedFund(uint256)").to_vec();
+        call.extend(encode(&[Token::Uint(U256::from(42))]));
+        let data = encode(&[
+            Token::Address(handler),
+            Token::Uint(U256::zero()),
+            Token::Bytes(call),
+        ]);
+        assert_eq!(decode_cancel_call_data(handler, &data), None);
+        assert_eq!(decode_cancel_call_data(handler, b"not abi"), None);
+    }
+
+    #[test]
+    fn selects_the_senders_unexpired_claims_newest_first() {
+        let sender = Address::from_low_u64_be(1);
+        let other = Address::from_low_u64_be(2);
+        let mut claimed = claim(5, true, sender, 400);
+        claimed.status = ClaimStatus::Claimed;
+        let claims = vec![
+            claim(1, true, sender, 200),
+            claim(2, false, sender, 300),
+            claim(3, true, other, 500),
+            claim(4, true, sender, 50),
+            claimed,
+        ];
+        let ids: Vec<U256> = select_cancellable_claims(claims, sender, 100)
+            .iter()
+            .map(|claim| claim.id)
+            .collect();
+        assert_eq!(ids, vec![U256::from(2), U256::from(1)]);
+    }
+}
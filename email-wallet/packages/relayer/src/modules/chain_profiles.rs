@@ -0,0 +1,306 @@
+use crate::*;
+
+use std::collections::HashMap;
+use std::ops::Deref;
+
+use axum::{
+    extract::Query,
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use regex::Regex;
+use serde::Deserialize;
+
+/// A chain the relayer serves: its own RPC, explorer and Email Wallet deployment.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChainProfile {
+    /// Used to select the chain, e.g. `[base-sepolia]` in a subject.
+    pub name: String,
+    pub chain_id: u32,
+    pub rpc_provider: String,
+    pub rpc_explorer: String,
+    pub core_contract_address: String,
+    /// A `ws://`/`wss://` URL or an IPC socket path to subscribe to logs with. Logs are polled over
+    /// `rpc_provider` if unset.
+    #[serde(default)]
+    pub subscription_provider: Option<String>,
+}
+
+tokio::task_local! {
+    static CURRENT_CHAIN: u32;
+}
+
+/// The chain configured by CHAIN_ID, CHAIN_RPC_PROVIDER, CHAIN_RPC_EXPLORER and CORE_CONTRACT_ADDRESS,
+/// named by CHAIN_NAME or else by its chain id.
+pub fn default_chain_profile(
+    name: Option<String>,
+    subscription_provider: Option<String>,
+) -> ChainProfile {
+    let chain_id = *CHAIN_ID.get().unwrap();
+    ChainProfile {
+        name: name.unwrap_or_else(|| chain_id.to_string()),
+        chain_id,
+        rpc_provider: CHAIN_RPC_PROVIDER.get().unwrap().clone(),
+        rpc_explorer: CHAIN_RPC_EXPLORER.get().unwrap().clone(),
+        core_contract_address: CORE_CONTRACT_ADDRESS.get().unwrap().clone(),
+        subscription_provider,
+    }
+}
+
+/// The default chain followed by the chains listed in the JSON file at `path`.
+pub fn load_chain_profiles(default: ChainProfile, path: Option<&str>) -> Result<Vec<ChainProfile>> {
+    let extra: Vec<ChainProfile> = match path {
+        Some(path) => serde_json::from_str(
+            &std::fs::read_to_string(path)
+                .map_err(|e| anyhow!("Failed to read chain profiles '{}': {}", path, e))?,
+        )?,
+        None => vec![],
+    };
+    add_chain_profiles(default, extra)
+}
+
+fn add_chain_profiles(
+    default: ChainProfile,
+    extra: Vec<ChainProfile>,
+) -> Result<Vec<ChainProfile>> {
+    let mut profiles = vec![default];
+    for profile in extra {
+        if profiles
+            .iter()
+            .any(|p| p.chain_id == profile.chain_id || p.name.eq_ignore_ascii_case(&profile.name))
+        {
+            bail!("Chain {} is configured twice", profile.name);
+        }
+        profiles.push(profile);
+    }
+    Ok(profiles)
+}
+
+pub fn chain_ids() -> Vec<u32> {
+    CHAIN_PROFILES
+        .get()
+        .unwrap()
+        .iter()
+        .map(|profile| profile.chain_id)
+        .collect()
+}
+
+/// Finds a configured chain by name, case-insensitively, or by chain id.
+pub fn find_chain(selector: &str) -> Option<&'static ChainProfile> {
+    find_chain_in(CHAIN_PROFILES.get().unwrap(), selector)
+}
+
+fn find_chain_in<'a>(profiles: &'a [ChainProfile], selector: &str) -> Option<&'a ChainProfile> {
+    let selector = selector.trim();
+    profiles.iter().find(|profile| {
+        profile.name.eq_ignore_ascii_case(selector) || profile.chain_id.to_string() == selector
+    })
+}
+
+/// Runs `f` against the given chain: `CLIENT`, the explorer links and the database rows all follow it.
+/// Tasks spawned inside `f` fall back to the default chain unless they are scoped again.
+/// Fails without running `f` if the chain is not configured.
+pub async fn with_chain<F: Future>(chain_id: u32, f: F) -> Result<F::Output> {
+    if !chain_ids().contains(&chain_id) {
+        bail!("Chain {} is not configured", chain_id);
+    }
+    Ok(CURRENT_CHAIN.scope(chain_id, f).await)
+}
+
+pub fn current_chain_id() -> u32 {
+    CURRENT_CHAIN
+        .try_with(|chain_id| *chain_id)
+        .unwrap_or_else(|_| *CHAIN_ID.get().unwrap())
+}
+
+pub fn current_chain() -> &'static ChainProfile {
+    let chain_id = current_chain_id();
+    CHAIN_PROFILES
+        .get()
+        .unwrap()
+        .iter()
+        .find(|profile| profile.chain_id == chain_id)
+        // `with_chain` only scopes configured chains and the default chain is always configured.
+        .expect("the current chain is configured")
+}
+
+pub fn chain_rpc_explorer() -> &'static str {
+    &current_chain().rpc_explorer
+}
+
+/// The chain selected by a `[chain]` tag in the subject, which must come before the command
+/// so that it stays in the skipped prefix of the proven subject. The default chain if there is no tag.
+/// Tags with whitespace, like the relayer's own `[batch ...]` and `[request ...]`, are not chains.
+pub fn chain_from_subject(subject: &str) -> Result<u32> {
+    chain_from_subject_in(CHAIN_PROFILES.get().unwrap(), subject)
+}
+
+// The default chain is the first profile, see `load_chain_profiles`.
+fn chain_from_subject_in(profiles: &[ChainProfile], subject: &str) -> Result<u32> {
+    let re = Regex::new(r"\[([^\[\]\s]+)\]").unwrap();
+    match re.captures(subject) {
+        Some(cap) => find_chain_in(profiles, &cap[1])
+            .map(|profile| profile.chain_id)
+            .ok_or_else(|| anyhow!("Unknown chain {}", &cap[1])),
+        None => Ok(profiles[0].chain_id),
+    }
+}
+
+/// The `[chain] ` tag for subjects the user replies to, so that the reply runs on the chain of the request.
+/// Empty on the default chain.
+pub fn chain_subject_prefix() -> String {
+    if current_chain_id() == *CHAIN_ID.get().unwrap() {
+        String::new()
+    } else {
+        format!("[{}] ", current_chain().name)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ChainQuery {
+    pub chain: Option<String>,
+}
+
+/// Scopes an API request to the chain given by the `chain` query parameter or the `X-Chain` header.
+pub async fn chain_scope<B>(
+    Query(query): Query<ChainQuery>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let selector = query.chain.or_else(|| {
+        request
+            .headers()
+            .get("x-chain")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    });
+    let chain_id = match selector {
+        Some(selector) => match find_chain(&selector) {
+            Some(profile) => profile.chain_id,
+            None => {
+                return (
+                    axum::http::StatusCode::BAD_REQUEST,
+                    format!("Unknown chain {}", selector),
+                )
+                    .into_response()
+            }
+        },
+        None => *CHAIN_ID.get().unwrap(),
+    };
+    match with_chain(chain_id, next.run(request)).await {
+        Ok(response) => response,
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+/// One `ChainClient` per configured chain.
+pub struct ChainClients(HashMap<u32, ChainClient>);
+
+impl ChainClients {
+    pub async fn setup() -> Result<Self> {
+        let mut clients = HashMap::new();
+        for profile in CHAIN_PROFILES.get().unwrap() {
+            clients.insert(profile.chain_id, ChainClient::setup(profile).await?);
+        }
+        Ok(Self(clients))
+    }
+
+    pub fn get(&self, chain_id: u32) -> Option<&ChainClient> {
+        self.0.get(&chain_id)
+    }
+}
+
+/// Dereferences to the client of the chain the current task is scoped to.
+pub struct CurrentChainClient;
+
+impl Deref for CurrentChainClient {
+    type Target = ChainClient;
+
+    fn deref(&self) -> &ChainClient {
+        let chain_id = current_chain_id();
+        // Every configured chain gets a client at startup, see `current_chain`.
+        CHAIN_CLIENTS
+            .get(chain_id)
+            .expect("the current chain has a client")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(name: &str, chain_id: u32) -> ChainProfile {
+        ChainProfile {
+            name: name.to_string(),
+            chain_id,
+            rpc_provider: format!("https://{}.example.com", name),
+            rpc_explorer: format!("https://explorer.{}.example.com", name),
+            core_contract_address: format!("{:?}", Address::zero()),
+            subscription_provider: None,
+        }
+    }
+
+    fn profiles() -> Vec<ChainProfile> {
+        add_chain_profiles(
+            profile("sepolia", 11155111),
+            vec![profile("base-sepolia", 84532)],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn finds_chains_by_name_or_id() {
+        let profiles = profiles();
+        assert_eq!(
+            find_chain_in(&profiles, "Base-Sepolia").map(|p| p.chain_id),
+            Some(84532)
+        );
+        assert_eq!(
+            find_chain_in(&profiles, " 11155111 ").map(|p| p.chain_id),
+            Some(11155111)
+        );
+        assert!(find_chain_in(&profiles, "mainnet").is_none());
+        assert!(find_chain_in(&profiles, "1").is_none());
+    }
+
+    #[test]
+    fn selects_the_chain_of_a_subject() {
+        let profiles = profiles();
+        let chain = |subject| chain_from_subject_in(&profiles, subject);
+        assert_eq!(chain("Send 1 ETH to bob@example.com").unwrap(), 11155111);
+        assert_eq!(
+            chain("[base-sepolia] Send 1 ETH to bob@example.com").unwrap(),
+            84532
+        );
+        assert_eq!(
+            chain("Re: [84532] Send 1 ETH to bob@example.com").unwrap(),
+            84532
+        );
+        // The relayer's own tags have spaces and are not chains.
+        assert_eq!(
+            chain("[batch 12] Send 1 ETH to bob@example.com").unwrap(),
+            11155111
+        );
+        assert!(chain("[mainnet] Send 1 ETH to bob@example.com").is_err());
+    }
+
+    #[test]
+    fn rejects_chains_configured_twice() {
+        assert!(
+            add_chain_profiles(profile("sepolia", 11155111), vec![profile("Sepolia", 1)]).is_err()
+        );
+        assert!(add_chain_profiles(
+            profile("sepolia", 11155111),
+            vec![profile("other", 84532), profile("base", 84532)]
+        )
+        .is_err());
+        assert_eq!(
+            add_chain_profiles(profile("sepolia", 11155111), vec![])
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use crate::*;
+
+use axum::response::Html;
+use hmac::{Hmac, Mac};
+use relayer_utils::{hex2field, AccountCode};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+/// How long a claim link in an invitation stays valid.
+pub const CLAIM_LINK_TTL: i64 = 14 * 24 * 60 * 60;
+/// A click sends at most one email to the recipient in this many seconds.
+pub const CLAIM_LINK_EMAIL_INTERVAL: i64 = 10 * 60;
+
+#[derive(Debug, Deserialize)]
+pub struct ClaimLinkQuery {
+    pub email: String,
+    pub expires: i64,
+    pub sig: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClaimLinkStats {
+    pub clicks: i64,
+    pub recipients_clicked: i64,
+    pub recipients_converted: i64,
+}
+
+// Only the relayer can sign links since CLAIM_LINK_KEY is derived from its private key.
+fn claim_link_mac(key: &[u8], email_addr: &str, expires: i64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any size");
+    // The email address is length-prefixed so that no other address and expiry give the same message.
+    mac.update(&(email_addr.len() as u64).to_be_bytes());
+    mac.update(email_addr.as_bytes());
+    mac.update(&expires.to_be_bytes());
+    mac
+}
+
+fn claim_link_signature(key: &[u8], email_addr: &str, expires: i64) -> String {
+    hex::encode(
+        claim_link_mac(key, email_addr, expires)
+            .finalize()
+            .into_bytes(),
+    )
+}
+
+fn is_valid_claim_link(key: &[u8], query: &ClaimLinkQuery, now: i64) -> bool {
+    let Ok(sig) = hex::decode(&query.sig) else {
+        return false;
+    };
+    // `verify_slice` compares in constant time so that the signature cannot be guessed byte by byte.
+    query.expires > now
+        && claim_link_mac(key, &query.email, query.expires)
+            .verify_slice(&sig)
+            .is_ok()
+}
+
+/// The signed link for the invitation of the email address, or None if CLAIM_LINK_BASE_URL is not set.
+pub fn generate_claim_link(email_addr: &str, now: i64) -> Result<Option<String>> {
+    let Some(base_url) = CLAIM_LINK_BASE_URL.get().unwrap() else {
+        return Ok(None);
+    };
+    let expires = now + CLAIM_LINK_TTL;
+    let url = reqwest::Url::parse_with_params(
+        &format!("{}/api/claimLink", base_url.trim_end_matches('/')),
+        &[
+            ("email", email_addr.to_string()),
+            ("expires", expires.to_string()),
+            (
+                "sig",
+                claim_link_signature(CLAIM_LINK_KEY.get().unwrap(), email_addr, expires),
+            ),
+            // Read by the chain scope of the API, so that the link resends the email for this chain.
+            ("chain", current_chain().name.clone()),
+        ],
+    )?;
+    Ok(Some(url.to_string()))
+}
+
+/// Handles a click on a claim link: sends the recipient a fresh invitation whose subject has not been
+/// through their mail client, and returns the page that tells them what to do with it.
+#[named]
+pub async fn serve_claim_link(query: ClaimLinkQuery) -> Result<Html<String>> {
+    let now = now();
+    let message = if !is_valid_claim_link(CLAIM_LINK_KEY.get().unwrap(), &query, now) {
+        "This link is invalid or has expired. Please reply to the invitation email instead."
+            .to_string()
+    } else {
+        let email_addr = query.email;
+        DB.record_claim_link_click(&email_addr, now).await?;
+        info!(LOG, "claim link clicked by {}", email_addr; "func" => function_name!());
+        match DB.get_account_code(&email_addr).await? {
+            None => "There is nothing to claim for this email address.".to_string(),
+            Some(account_code_str)
+                if CLIENT
+                    .check_if_account_created_by_account_code(&email_addr, &account_code_str)
+                    .await? =>
+            {
+                "Your account is already created. What was sent to you is claimed into your wallet automatically.".to_string()
+            }
+            Some(account_code_str) => {
+                if DB
+                    .take_claim_link_email_slot(&email_addr, now, CLAIM_LINK_EMAIL_INTERVAL)
+                    .await?
+                {
+                    handle_email_event(EmailWalletEvent::Invitation {
+                        email_addr: email_addr.clone(),
+                        account_code: AccountCode(hex2field(&account_code_str)?),
+                        is_first: false,
+                        tx_hash: "".to_string(),
+                    })
+                    .await?;
+                }
+                format!(
+                    "We sent a new email to {}. Reply to it without changing its subject; you do not have to write anything. Your account is created when the reply arrives.",
+                    email_addr
+                )
+            }
+        }
+    };
+    let render_data = serde_json::json!({"message": message});
+    Ok(Html(render_html("claim_link.html", render_data).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"claim link test key";
+    const NOW: i64 = 1_700_000_000;
+
+    fn signed_query(email: &str, expires: i64) -> ClaimLinkQuery {
+        ClaimLinkQuery {
+            email: email.to_string(),
+            expires,
+            sig: claim_link_signature(KEY, email, expires),
+        }
+    }
+
+    #[test]
+    fn accepts_a_signed_link_until_it_expires() {
+        let query = signed_query("alice@example.com", NOW + CLAIM_LINK_TTL);
+        assert!(is_valid_claim_link(KEY, &query, NOW));
+        assert!(is_valid_claim_link(KEY, &query, NOW + CLAIM_LINK_TTL - 1));
+        assert!(!is_valid_claim_link(KEY, &query, NOW + CLAIM_LINK_TTL));
+        assert!(!is_valid_claim_link(KEY, &query, NOW + CLAIM_LINK_TTL + 1));
+    }
+
+    #[test]
+    fn rejects_tampered_links() {
+        let query = signed_query("alice@example.com", NOW + 60);
+
+        let mut other_email = signed_query("alice@example.com", NOW + 60);
+        other_email.email = "mallory@example.com".to_string();
+        assert!(!is_valid_claim_link(KEY, &other_email, NOW));
+
+        let mut extended = signed_query("alice@example.com", NOW + 60);
+        extended.expires += CLAIM_LINK_TTL;
+        assert!(!is_valid_claim_link(KEY, &extended, NOW));
+
+        let mut flipped = signed_query("alice@example.com", NOW + 60);
+        let last = if flipped.sig.ends_with('0') { "1" } else { "0" };
+        flipped.sig.replace_range(flipped.sig.len() - 1.., last);
+        assert!(!is_valid_claim_link(KEY, &flipped, NOW));
+
+        let mut truncated = signed_query("alice@example.com", NOW + 60);
+        truncated.sig.truncate(32);
+        assert!(!is_valid_claim_link(KEY, &truncated, NOW));
+
+        let mut not_hex = signed_query("alice@example.com", NOW + 60);
+        not_hex.sig = "zz".repeat(32);
+        assert!(!is_valid_claim_link(KEY, &not_hex, NOW));
+
+        assert!(!is_valid_claim_link(b"another relayer", &query, NOW));
+    }
+
+    #[test]
+    fn derives_a_key_apart_from_the_relayer_rand() {
+        let private_key = format!("0x{}", "11".repeat(32));
+        let key = derive_claim_link_key(&private_key).unwrap();
+        assert_eq!(key.len(), 32);
+        assert_eq!(key, derive_claim_link_key(&private_key).unwrap());
+        assert_ne!(
+            key,
+            derive_claim_link_key(&format!("0x{}", "22".repeat(32))).unwrap()
+        );
+        let relayer_rand = derive_relayer_rand(&private_key).unwrap();
+        assert_ne!(
+            field2hex(&relayer_rand.0),
+            format!("0x{}", hex::encode(&key))
+        );
+    }
+}
@@ -3,6 +3,7 @@
 use crate::*;
 
 use ethers::types::Address;
+use rand::Rng;
 use relayer_utils::{
     field2hex, generate_claim_input, hex2field, u256_to_bytes32, AccountCode, AccountSalt,
     PaddedEmailAddr,
@@ -19,46 +20,256 @@ pub struct Claim {
     pub expiry_time: i64,
     pub is_fund: bool,
     pub is_announced: bool,
-    pub is_seen: bool,
+    pub status: ClaimStatus,
+    /// Number of claim and void transactions tried so far.
+    pub attempts: i32,
+    /// The wallet that registered the unclaimed fund or state.
+    pub sender: Address,
 }
 
-#[named]
-pub async fn claim_unclaims(mut claim: Claim) -> Result<EmailWalletEvent> {
-    let need_creation = true;
-    let is_seen = claim.is_seen;
-    if DB
-        .get_claims_by_id(&claim.id)
+/// After this many failed claim or void transactions a claim is left in `Failed` for an operator to look at.
+pub const MAX_CLAIM_ATTEMPTS: i32 = 5;
+
+/// Where a claim is in its lifecycle. Every change goes through `transition_claim`,
+/// which only applies the transitions allowed by `can_transition_to`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClaimStatus {
+    /// Recorded, nothing sent yet.
+    Registered,
+    /// The recipient was sent an invitation for this claim.
+    Invited,
+    /// The recipient's account is being created, or a claim transaction failed and will be retried.
+    AccountPending,
+    /// A claim proof is being generated or the claim transaction is in flight.
+    Claiming,
+    Claimed,
+    /// Expired; the void transaction is in flight.
+    Voiding,
+    Voided,
+    /// Undeliverable, or the claim or void transaction kept failing. See `last_error` in the database.
+    Failed,
+}
+
+impl ClaimStatus {
+    /// The statuses of claims that can still be claimed by the recipient.
+    pub const ACTIVE: [ClaimStatus; 3] = [
+        ClaimStatus::Registered,
+        ClaimStatus::Invited,
+        ClaimStatus::AccountPending,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ClaimStatus::Registered => "registered",
+            ClaimStatus::Invited => "invited",
+            ClaimStatus::AccountPending => "account_pending",
+            ClaimStatus::Claiming => "claiming",
+            ClaimStatus::Claimed => "claimed",
+            ClaimStatus::Voiding => "voiding",
+            ClaimStatus::Voided => "voided",
+            ClaimStatus::Failed => "failed",
+        }
+    }
+
+    pub fn parse(status: &str) -> Result<Self> {
+        Ok(match status {
+            "registered" => ClaimStatus::Registered,
+            "invited" => ClaimStatus::Invited,
+            "account_pending" => ClaimStatus::AccountPending,
+            "claiming" => ClaimStatus::Claiming,
+            "claimed" => ClaimStatus::Claimed,
+            "voiding" => ClaimStatus::Voiding,
+            "voided" => ClaimStatus::Voided,
+            "failed" => ClaimStatus::Failed,
+            _ => bail!("Unknown claim status {}", status),
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        Self::ACTIVE.contains(self)
+    }
+
+    pub fn can_transition_to(&self, next: ClaimStatus) -> bool {
+        use ClaimStatus::*;
+        match self {
+            Registered => matches!(
+                next,
+                Invited | AccountPending | Claiming | Voiding | Voided | Failed
+            ),
+            Invited => matches!(next, AccountPending | Claiming | Voiding | Voided | Failed),
+            AccountPending => matches!(next, Claiming | Voiding | Voided | Failed),
+            // A failed claim transaction is retried once the account is usable.
+            Claiming => matches!(next, Claimed | AccountPending | Failed),
+            Voiding => matches!(next, Voided | Failed),
+            // An expired failed claim still has to be voided to return the asset to the sender.
+            Failed => matches!(next, Voiding | Voided),
+            Claimed | Voided => false,
+        }
+    }
+}
+
+/// Moves the claim to `next` if the stored claim is still in the status the caller read.
+/// Returns false if another task changed it first, so that each step (e.g. an invitation) happens once.
+pub async fn transition_claim(
+    claim: &mut Claim,
+    next: ClaimStatus,
+    error: Option<&str>,
+) -> Result<bool> {
+    if !claim.status.can_transition_to(next) {
+        bail!(
+            "Claim {} cannot go from {} to {}",
+            claim.id,
+            claim.status.as_str(),
+            next.as_str()
+        );
+    }
+    let is_attempt = next == ClaimStatus::Claiming || next == ClaimStatus::Voiding;
+    if !DB
+        .update_claim_status(claim, next, is_attempt, error)
         .await?
-        .into_iter()
-        .filter(|c: &Claim| c.is_fund == claim.is_fund)
-        .collect::<Vec<_>>().is_empty()
     {
-        claim.is_seen = true;
-        DB.insert_claim(&claim).await?;
-        // let psi_client = PSIClient::new(
-        //     claim.email_address.to_string(),
-        //     claim.tx_hash.clone(),
-        //     claim.id,
-        //     claim.is_fund,
-        // )
-        // .await?;
-        // need_creation = psi_client.check_and_reveal().await?;
-    }
-    if need_creation && !DB.contains_user(&claim.email_address).await.unwrap() {
+        return Ok(false);
+    }
+    claim.status = next;
+    if is_attempt {
+        claim.attempts += 1;
+    }
+    Ok(true)
+}
+
+// Records a failed claim or void transaction. The claim goes back to waiting, or to `Failed` once it is out of attempts.
+async fn record_claim_failure(
+    claim: &mut Claim,
+    retry_status: ClaimStatus,
+    error: &str,
+) -> Result<()> {
+    let next = if claim.attempts >= MAX_CLAIM_ATTEMPTS {
+        ClaimStatus::Failed
+    } else {
+        retry_status
+    };
+    transition_claim(claim, next, Some(error)).await?;
+    Ok(())
+}
+
+/// How the claim loop retries claims that could not be claimed yet.
+#[derive(Debug, Clone)]
+pub struct ClaimRetryPolicy {
+    /// Claims processed at the same time.
+    pub concurrency: usize,
+    /// Delay before the first retry, doubled on every further retry.
+    pub base_secs: i64,
+    pub max_secs: i64,
+    /// Retries before the claim is marked failed and reported to the operators.
+    pub max_retries: i32,
+}
+
+impl ClaimRetryPolicy {
+    /// Seconds before retry number `retries` (1 for the first retry), scaled by `jitter`.
+    pub fn backoff_secs(&self, retries: i32, jitter: f64) -> i64 {
+        let doublings = retries.saturating_sub(1).clamp(0, 62) as u32;
+        let backoff = self
+            .base_secs
+            .saturating_mul(2i64.saturating_pow(doublings))
+            .min(self.max_secs);
+        (backoff as f64 * jitter) as i64
+    }
+}
+
+/// One pass of the claim loop over a due claim. If the claim is still waiting afterwards,
+/// its next try is scheduled with exponential backoff and jitter.
+#[named]
+pub async fn retry_claim(claim: Claim, now: i64) -> Result<()> {
+    let policy = CLAIM_RETRY_POLICY.get().unwrap();
+    let error = match claim_unclaims(claim.clone()).await {
+        Ok(event) => {
+            if let Err(e) = handle_email_event(event).await {
+                error!(LOG, "Error handling email event: {}", e; "func" => function_name!());
+            }
+            None
+        }
+        Err(e) => Some(e.to_string()),
+    };
+    // Between a half and the whole of the backoff, so that claims failing together do not retry together.
+    let jitter = rand::thread_rng().gen_range(0.5..=1.0);
+    let Some(retries) = DB
+        .schedule_claim_retry(&claim.id, claim.is_fund, now, policy, jitter)
+        .await?
+    else {
+        return Ok(());
+    };
+    trace!(LOG, "claim {} retry {} scheduled, last error: {:?}", claim.id, retries, error; "func" => function_name!());
+    if retries < policy.max_retries {
+        return Ok(());
+    }
+    let Some(mut claim) = DB.get_claim(&claim.id, claim.is_fund).await? else {
+        return Ok(());
+    };
+    let error = format!(
+        "Not claimed after {} retries: {}",
+        retries,
+        error.unwrap_or_else(|| "the recipient's account is not usable".to_string())
+    );
+    if transition_claim(&mut claim, ClaimStatus::Failed, Some(&error)).await? {
+        warn!(LOG, "claim {} failed: {}", claim.id, error; "func" => function_name!());
+        handle_email_event(EmailWalletEvent::ClaimFailed { claim, error }).await?;
+    }
+    Ok(())
+}
+
+#[named]
+pub async fn claim_unclaims(claim: Claim) -> Result<EmailWalletEvent> {
+    // The same claim is reported by the sender's email, the event listener and the REST API,
+    // so the stored claim decides what is left to do.
+    let mut claim = match DB.get_claim(&claim.id, claim.is_fund).await? {
+        Some(stored) => stored,
+        None => {
+            // Another relayer may already serve the recipient; if so, it claims for them instead.
+            if *PSI_ENABLED.get().unwrap()
+                && !DB.contains_user(&claim.email_address).await?
+                && !PSIClient::new(
+                    claim.email_address.clone(),
+                    claim.tx_hash.clone(),
+                    claim.id,
+                    claim.is_fund,
+                )
+                .await?
+                .check_and_reveal()
+                .await?
+            {
+                info!(LOG, "claim {} is handed to another relayer", claim.id; "func" => function_name!());
+                return Ok(EmailWalletEvent::NoOp);
+            }
+            DB.insert_claim(&claim).await?;
+            claim
+        }
+    };
+    if !claim.status.is_active() {
+        trace!(LOG, "claim {} is {}", claim.id, claim.status.as_str(); "func" => function_name!());
+        return Ok(EmailWalletEvent::NoOp);
+    }
+    if !DB.contains_user(&claim.email_address).await? {
+        if !transition_claim(&mut claim, ClaimStatus::Invited, None).await? {
+            return Ok(EmailWalletEvent::NoOp);
+        }
         let account_code = AccountCode::new(rand::thread_rng());
         let account_code_str = field2hex(&account_code.0);
-        // let psi_point = compute_psi_point(
-        //     CIRCUITS_DIR_PATH.get().unwrap(),
-        //     &claim.email_address,
-        //     &account_code_str,
-        // )
-        // .await?;
         let account_salt = AccountSalt::new(
             &PaddedEmailAddr::from_email_addr(&claim.email_address),
             account_code,
         )?;
-        // let tx_hash = CLIENT.register_psi_point(&psi_point, &account_salt).await?;
-        // info!(LOG, "register psi point tx hash: {}", tx_hash; "func" => function_name!());
+        if *PSI_ENABLED.get().unwrap() {
+            // Lets other relayers find out that this relayer serves the recipient.
+            let psi_point = compute_psi_point(
+                CIRCUITS_DIR_PATH.get().unwrap(),
+                &claim.email_address,
+                &account_code_str,
+            )
+            .await?;
+            let tx_hash = CLIENT.register_psi_point(&psi_point, &account_salt).await?;
+            info!(LOG, "register psi point tx hash: {}", tx_hash; "func" => function_name!());
+        }
         let wallet_addr = CLIENT.get_wallet_addr_from_salt(&account_salt.0).await?;
 
         DB.insert_user(
@@ -84,16 +295,19 @@ pub async fn claim_unclaims(mut claim: Claim) -> Result<EmailWalletEvent> {
     let is_account_created = CLIENT
         .check_if_account_created_by_account_code(&claim.email_address, &account_code_str)
         .await?;
-    if !is_seen && !is_account_created {
-        return Ok(EmailWalletEvent::Invitation {
-            email_addr: claim.email_address,
-            account_code: AccountCode(hex2field(&account_code_str)?),
-            is_first: false,
-            tx_hash: "".to_string(),
-        });
-        // return Err(anyhow!("Account not created"));
-    } else if !is_account_created {
-        return Err(anyhow!("Account not created"));
+    if !is_account_created {
+        // The recipient was invited for an earlier claim; remind them once for this one.
+        if claim.status == ClaimStatus::Registered
+            && transition_claim(&mut claim, ClaimStatus::Invited, None).await?
+        {
+            return Ok(EmailWalletEvent::Invitation {
+                email_addr: claim.email_address,
+                account_code: AccountCode(hex2field(&account_code_str)?),
+                is_first: false,
+                tx_hash: "".to_string(),
+            });
+        }
+        return Ok(EmailWalletEvent::NoOp);
     }
     let account_code = AccountCode(hex2field(&account_code_str)?);
     let padded_email_addr = PaddedEmailAddr::from_email_addr(&claim.email_address);
@@ -121,6 +335,31 @@ pub async fn claim_unclaims(mut claim: Claim) -> Result<EmailWalletEvent> {
         (None, Some(unclaimed_state))
     };
 
+    if !transition_claim(&mut claim, ClaimStatus::Claiming, None).await? {
+        return Ok(EmailWalletEvent::NoOp);
+    }
+    let tx_hash = match submit_claim(&claim, &account_code).await {
+        Ok(tx_hash) => tx_hash,
+        Err(err) => {
+            record_claim_failure(&mut claim, ClaimStatus::AccountPending, &err.to_string()).await?;
+            return Err(err);
+        }
+    };
+    transition_claim(&mut claim, ClaimStatus::Claimed, None).await?;
+    let wallet_addr = CLIENT.get_wallet_addr_from_salt(&account_salt.0).await?;
+    Ok(EmailWalletEvent::Claimed {
+        unclaimed_fund,
+        unclaimed_state,
+        email_addr: claim.email_address,
+        is_fund: claim.is_fund,
+        is_announced: claim.is_announced,
+        recipient_account_code: account_code,
+        tx_hash,
+    })
+}
+
+#[named]
+async fn submit_claim(claim: &Claim, account_code: &AccountCode) -> Result<String> {
     let input = generate_claim_input(
         &claim.email_address,
         &claim.random,
@@ -138,18 +377,7 @@ pub async fn claim_unclaims(mut claim: Claim) -> Result<EmailWalletEvent> {
         is_fund: claim.is_fund,
         proof,
     };
-    let tx_hash = CLIENT.claim(data).await?;
-    DB.delete_claim(&claim.id, claim.is_fund).await?;
-    let wallet_addr = CLIENT.get_wallet_addr_from_salt(&account_salt.0).await?;
-    Ok(EmailWalletEvent::Claimed {
-        unclaimed_fund,
-        unclaimed_state,
-        email_addr: claim.email_address,
-        is_fund: claim.is_fund,
-        is_announced: claim.is_announced,
-        recipient_account_code: account_code,
-        tx_hash,
-    })
+    CLIENT.claim(data).await
 }
 
 async fn is_installed_extension(
@@ -167,22 +395,44 @@ async fn is_installed_extension(
 }
 
 #[named]
-pub async fn void_unclaims(claim: Claim) -> Result<EmailWalletEvent> {
+pub async fn void_unclaims(mut claim: Claim) -> Result<EmailWalletEvent> {
     let now = now();
     let commit = hex2field(&claim.commit)?;
-    DB.delete_claim(&claim.id, claim.is_fund).await?;
-    info!(LOG, "claim deleted id {}", claim.id; "func" => function_name!());
-    let (reply_msg, sender, tx_hash) = if claim.is_fund {
+    if !transition_claim(&mut claim, ClaimStatus::Voiding, None).await? {
+        return Ok(EmailWalletEvent::NoOp);
+    }
+    info!(LOG, "voiding claim id {}, attempt {}", claim.id, claim.attempts; "func" => function_name!());
+    let (asset, sender, tx_hash) = match submit_void(&claim, now).await {
+        Ok(voided) => voided,
+        Err(err) => {
+            // The voider picks failed claims up again until they are out of attempts,
+            // and only the last failure is reported.
+            transition_claim(&mut claim, ClaimStatus::Failed, Some(&err.to_string())).await?;
+            if claim.attempts < MAX_CLAIM_ATTEMPTS {
+                error!(LOG, "voiding claim {} failed: {}", claim.id, err; "func" => function_name!());
+                return Ok(EmailWalletEvent::NoOp);
+            }
+            return Err(err);
+        }
+    };
+    transition_claim(&mut claim, ClaimStatus::Voided, None).await?;
+    Ok(EmailWalletEvent::Voided {
+        claim,
+        sender,
+        asset,
+        tx_hash,
+    })
+}
+
+async fn submit_void(claim: &Claim, now: i64) -> Result<(String, Address, String)> {
+    Ok(if claim.is_fund {
         let unclaimed_fund = CLIENT.query_unclaimed_fund(claim.id).await?;
         if unclaimed_fund.expiry_time.as_u64() > u64::try_from(now).unwrap() {
             return Err(anyhow!("Claim is not expired"));
         }
+        let asset = describe_unclaimed_fund(&unclaimed_fund).await?;
         let result = CLIENT.void(claim.id, true).await?;
-        (
-            format!("Voided fund: {}", unclaimed_fund.token_addr),
-            unclaimed_fund.sender,
-            result,
-        )
+        (asset, unclaimed_fund.sender, result)
     } else {
         let unclaimed_state = CLIENT.query_unclaimed_state(claim.id).await?;
         if unclaimed_state.expiry_time.as_u64() > u64::try_from(now).unwrap() {
@@ -190,10 +440,132 @@ pub async fn void_unclaims(claim: Claim) -> Result<EmailWalletEvent> {
         }
         let result = CLIENT.void(claim.id, false).await?;
         (
-            format!("Voided state: {}", unclaimed_state.extension_addr),
+            describe_unclaimed_state(&unclaimed_state),
             unclaimed_state.sender,
             result,
         )
-    };
-    Ok(EmailWalletEvent::Voided { claim, tx_hash })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [ClaimStatus; 8] = [
+        ClaimStatus::Registered,
+        ClaimStatus::Invited,
+        ClaimStatus::AccountPending,
+        ClaimStatus::Claiming,
+        ClaimStatus::Claimed,
+        ClaimStatus::Voiding,
+        ClaimStatus::Voided,
+        ClaimStatus::Failed,
+    ];
+
+    #[test]
+    fn parses_what_it_stores() {
+        for status in ALL {
+            assert_eq!(ClaimStatus::parse(status.as_str()).unwrap(), status);
+        }
+        assert!(ClaimStatus::parse("deleted").is_err());
+    }
+
+    #[test]
+    fn final_statuses_have_no_transition() {
+        for next in ALL {
+            assert!(!ClaimStatus::Claimed.can_transition_to(next));
+            assert!(!ClaimStatus::Voided.can_transition_to(next));
+        }
+    }
+
+    #[test]
+    fn no_status_transitions_to_itself() {
+        for status in ALL {
+            assert!(!status.can_transition_to(status), "{}", status.as_str());
+        }
+    }
+
+    #[test]
+    fn only_claiming_reaches_claimed() {
+        for status in ALL {
+            assert_eq!(
+                status.can_transition_to(ClaimStatus::Claimed),
+                status == ClaimStatus::Claiming,
+                "{}",
+                status.as_str()
+            );
+        }
+    }
+
+    #[test]
+    fn follows_the_claim_lifecycle() {
+        use ClaimStatus::*;
+        // Sent to a new user, who signs up and claims.
+        for (from, to) in [
+            (Registered, Invited),
+            (Invited, AccountPending),
+            (AccountPending, Claiming),
+            (Claiming, Claimed),
+        ] {
+            assert!(
+                from.can_transition_to(to),
+                "{} -> {}",
+                from.as_str(),
+                to.as_str()
+            );
+        }
+        // A failed claim transaction is retried, and an expired failed claim is voided.
+        assert!(Claiming.can_transition_to(AccountPending));
+        assert!(Claiming.can_transition_to(Failed));
+        assert!(Failed.can_transition_to(Voiding));
+        assert!(Failed.can_transition_to(Voided));
+        assert!(!Failed.can_transition_to(Claiming));
+        // A void in flight cannot be claimed any more.
+        assert!(!Voiding.can_transition_to(Claiming));
+        assert!(!Claiming.can_transition_to(Voiding));
+    }
+
+    fn policy() -> ClaimRetryPolicy {
+        ClaimRetryPolicy {
+            concurrency: 4,
+            base_secs: 60,
+            max_secs: 3600,
+            max_retries: 10,
+        }
+    }
+
+    #[test]
+    fn doubles_the_backoff_up_to_the_maximum() {
+        let policy = policy();
+        let backoffs: Vec<i64> = (1..=8)
+            .map(|retries| policy.backoff_secs(retries, 1.0))
+            .collect();
+        assert_eq!(backoffs, vec![60, 120, 240, 480, 960, 1920, 3600, 3600]);
+        assert_eq!(policy.backoff_secs(0, 1.0), 60);
+        assert_eq!(policy.backoff_secs(i32::MAX, 1.0), 3600);
+    }
+
+    #[test]
+    fn scales_the_backoff_by_the_jitter() {
+        let policy = policy();
+        assert_eq!(policy.backoff_secs(2, 0.5), 60);
+        assert_eq!(policy.backoff_secs(7, 0.5), 1800);
+        for retries in 1..=20 {
+            let backoff = policy.backoff_secs(retries, 0.75);
+            assert!(backoff >= policy.base_secs / 2 && backoff <= policy.max_secs);
+        }
+    }
+
+    #[test]
+    fn active_statuses_can_still_be_claimed_or_voided() {
+        for status in ALL {
+            assert_eq!(
+                status.is_active(),
+                status.can_transition_to(ClaimStatus::Claiming)
+                    && status.can_transition_to(ClaimStatus::Voiding),
+                "{}",
+                status.as_str()
+            );
+        }
+    }
 }
@@ -0,0 +1,294 @@
+use crate::*;
+
+use async_trait::async_trait;
+use regex::Regex;
+
+pub const MAX_CONTACTS_PER_USER: usize = 100;
+
+/// A nickname a user can write in the recipient position of a subject instead of the address.
+#[derive(Debug, Clone)]
+pub struct Contact {
+    pub nickname: String,
+    /// An email address or an Ethereum address.
+    pub recipient: String,
+}
+
+impl Contact {
+    pub fn describe(&self) -> String {
+        format!("{}: {}", self.nickname, self.recipient)
+    }
+}
+
+/// Handles the `Contact add <nickname> <recipient>`, `Contact remove <nickname>` and `Contacts` commands
+/// and returns the message for the reply.
+pub async fn handle_contact_command(
+    command: &str,
+    subject: &str,
+    email_addr: &str,
+) -> Result<String> {
+    if command == CONTACTS_COMMAND {
+        extract_template_vals_contacts(subject)?;
+        return Ok("Here are your contacts.".to_string());
+    }
+    match extract_template_vals_contact(subject)? {
+        (0, vals) => {
+            let [TemplateValue::String(nickname), recipient_val] = vals.as_slice() else {
+                bail!(WRONG_SUBJECT_FORMAT);
+            };
+            let recipient = match recipient_val {
+                TemplateValue::Recipient {
+                    email_addr: Some(email_addr),
+                    ..
+                } => email_addr.clone(),
+                TemplateValue::Recipient {
+                    eth_addr: Some(eth_addr),
+                    ..
+                } => format!("{:?}", eth_addr),
+                _ => bail!(WRONG_SUBJECT_FORMAT),
+            };
+            let nickname = nickname.to_lowercase();
+            if !Regex::new(r"^[a-z][a-z0-9_-]{0,31}$")
+                .unwrap()
+                .is_match(&nickname)
+            {
+                bail!("A nickname must start with a letter and have only letters, digits, `-` and `_`");
+            }
+            let contacts = DB.get_contacts(email_addr).await?;
+            if contacts.len() >= MAX_CONTACTS_PER_USER
+                && !contacts.iter().any(|contact| contact.nickname == nickname)
+            {
+                bail!("You can have at most {} contacts", MAX_CONTACTS_PER_USER);
+            }
+            DB.upsert_contact(
+                email_addr,
+                &Contact {
+                    nickname: nickname.clone(),
+                    recipient: recipient.clone(),
+                },
+            )
+            .await?;
+            Ok(format!("{} is saved as {}.", recipient, nickname))
+        }
+        (1, vals) => {
+            let Some(TemplateValue::String(nickname)) = vals.first() else {
+                bail!(WRONG_SUBJECT_FORMAT);
+            };
+            if !DB
+                .delete_contact(email_addr, &nickname.to_lowercase())
+                .await?
+            {
+                bail!("You have no contact named {}", nickname);
+            }
+            Ok(format!("{} is removed from your contacts.", nickname))
+        }
+        _ => bail!(WRONG_SUBJECT_FORMAT),
+    }
+}
+
+fn is_ens_name(word: &str) -> bool {
+    Regex::new(r"^([a-z0-9-]+\.)+[a-z]{2,}$")
+        .unwrap()
+        .is_match(&word.to_lowercase())
+}
+
+/// Where contact nicknames and ENS names are looked up.
+#[async_trait]
+trait RecipientNames: Send + Sync {
+    async fn contact(&self, email_addr: &str, nickname: &str) -> Result<Option<Contact>>;
+    async fn ens_address(&self, ens_name: &str) -> Result<Address>;
+}
+
+/// The contacts in the database and ENS on the current chain.
+struct StoredRecipientNames;
+
+#[async_trait]
+impl RecipientNames for StoredRecipientNames {
+    async fn contact(&self, email_addr: &str, nickname: &str) -> Result<Option<Contact>> {
+        DB.get_contact(email_addr, nickname).await
+    }
+
+    async fn ens_address(&self, ens_name: &str) -> Result<Address> {
+        CLIENT.resolve_ens_name(ens_name).await
+    }
+}
+
+/// Resolves a contact nickname or an ENS name into the address it stands for.
+/// Returns None for raw addresses and words that are neither.
+pub async fn resolve_recipient_name(email_addr: &str, word: &str) -> Result<Option<String>> {
+    resolve_name(&StoredRecipientNames, email_addr, word).await
+}
+
+async fn resolve_name(
+    names: &dyn RecipientNames,
+    email_addr: &str,
+    word: &str,
+) -> Result<Option<String>> {
+    if is_whole_match(EMAIL_ADDR_REGEX, word) || is_whole_match(ETH_ADDR_REGEX, word) {
+        return Ok(None);
+    }
+    if let Some(contact) = names.contact(email_addr, &word.to_lowercase()).await? {
+        return Ok(Some(contact.recipient));
+    }
+    if is_ens_name(word) {
+        let eth_addr = names
+            .ens_address(word)
+            .await
+            .map_err(|e| anyhow!("Could not resolve the ENS name {}: {}", word, e))?;
+        // A name without an address record reads as the zero address, which would burn the tokens.
+        if eth_addr == Address::zero() {
+            bail!("The ENS name {} has no address", word);
+        }
+        return Ok(Some(format!("{:?}", eth_addr)));
+    }
+    Ok(None)
+}
+
+/// Rewrites a subject whose recipient is a contact nickname or an ENS name into the subject with the address,
+/// which is the only form the contract verifies. Returns the subject and a note on what the name resolved to.
+pub async fn resolve_subject_recipient(
+    email_addr: &str,
+    command: &str,
+    subject: &str,
+) -> Result<Option<(String, String)>> {
+    resolve_recipient_in_subject(&StoredRecipientNames, email_addr, command, subject).await
+}
+
+async fn resolve_recipient_in_subject(
+    names: &dyn RecipientNames,
+    email_addr: &str,
+    command: &str,
+    subject: &str,
+) -> Result<Option<(String, String)>> {
+    let templates = match command {
+        SCHEDULE_COMMAND => schedule_subject_templates(),
+        SEND_COMMAND | CANCEL_COMMAND | REQUEST_COMMAND => {
+            vec![builtin_subject_templates(command).unwrap()]
+        }
+        _ => return Ok(None),
+    };
+    let tokens = tokenize_subject(subject);
+    for template in templates.iter() {
+        let Some(idx) = recipient_token_idx(template) else {
+            continue;
+        };
+        let Some(token) = tokens.get(idx) else {
+            continue;
+        };
+        let Some(resolved) = resolve_name(names, email_addr, &token.text).await? else {
+            continue;
+        };
+        let mut resolved_tokens = tokens.clone();
+        resolved_tokens[idx].text = resolved.clone();
+        if let Ok((_, canonical)) = parse_template(&resolved_tokens, template) {
+            return Ok(Some((canonical, format!("{} is {}", token.text, resolved))));
+        }
+    }
+    Ok(None)
+}
+
+// The index of the subject word that fills `{recipient}`. A `{tokenAmount}` takes two words.
+fn recipient_token_idx(template: &[String]) -> Option<usize> {
+    let mut idx = 0;
+    for word in template.iter() {
+        match word.as_str() {
+            "{recipient}" => return Some(idx),
+            "{tokenAmount}" => idx += 2,
+            _ => idx += 1,
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VITALIK: Address = H160([0xd8; 20]);
+
+    struct FixedNames;
+
+    #[async_trait]
+    impl RecipientNames for FixedNames {
+        async fn contact(&self, email_addr: &str, nickname: &str) -> Result<Option<Contact>> {
+            Ok(match (email_addr, nickname) {
+                ("alice@example.com", "bob") => Some(Contact {
+                    nickname: nickname.to_string(),
+                    recipient: "bob@example.com".to_string(),
+                }),
+                _ => None,
+            })
+        }
+
+        async fn ens_address(&self, ens_name: &str) -> Result<Address> {
+            match ens_name {
+                "vitalik.eth" => Ok(VITALIK),
+                "burned.eth" => Ok(Address::zero()),
+                _ => bail!("no resolver"),
+            }
+        }
+    }
+
+    async fn resolve(word: &str) -> Result<Option<String>> {
+        resolve_name(&FixedNames, "alice@example.com", word).await
+    }
+
+    #[tokio::test]
+    async fn resolves_nicknames() {
+        assert_eq!(
+            resolve("Bob").await.unwrap(),
+            Some("bob@example.com".to_string())
+        );
+        assert_eq!(
+            resolve_name(&FixedNames, "carol@example.com", "bob")
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(resolve("carol").await.unwrap(), None);
+        assert_eq!(resolve("bob@example.com").await.unwrap(), None);
+        assert_eq!(resolve(&format!("{:?}", VITALIK)).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn resolves_ens_names() {
+        assert_eq!(
+            resolve("vitalik.eth").await.unwrap(),
+            Some(format!("{:?}", VITALIK))
+        );
+        assert!(resolve("nobody.eth").await.is_err());
+        assert!(resolve("burned.eth").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rewrites_the_recipient_of_subjects() {
+        let resolve_subject = |subject: &'static str| {
+            resolve_recipient_in_subject(&FixedNames, "alice@example.com", SEND_COMMAND, subject)
+        };
+        assert_eq!(
+            resolve_subject("Send 1 ETH to Bob").await.unwrap(),
+            Some((
+                "Send 1 ETH to bob@example.com".to_string(),
+                "Bob is bob@example.com".to_string()
+            ))
+        );
+        assert_eq!(
+            resolve_subject("Send 1 ETH to bob@example.com")
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(resolve_subject("Send 1 ETH to carol").await.unwrap(), None);
+        assert!(resolve_subject("Send 1 ETH to burned.eth").await.is_err());
+        assert_eq!(
+            resolve_recipient_in_subject(
+                &FixedNames,
+                "alice@example.com",
+                BALANCE_COMMAND,
+                "Balance"
+            )
+            .await
+            .unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,218 @@
+use crate::*;
+
+use ethers::providers::{Ipc, Ws};
+use relayer_utils::u256_to_bytes32;
+
+/// Blocks per `eth_getLogs` call when catching up, to stay under the range limits of RPC providers.
+const LOG_BACKFILL_CHUNK: u64 = 2000;
+const RECONNECT_MIN_SECS: u64 = 1;
+const RECONNECT_MAX_SECS: u64 = 60;
+
+/// Invites recipients of unclaimed funds and states registered on the current chain.
+/// Logs come from an `eth_subscribe` subscription when the chain has a subscription provider,
+/// or else from polling `eth_getLogs` every EVENT_POLL_INTERVAL_SECS. Either way the listener resumes
+/// from the last checkpoint, so logs emitted while it was down or reconnecting are caught up first.
+#[named]
+pub async fn listen_unclaim_events() {
+    let mut reconnect_secs = RECONNECT_MIN_SECS;
+    loop {
+        let result = match current_chain().subscription_provider.as_deref() {
+            Some(url) => subscribe_unclaim_events(url).await,
+            None => poll_unclaim_events().await,
+        };
+        report_task_result(
+            &format!("event listener on chain {}", current_chain_id()),
+            &result,
+        )
+        .await;
+        match result {
+            Ok(()) => {
+                warn!(LOG, "log subscription on chain {} ended", current_chain_id(); "func" => function_name!());
+                reconnect_secs = RECONNECT_MIN_SECS;
+            }
+            Err(e) => {
+                error!(LOG, "Error at event_listener on chain {}: {}", current_chain_id(), e; "func" => function_name!());
+                reconnect_secs = (reconnect_secs * 2).min(RECONNECT_MAX_SECS);
+            }
+        }
+        sleep(Duration::from_secs(reconnect_secs)).await;
+    }
+}
+
+async fn poll_unclaim_events() -> Result<()> {
+    loop {
+        let head = CLIENT.get_latest_block_number().await?;
+        catch_up_unclaim_events(head).await?;
+        sleep(Duration::from_secs(
+            *EVENT_POLL_INTERVAL_SECS.get().unwrap(),
+        ))
+        .await;
+    }
+}
+
+async fn subscribe_unclaim_events(url: &str) -> Result<()> {
+    if url.starts_with("ws://") || url.starts_with("wss://") {
+        let provider = Provider::<Ws>::connect(url).await?;
+        follow_unclaim_events(&provider).await
+    } else {
+        let provider = Provider::<Ipc>::connect_ipc(url).await?;
+        follow_unclaim_events(&provider).await
+    }
+}
+
+#[named]
+async fn follow_unclaim_events<P: PubsubClient>(provider: &Provider<P>) -> Result<()> {
+    // Subscribe before catching up, so that nothing is logged in between.
+    let mut stream = provider
+        .subscribe_logs(&CLIENT.unclaim_registration_filter())
+        .await?;
+    let head = CLIENT.get_latest_block_number().await?;
+    catch_up_unclaim_events(head).await?;
+    info!(LOG, "subscribed to unclaim registrations on chain {} from block {}", current_chain_id(), head; "func" => function_name!());
+    while let Some(log) = stream.next().await {
+        let Some((registration, meta)) = decode_unclaim_registration(&log) else {
+            continue;
+        };
+        if meta.block_number <= head {
+            continue;
+        }
+        let block_number = meta.block_number;
+        handle_unclaim_registration(registration, meta).await;
+        // Other logs of the same block may still come, and handling a registration twice is harmless.
+        DB.set_event_checkpoint(block_number.as_u64() - 1).await?;
+    }
+    Ok(())
+}
+
+/// Handles the registrations after the checkpoint up to `head` and moves the checkpoint to `head`.
+/// Without a checkpoint, the listener starts at `head` as the relayer always did.
+async fn catch_up_unclaim_events(head: U64) -> Result<()> {
+    let Some(checkpoint) = DB.get_event_checkpoint().await? else {
+        DB.set_event_checkpoint(head.as_u64()).await?;
+        return Ok(());
+    };
+    for (from_block, to_block) in backfill_ranges(checkpoint, head.as_u64(), LOG_BACKFILL_CHUNK) {
+        for (registration, meta) in CLIENT
+            .query_unclaim_registrations(from_block.into(), to_block.into())
+            .await?
+        {
+            handle_unclaim_registration(registration, meta).await;
+        }
+        DB.set_event_checkpoint(to_block).await?;
+    }
+    Ok(())
+}
+
+/// The inclusive block ranges after `checkpoint` up to `head`, at most `chunk` blocks each.
+fn backfill_ranges(checkpoint: u64, head: u64, chunk: u64) -> Vec<(u64, u64)> {
+    let mut ranges = vec![];
+    let mut from_block = checkpoint + 1;
+    while from_block <= head {
+        let to_block = (from_block + chunk - 1).min(head);
+        ranges.push((from_block, to_block));
+        from_block = to_block + 1;
+    }
+    ranges
+}
+
+#[named]
+async fn handle_unclaim_registration(registration: UnclaimRegistration, meta: LogMeta) {
+    let claim = match claim_from_registration(registration, &meta) {
+        Ok(Some(claim)) => claim,
+        Ok(None) => return,
+        Err(e) => {
+            error!(LOG, "Invalid unclaim registration in {:?}: {}", meta.transaction_hash, e; "func" => function_name!());
+            return;
+        }
+    };
+    match claim_unclaims(claim).await {
+        Ok(value) => {
+            if let Err(e) = handle_email_event(value).await {
+                error!(LOG, "Error handling email event: {}", e; "func" => function_name!());
+            }
+        }
+        Err(e) => error!(LOG, "Error claiming: {}", e; "func" => function_name!()),
+    }
+}
+
+// Only announced registrations carry the email address; the others are claimed through the sender's email.
+fn claim_from_registration(
+    registration: UnclaimRegistration,
+    meta: &LogMeta,
+) -> Result<Option<Claim>> {
+    let (id, email_addr, commitment_randomness, email_addr_commit, expiry_time, is_fund, sender) =
+        match registration {
+            UnclaimRegistration::Fund(event) => (
+                event.id,
+                event.email_addr,
+                event.commitment_randomness,
+                event.email_addr_commit,
+                event.expiry_time,
+                true,
+                event.sender,
+            ),
+            UnclaimRegistration::State(event) => (
+                event.id,
+                event.email_addr,
+                event.commitment_randomness,
+                event.email_addr_commit,
+                event.expiry_time,
+                false,
+                event.sender,
+            ),
+        };
+    if email_addr.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Claim {
+        tx_hash: meta.transaction_hash.to_string(),
+        id,
+        email_address: email_addr,
+        random: field2hex(&bytes32_to_fr(&u256_to_bytes32(&commitment_randomness))?),
+        commit: field2hex(&bytes32_to_fr(&email_addr_commit)?),
+        expiry_time: i64::try_from(expiry_time.as_u64())?,
+        is_fund,
+        is_announced: true,
+        status: ClaimStatus::Registered,
+        attempts: 0,
+        sender,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backfill_ranges_up_to_date() {
+        assert!(backfill_ranges(100, 100, LOG_BACKFILL_CHUNK).is_empty());
+        // A head behind the checkpoint, e.g. from a lagging RPC, must not move the checkpoint back.
+        assert!(backfill_ranges(100, 90, LOG_BACKFILL_CHUNK).is_empty());
+    }
+
+    #[test]
+    fn test_backfill_ranges_within_one_chunk() {
+        assert_eq!(backfill_ranges(100, 101, 2000), vec![(101, 101)]);
+        assert_eq!(backfill_ranges(100, 2100, 2000), vec![(101, 2100)]);
+    }
+
+    #[test]
+    fn test_backfill_ranges_split_into_chunks() {
+        assert_eq!(
+            backfill_ranges(100, 4101, 2000),
+            vec![(101, 2100), (2101, 4100), (4101, 4101)]
+        );
+        assert_eq!(backfill_ranges(0, 6, 3), vec![(1, 3), (4, 6)]);
+    }
+
+    #[test]
+    fn test_backfill_ranges_cover_every_block_once() {
+        let ranges = backfill_ranges(7, 1000, 64);
+        assert_eq!(ranges.first().unwrap().0, 8);
+        assert_eq!(ranges.last().unwrap().1, 1000);
+        for window in ranges.windows(2) {
+            assert_eq!(window[0].1 + 1, window[1].0);
+        }
+        assert!(ranges.iter().all(|(from, to)| from <= to && to - from < 64));
+    }
+}
@@ -0,0 +1,151 @@
+use crate::*;
+
+use relayer_utils::{hex2field, AccountCode};
+use std::collections::HashSet;
+
+/// Parses reminder offsets such as `7d,1d,12h` into seconds before the expiry, largest first.
+pub fn parse_expiry_reminder_offsets(offsets: &str) -> Result<Vec<i64>> {
+    let mut parsed = vec![];
+    for offset in offsets.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let (value, unit_secs) = if let Some(value) = offset.strip_suffix('d') {
+            (value, 24 * 60 * 60)
+        } else if let Some(value) = offset.strip_suffix('h') {
+            (value, 60 * 60)
+        } else if let Some(value) = offset.strip_suffix('m') {
+            (value, 60)
+        } else {
+            bail!("Expiry reminder offset {} must end with d, h or m", offset);
+        };
+        let value: i64 = match value.parse() {
+            Ok(value) if value > 0 => value,
+            _ => bail!("Invalid expiry reminder offset {}", offset),
+        };
+        parsed.push(value * unit_secs);
+    }
+    parsed.sort_unstable_by(|a, b| b.cmp(a));
+    parsed.dedup();
+    Ok(parsed)
+}
+
+/// The number of reminders a claim is due for if it has not had all of them yet.
+/// `offsets` are largest first, so reminder `n` is due once the claim is within `offsets[n - 1]` of its expiry.
+fn due_reminder(offsets: &[i64], expiry_time: i64, now: i64, reminders_sent: i32) -> Option<i32> {
+    let due = offsets
+        .iter()
+        .filter(|offset| expiry_time - now <= **offset)
+        .count() as i32;
+    (due > reminders_sent).then_some(due)
+}
+
+/// Reminds recipients of unclaimed claims that reached a reminder offset before their expiry,
+/// and tells the sender at the last offset that the asset is about to come back.
+#[named]
+pub async fn send_expiry_reminders(now: i64) -> Result<()> {
+    let offsets = EXPIRY_REMINDER_OFFSETS.get().unwrap();
+    let Some(first_offset) = offsets.first() else {
+        return Ok(());
+    };
+    // A recipient gets one reminder per pass, which lists all of their claims.
+    let mut reminded = HashSet::new();
+    for (claim, reminders_sent) in DB.get_claims_to_remind(now, now + first_offset).await? {
+        let Some(due) = due_reminder(offsets, claim.expiry_time, now, reminders_sent) else {
+            continue;
+        };
+        if !DB
+            .update_claim_reminders_sent(&claim, reminders_sent, due)
+            .await?
+        {
+            continue;
+        }
+        info!(LOG, "claim {} reached expiry reminder {}", claim.id, due; "func" => function_name!());
+        let mut events = vec![];
+        if reminded.insert(claim.email_address.clone()) {
+            if let Some(account_code) = DB.get_account_code(&claim.email_address).await? {
+                events.push(EmailWalletEvent::ExpiryReminder {
+                    email_addr: claim.email_address.clone(),
+                    account_code: AccountCode(hex2field(&account_code)?),
+                    expiry_time: claim.expiry_time,
+                });
+            }
+        }
+        if due as usize == offsets.len() {
+            let (sender, asset) = describe_claim(&claim).await?;
+            let sender_wallet_addr = format!("0x{}", hex::encode(sender.as_bytes()));
+            match DB.get_email_by_wallet(&sender_wallet_addr).await {
+                Ok(sender_email_addr) => events.push(EmailWalletEvent::ClaimExpiring {
+                    claim,
+                    sender_email_addr,
+                    sender_wallet_addr,
+                    asset,
+                }),
+                Err(_) => {
+                    info!(LOG, "sender {} is not our user", sender_wallet_addr; "func" => function_name!())
+                }
+            }
+        }
+        for event in events {
+            if let Err(e) = handle_email_event(event).await {
+                error!(LOG, "Error handling email event: {}", e; "func" => function_name!());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: i64 = 60 * 60;
+    const DAY: i64 = 24 * HOUR;
+
+    #[test]
+    fn parses_offsets() {
+        assert_eq!(
+            parse_expiry_reminder_offsets("7d, 1d,12h").unwrap(),
+            vec![7 * DAY, DAY, 12 * HOUR]
+        );
+        assert_eq!(
+            parse_expiry_reminder_offsets("1d,30m,24h,7d,").unwrap(),
+            vec![7 * DAY, DAY, 30 * 60]
+        );
+        assert!(parse_expiry_reminder_offsets("").unwrap().is_empty());
+        for invalid in ["7", "7w", "d", "1.5d", "-1d", "0h"] {
+            assert!(
+                parse_expiry_reminder_offsets(invalid).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn picks_the_due_reminder() {
+        let offsets = [7 * DAY, DAY];
+        let expiry_time = 100 * DAY;
+        assert_eq!(due_reminder(&offsets, expiry_time, 90 * DAY, 0), None);
+        assert_eq!(due_reminder(&offsets, expiry_time, 93 * DAY, 0), Some(1));
+        assert_eq!(due_reminder(&offsets, expiry_time, 99 * DAY, 1), Some(2));
+        // After downtime both offsets have passed, and the claim gets the last reminder only.
+        assert_eq!(
+            due_reminder(&offsets, expiry_time, 99 * DAY + HOUR, 0),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn does_not_repeat_sent_reminders() {
+        let offsets = [7 * DAY, DAY];
+        let expiry_time = 100 * DAY;
+        let mut reminders_sent = 0;
+        let mut sent_at = vec![];
+        for now in (90 * DAY..expiry_time).step_by(HOUR as usize) {
+            if let Some(due) = due_reminder(&offsets, expiry_time, now, reminders_sent) {
+                reminders_sent = due;
+                sent_at.push(now);
+            }
+        }
+        assert_eq!(sent_at, vec![93 * DAY, 99 * DAY]);
+        assert_eq!(due_reminder(&offsets, expiry_time, expiry_time, 2), None);
+    }
+}
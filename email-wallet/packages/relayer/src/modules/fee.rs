@@ -0,0 +1,559 @@
+use crate::*;
+
+use async_trait::async_trait;
+use relayer_utils::{hex2field, AccountCode, AccountSalt, PaddedEmailAddr};
+use std::collections::HashMap;
+
+/// Gas of an email op with its proof verification, used to estimate the fee before the proof is generated.
+pub const EMAIL_OP_GAS_ESTIMATE: u64 = 750_000;
+pub const FEE_TOKEN_AUTO: &str = "auto";
+/// Commands the relayer answers itself, which therefore cost no fee.
+pub const RELAYER_ONLY_COMMANDS: [&str; 10] = [
+    BALANCE_COMMAND,
+    CANCEL_COMMAND,
+    SCHEDULE_COMMAND,
+    SCHEDULES_COMMAND,
+    UNSCHEDULE_COMMAND,
+    REQUEST_COMMAND,
+    FEE_COMMAND,
+    CONTACT_COMMAND,
+    CONTACTS_COMMAND,
+    NOTIFICATIONS_COMMAND,
+];
+
+/// Prices the fee tokens. The core contract charges `fee in ETH * price / 1e18` of the fee token.
+#[async_trait]
+pub trait PriceSource: Send + Sync {
+    /// Returns how many of the token, in its smallest unit, 1 ETH is worth. Zero if the token has no price.
+    async fn price_in_eth(&self, token_name: &str) -> Result<U256>;
+}
+
+/// The price oracle the core contract itself uses, so the estimate matches the fee actually charged.
+pub struct OraclePriceSource;
+
+#[async_trait]
+impl PriceSource for OraclePriceSource {
+    async fn price_in_eth(&self, token_name: &str) -> Result<U256> {
+        if is_eth(token_name) {
+            return Ok(eth_price());
+        }
+        CLIENT.query_oracle_price_in_eth(token_name).await
+    }
+}
+
+/// Fixed prices of 1 ETH in whole tokens, e.g. `DAI=3000,USDC=3000`.
+pub struct StaticPriceSource {
+    prices: HashMap<String, String>,
+}
+
+impl StaticPriceSource {
+    pub fn new(table: &str) -> Result<Self> {
+        let mut prices = HashMap::new();
+        for entry in table.split(',').filter(|entry| !entry.trim().is_empty()) {
+            let (token_name, price) = entry
+                .split_once('=')
+                .ok_or(anyhow!("Invalid price `{}`, expected TOKEN=PRICE", entry))?;
+            prices.insert(token_name.trim().to_string(), price.trim().to_string());
+        }
+        Ok(Self { prices })
+    }
+}
+
+#[async_trait]
+impl PriceSource for StaticPriceSource {
+    async fn price_in_eth(&self, token_name: &str) -> Result<U256> {
+        price_from_table(&self.prices, token_name).await
+    }
+}
+
+/// Prices of 1 ETH in whole tokens read from a JSON object like `{"DAI": "3000"}`.
+/// The file is read on every query so that tests and operators can change it while the relayer runs.
+pub struct FilePriceSource {
+    path: String,
+}
+
+impl FilePriceSource {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl PriceSource for FilePriceSource {
+    async fn price_in_eth(&self, token_name: &str) -> Result<U256> {
+        let prices: HashMap<String, String> = serde_json::from_str(
+            &tokio::fs::read_to_string(&self.path)
+                .await
+                .map_err(|e| anyhow!("Failed to read price file '{}': {}", self.path, e))?,
+        )?;
+        price_from_table(&prices, token_name).await
+    }
+}
+
+/// Parses the `PRICE_SOURCE` config: `oracle`, `static:<TOKEN=PRICE,...>` or `file:<path>`.
+pub fn load_price_source(config: &str) -> Result<Box<dyn PriceSource>> {
+    let config = config.trim();
+    if config == "oracle" {
+        Ok(Box::new(OraclePriceSource))
+    } else if let Some(table) = config.strip_prefix("static:") {
+        Ok(Box::new(StaticPriceSource::new(table)?))
+    } else if let Some(path) = config.strip_prefix("file:") {
+        Ok(Box::new(FilePriceSource::new(path.trim())))
+    } else {
+        bail!("Unknown price source `{}`", config)
+    }
+}
+
+fn is_eth(token_name: &str) -> bool {
+    token_name.eq_ignore_ascii_case("ETH") || token_name.eq_ignore_ascii_case("WETH")
+}
+
+fn eth_price() -> U256 {
+    U256::exp10(18)
+}
+
+async fn price_from_table(prices: &HashMap<String, String>, token_name: &str) -> Result<U256> {
+    if is_eth(token_name) {
+        return Ok(eth_price());
+    }
+    let Some(price) = prices
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(token_name))
+        .map(|(_, price)| price)
+    else {
+        bail!("{} has no price", token_name);
+    };
+    let decimals = CLIENT.query_decimals_of_erc20(token_name).await?;
+    TemplateValue::amount_to_uint(price, decimals)
+}
+
+/// The fee of an email op in the token it is paid with.
+#[derive(Debug, Clone)]
+pub struct FeeQuote {
+    pub token_name: String,
+    /// Fee per gas the email op is sent with, so that the fee charged is the one quoted.
+    pub fee_per_gas: U256,
+    pub fee_in_eth: U256,
+    pub amount: U256,
+    pub decimals: u8,
+}
+
+impl FeeQuote {
+    pub fn describe(&self) -> String {
+        format!(
+            "about {} {}",
+            uint_to_decimal_string(self.amount, self.decimals as usize),
+            self.token_name
+        )
+    }
+}
+
+/// Estimates the gas an email op is charged for. An email recipient adds the gas the contract reserves for the claim.
+pub async fn estimate_email_op_gas(email_recipient_is_fund: Option<bool>) -> Result<U256> {
+    let mut gas = U256::from(EMAIL_OP_GAS_ESTIMATE);
+    if let Some(is_fund) = email_recipient_is_fund {
+        gas += CLIENT.query_unclaim_claim_gas(is_fund).await?;
+    }
+    Ok(gas)
+}
+
+/// Converts a fee in ETH into the smallest unit of a token at the price of the price source.
+pub async fn convert_fee(token_name: &str, fee_in_eth: U256) -> Result<U256> {
+    let price = PRICE_SOURCE.get().unwrap().price_in_eth(token_name).await?;
+    if price.is_zero() {
+        bail!("{} has no price", token_name);
+    }
+    Ok(fee_in_eth.saturating_mul(price) / eth_price())
+}
+
+/// Picks the fee token of an email op before its proof is generated.
+/// The user's preferred token is used if it covers the fee; otherwise the accepted token whose balance is worth the most.
+/// `gas` is the estimated gas of the email ops the fee pays for, at the current fee per gas.
+/// `spent` is the tokens and amounts the email op itself takes out of the wallet, which the fee must not eat into.
+#[named]
+pub async fn select_fee_token(
+    account_salt: &AccountSalt,
+    email_addr: &str,
+    gas: U256,
+    spent: &[(&str, U256)],
+) -> Result<FeeQuote> {
+    let fee_per_gas = email_op_fee_per_gas().await?;
+    let fee_in_eth = gas * fee_per_gas;
+    let preference = DB.get_fee_token_preference(email_addr).await?;
+    let price_source = PRICE_SOURCE.get().unwrap();
+    let mut options = vec![];
+    for token_name in FEE_TOKENS.get().unwrap().iter() {
+        let price = match price_source.price_in_eth(token_name).await {
+            Ok(price) if !price.is_zero() => price,
+            Ok(_) => continue,
+            Err(e) => {
+                warn!(LOG, "No price for fee token {}: {}", token_name, e; "func" => function_name!());
+                continue;
+            }
+        };
+        let balance = CLIENT
+            .query_user_erc20_balance(account_salt, token_name)
+            .await?;
+        options.push(FeeTokenOption {
+            token_name: token_name.clone(),
+            price,
+            balance,
+        });
+    }
+    let Some((token_name, fee)) =
+        choose_fee_token(&options, preference.as_deref(), fee_in_eth, spent)
+    else {
+        bail!(
+            "{}: no token in your wallet covers the fee of about {} ETH",
+            INSUFFICIENT_BALANCE,
+            uint_to_decimal_string(fee_in_eth, 18)
+        );
+    };
+    Ok(FeeQuote {
+        token_name: token_name.to_string(),
+        fee_per_gas,
+        fee_in_eth,
+        amount: fee,
+        decimals: CLIENT.query_decimals_of_erc20(token_name).await?,
+    })
+}
+
+/// A priced fee token and the user's balance of it.
+struct FeeTokenOption {
+    token_name: String,
+    price: U256,
+    balance: U256,
+}
+
+/// Returns the fee token and the fee in it: the preferred token if what is left of its balance after `spent` covers the fee,
+/// otherwise the covering token whose remaining balance is worth the most.
+fn choose_fee_token<'a>(
+    options: &'a [FeeTokenOption],
+    preference: Option<&str>,
+    fee_in_eth: U256,
+    spent: &[(&str, U256)],
+) -> Option<(&'a str, U256)> {
+    let mut best: Option<(&str, U256, U256)> = None;
+    for option in options.iter() {
+        let fee = fee_in_eth.saturating_mul(option.price) / eth_price();
+        let mut balance = option.balance;
+        for (spent_token, amount) in spent.iter() {
+            if spent_token.eq_ignore_ascii_case(&option.token_name) {
+                balance = balance.saturating_sub(*amount);
+            }
+        }
+        if balance < fee {
+            continue;
+        }
+        let is_preferred = preference
+            .is_some_and(|preference| preference.eq_ignore_ascii_case(&option.token_name));
+        if is_preferred {
+            return Some((&option.token_name, fee));
+        }
+        let value_in_eth = balance.saturating_mul(eth_price()) / option.price;
+        if best.map_or(true, |(_, _, best)| value_in_eth > best) {
+            best = Some((&option.token_name, fee, value_in_eth));
+        }
+    }
+    best.map(|(token_name, fee, _)| (token_name, fee))
+}
+
+/// Quotes the fee of the email op a subject asks for, for confirmation and acknowledgement emails.
+/// Returns None for commands the relayer answers without an email op.
+pub async fn quote_fee(email_addr: &str, subject: &str) -> Result<Option<FeeQuote>> {
+    let Some(account_code) = DB.get_account_code(email_addr).await? else {
+        return Ok(None);
+    };
+    let account_salt = AccountSalt::new(
+        &PaddedEmailAddr::from_email_addr(email_addr),
+        AccountCode(hex2field(&account_code)?),
+    )?;
+    let subject = get_code_masked_subject(subject)?;
+    let (command, skip_subject_prefix) =
+        extract_command_from_subject(&subject, &account_salt).await?;
+    if RELAYER_ONLY_COMMANDS.contains(&command.as_str()) {
+        return Ok(None);
+    }
+    let (email_recipient_is_fund, spent) = if command == SEND_COMMAND {
+        // Batches are quoted per transfer when the sender confirms them.
+        let Ok(vals) = extract_template_vals_send(&subject[skip_subject_prefix..]) else {
+            return Ok(None);
+        };
+        let (
+            TemplateValue::TokenAmount { token_name, amount },
+            TemplateValue::Recipient { is_email, .. },
+        ) = (&vals[0], &vals[1])
+        else {
+            bail!(WRONG_SUBJECT_FORMAT);
+        };
+        let decimals = CLIENT.query_decimals_of_erc20(token_name).await?;
+        let amount = TemplateValue::amount_to_uint(amount, decimals)?;
+        (is_email.then_some(true), Some((token_name.clone(), amount)))
+    } else {
+        (None, None)
+    };
+    let gas = estimate_email_op_gas(email_recipient_is_fund).await?;
+    let spent: Vec<(&str, U256)> = spent
+        .iter()
+        .map(|(token_name, amount)| (token_name.as_str(), *amount))
+        .collect();
+    let quote = select_fee_token(&account_salt, email_addr, gas, &spent).await?;
+    Ok(Some(quote))
+}
+
+/// The fee quote shown in confirmation and acknowledgement emails. A quote that fails is only logged.
+#[named]
+pub async fn describe_fee_quote(email_addr: &str, subject: &str) -> Option<String> {
+    match quote_fee(email_addr, subject).await {
+        Ok(quote) => quote.map(|quote| quote.describe()),
+        Err(e) => {
+            warn!(LOG, "Failed to quote the fee of {}: {}", subject, e; "func" => function_name!());
+            None
+        }
+    }
+}
+
+/// Reads a `Max fee <amount> <token>` clause in the part of the subject before the command.
+pub fn extract_max_fee_clause(prefix: &str) -> Result<Option<(String, String)>> {
+    let tokens = tokenize_subject(prefix);
+    let Some(idx) = tokens.windows(2).position(|words| {
+        words[0].text.eq_ignore_ascii_case(MAX_FEE_CLAUSE_WORD)
+            && words[1].text.eq_ignore_ascii_case(FEE_COMMAND)
+    }) else {
+        return Ok(None);
+    };
+    match (tokens.get(idx + 2), tokens.get(idx + 3)) {
+        (Some(amount), Some(token_name)) if normalize_amount(&amount.text).is_some() => {
+            Ok(Some((amount.text.clone(), token_name.text.clone())))
+        }
+        _ => bail!(
+            "The maximum fee must be written like `{} fee 0.5 USDC` before the command",
+            MAX_FEE_CLAUSE_WORD
+        ),
+    }
+}
+
+/// Rejects a fee above the maximum in the subject or, without one, the maximum the user set with `Fee cap`.
+pub async fn check_fee_cap(email_addr: &str, subject_prefix: &str, fee_in_eth: U256) -> Result<()> {
+    let user_cap = DB.get_fee_cap(email_addr).await?;
+    let Some((cap_amount, token_name)) = applicable_fee_cap(subject_prefix, user_cap)? else {
+        return Ok(());
+    };
+    let decimals = CLIENT.query_decimals_of_erc20(&token_name).await?;
+    let fee = convert_fee(&token_name, fee_in_eth).await?;
+    check_fee_within_cap(fee, &cap_amount, &token_name, decimals)
+}
+
+/// The `Max fee` clause of the subject if there is one, otherwise the cap the user set with `Fee cap`.
+fn applicable_fee_cap(
+    subject_prefix: &str,
+    user_cap: Option<(String, String)>,
+) -> Result<Option<(String, String)>> {
+    Ok(extract_max_fee_clause(subject_prefix)?.or(user_cap))
+}
+
+/// `fee` is in the smallest unit of the cap's token.
+fn check_fee_within_cap(fee: U256, cap_amount: &str, token_name: &str, decimals: u8) -> Result<()> {
+    let cap = TemplateValue::amount_to_uint(cap_amount, decimals)?;
+    if fee > cap {
+        bail!(
+            "The estimated fee of about {} {} exceeds your maximum fee of {} {}",
+            uint_to_decimal_string(fee, decimals as usize),
+            token_name,
+            cap_amount,
+            token_name
+        );
+    }
+    Ok(())
+}
+
+/// Handles the `Fee token <token>`, `Fee cap <amount> <token>` and `Fee cap none` commands
+/// and returns the message for the reply. `Fee token auto` removes the token preference.
+pub async fn handle_fee_command(email_addr: &str, subject: &str) -> Result<String> {
+    match extract_template_vals_fee(subject)? {
+        (0, vals) => {
+            let Some(TemplateValue::String(token_name)) = vals.first() else {
+                bail!(WRONG_SUBJECT_FORMAT);
+            };
+            set_fee_token_preference(email_addr, token_name.trim()).await
+        }
+        (1, vals) => {
+            let Some(TemplateValue::TokenAmount { token_name, amount }) = vals.first() else {
+                bail!(WRONG_SUBJECT_FORMAT);
+            };
+            let decimals = CLIENT.query_decimals_of_erc20(token_name).await?;
+            TemplateValue::amount_to_uint(amount, decimals)?;
+            let price = PRICE_SOURCE.get().unwrap().price_in_eth(token_name).await?;
+            if price.is_zero() {
+                bail!("{} has no price to compare fees with", token_name);
+            }
+            DB.set_fee_cap(email_addr, Some((amount, token_name)))
+                .await?;
+            Ok(format!(
+                "Emails whose estimated fee is above {} {} will be rejected before anything is sent.",
+                amount, token_name
+            ))
+        }
+        (2, _) => {
+            DB.set_fee_cap(email_addr, None).await?;
+            Ok("Your maximum fee is removed.".to_string())
+        }
+        _ => bail!(WRONG_SUBJECT_FORMAT),
+    }
+}
+
+async fn set_fee_token_preference(email_addr: &str, token_name: &str) -> Result<String> {
+    if token_name.eq_ignore_ascii_case(FEE_TOKEN_AUTO) {
+        DB.set_fee_token_preference(email_addr, None).await?;
+        return Ok(
+            "Your fees will be paid in the token with the largest balance in your wallet."
+                .to_string(),
+        );
+    }
+    let fee_tokens = FEE_TOKENS.get().unwrap();
+    let Some(token_name) = fee_tokens
+        .iter()
+        .find(|fee_token| fee_token.eq_ignore_ascii_case(token_name))
+    else {
+        bail!(
+            "{} is not accepted for fees. Choose one of {} or {}",
+            token_name,
+            fee_tokens.join(", "),
+            FEE_TOKEN_AUTO
+        );
+    };
+    DB.set_fee_token_preference(email_addr, Some(token_name))
+        .await?;
+    Ok(format!(
+        "Your fees will be paid in {} whenever your balance covers them.",
+        token_name
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn option(token_name: &str, price: U256, balance: U256) -> FeeTokenOption {
+        FeeTokenOption {
+            token_name: token_name.to_string(),
+            price,
+            balance,
+        }
+    }
+
+    fn usdc(amount: u64) -> U256 {
+        U256::from(amount) * U256::exp10(6)
+    }
+
+    fn milli_eth(amount: u64) -> U256 {
+        U256::from(amount) * U256::exp10(15)
+    }
+
+    // 1 ETH is worth 3000 USDC, and USDC has 6 decimals.
+    fn options(eth_balance: U256, usdc_balance: U256) -> Vec<FeeTokenOption> {
+        vec![
+            option("ETH", eth_price(), eth_balance),
+            option("USDC", usdc(3000), usdc_balance),
+        ]
+    }
+
+    #[test]
+    fn picks_the_token_worth_the_most() {
+        // 0.01 ETH is worth 30 USDC, less than the 100 USDC balance although its raw amount is larger.
+        let few_eth = options(milli_eth(10), usdc(100));
+        assert_eq!(
+            choose_fee_token(&few_eth, None, milli_eth(1), &[]),
+            Some(("USDC", usdc(3)))
+        );
+        let more_eth = options(milli_eth(100), usdc(100));
+        assert_eq!(
+            choose_fee_token(&more_eth, None, milli_eth(1), &[]),
+            Some(("ETH", milli_eth(1)))
+        );
+    }
+
+    #[test]
+    fn prefers_the_users_token_if_it_covers_the_fee() {
+        let options = options(milli_eth(2), usdc(100));
+        assert_eq!(
+            choose_fee_token(&options, Some("eth"), milli_eth(1), &[]),
+            Some(("ETH", milli_eth(1)))
+        );
+        assert_eq!(
+            choose_fee_token(&options, Some("ETH"), milli_eth(5), &[]),
+            Some(("USDC", usdc(15)))
+        );
+        assert_eq!(
+            choose_fee_token(&options, Some("DAI"), milli_eth(1), &[]),
+            Some(("USDC", usdc(3)))
+        );
+    }
+
+    #[test]
+    fn leaves_out_the_amount_sent() {
+        let options = options(milli_eth(2), usdc(100));
+        assert_eq!(
+            choose_fee_token(&options, Some("USDC"), milli_eth(1), &[("USDC", usdc(98))]),
+            Some(("ETH", milli_eth(1)))
+        );
+        assert_eq!(
+            choose_fee_token(
+                &options,
+                None,
+                milli_eth(1),
+                &[("USDC", usdc(98)), ("ETH", milli_eth(2))]
+            ),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_to_price_unknown_tokens() {
+        let prices = StaticPriceSource::new("DAI=3000, USDC=3000").unwrap();
+        assert!(prices.price_in_eth("WBTC").await.is_err());
+        assert_eq!(prices.price_in_eth("weth").await.unwrap(), eth_price());
+        assert!(StaticPriceSource::new("DAI").is_err());
+    }
+
+    #[test]
+    fn extracts_max_fee_clauses() {
+        assert_eq!(
+            extract_max_fee_clause("Max fee 0.5 USDC").unwrap(),
+            Some(("0.5".to_string(), "USDC".to_string()))
+        );
+        assert_eq!(
+            extract_max_fee_clause("Re: please, max FEE 1 cbETH").unwrap(),
+            Some(("1".to_string(), "cbETH".to_string()))
+        );
+        assert_eq!(extract_max_fee_clause("").unwrap(), None);
+        assert_eq!(extract_max_fee_clause("Re: Max payment").unwrap(), None);
+        assert!(extract_max_fee_clause("Max fee USDC").is_err());
+        assert!(extract_max_fee_clause("Max fee 0.5").is_err());
+        assert!(extract_max_fee_clause("Max fee ten USDC").is_err());
+    }
+
+    #[test]
+    fn prefers_the_max_fee_clause_to_the_users_cap() {
+        let user_cap = Some(("2".to_string(), "USDC".to_string()));
+        assert_eq!(
+            applicable_fee_cap("Max fee 0.5 DAI", user_cap.clone()).unwrap(),
+            Some(("0.5".to_string(), "DAI".to_string()))
+        );
+        assert_eq!(
+            applicable_fee_cap("Re:", user_cap.clone()).unwrap(),
+            user_cap
+        );
+        assert_eq!(applicable_fee_cap("Re:", None).unwrap(), None);
+        assert!(applicable_fee_cap("Max fee 0.5", user_cap).is_err());
+    }
+
+    #[test]
+    fn rejects_fees_above_the_cap() {
+        assert!(check_fee_within_cap(usdc(1) / 2, "0.5", "USDC", 6).is_ok());
+        assert!(check_fee_within_cap(usdc(1) / 2 + 1, "0.5", "USDC", 6).is_err());
+        assert!(check_fee_within_cap(U256::zero(), "0.0000001", "USDC", 6).is_err());
+    }
+}
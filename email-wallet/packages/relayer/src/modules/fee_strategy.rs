@@ -0,0 +1,251 @@
+use crate::*;
+
+pub const FEE_STRATEGY_EIP1559: &str = "eip1559";
+pub const FEE_STRATEGY_STATIC: &str = "static";
+/// Percentile of the priority fees paid in recent blocks that the relayer tips.
+const PRIORITY_FEE_PERCENTILE: f64 = 50.0;
+
+/// How the relayer prices gas: `eip1559` estimates it from recent blocks, `static` uses FEE_PER_GAS.
+#[derive(Debug, Clone)]
+pub struct FeeStrategy {
+    pub kind: String,
+    /// Percent added on top of the estimated gas price in the fee charged to users.
+    pub margin_percent: u64,
+    /// Upper bound in wei on the fee per gas charged and on the max fee of relayer transactions.
+    pub max_fee_per_gas: Option<U256>,
+    pub history_blocks: u64,
+}
+
+/// The EIP-1559 fees of a transaction sent now.
+#[derive(Debug, Clone, Copy)]
+pub struct GasPrices {
+    pub base_fee_per_gas: U256,
+    pub max_priority_fee_per_gas: U256,
+    /// Twice the base fee plus the priority fee, so that the transaction survives a few full blocks.
+    pub max_fee_per_gas: U256,
+}
+
+impl FeeStrategy {
+    pub fn new(
+        kind: String,
+        margin_percent: u64,
+        max_fee_per_gas: Option<U256>,
+        history_blocks: u64,
+    ) -> Result<Self> {
+        if kind != FEE_STRATEGY_EIP1559 && kind != FEE_STRATEGY_STATIC {
+            bail!("Unknown fee strategy {}", kind);
+        }
+        Ok(Self {
+            kind,
+            margin_percent,
+            max_fee_per_gas,
+            history_blocks: history_blocks.max(1),
+        })
+    }
+
+    pub fn is_static(&self) -> bool {
+        self.kind == FEE_STRATEGY_STATIC
+    }
+
+    fn cap(&self, fee: U256) -> U256 {
+        match self.max_fee_per_gas {
+            Some(max) => fee.min(max),
+            None => fee,
+        }
+    }
+}
+
+impl ChainClient {
+    /// Estimates the next base fee and the median priority fee from the last blocks of the chain.
+    pub async fn estimate_gas_prices(&self) -> Result<GasPrices> {
+        let strategy = FEE_STRATEGY.get().unwrap();
+        let history = self
+            .client
+            .fee_history(
+                strategy.history_blocks,
+                BlockNumber::Latest,
+                &[PRIORITY_FEE_PERCENTILE],
+            )
+            .await?;
+        // The last entry is the base fee of the next block.
+        let base_fee_per_gas = *history
+            .base_fee_per_gas
+            .last()
+            .ok_or(anyhow!("No base fee in the fee history"))?;
+        let mut rewards = history
+            .reward
+            .iter()
+            .filter_map(|reward| reward.first().copied())
+            .collect::<Vec<_>>();
+        rewards.sort();
+        let max_priority_fee_per_gas = rewards.get(rewards.len() / 2).copied().unwrap_or_default();
+        let max_fee_per_gas = strategy.cap(base_fee_per_gas * 2 + max_priority_fee_per_gas);
+        Ok(GasPrices {
+            base_fee_per_gas,
+            max_priority_fee_per_gas: max_priority_fee_per_gas.min(max_fee_per_gas),
+            max_fee_per_gas,
+        })
+    }
+}
+
+/// The fee per gas charged in an email op: the estimated gas price plus FEE_MARGIN_PERCENT, capped by
+/// MAX_FEE_PER_GAS and by the max fee per gas of the core contract. FEE_PER_GAS with the static strategy,
+/// or if the gas price or the contract's max fee per gas cannot be read.
+#[named]
+pub async fn email_op_fee_per_gas() -> Result<U256> {
+    let strategy = FEE_STRATEGY.get().unwrap();
+    let static_fee = *FEE_PER_GAS.get().unwrap();
+    if strategy.is_static() {
+        return Ok(static_fee);
+    }
+    let prices = match CLIENT.estimate_gas_prices().await {
+        Ok(prices) => prices,
+        Err(e) => {
+            warn!(LOG, "Failed to estimate gas prices, charging FEE_PER_GAS: {}", e; "func" => function_name!());
+            return Ok(static_fee);
+        }
+    };
+    let fee = (prices.base_fee_per_gas + prices.max_priority_fee_per_gas)
+        * (100 + strategy.margin_percent)
+        / 100;
+    let contract_max = match CLIENT.core.max_fee_per_gas().call().await {
+        Ok(contract_max) => contract_max,
+        Err(e) => {
+            warn!(LOG, "Failed to read the max fee per gas of the core contract, charging FEE_PER_GAS: {}", e; "func" => function_name!());
+            return Ok(static_fee);
+        }
+    };
+    Ok(strategy.cap(fee).min(contract_max))
+}
+
+/// Records what the relayer paid for an email op against the fee it charged, and warns if it lost money.
+#[named]
+pub async fn record_email_op_fee(email_op: &EmailOp, receipt: &TransactionReceipt) -> Result<()> {
+    let gas_used = receipt.gas_used.unwrap_or_default();
+    let effective_gas_price = receipt.effective_gas_price.unwrap_or_default();
+    let fee_token_addr = CLIENT.query_erc20_address(&email_op.fee_token_name).await?;
+    let fee_charged = charged_fee_in_token(receipt, fee_token_addr, CLIENT.self_eth_addr());
+    // The contract converts the fee in ETH into the fee token at the oracle price, so this reverses it.
+    let rate = OraclePriceSource
+        .price_in_eth(&email_op.fee_token_name)
+        .await?;
+    if rate.is_zero() {
+        bail!("{} has no price", email_op.fee_token_name);
+    }
+    let record = EmailOpFee {
+        tx_hash: format!("0x{}", hex::encode(receipt.transaction_hash.as_bytes())),
+        command: email_op.command.clone(),
+        fee_token: email_op.fee_token_name.clone(),
+        fee_per_gas_charged: email_op.fee_per_gas,
+        gas_used,
+        effective_gas_price,
+        fee_charged,
+        fee_charged_wei: fee_charged * U256::exp10(18) / rate,
+        fee_paid_wei: gas_used * effective_gas_price,
+    };
+    if record.fee_paid_wei > record.fee_charged_wei {
+        warn!(
+            LOG,
+            "email op {} paid {} wei in gas but charged {} wei",
+            record.tx_hash,
+            record.fee_paid_wei,
+            record.fee_charged_wei;
+            "func" => function_name!()
+        );
+    }
+    DB.insert_email_op_fee(&record).await
+}
+
+/// The fee paid and the fee charged for one email op, all amounts in wei unless noted.
+#[derive(Debug, Clone)]
+pub struct EmailOpFee {
+    pub tx_hash: String,
+    pub command: String,
+    pub fee_token: String,
+    pub fee_per_gas_charged: U256,
+    pub gas_used: U256,
+    pub effective_gas_price: U256,
+    /// In the smallest unit of the fee token.
+    pub fee_charged: U256,
+    pub fee_charged_wei: U256,
+    pub fee_paid_wei: U256,
+}
+
+/// The fee the core contract took from the wallet, including the claim gas of a registered unclaimed
+/// fund or state: the last transfer of the fee token to the relayer, since the fee is taken after the
+/// email op is executed. Zero if no fee was taken.
+fn charged_fee_in_token(
+    receipt: &TransactionReceipt,
+    fee_token_addr: Address,
+    relayer: Address,
+) -> U256 {
+    receipt
+        .logs
+        .iter()
+        .rev()
+        .filter(|log| log.address == fee_token_addr)
+        .filter_map(|log| ethers::contract::parse_log::<erc20::TransferFilter>(log.clone()).ok())
+        .find(|transfer| transfer.to == relayer)
+        .map(|transfer| transfer.value)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transfer_log(token: Address, from: Address, to: Address, value: u64) -> Log {
+        Log {
+            address: token,
+            topics: vec![
+                erc20::TransferFilter::signature(),
+                H256::from(from),
+                H256::from(to),
+            ],
+            data: ethers::abi::encode(&[ethers::abi::Token::Uint(U256::from(value))]).into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_charged_fee_is_the_last_fee_token_transfer_to_the_relayer() {
+        let fee_token = Address::from_low_u64_be(1);
+        let other_token = Address::from_low_u64_be(2);
+        let wallet = Address::from_low_u64_be(3);
+        let relayer = Address::from_low_u64_be(4);
+        let recipient = Address::from_low_u64_be(5);
+        let receipt = TransactionReceipt {
+            logs: vec![
+                // The email op itself sends the fee token to the relayer and to someone else.
+                transfer_log(fee_token, wallet, relayer, 100),
+                transfer_log(fee_token, wallet, recipient, 200),
+                transfer_log(other_token, wallet, relayer, 300),
+                transfer_log(fee_token, wallet, relayer, 42),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            charged_fee_in_token(&receipt, fee_token, relayer),
+            U256::from(42)
+        );
+    }
+
+    #[test]
+    fn test_charged_fee_is_zero_without_a_transfer() {
+        let fee_token = Address::from_low_u64_be(1);
+        let relayer = Address::from_low_u64_be(4);
+        let receipt = TransactionReceipt {
+            logs: vec![transfer_log(
+                fee_token,
+                Address::from_low_u64_be(3),
+                Address::from_low_u64_be(5),
+                7,
+            )],
+            ..Default::default()
+        };
+        assert_eq!(
+            charged_fee_in_token(&receipt, fee_token, relayer),
+            U256::zero()
+        );
+    }
+}
@@ -42,6 +42,107 @@ pub enum EmailWalletEvent {
     },
     Voided {
         claim: Claim,
+        sender: Address,
+        asset: String,
+        tx_hash: String,
+    },
+    ClaimFailed {
+        claim: Claim,
+        error: String,
+    },
+    ExpiryReminder {
+        email_addr: String,
+        account_code: AccountCode,
+        expiry_time: i64,
+    },
+    ClaimExpiring {
+        claim: Claim,
+        sender_email_addr: String,
+        sender_wallet_addr: String,
+        asset: String,
+    },
+    Undeliverable {
+        claim: Claim,
+        sender_email_addr: String,
+        sender_wallet_addr: String,
+        asset: String,
+        diagnostic: String,
+    },
+    Balance {
+        email_addr: String,
+        wallet_addr: Address,
+        summary: BalanceSummary,
+        original_subject: String,
+        message_id: String,
+    },
+    ConfirmationRequest {
+        email_addr: String,
+        subject: String,
+        wallet_addr: Address,
+        /// What a nickname or an ENS name in the original subject resolved to, e.g. `bob is bob@example.com`.
+        resolved_recipient: Option<String>,
+    },
+    BatchRequest {
+        batch: Batch,
+        wallet_addr: Address,
+    },
+    BatchCompleted {
+        batch: Batch,
+    },
+    Schedules {
+        email_addr: String,
+        message: String,
+        schedules: Vec<Schedule>,
+        original_subject: String,
+        message_id: String,
+    },
+    ScheduledPaymentDue {
+        schedule: Schedule,
+    },
+    Contacts {
+        email_addr: String,
+        message: String,
+        contacts: Vec<Contact>,
+        original_subject: String,
+        message_id: String,
+    },
+    PaymentRequested {
+        request: PaymentRequest,
+        is_reminder: bool,
+    },
+    PaymentRequestCreated {
+        request: PaymentRequest,
+        original_subject: String,
+        message_id: String,
+    },
+    PaymentRequestPaid {
+        request: PaymentRequest,
+    },
+    FeeTokenPreference {
+        email_addr: String,
+        message: String,
+        original_subject: String,
+        message_id: String,
+    },
+    NotificationPreference {
+        email_addr: String,
+        message: String,
+        original_subject: String,
+        message_id: String,
+    },
+    CancelRequest {
+        email_addr: String,
+        wallet_addr: Address,
+        subject: String,
+        claim: Claim,
+        asset: String,
+    },
+    Cancelled {
+        claim: Claim,
+        sender_email_addr: String,
+        asset: String,
+        original_subject: String,
+        message_id: String,
         tx_hash: String,
     },
     Error {
@@ -114,10 +215,10 @@ pub async fn handle_email_event(event: EmailWalletEvent) -> Result<()> {
                            you can send any currency we support directly to an email address by
                            sending an email with the amount, currency name, and recipient's
                            email address replaced respectively in the subject line.\n{}\nYour wallet address: {}/address/{}.\nCheck the transaction on etherscan: {}/tx/{}",
-                           email_addr, RELAYER_EMAIL_ADDRESS.get().unwrap(), ONBOARDING_REPLY_MSG.get().unwrap_or(&String::new()), CHAIN_RPC_EXPLORER.get().unwrap(), wallet_addr, CHAIN_RPC_EXPLORER.get().unwrap(), tx_hash
+                           email_addr, RELAYER_EMAIL_ADDRESS.get().unwrap(), ONBOARDING_REPLY_MSG.get().unwrap_or(&String::new()), chain_rpc_explorer(), wallet_addr, chain_rpc_explorer(), tx_hash
                         );
             let account_code_str = field2hex(&account_code.0);
-            let render_data = serde_json::json!({"userEmailAddr": email_addr, "relayerEmailAddr": RELAYER_EMAIL_ADDRESS.get().unwrap(), "faucetMessage": ONBOARDING_REPLY_MSG.get().unwrap_or(&String::new()), "walletAddr":wallet_addr, "transactionHash": tx_hash, "chainRPCExplorer": CHAIN_RPC_EXPLORER.get().unwrap(), "accountCode": account_code_str});
+            let render_data = serde_json::json!({"userEmailAddr": email_addr, "relayerEmailAddr": RELAYER_EMAIL_ADDRESS.get().unwrap(), "faucetMessage": ONBOARDING_REPLY_MSG.get().unwrap_or(&String::new()), "walletAddr":wallet_addr, "transactionHash": tx_hash, "chainRPCExplorer": chain_rpc_explorer(), "accountCode": account_code_str});
             let body_html = render_html("account_created.html", render_data).await?;
             let email = EmailMessage {
                 to: email_addr,
@@ -148,9 +249,9 @@ pub async fn handle_email_event(event: EmailWalletEvent) -> Result<()> {
             let body_plain = format!(
                             "Hi {}!\nYour transaction request {} is completed in
                             this transaction {}/tx/{}. Thank you for using Email Wallet!\nYour wallet address: {}/address/{}.\nCheck the transaction on etherscan: {}/tx/{}",
-                            sender_email_addr, original_subject, CHAIN_RPC_EXPLORER.get().unwrap(), &tx_hash,CHAIN_RPC_EXPLORER.get().unwrap(), wallet_addr, CHAIN_RPC_EXPLORER.get().unwrap(), &tx_hash
+                            sender_email_addr, original_subject, chain_rpc_explorer(), &tx_hash,chain_rpc_explorer(), wallet_addr, chain_rpc_explorer(), &tx_hash
                         );
-            let render_data = serde_json::json!({"userEmailAddr": sender_email_addr, "originalSubject": original_subject, "walletAddr":wallet_addr, "transactionHash": tx_hash, "chainRPCExplorer": CHAIN_RPC_EXPLORER.get().unwrap()});
+            let render_data = serde_json::json!({"userEmailAddr": sender_email_addr, "originalSubject": original_subject, "walletAddr":wallet_addr, "transactionHash": tx_hash, "chainRPCExplorer": chain_rpc_explorer()});
             let body_html = render_html("email_handled.html", render_data).await?;
             let email = EmailMessage {
                 to: sender_email_addr,
@@ -186,7 +287,7 @@ pub async fn handle_email_event(event: EmailWalletEvent) -> Result<()> {
                         let token_decimal =
                             CLIENT.query_decimals_of_erc20_address(*token_addr).await?;
                         *amount_str =
-                            uint_to_decimal_string(amount.as_u128(), token_decimal as usize);
+                            uint_to_decimal_string(*amount, token_decimal as usize);
                     }
                 }
             }
@@ -197,19 +298,33 @@ pub async fn handle_email_event(event: EmailWalletEvent) -> Result<()> {
                 invitation_code_hex
             );
 
-            let (assets_list_plain, assets_list_html) =
+            let has_nft = assets
+                .iter()
+                .any(|asset| matches!(asset, Asset::ERC721 { .. }));
+            let (assets_list_plain, assets_list_html, attachments) =
                 generate_asset_list_body(&assets, assets_msgs).await?;
 
             let account_salt =
                 AccountSalt::new(&PaddedEmailAddr::from_email_addr(&email_addr), account_code)?;
             let wallet_addr = CLIENT.get_wallet_addr_from_salt(&account_salt.0).await?;
-            let body_plain = format!(
+            let mut body_plain = format!(
                             "Hi {}!\nYour Email Wallet account is ready to be deployed. Your wallet address: {}/address/{}.\nPlease reply to this email to start using Email Wallet. You don't have to add any message in the reply 😄.",
-                            email_addr, CHAIN_RPC_EXPLORER.get().unwrap(), wallet_addr,
+                            email_addr, chain_rpc_explorer(), wallet_addr,
                         );
-            let render_data = serde_json::json!({"userEmailAddr": email_addr, "walletAddr": wallet_addr, "assetsList": assets_list_html, "chainRPCExplorer": CHAIN_RPC_EXPLORER.get().unwrap()});
-            println!("render_data: {:?}", render_data);
-            let body_html = render_html("invitation.html", render_data).await?;
+            let claim_link = generate_claim_link(&email_addr, now())?;
+            if let Some(claim_link) = claim_link.as_ref() {
+                body_plain.push_str(&format!(
+                    "\nIf replying does not work, open {} and we will send you a new email to reply to.",
+                    claim_link
+                ));
+            }
+            let render_data = serde_json::json!({"userEmailAddr": email_addr, "walletAddr": wallet_addr, "assetsList": assets_list_html, "claimLink": claim_link, "chainRPCExplorer": chain_rpc_explorer()});
+            let template = if has_nft {
+                "invitation_nft.html"
+            } else {
+                "invitation.html"
+            };
+            let body_html = render_html(template, render_data).await?;
             let email = EmailMessage {
                 to: email_addr.to_string(),
                 subject,
@@ -217,13 +332,13 @@ pub async fn handle_email_event(event: EmailWalletEvent) -> Result<()> {
                 body_html,
                 reference: None,
                 reply_to: None,
-                body_attachments: None,
+                body_attachments: Some(attachments),
             };
             send_email(email).await?;
         }
         EmailWalletEvent::Claimed {
-            unclaimed_fund: _,
-            unclaimed_state: _,
+            unclaimed_fund,
+            unclaimed_state,
             email_addr,
             is_fund,
             is_announced: _,
@@ -245,23 +360,84 @@ pub async fn handle_email_event(event: EmailWalletEvent) -> Result<()> {
             let wallet_addr = CLIENT.get_wallet_addr_from_salt(&account_salt.0).await?;
             let body_plain = format!(
                             "Hi {}!\nCheck the transaction for you on etherscan: {}/tx/{}.\nNote that your wallet address is {}\n",
-                            email_addr, CHAIN_RPC_EXPLORER.get().unwrap(), &tx_hash, wallet_addr
+                            email_addr, chain_rpc_explorer(), &tx_hash, wallet_addr
                         );
             let account_code_str = field2hex(&recipient_account_code.0);
-            let render_data = serde_json::json!({"userEmailAddr": email_addr, "walletAddr":wallet_addr, "transactionHash": tx_hash, "chainRPCExplorer": CHAIN_RPC_EXPLORER.get().unwrap(), "accountCode": account_code_str});
-            let body_html = render_html("claimed.html", render_data).await?;
+            let mut render_data = serde_json::json!({"userEmailAddr": email_addr, "walletAddr":wallet_addr, "transactionHash": tx_hash, "chainRPCExplorer": chain_rpc_explorer(), "accountCode": account_code_str});
+            let mut template = "claimed.html";
+            let mut attachments = vec![];
+            if let Some(unclaimed_state) = unclaimed_state.as_ref() {
+                if unclaimed_state.extension_addr
+                    == CLIENT.query_default_extension_for_command("NFT").await?
+                {
+                    let (_, nft_id, nft_name, nft_uri) =
+                        get_nft_info(&unclaimed_state.state).await?;
+                    let img = match fetch_nft_image(&nft_uri, "nft-0").await {
+                        Ok(attachment) => {
+                            attachments.push(attachment);
+                            "cid:nft-0".to_string()
+                        }
+                        Err(e) => {
+                            warn!(LOG, "Failed to fetch the image of NFT {}: {}", nft_id, e);
+                            String::new()
+                        }
+                    };
+                    render_data["nftId"] = serde_json::json!(nft_id.to_string());
+                    render_data["nftName"] = serde_json::json!(nft_name);
+                    render_data["senderAddr"] = serde_json::json!(format!(
+                        "0x{}",
+                        hex::encode(unclaimed_state.sender.as_bytes())
+                    ));
+                    render_data["img"] = serde_json::json!(img);
+                    template = "claimed_nft.html";
+                }
+            }
+            let body_html = render_html(template, render_data).await?;
             let email = EmailMessage {
-                to: email_addr,
+                to: email_addr.clone(),
                 subject,
                 body_plain,
                 body_html,
                 reference: None,
                 reply_to: None,
-                body_attachments: None,
+                body_attachments: Some(attachments),
             };
             send_email(email).await?;
+
+            // The recipient has their email, so a failure to tell the sender is only logged.
+            let notified = async {
+                let sender_and_asset = match (unclaimed_fund, unclaimed_state) {
+                    (Some(unclaimed_fund), _) => Some((
+                        unclaimed_fund.sender,
+                        describe_unclaimed_fund(&unclaimed_fund).await?,
+                    )),
+                    (_, Some(unclaimed_state)) => Some((
+                        unclaimed_state.sender,
+                        describe_unclaimed_state(&unclaimed_state),
+                    )),
+                    _ => None,
+                };
+                if let Some((sender, asset)) = sender_and_asset {
+                    let message = format!("{} claimed your transfer of {}.", email_addr, asset);
+                    notify_sender(sender, message, &tx_hash).await?;
+                }
+                Ok::<(), anyhow::Error>(())
+            }
+            .await;
+            if let Err(e) = notified {
+                warn!(LOG, "Sender of claim {} not notified: {}", tx_hash, e);
+            }
         }
-        EmailWalletEvent::Voided { claim, tx_hash } => {
+        EmailWalletEvent::Voided {
+            claim,
+            sender,
+            asset,
+            tx_hash,
+        } => {
+            let message = format!(
+                "Your transfer of {} to {} was not claimed before it expired and has been returned to your wallet.",
+                asset, claim.email_address
+            );
             let subject = format!(
                 "Email Wallet Notification. {}",
                 if claim.is_fund {
@@ -282,9 +458,9 @@ pub async fn handle_email_event(event: EmailWalletEvent) -> Result<()> {
             let wallet_addr = CLIENT.get_wallet_addr_from_salt(&account_salt.0).await?;
             let body_plain = format!(
                             "Hi {}!\nCheck the transaction for you on etherscan: {}/tx/{}.\nNote that your wallet address is {}\n",
-                            claim.email_address, CHAIN_RPC_EXPLORER.get().unwrap(), &tx_hash, wallet_addr
+                            claim.email_address, chain_rpc_explorer(), &tx_hash, wallet_addr
                         );
-            let render_data = serde_json::json!({"userEmailAddr": claim.email_address, "walletAddr":wallet_addr, "transactionHash": tx_hash, "chainRPCExplorer": CHAIN_RPC_EXPLORER.get().unwrap()});
+            let render_data = serde_json::json!({"userEmailAddr": claim.email_address, "walletAddr":wallet_addr, "transactionHash": tx_hash, "chainRPCExplorer": chain_rpc_explorer()});
             let body_html = render_html("voided.html", render_data).await?;
             let email = EmailMessage {
                 to: claim.email_address,
@@ -296,6 +472,519 @@ pub async fn handle_email_event(event: EmailWalletEvent) -> Result<()> {
                 body_attachments: None,
             };
             send_email(email).await?;
+            if let Err(e) = notify_sender(sender, message, &tx_hash).await {
+                warn!(LOG, "Sender of void {} not notified: {}", tx_hash, e);
+            }
+        }
+        EmailWalletEvent::Undeliverable {
+            claim,
+            sender_email_addr,
+            sender_wallet_addr,
+            asset,
+            diagnostic,
+        } => {
+            let subject =
+                "Email Wallet Notification. Your recipient could not be reached".to_string();
+            let expiry_time = chrono::DateTime::from_timestamp(claim.expiry_time, 0)
+                .map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string())
+                .unwrap_or(claim.expiry_time.to_string());
+            let body_plain = format!(
+                "Hi {}!\nYour transfer of {} to {} could not be delivered: {}\nThe recipient's mail server rejected the invitation email. If the recipient does not claim it, it will be returned to your wallet after {}. You can also take it back now by sending an email with the subject \"Cancel {}\".",
+                sender_email_addr, asset, claim.email_address, diagnostic, expiry_time, claim.email_address
+            );
+            let render_data = serde_json::json!({"userEmailAddr": sender_email_addr, "recipientEmailAddr": claim.email_address, "asset": asset, "diagnostic": diagnostic, "expiryTime": expiry_time, "walletAddr": sender_wallet_addr, "chainRPCExplorer": chain_rpc_explorer()});
+            let body_html = render_html("undeliverable.html", render_data).await?;
+            let email = EmailMessage {
+                to: sender_email_addr,
+                subject,
+                body_plain,
+                body_html,
+                reference: None,
+                reply_to: None,
+                body_attachments: None,
+            };
+            send_email(email).await?;
+        }
+        EmailWalletEvent::ExpiryReminder {
+            email_addr,
+            account_code,
+            expiry_time,
+        } => {
+            let assets = search_user_assets(&email_addr).await?;
+            // The code in the subject makes a reply create the account, like a reply to the invitation.
+            let subject = format!(
+                "Your Email Wallet assets expire soon. Code {}",
+                &field2hex(&account_code.0)[2..]
+            );
+            let expiry_time = chrono::DateTime::from_timestamp(expiry_time, 0)
+                .map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string())
+                .unwrap_or(expiry_time.to_string());
+            let (assets_list_plain, assets_list_html, attachments) =
+                generate_asset_list_body(&assets, vec![]).await?;
+            let account_salt =
+                AccountSalt::new(&PaddedEmailAddr::from_email_addr(&email_addr), account_code)?;
+            let wallet_addr = CLIENT.get_wallet_addr_from_salt(&account_salt.0).await?;
+            let body_plain = format!(
+                "Hi {}!\nYou have not claimed the assets sent to you yet. They will be returned to their senders after {}.\nPlease reply to this email to create your account and claim them. You don't have to add any message in the reply.",
+                email_addr, expiry_time
+            );
+            let render_data = serde_json::json!({"userEmailAddr": email_addr, "walletAddr": wallet_addr, "assetsList": assets_list_html, "expiryTime": expiry_time, "chainRPCExplorer": chain_rpc_explorer()});
+            let body_html = render_html("expiry_reminder.html", render_data).await?;
+            let email = EmailMessage {
+                to: email_addr,
+                subject,
+                body_plain,
+                body_html,
+                reference: None,
+                reply_to: None,
+                body_attachments: Some(attachments),
+            };
+            send_email(email).await?;
+        }
+        EmailWalletEvent::ClaimExpiring {
+            claim,
+            sender_email_addr,
+            sender_wallet_addr,
+            asset,
+        } => {
+            let subject = "Email Wallet Notification. Your transfer expires soon".to_string();
+            let expiry_time = chrono::DateTime::from_timestamp(claim.expiry_time, 0)
+                .map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string())
+                .unwrap_or(claim.expiry_time.to_string());
+            let body_plain = format!(
+                "Hi {}!\nYour transfer of {} to {} has not been claimed yet. It will be returned to your wallet after {}. You can also take it back now by sending an email with the subject \"Cancel {}\".",
+                sender_email_addr, asset, claim.email_address, expiry_time, claim.email_address
+            );
+            let render_data = serde_json::json!({"userEmailAddr": sender_email_addr, "recipientEmailAddr": claim.email_address, "asset": asset, "expiryTime": expiry_time, "walletAddr": sender_wallet_addr, "chainRPCExplorer": chain_rpc_explorer()});
+            let body_html = render_html("claim_expiring.html", render_data).await?;
+            let email = EmailMessage {
+                to: sender_email_addr,
+                subject,
+                body_plain,
+                body_html,
+                reference: None,
+                reply_to: None,
+                body_attachments: None,
+            };
+            send_email(email).await?;
+        }
+        EmailWalletEvent::Balance {
+            email_addr,
+            wallet_addr,
+            summary,
+            original_subject,
+            message_id,
+        } => {
+            let subject = format!("Re: {}", original_subject);
+            let mut body_plain = format!("Hi {}!\nYour balances:\n", email_addr);
+            for balance in summary.balances.iter() {
+                body_plain.push_str(&format!("{}\n", balance));
+            }
+            if !summary.pending_incoming.is_empty() {
+                body_plain.push_str("Pending funds sent to you:\n");
+                for pending in summary.pending_incoming.iter() {
+                    body_plain.push_str(&format!("{}\n", pending));
+                }
+            }
+            if !summary.pending_outgoing.is_empty() {
+                body_plain.push_str("Funds you sent that are not claimed yet:\n");
+                for pending in summary.pending_outgoing.iter() {
+                    body_plain.push_str(&format!("{}\n", pending));
+                }
+            }
+            body_plain.push_str(&format!(
+                "Your wallet address: {}/address/{:?}.",
+                chain_rpc_explorer(),
+                wallet_addr
+            ));
+            let render_data = serde_json::json!({"userEmailAddr": email_addr, "balances": summary.balances, "pendingIncoming": summary.pending_incoming, "pendingOutgoing": summary.pending_outgoing, "walletAddr": wallet_addr, "chainRPCExplorer": chain_rpc_explorer()});
+            let body_html = render_html("balance.html", render_data).await?;
+            let email = EmailMessage {
+                to: email_addr,
+                subject,
+                body_plain,
+                body_html,
+                reference: Some(message_id.clone()),
+                reply_to: Some(message_id),
+                body_attachments: None,
+            };
+            send_email(email).await?;
+        }
+        EmailWalletEvent::ConfirmationRequest {
+            email_addr,
+            subject,
+            wallet_addr,
+            resolved_recipient,
+        } => {
+            let fee_quote = describe_fee_quote(&email_addr, &subject).await;
+            let mut body_plain = format!(
+                "Hi {}! Please reply to this email to confirm your request: {}.\nYou don't have to add any message in the reply 😄.\nYour wallet address: {}/address/{:?}.",
+                email_addr, subject, chain_rpc_explorer(), wallet_addr,
+            );
+            if let Some(fee_quote) = fee_quote.as_ref() {
+                body_plain.push_str(&format!("\nThe estimated fee is {}.", fee_quote));
+            }
+            if let Some(resolved_recipient) = resolved_recipient.as_ref() {
+                body_plain.push_str(&format!(
+                    "\nPlease check the recipient before replying: {}.",
+                    resolved_recipient
+                ));
+            }
+            let render_data = serde_json::json!({"userEmailAddr": email_addr, "originalSubject": subject, "feeQuote": fee_quote, "resolvedRecipient": resolved_recipient, "walletAddr": wallet_addr, "chainRPCExplorer": chain_rpc_explorer()});
+            let body_html = render_html("send_request.html", render_data).await?;
+            let email = EmailMessage {
+                to: email_addr,
+                subject,
+                body_plain,
+                body_html,
+                reference: None,
+                reply_to: None,
+                body_attachments: None,
+            };
+            send_email(email).await?;
+        }
+        EmailWalletEvent::BatchRequest { batch, wallet_addr } => {
+            let subject = format!("Re: {}", batch.original_subject);
+            let confirmations = batch.confirmations();
+            let mut body_plain = format!(
+                "Hi {}!\nYour balance covers all {} transfers of the batch and their fees. Please confirm them by sending one email with each of the following {} subjects. You don't have to add any message 😄.\n",
+                batch.email_address,
+                batch.items.len(),
+                confirmations.len()
+            );
+            let mut items = vec![];
+            for item in confirmations.iter() {
+                let reply_subject = batch_reply_subject(&batch.id, item);
+                let num_transfers = batch.confirmed_idxs(item.idx).len();
+                body_plain.push_str(&format!("{}\n", reply_subject));
+                items.push(serde_json::json!({"subject": reply_subject, "numTransfers": num_transfers, "link": mailto_link(&reply_subject)}));
+            }
+            let has_ungrouped_addresses = confirmations
+                .iter()
+                .filter(|item| !item.is_grouped() && item.recipient.starts_with("0x"))
+                .count()
+                >= 2;
+            if has_ungrouped_addresses {
+                body_plain.push_str("Transfers to ETH addresses can be confirmed with a single email once you install the batch transfer extension with the subject \"Install extension BatchTransferExtension\".\n");
+            }
+            body_plain.push_str("We will reply with a summary once all the transfers are done.");
+            let render_data = serde_json::json!({"userEmailAddr": batch.email_address, "numItems": batch.items.len(), "numConfirmations": confirmations.len(), "items": items, "suggestExtension": has_ungrouped_addresses, "walletAddr": wallet_addr, "chainRPCExplorer": chain_rpc_explorer()});
+            let body_html = render_html("batch_request.html", render_data).await?;
+            let email = EmailMessage {
+                to: batch.email_address,
+                subject,
+                body_plain,
+                body_html,
+                reference: Some(batch.message_id.clone()),
+                reply_to: Some(batch.message_id),
+                body_attachments: None,
+            };
+            send_email(email).await?;
+        }
+        EmailWalletEvent::BatchCompleted { batch } => {
+            let subject = format!("Re: {}", batch.original_subject);
+            let mut body_plain = format!(
+                "Hi {}!\nAll the transfers of your batch are done:\n",
+                batch.email_address
+            );
+            let mut items = vec![];
+            for item in batch.items.iter() {
+                let result = match item.status.as_str() {
+                    BATCH_ITEM_SENT => format!(
+                        "sent in transaction {}",
+                        item.detail.clone().unwrap_or_default()
+                    ),
+                    _ => format!("failed: {}", item.detail.clone().unwrap_or_default()),
+                };
+                body_plain.push_str(&format!("{}: {}\n", item.subject, result));
+                items.push(serde_json::json!({"subject": item.subject, "result": result}));
+            }
+            let render_data = serde_json::json!({"userEmailAddr": batch.email_address, "items": items, "chainRPCExplorer": chain_rpc_explorer()});
+            let body_html = render_html("batch_summary.html", render_data).await?;
+            let email = EmailMessage {
+                to: batch.email_address,
+                subject,
+                body_plain,
+                body_html,
+                reference: Some(batch.message_id.clone()),
+                reply_to: Some(batch.message_id),
+                body_attachments: None,
+            };
+            send_email(email).await?;
+        }
+        EmailWalletEvent::Schedules {
+            email_addr,
+            message,
+            schedules,
+            original_subject,
+            message_id,
+        } => {
+            let subject = format!("Re: {}", original_subject);
+            let schedules: Vec<String> = schedules.iter().map(Schedule::describe).collect();
+            let mut body_plain = format!("Hi {}!\n{}\n", email_addr, message);
+            if schedules.is_empty() {
+                body_plain.push_str("You have no scheduled payments.\n");
+            } else {
+                body_plain.push_str("Your scheduled payments:\n");
+                for schedule in schedules.iter() {
+                    body_plain.push_str(&format!("{}\n", schedule));
+                }
+            }
+            let render_data = serde_json::json!({"userEmailAddr": email_addr, "message": message, "schedules": schedules, "chainRPCExplorer": chain_rpc_explorer()});
+            let body_html = render_html("schedules.html", render_data).await?;
+            let email = EmailMessage {
+                to: email_addr,
+                subject,
+                body_plain,
+                body_html,
+                reference: Some(message_id.clone()),
+                reply_to: Some(message_id),
+                body_attachments: None,
+            };
+            send_email(email).await?;
+        }
+        EmailWalletEvent::Contacts {
+            email_addr,
+            message,
+            contacts,
+            original_subject,
+            message_id,
+        } => {
+            let subject = format!("Re: {}", original_subject);
+            let contacts: Vec<String> = contacts.iter().map(Contact::describe).collect();
+            let mut body_plain = format!("Hi {}!\n{}\n", email_addr, message);
+            if contacts.is_empty() {
+                body_plain.push_str("You have no contacts.\n");
+            } else {
+                body_plain.push_str("Your contacts:\n");
+                for contact in contacts.iter() {
+                    body_plain.push_str(&format!("{}\n", contact));
+                }
+            }
+            let render_data = serde_json::json!({"userEmailAddr": email_addr, "message": message, "contacts": contacts, "chainRPCExplorer": chain_rpc_explorer()});
+            let body_html = render_html("contacts.html", render_data).await?;
+            let email = EmailMessage {
+                to: email_addr,
+                subject,
+                body_plain,
+                body_html,
+                reference: Some(message_id.clone()),
+                reply_to: Some(message_id),
+                body_attachments: None,
+            };
+            send_email(email).await?;
+        }
+        EmailWalletEvent::ScheduledPaymentDue { schedule } => {
+            let body_plain = format!(
+                "Hi {}! Your scheduled payment #{} is due: {}.\nPlease reply to this email to send it. You don't have to add any message in the reply 😄.\nTo stop this schedule, send an email with the subject \"{} {}\".",
+                schedule.email_address, schedule.id, schedule.subject, UNSCHEDULE_COMMAND, schedule.id
+            );
+            let render_data = serde_json::json!({"userEmailAddr": schedule.email_address, "scheduleId": schedule.id, "originalSubject": schedule.subject, "unscheduleSubject": format!("{} {}", UNSCHEDULE_COMMAND, schedule.id), "walletAddr": schedule.wallet_addr, "chainRPCExplorer": chain_rpc_explorer()});
+            let body_html = render_html("scheduled_payment.html", render_data).await?;
+            let email = EmailMessage {
+                to: schedule.email_address,
+                subject: schedule.subject,
+                body_plain,
+                body_html,
+                reference: None,
+                reply_to: None,
+                body_attachments: None,
+            };
+            send_email(email).await?;
+        }
+        EmailWalletEvent::PaymentRequested {
+            request,
+            is_reminder,
+        } => {
+            let reply_subject = payment_request_reply_subject(&request);
+            let link = mailto_link(&reply_subject);
+            let body_plain = format!(
+                "Hi {}!\n{}{} requests {} from you.\nPlease reply to this email or send an email to {} with the subject \"{}\" to pay it. You don't have to add any message 😄.",
+                request.payer_email,
+                if is_reminder { "Reminder: " } else { "" },
+                request.requester_email,
+                request.asset,
+                RELAYER_EMAIL_ADDRESS.get().unwrap(),
+                reply_subject
+            );
+            let render_data = serde_json::json!({"userEmailAddr": request.payer_email, "requesterEmailAddr": request.requester_email, "asset": request.asset, "isReminder": is_reminder, "link": link, "chainRPCExplorer": chain_rpc_explorer()});
+            let body_html = render_html("payment_request.html", render_data).await?;
+            let email = EmailMessage {
+                to: request.payer_email,
+                subject: reply_subject,
+                body_plain,
+                body_html,
+                reference: None,
+                reply_to: None,
+                body_attachments: None,
+            };
+            send_email(email).await?;
+        }
+        EmailWalletEvent::PaymentRequestCreated {
+            request,
+            original_subject,
+            message_id,
+        } => {
+            let message = format!(
+                "Your request #{} for {} has been sent to {}. We will let you know when it is paid.",
+                request.id, request.asset, request.payer_email
+            );
+            let body_plain = format!("Hi {}!\n{}", request.requester_email, message);
+            let render_data = serde_json::json!({"userEmailAddr": request.requester_email, "message": message, "chainRPCExplorer": chain_rpc_explorer()});
+            let body_html = render_html("payment_request_status.html", render_data).await?;
+            let email = EmailMessage {
+                to: request.requester_email,
+                subject: format!("Re: {}", original_subject),
+                body_plain,
+                body_html,
+                reference: Some(message_id.clone()),
+                reply_to: Some(message_id),
+                body_attachments: None,
+            };
+            send_email(email).await?;
+        }
+        EmailWalletEvent::PaymentRequestPaid { request } => {
+            let tx_hash = request.tx_hash.clone().unwrap_or_default();
+            let message = format!(
+                "{} paid your request #{} for {}.",
+                request.payer_email, request.id, request.asset
+            );
+            let body_plain = format!(
+                "Hi {}!\n{}\nCheck the transaction on etherscan: {}/tx/{}.",
+                request.requester_email,
+                message,
+                chain_rpc_explorer(),
+                tx_hash
+            );
+            let render_data = serde_json::json!({"userEmailAddr": request.requester_email, "message": message, "transactionHash": tx_hash, "chainRPCExplorer": chain_rpc_explorer()});
+            let body_html = render_html("payment_request_status.html", render_data).await?;
+            let email = EmailMessage {
+                to: request.requester_email,
+                subject: "Email Wallet Notification. Your payment request is paid".to_string(),
+                body_plain,
+                body_html,
+                reference: None,
+                reply_to: None,
+                body_attachments: None,
+            };
+            send_email(email).await?;
+        }
+        EmailWalletEvent::FeeTokenPreference {
+            email_addr,
+            message,
+            original_subject,
+            message_id,
+        } => {
+            let body_plain = format!(
+                "Hi {}!\n{}\nTo let us pick the token again, send an email with the subject \"{} token {}\".",
+                email_addr, message, FEE_COMMAND, FEE_TOKEN_AUTO
+            );
+            let render_data = serde_json::json!({"userEmailAddr": email_addr, "message": message, "autoSubject": format!("{} token {}", FEE_COMMAND, FEE_TOKEN_AUTO), "chainRPCExplorer": chain_rpc_explorer()});
+            let body_html = render_html("fee_token.html", render_data).await?;
+            let email = EmailMessage {
+                to: email_addr,
+                subject: format!("Re: {}", original_subject),
+                body_plain,
+                body_html,
+                reference: Some(message_id.clone()),
+                reply_to: Some(message_id),
+                body_attachments: None,
+            };
+            send_email(email).await?;
+        }
+        EmailWalletEvent::NotificationPreference {
+            email_addr,
+            message,
+            original_subject,
+            message_id,
+        } => {
+            let toggle_subject = if DB.get_sender_notifications(&email_addr).await? {
+                format!("{} off", NOTIFICATIONS_COMMAND)
+            } else {
+                format!("{} on", NOTIFICATIONS_COMMAND)
+            };
+            let body_plain = format!(
+                "Hi {}!\n{}\nTo change this, send an email with the subject \"{}\".",
+                email_addr, message, toggle_subject
+            );
+            let render_data = serde_json::json!({"userEmailAddr": email_addr, "message": message, "toggleSubject": toggle_subject, "chainRPCExplorer": chain_rpc_explorer()});
+            let body_html = render_html("notifications.html", render_data).await?;
+            let email = EmailMessage {
+                to: email_addr,
+                subject: format!("Re: {}", original_subject),
+                body_plain,
+                body_html,
+                reference: Some(message_id.clone()),
+                reply_to: Some(message_id),
+                body_attachments: None,
+            };
+            send_email(email).await?;
+        }
+        EmailWalletEvent::CancelRequest {
+            email_addr,
+            wallet_addr,
+            subject,
+            claim,
+            asset,
+        } => {
+            let body_plain = format!(
+                "Hi {}! Please reply to this email to cancel your transfer of {} to {}.\nThe subject of this email is the transaction that returns it to your wallet. You don't have to add any message in the reply 😄.\nYour wallet address: {}/address/{:?}.",
+                email_addr, asset, claim.email_address, chain_rpc_explorer(), wallet_addr,
+            );
+            let render_data = serde_json::json!({"userEmailAddr": email_addr, "recipientEmailAddr": claim.email_address, "asset": asset, "walletAddr": wallet_addr, "chainRPCExplorer": chain_rpc_explorer()});
+            let body_html = render_html("cancel_request.html", render_data).await?;
+            let email = EmailMessage {
+                to: email_addr,
+                subject,
+                body_plain,
+                body_html,
+                reference: None,
+                reply_to: None,
+                body_attachments: None,
+            };
+            send_email(email).await?;
+        }
+        EmailWalletEvent::Cancelled {
+            claim,
+            sender_email_addr,
+            asset,
+            original_subject,
+            message_id,
+            tx_hash,
+        } => {
+            let body_plain = format!(
+                "Hi {}!\nYour transfer of {} to {} is cancelled and returned to your wallet.\nCheck the transaction on etherscan: {}/tx/{}.",
+                sender_email_addr, asset, claim.email_address, chain_rpc_explorer(), tx_hash
+            );
+            let render_data = serde_json::json!({"userEmailAddr": sender_email_addr, "isSender": true, "senderEmailAddr": sender_email_addr, "recipientEmailAddr": claim.email_address, "asset": asset, "transactionHash": tx_hash, "chainRPCExplorer": chain_rpc_explorer()});
+            let body_html = render_html("cancelled.html", render_data).await?;
+            let email = EmailMessage {
+                to: sender_email_addr.clone(),
+                subject: format!("Re: {}", original_subject),
+                body_plain,
+                body_html,
+                reference: Some(message_id.clone()),
+                reply_to: Some(message_id),
+                body_attachments: None,
+            };
+            send_email(email).await?;
+
+            let body_plain = format!(
+                "Hi {}!\nThe transfer of {} from {} to you is cancelled by the sender, so there is nothing left to claim.\nCheck the transaction on etherscan: {}/tx/{}.",
+                claim.email_address, asset, sender_email_addr, chain_rpc_explorer(), tx_hash
+            );
+            let render_data = serde_json::json!({"userEmailAddr": claim.email_address, "isSender": false, "senderEmailAddr": sender_email_addr, "recipientEmailAddr": claim.email_address, "asset": asset, "transactionHash": tx_hash, "chainRPCExplorer": chain_rpc_explorer()});
+            let body_html = render_html("cancelled.html", render_data).await?;
+            let email = EmailMessage {
+                to: claim.email_address,
+                subject: "Email Wallet Notification. A transfer to you was cancelled".to_string(),
+                body_plain,
+                body_html,
+                reference: None,
+                reply_to: None,
+                body_attachments: None,
+            };
+            send_email(email).await?;
         }
         EmailWalletEvent::Error {
             email_addr,
@@ -306,7 +995,7 @@ pub async fn handle_email_event(event: EmailWalletEvent) -> Result<()> {
             if let Some(error) = error {
                 let subject = "Email Wallet Notification. Error occurred.".to_string();
                 let body_plain = format!("Hi {}!\nError occurred: {}", email_addr, error);
-                let render_data = serde_json::json!({"userEmailAddr": email_addr, "chainRPCExplorer": CHAIN_RPC_EXPLORER.get().unwrap()});
+                let render_data = serde_json::json!({"userEmailAddr": email_addr, "errorMsg": error, "chainRPCExplorer": chain_rpc_explorer()});
                 let body_html = render_html("error.html", render_data).await?;
                 let email = EmailMessage {
                     to: email_addr.clone(),
@@ -320,6 +1009,11 @@ pub async fn handle_email_event(event: EmailWalletEvent) -> Result<()> {
                 send_email(email).await?;
 
                 // Send error email to team email addresses
+                if alert_digest_enabled() {
+                    let message =
+                        format!("{} from {} failed: {}", error_subject, email_addr, error);
+                    return notify_team("User error", &message).await;
+                }
                 let error_email_addresses = ERROR_EMAIL_ADDRESSES.get().unwrap();
                 for error_email_addr in error_email_addresses {
                     let subject = "Email Wallet Notification. Error occurred.".to_string();
@@ -339,16 +1033,49 @@ pub async fn handle_email_event(event: EmailWalletEvent) -> Result<()> {
                 }
             }
         }
+        EmailWalletEvent::ClaimFailed { claim, error } => {
+            // The recipient cannot do anything about it, so only the team is told.
+            let error_subject = format!(
+                "Claim of {} {}",
+                if claim.is_fund { "fund" } else { "state" },
+                claim.id
+            );
+            if alert_digest_enabled() {
+                let message = format!(
+                    "{} for {} failed: {}",
+                    error_subject, claim.email_address, error
+                );
+                return notify_team("Claim failed", &message).await;
+            }
+            for error_email_addr in ERROR_EMAIL_ADDRESSES.get().unwrap() {
+                let render_data = serde_json::json!({"userEmailAddr": error_email_addr, "error": error, "subject": error_subject, "emailAddr": claim.email_address});
+                let body_html = render_html("error_alert.html", render_data).await?;
+                let email = EmailMessage {
+                    to: error_email_addr.clone(),
+                    subject: "Email Wallet Notification. Claim failed.".to_string(),
+                    body_plain: format!("{} failed: {}", error_subject, error),
+                    body_html,
+                    reference: None,
+                    reply_to: None,
+                    body_attachments: None,
+                };
+                send_email(email).await?;
+            }
+        }
         EmailWalletEvent::Ack {
             email_addr,
             subject,
             original_message_id,
         } => {
-            let body_plain = format!(
+            let fee_quote = describe_fee_quote(&email_addr, &subject).await;
+            let mut body_plain = format!(
                 "Hi {}!\nYour email with the subject {} is received.",
                 email_addr, subject
             );
-            let render_data = serde_json::json!({"userEmailAddr": email_addr, "request": subject});
+            if let Some(fee_quote) = fee_quote.as_ref() {
+                body_plain.push_str(&format!("\nThe estimated fee is {}.", fee_quote));
+            }
+            let render_data = serde_json::json!({"userEmailAddr": email_addr, "request": subject, "feeQuote": fee_quote});
             let body_html = render_html("acknowledgement.html", render_data).await?;
             let subject = format!("Re: {}", subject);
             let email = EmailMessage {
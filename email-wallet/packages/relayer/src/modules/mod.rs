@@ -1,17 +1,49 @@
+pub mod balance;
+pub mod batch;
+pub mod bounce;
+pub mod cancel;
+pub mod chain_profiles;
+pub mod claim_link;
 pub mod claimer;
+pub mod contacts;
 pub mod dkim_oracle;
 pub mod emails_pool;
+pub mod event_listener;
+pub mod expiry_reminder;
+pub mod fee;
+pub mod fee_strategy;
 pub mod mail;
+pub mod monitor;
+pub mod notifications;
+pub mod payment_request;
+pub mod preflight;
 pub mod psi;
 pub mod safe;
+pub mod schedule;
 pub mod subgraph;
 pub mod web_server;
 
+pub use balance::*;
+pub use batch::*;
+pub use bounce::*;
+pub use cancel::*;
+pub use chain_profiles::*;
+pub use claim_link::*;
 pub use claimer::*;
+pub use contacts::*;
 pub use dkim_oracle::*;
 pub use emails_pool::*;
+pub use event_listener::*;
+pub use expiry_reminder::*;
+pub use fee::*;
+pub use fee_strategy::*;
 pub use mail::*;
+pub use monitor::*;
+pub use notifications::*;
+pub use payment_request::*;
+pub use preflight::*;
 pub use psi::*;
 pub use safe::*;
+pub use schedule::*;
 pub use subgraph::*;
 pub use web_server::*;
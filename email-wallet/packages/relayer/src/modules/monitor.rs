@@ -0,0 +1,342 @@
+use crate::*;
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+/// Thresholds and delivery of the alerts sent to ERROR_EMAIL_ADDRESSES about the relayer itself.
+#[derive(Debug, Clone)]
+pub struct AlertPolicy {
+    /// Wei of ETH below which the signer of the relayer is reported as running out of gas money.
+    pub min_eth_balance: U256,
+    /// Smallest units of the onboarding token below which the relayer is reported as running out of it.
+    pub min_onboarding_token_balance: U256,
+    pub check_interval_secs: u64,
+    /// Consecutive failures of a background task before it is reported.
+    pub task_failure_threshold: u32,
+    /// An alert that is still raised is not sent again within this many seconds.
+    pub dedup_secs: i64,
+    /// If set, alerts and user errors are collected and sent as one digest every this many seconds.
+    pub digest_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AlertEntry {
+    pub time: String,
+    pub subject: String,
+    pub message: String,
+}
+
+#[derive(Default)]
+struct AlertState {
+    // When each raised alert was last sent, by alert key.
+    last_sent: HashMap<String, i64>,
+    task_failures: HashMap<String, u32>,
+    digest: Vec<AlertEntry>,
+}
+
+impl AlertState {
+    /// Whether the alert `key` is due at `now`, and if so marks it as sent.
+    fn take_alert(&mut self, key: &str, now: i64, dedup_secs: i64) -> bool {
+        if let Some(last_sent) = self.last_sent.get(key) {
+            if now - last_sent < dedup_secs {
+                return false;
+            }
+        }
+        self.last_sent.insert(key.to_string(), now);
+        true
+    }
+
+    /// Returns false if the alert was not raised.
+    fn resolve_alert(&mut self, key: &str) -> bool {
+        self.last_sent.remove(key).is_some()
+    }
+
+    /// The consecutive failures of the task after this pass; None if it succeeded after succeeding before.
+    fn count_task_result(&mut self, task: &str, succeeded: bool) -> Option<u32> {
+        if succeeded {
+            return self.task_failures.remove(task).map(|_| 0);
+        }
+        let failures = self.task_failures.entry(task.to_string()).or_insert(0);
+        *failures += 1;
+        Some(*failures)
+    }
+
+    /// Puts entries of a digest that could not be sent back in front of the entries collected since.
+    fn requeue_digest(&mut self, entries: Vec<AlertEntry>) {
+        let newer = std::mem::replace(&mut self.digest, entries);
+        self.digest.extend(newer);
+    }
+}
+
+lazy_static! {
+    static ref ALERT_STATE: Mutex<AlertState> = Mutex::new(AlertState::default());
+}
+
+/// Raises the alert `key` unless it was already sent within ALERT_DEDUP_SECS.
+/// The alert is raised again once it is resolved and crossed anew.
+#[named]
+pub async fn raise_alert(key: &str, subject: &str, message: &str) -> Result<()> {
+    let dedup_secs = ALERT_POLICY.get().unwrap().dedup_secs;
+    if !ALERT_STATE.lock().await.take_alert(key, now(), dedup_secs) {
+        return Ok(());
+    }
+    warn!(LOG, "alert {}: {}", subject, message; "func" => function_name!());
+    notify_team(subject, message).await
+}
+
+/// Clears the alert `key`, so that it is sent right away the next time it is raised.
+#[named]
+pub async fn resolve_alert(key: &str) {
+    if ALERT_STATE.lock().await.resolve_alert(key) {
+        info!(LOG, "alert {} resolved", key; "func" => function_name!());
+    }
+}
+
+/// Whether team notifications are collected into digests instead of sent one by one.
+pub fn alert_digest_enabled() -> bool {
+    ALERT_POLICY.get().unwrap().digest_secs.is_some()
+}
+
+/// Sends an entry to the team, or adds it to the next digest in digest mode.
+pub async fn notify_team(subject: &str, message: &str) -> Result<()> {
+    let entry = AlertEntry {
+        time: chrono::Utc::now().to_rfc3339(),
+        subject: subject.to_string(),
+        message: message.to_string(),
+    };
+    if alert_digest_enabled() {
+        ALERT_STATE.lock().await.digest.push(entry);
+        return Ok(());
+    }
+    send_alert_email(&format!("Email Wallet Alert. {}", subject), &[entry]).await
+}
+
+/// Counts the outcome of a pass of a background task and alerts once it failed ALERT_TASK_FAILURE_THRESHOLD times in a row.
+pub async fn report_task_result(task: &str, result: &Result<()>) {
+    let key = format!("task:{}", task);
+    let failures = ALERT_STATE
+        .lock()
+        .await
+        .count_task_result(task, result.is_ok());
+    match (result, failures) {
+        (Ok(()), Some(_)) => resolve_alert(&key).await,
+        (Err(e), Some(failures))
+            if failures >= ALERT_POLICY.get().unwrap().task_failure_threshold =>
+        {
+            let message = format!("{} failed {} times in a row: {}", task, failures, e);
+            log_alert_error(raise_alert(&key, &format!("{} is failing", task), &message).await);
+        }
+        _ => {}
+    }
+}
+
+/// Checks the balances of the relayer on every chain and the health of the RPCs and the prover.
+#[named]
+pub async fn monitor_relayer() {
+    let interval = ALERT_POLICY.get().unwrap().check_interval_secs;
+    loop {
+        for chain_id in chain_ids() {
+            if let Err(e) = with_chain(chain_id, check_chain_health()).await {
+                error!(LOG, "Error checking chain {}: {}", chain_id, e; "func" => function_name!());
+            }
+        }
+        check_prover_health().await;
+        info!(LOG, "relayer health checked"; "func" => function_name!());
+        sleep(Duration::from_secs(interval)).await;
+    }
+}
+
+/// Sends the collected alerts as one email every ALERT_DIGEST_SECS. Does nothing without digest mode.
+#[named]
+pub async fn send_alert_digests() {
+    let Some(digest_secs) = ALERT_POLICY.get().unwrap().digest_secs else {
+        return;
+    };
+    loop {
+        sleep(Duration::from_secs(digest_secs)).await;
+        let entries = std::mem::take(&mut ALERT_STATE.lock().await.digest);
+        if entries.is_empty() {
+            continue;
+        }
+        let subject = format!("Email Wallet Alert Digest. {} alerts.", entries.len());
+        if let Err(e) = send_alert_email(&subject, &entries).await {
+            error!(LOG, "Failed to send the alert digest: {}", e; "func" => function_name!());
+            // Keep the entries for the next digest.
+            ALERT_STATE.lock().await.requeue_digest(entries);
+        }
+    }
+}
+
+async fn check_chain_health() {
+    let chain = &current_chain().name;
+    let rpc_key = format!("rpc:{}", chain);
+    let balance = match CLIENT
+        .client
+        .get_balance(CLIENT.self_eth_addr(), None)
+        .await
+    {
+        Ok(balance) => {
+            resolve_alert(&rpc_key).await;
+            balance
+        }
+        Err(e) => {
+            let message = format!("The RPC of chain {} does not respond: {}", chain, e);
+            log_alert_error(raise_alert(&rpc_key, "RPC is unreachable", &message).await);
+            return;
+        }
+    };
+
+    let policy = ALERT_POLICY.get().unwrap();
+    let eth_key = format!("eth_balance:{}", chain);
+    if balance < policy.min_eth_balance {
+        let message = format!(
+            "The relayer {:?} has {} ETH left on chain {}, below the threshold of {} ETH.",
+            CLIENT.self_eth_addr(),
+            uint_to_decimal_string(balance, 18),
+            chain,
+            uint_to_decimal_string(policy.min_eth_balance, 18)
+        );
+        log_alert_error(raise_alert(&eth_key, "Relayer ETH balance is low", &message).await);
+    } else {
+        resolve_alert(&eth_key).await;
+    }
+
+    // The onboarding token is only configured for the default chain.
+    let token_addr = *ONBOARDING_TOKEN_ADDR.get().unwrap();
+    if current_chain_id() != *CHAIN_ID.get().unwrap() || token_addr.is_zero() {
+        return;
+    }
+    let token_key = "onboarding_token_balance";
+    let erc20 = ERC20::new(token_addr, CLIENT.client.clone());
+    match erc20.balance_of(CLIENT.self_eth_addr()).call().await {
+        Ok(balance) if balance < policy.min_onboarding_token_balance => {
+            let message = format!(
+                "The relayer {:?} has {} of the onboarding token {:?} left, below the threshold of {}.",
+                CLIENT.self_eth_addr(),
+                balance,
+                token_addr,
+                policy.min_onboarding_token_balance
+            );
+            log_alert_error(
+                raise_alert(token_key, "Onboarding token balance is low", &message).await,
+            );
+        }
+        Ok(_) => resolve_alert(token_key).await,
+        Err(e) => {
+            let message = format!("Failed to query the onboarding token balance: {}", e);
+            log_alert_error(
+                raise_alert(token_key, "Onboarding token balance is unknown", &message).await,
+            );
+        }
+    }
+}
+
+// Any response but a server error counts as healthy, since the prover has no status endpoint.
+async fn check_prover_health() {
+    let key = "prover";
+    let result = reqwest::Client::new()
+        .get(PROVER_ADDRESS.get().unwrap())
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await;
+    match result {
+        Ok(res) if !res.status().is_server_error() => resolve_alert(key).await,
+        Ok(res) => {
+            let message = format!("The prover responded with {}", res.status());
+            log_alert_error(raise_alert(key, "Prover is unhealthy", &message).await);
+        }
+        Err(e) => {
+            let message = format!("The prover is unreachable: {}", e);
+            log_alert_error(raise_alert(key, "Prover is unhealthy", &message).await);
+        }
+    }
+}
+
+async fn send_alert_email(subject: &str, entries: &[AlertEntry]) -> Result<()> {
+    let relayer_addr = format!("{:?}", CLIENT.self_eth_addr());
+    let body_plain = entries
+        .iter()
+        .map(|entry| format!("[{}] {}: {}", entry.time, entry.subject, entry.message))
+        .collect::<Vec<_>>()
+        .join("\n");
+    for error_email_addr in ERROR_EMAIL_ADDRESSES.get().unwrap() {
+        let render_data = serde_json::json!({"userEmailAddr": error_email_addr, "relayerAddr": relayer_addr, "alerts": entries, "chainRPCExplorer": chain_rpc_explorer()});
+        let body_html = render_html("relayer_alert.html", render_data).await?;
+        let email = EmailMessage {
+            to: error_email_addr.clone(),
+            subject: subject.to_string(),
+            body_plain: body_plain.clone(),
+            body_html,
+            reference: None,
+            reply_to: None,
+            body_attachments: None,
+        };
+        send_email(email).await?;
+    }
+    Ok(())
+}
+
+#[named]
+fn log_alert_error(result: Result<()>) {
+    if let Err(e) = result {
+        error!(LOG, "Failed to send an alert: {}", e; "func" => function_name!());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(subject: &str) -> AlertEntry {
+        AlertEntry {
+            time: String::new(),
+            subject: subject.to_string(),
+            message: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_alert_is_not_resent_within_dedup_window() {
+        let mut state = AlertState::default();
+        assert!(state.take_alert("prover", 1000, 3600));
+        assert!(!state.take_alert("prover", 1001, 3600));
+        assert!(!state.take_alert("prover", 4599, 3600));
+        // Other alerts are deduplicated on their own.
+        assert!(state.take_alert("rpc:base", 1001, 3600));
+        assert!(state.take_alert("prover", 4600, 3600));
+        assert!(!state.take_alert("prover", 4601, 3600));
+    }
+
+    #[test]
+    fn test_resolved_alert_is_sent_again_right_away() {
+        let mut state = AlertState::default();
+        assert!(!state.resolve_alert("prover"));
+        assert!(state.take_alert("prover", 1000, 3600));
+        assert!(state.resolve_alert("prover"));
+        assert!(!state.resolve_alert("prover"));
+        assert!(state.take_alert("prover", 1001, 3600));
+    }
+
+    #[test]
+    fn test_task_failures_count_consecutive_failures() {
+        let mut state = AlertState::default();
+        assert_eq!(state.count_task_result("safe", true), None);
+        assert_eq!(state.count_task_result("safe", false), Some(1));
+        assert_eq!(state.count_task_result("safe", false), Some(2));
+        assert_eq!(state.count_task_result("scheduler", false), Some(1));
+        // A success after failures resets the count once.
+        assert_eq!(state.count_task_result("safe", true), Some(0));
+        assert_eq!(state.count_task_result("safe", true), None);
+        assert_eq!(state.count_task_result("safe", false), Some(1));
+    }
+
+    #[test]
+    fn test_unsent_digest_is_kept_before_newer_entries() {
+        let mut state = AlertState::default();
+        state.digest.push(entry("newer"));
+        state.requeue_digest(vec![entry("older")]);
+        let subjects: Vec<_> = state.digest.iter().map(|e| e.subject.as_str()).collect();
+        assert_eq!(subjects, vec!["older", "newer"]);
+    }
+}
@@ -0,0 +1,134 @@
+use crate::*;
+
+use async_trait::async_trait;
+
+/// Handles `Notifications on` and `Notifications off` and returns the message for the reply.
+pub async fn handle_notifications_command(email_addr: &str, subject: &str) -> Result<String> {
+    let enabled = extract_template_vals_notifications(subject)? == 0;
+    DB.set_sender_notifications(email_addr, enabled).await?;
+    Ok(if enabled {
+        "You will get an email when a transfer you sent is claimed or returned to you.".to_string()
+    } else {
+        "You will no longer get an email when a transfer you sent is claimed or returned to you."
+            .to_string()
+    })
+}
+
+/// Where the users behind wallets and their notification preferences are looked up.
+#[async_trait]
+trait SenderDirectory: Send + Sync {
+    async fn email_of_wallet(&self, wallet_addr: &str) -> Result<String>;
+    async fn sender_notifications(&self, email_addr: &str) -> Result<bool>;
+}
+
+struct StoredSenders;
+
+#[async_trait]
+impl SenderDirectory for StoredSenders {
+    async fn email_of_wallet(&self, wallet_addr: &str) -> Result<String> {
+        DB.get_email_by_wallet(wallet_addr).await
+    }
+
+    async fn sender_notifications(&self, email_addr: &str) -> Result<bool> {
+        DB.get_sender_notifications(email_addr).await
+    }
+}
+
+/// The email address to tell about a claim or void of what the wallet sent,
+/// or None if the wallet is not one of our users or they turned the notifications off.
+pub async fn sender_to_notify(sender: Address) -> Result<Option<String>> {
+    find_sender_to_notify(&StoredSenders, sender).await
+}
+
+async fn find_sender_to_notify(
+    senders: &dyn SenderDirectory,
+    sender: Address,
+) -> Result<Option<String>> {
+    let sender_wallet_addr = format!("0x{}", hex::encode(sender.as_bytes()));
+    let Ok(email_addr) = senders.email_of_wallet(&sender_wallet_addr).await else {
+        return Ok(None);
+    };
+    if !senders.sender_notifications(&email_addr).await? {
+        return Ok(None);
+    }
+    Ok(Some(email_addr))
+}
+
+/// Tells the sender what became of their transfer, unless they turned it off.
+pub async fn notify_sender(sender: Address, message: String, tx_hash: &str) -> Result<()> {
+    let Some(email_addr) = sender_to_notify(sender).await? else {
+        return Ok(());
+    };
+    let opt_out_subject = format!("{} off", NOTIFICATIONS_COMMAND);
+    let body_plain = format!(
+        "Hi {}!\n{}\nCheck the transaction on etherscan: {}/tx/{}.\nYou can stop these emails by sending an email with the subject \"{}\".",
+        email_addr,
+        message,
+        chain_rpc_explorer(),
+        tx_hash,
+        opt_out_subject
+    );
+    let render_data = serde_json::json!({"userEmailAddr": email_addr, "message": message, "optOutSubject": opt_out_subject, "transactionHash": tx_hash, "chainRPCExplorer": chain_rpc_explorer()});
+    let body_html = render_html("transfer_settled.html", render_data).await?;
+    let email = EmailMessage {
+        to: email_addr,
+        subject: "Email Wallet Notification. Your transfer is settled".to_string(),
+        body_plain,
+        body_html,
+        reference: None,
+        reply_to: None,
+        body_attachments: None,
+    };
+    send_email(email).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: Address = H160([0xaa; 20]);
+    const BOB: Address = H160([0xbb; 20]);
+    const CAROL: Address = H160([0xcc; 20]);
+    const STRANGER: Address = H160([0x11; 20]);
+
+    /// Alice kept the notifications on, Bob turned them off and Carol's preference cannot be read.
+    struct FixedSenders;
+
+    #[async_trait]
+    impl SenderDirectory for FixedSenders {
+        async fn email_of_wallet(&self, wallet_addr: &str) -> Result<String> {
+            [(ALICE, "alice"), (BOB, "bob"), (CAROL, "carol")]
+                .iter()
+                .find(|(addr, _)| format!("{:?}", addr) == wallet_addr)
+                .map(|(_, name)| format!("{}@example.com", name))
+                .ok_or(anyhow!("no user with wallet {}", wallet_addr))
+        }
+
+        async fn sender_notifications(&self, email_addr: &str) -> Result<bool> {
+            match email_addr {
+                "alice@example.com" => Ok(true),
+                "bob@example.com" => Ok(false),
+                _ => bail!("database is down"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn notifies_only_users_who_did_not_opt_out() {
+        assert_eq!(
+            find_sender_to_notify(&FixedSenders, ALICE).await.unwrap(),
+            Some("alice@example.com".to_string())
+        );
+        assert_eq!(
+            find_sender_to_notify(&FixedSenders, BOB).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            find_sender_to_notify(&FixedSenders, STRANGER)
+                .await
+                .unwrap(),
+            None
+        );
+        assert!(find_sender_to_notify(&FixedSenders, CAROL).await.is_err());
+    }
+}
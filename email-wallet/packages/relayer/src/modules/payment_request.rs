@@ -0,0 +1,168 @@
+use crate::*;
+
+use regex::Regex;
+
+pub const PAYMENT_REQUEST_PENDING: &str = "pending";
+pub const PAYMENT_REQUEST_PAID: &str = "paid";
+pub const PAYMENT_REQUEST_REMINDER_INTERVAL: i64 = 3 * 24 * 60 * 60;
+pub const PAYMENT_REQUEST_MAX_REMINDERS: i32 = 3;
+
+/// A request for money sent by email. The payer gets the `Send` subject that pays it back to the requester.
+#[derive(Debug, Clone)]
+pub struct PaymentRequest {
+    pub id: i64,
+    pub requester_email: String,
+    pub payer_email: String,
+    /// Amount and token name, e.g. `10 USDC`.
+    pub asset: String,
+    /// Canonical `Send` subject the payer replies with.
+    pub subject: String,
+    pub status: String,
+    pub reminders_sent: i32,
+    pub tx_hash: Option<String>,
+}
+
+/// The subject the payer replies with. The `[request <id>]` tag before the command tells which request the `Send` pays,
+/// and is skipped by the proof as part of the subject prefix.
+pub fn payment_request_reply_subject(request: &PaymentRequest) -> String {
+    format!(
+        "{}[request {}] {}",
+        chain_subject_prefix(),
+        request.id,
+        request.subject
+    )
+}
+
+pub fn parse_payment_request_tag(subject: &str) -> Option<i64> {
+    Regex::new(r"\[request ([0-9]+)\]")
+        .unwrap()
+        .captures(subject)?[1]
+        .parse()
+        .ok()
+}
+
+pub fn payment_request_subject(amount: &str, token_name: &str, payer_email: &str) -> String {
+    format!(
+        "{} {} {} from {}",
+        REQUEST_COMMAND, amount, token_name, payer_email
+    )
+}
+
+/// Stores the request of a `Request <amount> <token> from <email>` subject.
+pub async fn create_payment_request(
+    requester_email: &str,
+    subject: &str,
+) -> Result<PaymentRequest> {
+    let vals = extract_template_vals_request(subject)?;
+    let [TemplateValue::TokenAmount { token_name, amount }, payer_val] = vals.as_slice() else {
+        bail!(WRONG_SUBJECT_FORMAT);
+    };
+    let TemplateValue::Recipient {
+        email_addr: Some(payer_email),
+        ..
+    } = payer_val
+    else {
+        bail!("Money can only be requested from an email address");
+    };
+    if payer_email.eq_ignore_ascii_case(requester_email) {
+        bail!("You cannot request money from yourself");
+    }
+    let decimals = CLIENT.query_decimals_of_erc20(token_name).await?;
+    if TemplateValue::amount_to_uint(amount, decimals)?.is_zero() {
+        bail!("The amount must be greater than zero");
+    }
+    let mut request = PaymentRequest {
+        id: 0,
+        requester_email: requester_email.to_string(),
+        payer_email: payer_email.clone(),
+        asset: format!("{} {}", amount, token_name),
+        subject: format!(
+            "{} {} {} to {}",
+            SEND_COMMAND, amount, token_name, requester_email
+        ),
+        status: PAYMENT_REQUEST_PENDING.to_string(),
+        reminders_sent: 0,
+        tx_hash: None,
+    };
+    request.id = DB.insert_payment_request(&request, now()).await?;
+    Ok(request)
+}
+
+/// Marks the pending request whose tag is in the subject of a handled `Send`, and notifies the requester.
+#[named]
+pub async fn record_payment_request_paid(
+    payer_email: &str,
+    subject: &str,
+    tx_hash: &str,
+) -> Result<()> {
+    let Some(id) = parse_payment_request_tag(subject) else {
+        return Ok(());
+    };
+    let Some(mut request) = DB.get_payment_request(id).await? else {
+        return Ok(());
+    };
+    // A subject edited after the tag pays something else than the request.
+    if !request.payer_email.eq_ignore_ascii_case(payer_email)
+        || request.status != PAYMENT_REQUEST_PENDING
+        || !subject.trim().ends_with(&request.subject)
+    {
+        return Ok(());
+    }
+    if !DB.mark_payment_request_paid(request.id, tx_hash).await? {
+        return Ok(());
+    }
+    info!(LOG, "payment request {} paid in {}", request.id, tx_hash; "func" => function_name!());
+    request.status = PAYMENT_REQUEST_PAID.to_string();
+    request.tx_hash = Some(tx_hash.to_string());
+    handle_email_event(EmailWalletEvent::PaymentRequestPaid { request }).await
+}
+
+/// Emails the payers of requests that are still unpaid after the reminder interval.
+#[named]
+pub async fn send_payment_request_reminders(now: i64) -> Result<()> {
+    let requests = DB
+        .get_payment_requests_to_remind(
+            now - PAYMENT_REQUEST_REMINDER_INTERVAL,
+            PAYMENT_REQUEST_MAX_REMINDERS,
+        )
+        .await?;
+    for mut request in requests {
+        DB.update_payment_request_reminded(request.id, now).await?;
+        request.reminders_sent += 1;
+        info!(LOG, "reminding payment request {}", request.id; "func" => function_name!());
+        if let Err(e) = handle_email_event(EmailWalletEvent::PaymentRequested {
+            request,
+            is_reminder: true,
+        })
+        .await
+        {
+            error!(LOG, "Error handling email event: {}", e; "func" => function_name!());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_payment_request_tags() {
+        assert_eq!(
+            parse_payment_request_tag("Re: [request 42] Send 10 USDC to bob@example.com"),
+            Some(42)
+        );
+        assert_eq!(
+            parse_payment_request_tag("[sepolia] [request 7] Send 10 USDC to bob@example.com"),
+            Some(7)
+        );
+        assert_eq!(
+            parse_payment_request_tag("Send 10 USDC to bob@example.com"),
+            None
+        );
+        assert_eq!(
+            parse_payment_request_tag("[request abc] Send 10 USDC to bob@example.com"),
+            None
+        );
+    }
+}
@@ -0,0 +1,476 @@
+use crate::*;
+
+use async_trait::async_trait;
+use relayer_utils::{AccountSalt, ParsedEmail};
+
+/// What an email op is going to do, known from its subject before the proof is generated.
+pub struct PreflightParams<'a> {
+    pub from_addr: &'a str,
+    pub account_salt: &'a AccountSalt,
+    pub wallet_addr: Address,
+    pub command: &'a str,
+    pub template_vals: &'a [TemplateValue],
+    pub extension_name: &'a str,
+    pub recipient_email_addr: Option<&'a str>,
+    pub recipient_eth_addr: Address,
+    /// The part of the subject before the command, which may hold a `Max fee` clause.
+    pub subject_prefix: &'a str,
+}
+
+/// The contract state the preflight checks read.
+#[async_trait]
+trait PreflightSource: Send + Sync {
+    async fn token_address(&self, token_name: &str) -> Result<Address>;
+    async fn token_decimals(&self, token_name: &str) -> Result<u8>;
+    async fn balance(&self, account_salt: &AccountSalt, token_name: &str) -> Result<U256>;
+    async fn is_dkim_key_registered(
+        &self,
+        account_salt: &AccountSalt,
+        domain: &str,
+        public_key_hash: [u8; 32],
+    ) -> Result<bool>;
+    async fn extension_address(&self, extension_name: &str) -> Result<Address>;
+    async fn extension_templates(&self, extension_addr: Address) -> Result<Vec<Vec<String>>>;
+    async fn installed_extension(
+        &self,
+        account_salt: &AccountSalt,
+        command: &str,
+    ) -> Result<Address>;
+}
+
+/// Reads the contracts of the current chain.
+struct ChainPreflightSource;
+
+#[async_trait]
+impl PreflightSource for ChainPreflightSource {
+    async fn token_address(&self, token_name: &str) -> Result<Address> {
+        CLIENT.query_erc20_address(token_name).await
+    }
+
+    async fn token_decimals(&self, token_name: &str) -> Result<u8> {
+        CLIENT.query_decimals_of_erc20(token_name).await
+    }
+
+    async fn balance(&self, account_salt: &AccountSalt, token_name: &str) -> Result<U256> {
+        CLIENT
+            .query_user_erc20_balance(account_salt, token_name)
+            .await
+    }
+
+    async fn is_dkim_key_registered(
+        &self,
+        account_salt: &AccountSalt,
+        domain: &str,
+        public_key_hash: [u8; 32],
+    ) -> Result<bool> {
+        CLIENT
+            .check_if_dkim_public_key_hash_valid_for_account(
+                account_salt,
+                domain.to_string(),
+                public_key_hash,
+            )
+            .await
+    }
+
+    async fn extension_address(&self, extension_name: &str) -> Result<Address> {
+        CLIENT.query_extension_addr_of_name(extension_name).await
+    }
+
+    async fn extension_templates(&self, extension_addr: Address) -> Result<Vec<Vec<String>>> {
+        CLIENT
+            .query_subject_templates_of_extension(extension_addr)
+            .await
+    }
+
+    async fn installed_extension(
+        &self,
+        account_salt: &AccountSalt,
+        command: &str,
+    ) -> Result<Address> {
+        CLIENT
+            .query_user_extension_for_command(account_salt, command)
+            .await
+    }
+}
+
+/// Checks everything the core contract would reject an email op for that can be known before proving,
+/// so that the user gets a precise error without waiting for a proof. Returns the fee the op is paid with.
+#[named]
+pub async fn preflight_email_op(
+    parsed_email: &ParsedEmail,
+    params: &PreflightParams<'_>,
+) -> Result<FeeQuote> {
+    let mut public_key_n = parsed_email.public_key.clone();
+    public_key_n.reverse();
+    let public_key_hash = fr_to_bytes32(&public_key_hash(&public_key_n)?)?;
+    let domain = parsed_email.get_email_domain()?;
+    let source = ChainPreflightSource;
+    check_dkim_registration(&source, params.account_salt, &domain, public_key_hash).await?;
+    let spent = check_token_amounts(&source, params).await?;
+    check_recipient(params)?;
+    check_extension(&source, params).await?;
+    let email_recipient_is_fund = params
+        .recipient_email_addr
+        .map(|_| params.command == SEND_COMMAND);
+    let gas = estimate_email_op_gas(email_recipient_is_fund).await?;
+    let spent: Vec<(&str, U256)> = spent
+        .iter()
+        .map(|(token_name, amount)| (token_name.as_str(), *amount))
+        .collect();
+    let quote = select_fee_token(params.account_salt, params.from_addr, gas, &spent).await?;
+    check_fee_cap(params.from_addr, params.subject_prefix, quote.fee_in_eth).await?;
+    info!(LOG, "preflight passed, fee {}", quote.describe(); "func" => function_name!());
+    Ok(quote)
+}
+
+/// The core contract checks the DKIM key against the registry of the account, which the `DKIM` command may have changed.
+async fn check_dkim_registration(
+    source: &dyn PreflightSource,
+    account_salt: &AccountSalt,
+    domain: &str,
+    public_key_hash: [u8; 32],
+) -> Result<()> {
+    if !source
+        .is_dkim_key_registered(account_salt, domain, public_key_hash)
+        .await?
+    {
+        bail!(
+            "The DKIM public key of {} is not registered in the DKIM registry of your wallet",
+            domain
+        );
+    }
+    Ok(())
+}
+
+/// Checks that every token in the subject is registered and that the wallet holds the amounts.
+/// Returns the token and amount a `Send` takes out of the wallet.
+async fn check_token_amounts(
+    source: &dyn PreflightSource,
+    params: &PreflightParams<'_>,
+) -> Result<Option<(String, U256)>> {
+    let mut spent = None;
+    for val in params.template_vals.iter() {
+        let TemplateValue::TokenAmount { token_name, amount } = val else {
+            continue;
+        };
+        if source.token_address(token_name).await? == Address::zero() {
+            bail!("{} is not a token supported by Email Wallet", token_name);
+        }
+        let decimals = source.token_decimals(token_name).await?;
+        let amount_uint = TemplateValue::amount_to_uint(amount, decimals)?;
+        if amount_uint.is_zero() {
+            bail!("The amount of {} must be more than zero", token_name);
+        }
+        let balance = source.balance(params.account_salt, token_name).await?;
+        if balance < amount_uint {
+            bail!(
+                "{}: you asked for {} {} but your wallet holds {} {}",
+                INSUFFICIENT_BALANCE,
+                amount,
+                token_name,
+                uint_to_decimal_string(balance, decimals as usize),
+                token_name
+            );
+        }
+        if params.command == SEND_COMMAND {
+            spent = Some((token_name.clone(), amount_uint));
+        }
+    }
+    Ok(spent)
+}
+
+fn check_recipient(params: &PreflightParams<'_>) -> Result<()> {
+    if params.recipient_email_addr.is_some() {
+        return Ok(());
+    }
+    if params.command == SEND_COMMAND {
+        if params.recipient_eth_addr == Address::zero() {
+            bail!("The recipient address cannot be the zero address");
+        }
+        if params.recipient_eth_addr == params.wallet_addr {
+            bail!("You cannot send to your own wallet address");
+        }
+    }
+    Ok(())
+}
+
+async fn check_extension(source: &dyn PreflightSource, params: &PreflightParams<'_>) -> Result<()> {
+    if params.command != INSTALL_COMMAND && params.command != UNINSTALL_COMMAND {
+        return Ok(());
+    }
+    let extension_addr = source.extension_address(params.extension_name).await?;
+    if extension_addr == Address::zero() {
+        bail!("There is no extension named {}", params.extension_name);
+    }
+    let templates = source.extension_templates(extension_addr).await?;
+    let command = templates
+        .first()
+        .and_then(|template| template.first())
+        .ok_or(anyhow!(
+            "The extension {} has no subject template",
+            params.extension_name
+        ))?;
+    let installed = source
+        .installed_extension(params.account_salt, command)
+        .await?
+        == extension_addr;
+    if params.command == INSTALL_COMMAND && installed {
+        bail!(
+            "The extension {} is already installed",
+            params.extension_name
+        );
+    }
+    if params.command == UNINSTALL_COMMAND && !installed {
+        bail!("The extension {} is not installed", params.extension_name);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USDC: Address = H160([0x11; 20]);
+    const UNISWAP: Address = H160([0x22; 20]);
+    const WALLET: Address = H160([0x33; 20]);
+    const ALICE: Address = H160([0x44; 20]);
+
+    /// A wallet holding 20 USDC, with the Uniswap extension installed for `Swap` if `swap_installed`.
+    struct FixedSource {
+        swap_installed: bool,
+    }
+
+    #[async_trait]
+    impl PreflightSource for FixedSource {
+        async fn token_address(&self, token_name: &str) -> Result<Address> {
+            Ok(match token_name {
+                "USDC" => USDC,
+                _ => Address::zero(),
+            })
+        }
+
+        async fn token_decimals(&self, token_name: &str) -> Result<u8> {
+            match token_name {
+                "USDC" => Ok(6),
+                _ => bail!("unknown token {}", token_name),
+            }
+        }
+
+        async fn balance(&self, _: &AccountSalt, token_name: &str) -> Result<U256> {
+            match token_name {
+                "USDC" => Ok(U256::from(20_000_000)),
+                _ => bail!("unknown token {}", token_name),
+            }
+        }
+
+        async fn is_dkim_key_registered(
+            &self,
+            _: &AccountSalt,
+            domain: &str,
+            public_key_hash: [u8; 32],
+        ) -> Result<bool> {
+            Ok(domain == "gmail.com" && public_key_hash == [1; 32])
+        }
+
+        async fn extension_address(&self, extension_name: &str) -> Result<Address> {
+            Ok(match extension_name {
+                "Uniswap" => UNISWAP,
+                _ => Address::zero(),
+            })
+        }
+
+        async fn extension_templates(&self, extension_addr: Address) -> Result<Vec<Vec<String>>> {
+            assert_eq!(extension_addr, UNISWAP);
+            Ok(vec![vec![
+                "Swap".to_string(),
+                "{tokenAmount}".to_string(),
+                "to".to_string(),
+                "{string}".to_string(),
+            ]])
+        }
+
+        async fn installed_extension(&self, _: &AccountSalt, command: &str) -> Result<Address> {
+            Ok(match command {
+                "Swap" if self.swap_installed => UNISWAP,
+                _ => Address::zero(),
+            })
+        }
+    }
+
+    fn params<'a>(
+        account_salt: &'a AccountSalt,
+        command: &'a str,
+        template_vals: &'a [TemplateValue],
+    ) -> PreflightParams<'a> {
+        PreflightParams {
+            from_addr: "alice@example.com",
+            account_salt,
+            wallet_addr: WALLET,
+            command,
+            template_vals,
+            extension_name: "",
+            recipient_email_addr: None,
+            recipient_eth_addr: ALICE,
+            subject_prefix: "",
+        }
+    }
+
+    fn token_amount(amount: &str, token_name: &str) -> TemplateValue {
+        TemplateValue::TokenAmount {
+            token_name: token_name.to_string(),
+            amount: amount.to_string(),
+        }
+    }
+
+    fn assert_outcome<T: std::fmt::Debug>(case: &str, result: Result<T>, error: Option<&str>) {
+        match (result, error) {
+            (Ok(_), None) => {}
+            (Err(e), Some(error)) => assert!(
+                e.to_string().contains(error),
+                "{}: expected an error containing `{}`, got `{}`",
+                case,
+                error,
+                e
+            ),
+            (result, _) => panic!("{}: unexpected result {:?}", case, result),
+        }
+    }
+
+    #[test]
+    fn checks_the_recipient() {
+        let account_salt = AccountSalt(Fr::from(1));
+        let mut send = params(&account_salt, SEND_COMMAND, &[]);
+        assert!(check_recipient(&send).is_ok());
+        send.recipient_eth_addr = Address::zero();
+        assert!(check_recipient(&send).is_err());
+        send.recipient_email_addr = Some("bob@example.com");
+        assert!(check_recipient(&send).is_ok());
+        send.recipient_email_addr = None;
+        send.recipient_eth_addr = WALLET;
+        assert!(check_recipient(&send).is_err());
+        let mut execute = params(&account_salt, EXECUTE_COMMAND, &[]);
+        execute.recipient_eth_addr = Address::zero();
+        assert!(check_recipient(&execute).is_ok());
+    }
+
+    #[tokio::test]
+    async fn checks_token_amounts() {
+        let account_salt = AccountSalt(Fr::from(1));
+        let source = FixedSource {
+            swap_installed: false,
+        };
+        let cases = [
+            ("send", SEND_COMMAND, token_amount("10", "USDC"), None),
+            (
+                "whole balance",
+                SEND_COMMAND,
+                token_amount("20", "USDC"),
+                None,
+            ),
+            ("other command", "Swap", token_amount("10", "USDC"), None),
+            (
+                "above balance",
+                SEND_COMMAND,
+                token_amount("20.000001", "USDC"),
+                Some(INSUFFICIENT_BALANCE),
+            ),
+            (
+                "zero",
+                SEND_COMMAND,
+                token_amount("0", "USDC"),
+                Some("more than zero"),
+            ),
+            (
+                "too many decimals",
+                SEND_COMMAND,
+                token_amount("0.0000001", "USDC"),
+                Some("too many decimal places"),
+            ),
+            (
+                "unknown token",
+                SEND_COMMAND,
+                token_amount("1", "FOO"),
+                Some("not a token supported"),
+            ),
+        ];
+        for (case, command, val, error) in cases {
+            let vals = [val];
+            let result = check_token_amounts(&source, &params(&account_salt, command, &vals)).await;
+            assert_outcome(case, result, error);
+        }
+
+        let vals = [token_amount("10", "USDC")];
+        assert_eq!(
+            check_token_amounts(&source, &params(&account_salt, SEND_COMMAND, &vals))
+                .await
+                .unwrap(),
+            Some(("USDC".to_string(), U256::from(10_000_000)))
+        );
+        assert_eq!(
+            check_token_amounts(&source, &params(&account_salt, "Swap", &vals))
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn checks_extensions() {
+        let account_salt = AccountSalt(Fr::from(1));
+        let cases = [
+            ("install", INSTALL_COMMAND, "Uniswap", false, None),
+            (
+                "install twice",
+                INSTALL_COMMAND,
+                "Uniswap",
+                true,
+                Some("already installed"),
+            ),
+            ("uninstall", UNINSTALL_COMMAND, "Uniswap", true, None),
+            (
+                "uninstall twice",
+                UNINSTALL_COMMAND,
+                "Uniswap",
+                false,
+                Some("not installed"),
+            ),
+            (
+                "unknown extension",
+                INSTALL_COMMAND,
+                "Sushiswap",
+                false,
+                Some("no extension named"),
+            ),
+            ("other command", SEND_COMMAND, "Sushiswap", false, None),
+        ];
+        for (case, command, extension_name, swap_installed, error) in cases {
+            let mut params = params(&account_salt, command, &[]);
+            params.extension_name = extension_name;
+            let result = check_extension(&FixedSource { swap_installed }, &params).await;
+            assert_outcome(case, result, error);
+        }
+    }
+
+    #[tokio::test]
+    async fn checks_the_dkim_registration() {
+        let account_salt = AccountSalt(Fr::from(1));
+        let source = FixedSource {
+            swap_installed: false,
+        };
+        let cases = [
+            ("registered", "gmail.com", [1; 32], None),
+            ("other key", "gmail.com", [2; 32], Some("is not registered")),
+            (
+                "other domain",
+                "example.com",
+                [1; 32],
+                Some("is not registered"),
+            ),
+        ];
+        for (case, domain, public_key_hash, error) in cases {
+            let result =
+                check_dkim_registration(&source, &account_salt, domain, public_key_hash).await;
+            assert_outcome(case, result, error);
+        }
+    }
+}
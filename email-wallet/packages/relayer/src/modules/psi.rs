@@ -1,13 +1,20 @@
 use crate::*;
 
 use std::path::Path;
+use std::str::FromStr;
 
+use async_trait::async_trait;
 use axum::Json;
 use ff::Field;
+use futures::future::join_all;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use tokio::fs::{read_to_string, remove_file};
 
 const DELAY: u64 = 300;
+/// A peer relayer that does not answer within this many seconds is skipped.
+const PSI_PEER_TIMEOUT: u64 = 10;
+/// Signed PSI requests older than this many seconds are rejected so that they cannot be replayed later.
+const PSI_REQUEST_MAX_AGE: i64 = 300;
 
 pub enum UnclaimType {
     Fund(UnclaimedFund),
@@ -36,6 +43,89 @@ pub struct Point {
     pub y: String,
 }
 
+/// A PSI request signed by the private key of the relayer that sends it.
+/// Peers only serve relayers registered in the relayer handler.
+#[derive(Serialize, Deserialize)]
+pub struct SignedPsiRequest {
+    pub relayer: Address,
+    pub timestamp: i64,
+    /// The JSON of the request, signed as is.
+    pub body: String,
+    pub signature: String,
+}
+
+fn psi_request_message(timestamp: i64, body: &str) -> String {
+    format!("EMAIL WALLET PSI {} {}", timestamp, body)
+}
+
+pub async fn sign_psi_request<T: Serialize>(request: &T) -> Result<SignedPsiRequest> {
+    let wallet: LocalWallet = PRIVATE_KEY.get().unwrap().parse()?;
+    let timestamp = now();
+    let body = serde_json::to_string(request)?;
+    let signature = wallet
+        .sign_message(psi_request_message(timestamp, &body))
+        .await?;
+    Ok(SignedPsiRequest {
+        relayer: wallet.address(),
+        timestamp,
+        body,
+        signature: signature.to_string(),
+    })
+}
+
+/// Checks that the request is recent and signed by a registered relayer, and returns the request.
+pub async fn verify_psi_request<T: DeserializeOwned>(request: SignedPsiRequest) -> Result<T> {
+    if (now() - request.timestamp).abs() > PSI_REQUEST_MAX_AGE {
+        bail!("PSI request is too old");
+    }
+    let signature = Signature::from_str(&request.signature)?;
+    signature.verify(
+        psi_request_message(request.timestamp, &request.body),
+        request.relayer,
+    )?;
+    if !get_psi_relayers()
+        .await?
+        .iter()
+        .any(|(addr, _)| *addr == request.relayer)
+    {
+        bail!("{:?} is not a registered relayer", request.relayer);
+    }
+    Ok(serde_json::from_str(&request.body)?)
+}
+
+/// Relayers that take part in PSI: PSI_PEERS if it is set, otherwise the relayers registered in the subgraph.
+pub async fn get_psi_relayers() -> Result<Vec<(Address, String)>> {
+    match PSI_PEERS.get().unwrap() {
+        Some(peers) => Ok(peers.clone()),
+        None => SubgraphClient::new().get_all_relayers_for_psi().await,
+    }
+}
+
+/// Parses PSI_PEERS, a comma separated list of `<relayer address>@<hostname or URL>`.
+pub fn parse_psi_peers(peers: &str) -> Result<Vec<(Address, String)>> {
+    peers
+        .split(',')
+        .map(str::trim)
+        .filter(|peer| !peer.is_empty())
+        .map(|peer| {
+            let (addr, host) = peer
+                .split_once('@')
+                .ok_or(anyhow!("PSI peer {} must be <address>@<host>", peer))?;
+            Ok((Address::from_str(addr)?, host.to_string()))
+        })
+        .collect()
+}
+
+// Registered hostnames have no scheme; PSI_PEERS may point at a local relayer over http.
+fn psi_api_url(host: &str, path: &str) -> String {
+    let base = if host.starts_with("http://") || host.starts_with("https://") {
+        host.trim_end_matches('/').to_string()
+    } else {
+        format!("https://{}", host)
+    };
+    format!("{}/api/{}", base, path)
+}
+
 pub struct PSIClient {
     pub tx_hash: String,
     pub point: Point,
@@ -43,6 +133,7 @@ pub struct PSIClient {
     pub email_addr: String,
     pub id: U256,
     pub is_fund: bool,
+    http: reqwest::Client,
 }
 
 impl PSIClient {
@@ -60,9 +151,14 @@ impl PSIClient {
             is_fund,
             random,
             point,
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(PSI_PEER_TIMEOUT))
+                .build()?,
         })
     }
 
+    /// Runs PSI with the other relayers and reveals the claim to the one that owns the recipient's account, if any.
+    /// Returns whether this relayer has to invite the recipient itself.
     pub async fn check_and_reveal(&self) -> Result<bool> {
         if let Some(account_code) = DB.get_account_code(&self.email_addr).await? {
             if CLIENT
@@ -72,104 +168,179 @@ impl PSIClient {
                 return Ok(false);
             }
         }
-        let (created_relayers, inited_relayers) = self.find().await?;
-        if !inited_relayers.is_empty() {
-            self.reveal(inited_relayers).await?;
-            Ok(false)
-        } else {
-            self.reveal(created_relayers).await?;
-            Ok(true)
-        }
-    }
-
-    pub async fn check(&self, address: &str) -> Result<(bool, bool)> {
-        let client = reqwest::Client::new();
-        let res = client
-            .post(format!("{}/serveCheck/", address))
-            .json(&CheckRequest {
+        run_psi(
+            &HttpPsiNetwork {
+                http: self.http.clone(),
+            },
+            &CheckRequest {
                 point: self.point.clone(),
                 id: self.id,
                 is_fund: self.is_fund,
-            })
-            .send()
-            .await?
-            .error_for_status()?;
-
-        let response_point = res.json::<Point>().await?;
-
-        let result_point = psi_step3(
-            CIRCUITS_DIR_PATH.get().unwrap(),
-            response_point,
+            },
             &self.random,
+            &RevealRequest {
+                tx_hash: self.tx_hash.clone(),
+                id: self.id,
+                email_address: self.email_addr.clone(),
+                randomness: self.random.clone(),
+                is_fund: self.is_fund,
+            },
         )
-        .await?;
+        .await
+    }
+}
 
-        let is_point_registered = CLIENT
-            .check_if_point_registered(result_point.clone())
-            .await?;
-        let is_account_created = CLIENT
-            .check_if_account_created_by_point(result_point)
-            .await?;
+/// What the PSI check with a peer tells about the recipient.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PsiPeerStatus {
+    /// The peer does not serve the recipient.
+    Unknown,
+    /// The peer created the recipient's account, which is not initialized yet.
+    Created,
+    /// The recipient's account at the peer is initialized.
+    Initialized,
+}
 
-        Ok((is_point_registered, is_account_created))
-    }
+/// The other relayers and the chain as a PSI round sees them. `HttpPsiNetwork` is the real one.
+#[async_trait]
+pub trait PsiNetwork {
+    /// The other relayers taking part in PSI, as `(address, host)`.
+    async fn peers(&self) -> Result<Vec<(Address, String)>>;
+    /// Sends the blinded point of the recipient to a peer, which multiplies it by its relayer rand.
+    async fn check(&self, host: &str, request: &CheckRequest) -> Result<Point>;
+    /// Looks up the PSI point of the recipient under a peer on chain.
+    async fn point_status(&self, point: Point) -> Result<PsiPeerStatus>;
+    async fn reveal(&self, host: &str, request: &RevealRequest) -> Result<()>;
+}
+
+/// The peer that owns the recipient's account. A PSI point is registered by the relayer that created the account,
+/// so only its owner sees the point as registered. An initialized account wins over one that was only created.
+pub fn psi_owner(statuses: &[(String, PsiPeerStatus)]) -> Option<&str> {
+    statuses
+        .iter()
+        .filter(|(_, status)| *status != PsiPeerStatus::Unknown)
+        // The first of the peers with the highest status.
+        .fold(
+            None,
+            |owner: Option<&(String, PsiPeerStatus)>, peer| match owner {
+                Some(owner) if owner.1 >= peer.1 => Some(owner),
+                _ => Some(peer),
+            },
+        )
+        .map(|(host, _)| host.as_str())
+}
 
-    pub async fn find<'a>(&self) -> Result<(Vec<String>, Vec<String>)> {
-        let subgraph_client = SubgraphClient::new();
-        let relayers = subgraph_client.get_all_relayers_for_psi().await?;
-        let mut created_hosts = vec![];
-        let mut inited_hosts = vec![];
-
-        for relayer in relayers {
-            let (is_point_registered, is_account_created) = self.check(&relayer.1).await?;
-            if is_account_created {
-                inited_hosts.push(relayer.1.to_string());
-            } else if is_point_registered {
-                created_hosts.push(relayer.1.to_string());
+/// One PSI round for a claim. Whether this relayer has to create the recipient's account is decided from the checks
+/// alone, and the email address and randomness of the claim are revealed only to the owner of the account.
+/// Returns whether this relayer has to invite the recipient itself.
+#[named]
+pub async fn run_psi<N: PsiNetwork + Sync>(
+    network: &N,
+    check: &CheckRequest,
+    client_rand: &str,
+    reveal: &RevealRequest,
+) -> Result<bool> {
+    let peers = network.peers().await?;
+    let results = join_all(peers.iter().map(|(_, host)| async move {
+        let point = psi_step3(network.check(host, check).await?, client_rand)?;
+        network.point_status(point).await
+    }))
+    .await;
+    let mut statuses = vec![];
+    for ((_, host), result) in peers.into_iter().zip(results) {
+        match result {
+            Ok(status) => statuses.push((host, status)),
+            Err(e) => {
+                warn!(LOG, "PSI check with {} failed: {}", host, e; "func" => function_name!())
             }
         }
+    }
+    let Some(owner) = psi_owner(&statuses) else {
+        return Ok(true);
+    };
+    match network.reveal(owner, reveal).await {
+        Ok(()) => Ok(false),
+        Err(e) => {
+            // The owner is unreachable, so the recipient is invited to an account at this relayer instead.
+            warn!(LOG, "PSI reveal of claim {} to {} failed: {}", reveal.id, owner, e; "func" => function_name!());
+            Ok(true)
+        }
+    }
+}
+
+/// PSI with the relayers of PSI_PEERS or the subgraph over their APIs, looking points up on chain.
+pub struct HttpPsiNetwork {
+    http: reqwest::Client,
+}
 
-        Ok((created_hosts, inited_hosts))
+#[async_trait]
+impl PsiNetwork for HttpPsiNetwork {
+    async fn peers(&self) -> Result<Vec<(Address, String)>> {
+        let wallet: LocalWallet = PRIVATE_KEY.get().unwrap().parse()?;
+        Ok(get_psi_relayers()
+            .await?
+            .into_iter()
+            .filter(|(addr, _)| *addr != wallet.address())
+            .collect())
     }
 
-    pub async fn reveal(&self, addresses: Vec<String>) -> Result<()> {
-        let client = reqwest::Client::new();
-        for address in addresses.into_iter() {
-            let res = client
-                .post(format!("{}/serveReveal/", address))
-                .json(&RevealRequest {
-                    tx_hash: self.tx_hash.clone(),
-                    id: self.id,
-                    email_address: self.email_addr.clone(),
-                    randomness: self.random.clone(),
-                    is_fund: self.is_fund,
-                })
-                .send()
-                .await?
-                .error_for_status()?;
+    async fn check(&self, host: &str, request: &CheckRequest) -> Result<Point> {
+        Ok(self
+            .http
+            .post(psi_api_url(host, "serveCheck/"))
+            .json(&sign_psi_request(request).await?)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Point>()
+            .await?)
+    }
+
+    async fn point_status(&self, point: Point) -> Result<PsiPeerStatus> {
+        if CLIENT
+            .check_if_account_created_by_point(point.clone())
+            .await?
+        {
+            Ok(PsiPeerStatus::Initialized)
+        } else if CLIENT.check_if_point_registered(point).await? {
+            Ok(PsiPeerStatus::Created)
+        } else {
+            Ok(PsiPeerStatus::Unknown)
         }
+    }
 
+    async fn reveal(&self, host: &str, request: &RevealRequest) -> Result<()> {
+        self.http
+            .post(psi_api_url(host, "serveReveal/"))
+            .json(&sign_psi_request(request).await?)
+            .send()
+            .await?
+            .error_for_status()?;
         Ok(())
     }
 }
 
-pub async fn serve_check_request(payload: CheckRequest) -> Result<Json<Point>> {
+pub async fn serve_check_request(payload: SignedPsiRequest) -> Result<Json<Point>> {
+    let payload: CheckRequest = verify_psi_request(payload).await?;
     check_unclaim_valid(&payload.id, payload.is_fund).await?;
 
-    let res = psi_step2(
-        CIRCUITS_DIR_PATH.get().unwrap(),
-        payload.point,
-        RELAYER_RAND.get().unwrap(),
-    )
-    .await?;
+    let res = psi_step2(payload.point, RELAYER_RAND.get().unwrap())?;
 
     Ok(axum::response::Json(res))
 }
 
-pub async fn serve_reveal_request(payload: RevealRequest) -> Result<String> {
+pub async fn serve_reveal_request(payload: SignedPsiRequest) -> Result<String> {
+    let payload: RevealRequest = verify_psi_request(payload).await?;
+    if !DB.contains_user(&payload.email_address).await? {
+        bail!("The revealed email address is not served by this relayer");
+    }
+    let commit = PaddedEmailAddr::from_email_addr(&payload.email_address)
+        .to_commitment(&hex2field(&payload.randomness)?)?;
     match check_unclaim_valid(&payload.id, payload.is_fund).await? {
         UnclaimType::Fund(unclaimed_fund) => {
-            // TODO: local check of recipient_commit = hash(random, email_addr)
+            if field2hex(&bytes32_to_fr(&unclaimed_fund.email_addr_commit)?) != field2hex(&commit) {
+                bail!("The revealed email address does not match the unclaimed fund");
+            }
             claim_unclaims(Claim {
                 tx_hash: payload.tx_hash,
                 id: payload.id,
@@ -179,7 +350,9 @@ pub async fn serve_reveal_request(payload: RevealRequest) -> Result<String> {
                 expiry_time: unclaimed_fund.expiry_time.as_u64() as i64,
                 is_fund: true,
                 is_announced: false,
-                is_seen: false,
+                status: ClaimStatus::Registered,
+                attempts: 0,
+                sender: unclaimed_fund.sender,
             })
             .await?;
             Ok(format!(
@@ -188,7 +361,10 @@ pub async fn serve_reveal_request(payload: RevealRequest) -> Result<String> {
             ))
         }
         UnclaimType::State(unclaimed_state) => {
-            // TODO: local check of recipient_commit = hash(random, email_addr)
+            if field2hex(&bytes32_to_fr(&unclaimed_state.email_addr_commit)?) != field2hex(&commit)
+            {
+                bail!("The revealed email address does not match the unclaimed state");
+            }
             claim_unclaims(Claim {
                 tx_hash: payload.tx_hash,
                 id: payload.id,
@@ -198,7 +374,9 @@ pub async fn serve_reveal_request(payload: RevealRequest) -> Result<String> {
                 expiry_time: unclaimed_state.expiry_time.as_u64() as i64,
                 is_fund: false,
                 is_announced: false,
-                is_seen: false,
+                status: ClaimStatus::Registered,
+                attempts: 0,
+                sender: unclaimed_state.sender,
             })
             .await?;
             Ok(format!(
@@ -237,110 +415,210 @@ pub async fn psi_step1(
     compute_psi_point(circuits_dir_path, email_addr, client_rand).await
 }
 
-pub async fn psi_step2(
-    circuits_dir_path: &Path,
-    point: Point,
-    relayer_rand: &str,
-) -> Result<Point> {
-    let input_file_name = calculate_default_hash(&point.x);
-    let input_file_path = PathBuf::new()
-        .join(INPUT_FILES_DIR.get().unwrap())
-        .join(input_file_name + ".json");
-
-    let command_str = format!(
-        "--cwd {} psi-step2 --x {} --y {} --relayer-rand {} --output {}",
-        circuits_dir_path.to_str().unwrap(),
-        point.x,
-        point.y,
-        relayer_rand,
-        input_file_path.to_str().unwrap()
-    );
+pub fn psi_step2(point: Point, relayer_rand: &str) -> Result<Point> {
+    point_scalar_mul(&point, &parse_grumpkin_scalar(relayer_rand)?)
+}
 
-    let mut proc = tokio::process::Command::new("yarn")
-        .args(command_str.split_whitespace())
-        .spawn()
-        .map_err(|e| anyhow::anyhow!("Failed to spawn psi-step2 process: {}", e))?;
+pub fn psi_step3(point: Point, client_rand: &str) -> Result<Point> {
+    let inv_client_rand = invert_grumpkin_scalar(&parse_grumpkin_scalar(client_rand)?)?;
+    point_scalar_mul(&point, &inv_client_rand)
+}
 
-    let status = proc
-        .wait()
-        .await
-        .map_err(|e| anyhow::anyhow!("Failed to wait for psi-step2 process: {}", e))?;
-    assert!(status.success());
-
-    let result = read_to_string(&input_file_path).await.map_err(|e| {
-        anyhow::anyhow!(
-            "Failed to read PSI result from file '{}': {}",
-            input_file_path.display(),
-            e
-        )
-    })?;
-    remove_file(&input_file_path).await.map_err(|e| {
-        anyhow::anyhow!(
-            "Failed to remove temporary file '{}': {}",
-            input_file_path.display(),
-            e
-        )
-    })?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let point: Point = serde_json::from_str(&result).map_err(|e| {
-        anyhow::anyhow!(
-            "Failed to parse PSI result from file '{}': {}",
-            input_file_path.display(),
-            e
+    use ethers::utils::keccak256;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    const GENERATOR: (&str, &str) = (
+        "1",
+        "17631683881184975370165255887551781615748388533673675138860",
+    );
+
+    // Stands in for hash-to-curve, which only has to be the same for every relayer.
+    fn hashed_email(email_addr: &str) -> Point {
+        point_scalar_mul(
+            &Point {
+                x: GENERATOR.0.to_string(),
+                y: GENERATOR.1.to_string(),
+            },
+            &parse_grumpkin_scalar(&format!("0x{}", hex::encode(keccak256(email_addr)))).unwrap(),
         )
-    })?;
+        .unwrap()
+    }
 
-    Ok(point)
-}
+    struct InProcessRelayer {
+        host: String,
+        rand: String,
+        reveals: Mutex<Vec<String>>,
+        is_down: bool,
+    }
 
-pub async fn psi_step3(circuits_dir_path: &Path, point: Point, client_rand: &str) -> Result<Point> {
-    let input_file_name = calculate_default_hash(&point.x);
-    let input_file_path = PathBuf::new()
-        .join(INPUT_FILES_DIR.get().unwrap())
-        .join(input_file_name + ".json");
-
-    let command_str = format!(
-        "--cwd {} psi-step3 --x {} --y {} --client-rand {} --output {}",
-        circuits_dir_path.to_str().unwrap(),
-        point.x,
-        point.y,
-        client_rand,
-        input_file_path.to_str().unwrap()
-    );
+    /// Two relayers running in this process, a subgraph stand-in listing them and a chain stand-in with the PSI points.
+    struct InProcessNetwork {
+        relayers: Vec<InProcessRelayer>,
+        subgraph: Vec<(Address, String)>,
+        chain: HashMap<(String, String), PsiPeerStatus>,
+    }
 
-    let mut proc = tokio::process::Command::new("yarn")
-        .args(command_str.split_whitespace())
-        .spawn()
-        .map_err(|e| anyhow::anyhow!("Failed to spawn psi-step3 process: {}", e))?;
+    impl InProcessNetwork {
+        fn new() -> Self {
+            let relayers: Vec<InProcessRelayer> = ["relayer-a.test", "relayer-b.test"]
+                .iter()
+                .map(|host| InProcessRelayer {
+                    host: host.to_string(),
+                    rand: format!("0x{}", hex::encode(keccak256(host.as_bytes()))),
+                    reveals: Mutex::new(vec![]),
+                    is_down: false,
+                })
+                .collect();
+            let mut subgraph: Vec<(Address, String)> = relayers
+                .iter()
+                .enumerate()
+                .map(|(i, relayer)| (Address::from_low_u64_be(i as u64 + 1), relayer.host.clone()))
+                .collect();
+            // Registered in the subgraph but not running.
+            subgraph.push((Address::from_low_u64_be(9), "relayer-gone.test".to_string()));
+            Self {
+                relayers,
+                subgraph,
+                chain: HashMap::new(),
+            }
+        }
 
-    let status = proc
-        .wait()
-        .await
-        .map_err(|e| anyhow::anyhow!("Failed to wait for psi-step3 process: {}", e))?;
-    assert!(status.success());
-
-    let result = read_to_string(&input_file_path).await.map_err(|e| {
-        anyhow::anyhow!(
-            "Failed to read PSI result from file '{}': {}",
-            input_file_path.display(),
-            e
-        )
-    })?;
-    remove_file(&input_file_path).await.map_err(|e| {
-        anyhow::anyhow!(
-            "Failed to remove temporary file '{}': {}",
-            input_file_path.display(),
-            e
-        )
-    })?;
+        fn relayer(&self, host: &str) -> Result<&InProcessRelayer> {
+            self.relayers
+                .iter()
+                .find(|relayer| relayer.host == host && !relayer.is_down)
+                .ok_or(anyhow!("{} is not reachable", host))
+        }
 
-    let point: Point = serde_json::from_str(&result).map_err(|e| {
-        anyhow::anyhow!(
-            "Failed to parse PSI result from file '{}': {}",
-            input_file_path.display(),
-            e
-        )
-    })?;
+        // What the relayer registers on chain when it creates the account of the email address.
+        fn create_account(&mut self, host: &str, email_addr: &str, status: PsiPeerStatus) {
+            let point =
+                psi_step2(hashed_email(email_addr), &self.relayer(host).unwrap().rand).unwrap();
+            self.chain.insert((point.x, point.y), status);
+        }
+
+        fn reveals(&self, host: &str) -> Vec<String> {
+            self.relayer(host).unwrap().reveals.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl PsiNetwork for InProcessNetwork {
+        async fn peers(&self) -> Result<Vec<(Address, String)>> {
+            Ok(self.subgraph.clone())
+        }
+
+        async fn check(&self, host: &str, request: &CheckRequest) -> Result<Point> {
+            psi_step2(request.point.clone(), &self.relayer(host)?.rand)
+        }
 
-    Ok(point)
+        async fn point_status(&self, point: Point) -> Result<PsiPeerStatus> {
+            Ok(*self
+                .chain
+                .get(&(point.x, point.y))
+                .unwrap_or(&PsiPeerStatus::Unknown))
+        }
+
+        async fn reveal(&self, host: &str, request: &RevealRequest) -> Result<()> {
+            self.relayer(host)?
+                .reveals
+                .lock()
+                .unwrap()
+                .push(request.email_address.clone());
+            Ok(())
+        }
+    }
+
+    async fn run(network: &InProcessNetwork, email_addr: &str) -> bool {
+        let client_rand = "0x1234567890abcdef";
+        let check = CheckRequest {
+            point: point_scalar_mul(
+                &hashed_email(email_addr),
+                &parse_grumpkin_scalar(client_rand).unwrap(),
+            )
+            .unwrap(),
+            id: U256::one(),
+            is_fund: true,
+        };
+        let reveal = RevealRequest {
+            tx_hash: "0x01".to_string(),
+            id: U256::one(),
+            is_fund: true,
+            randomness: client_rand.to_string(),
+            email_address: email_addr.to_string(),
+        };
+        run_psi(network, &check, client_rand, &reveal)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn reveals_only_to_the_relayer_of_the_account() {
+        let mut network = InProcessNetwork::new();
+        network.create_account(
+            "relayer-b.test",
+            "bob@example.com",
+            PsiPeerStatus::Initialized,
+        );
+        network.create_account(
+            "relayer-a.test",
+            "carol@example.com",
+            PsiPeerStatus::Created,
+        );
+
+        assert!(!run(&network, "bob@example.com").await);
+        assert!(network.reveals("relayer-a.test").is_empty());
+        assert_eq!(network.reveals("relayer-b.test"), vec!["bob@example.com"]);
+
+        assert!(!run(&network, "carol@example.com").await);
+        assert_eq!(network.reveals("relayer-a.test"), vec!["carol@example.com"]);
+        assert_eq!(network.reveals("relayer-b.test"), vec!["bob@example.com"]);
+    }
+
+    #[tokio::test]
+    async fn invites_an_unknown_recipient_without_revealing() {
+        let mut network = InProcessNetwork::new();
+        network.create_account(
+            "relayer-b.test",
+            "bob@example.com",
+            PsiPeerStatus::Initialized,
+        );
+
+        assert!(run(&network, "dave@example.com").await);
+        assert!(network.reveals("relayer-a.test").is_empty());
+        assert!(network.reveals("relayer-b.test").is_empty());
+    }
+
+    #[tokio::test]
+    async fn invites_the_recipient_when_the_owner_is_down() {
+        let mut network = InProcessNetwork::new();
+        network.create_account(
+            "relayer-b.test",
+            "bob@example.com",
+            PsiPeerStatus::Initialized,
+        );
+        network.relayers[1].is_down = true;
+
+        assert!(run(&network, "bob@example.com").await);
+        assert!(network.reveals("relayer-a.test").is_empty());
+    }
+
+    #[test]
+    fn prefers_the_initialized_account() {
+        let statuses = vec![
+            ("a".to_string(), PsiPeerStatus::Created),
+            ("b".to_string(), PsiPeerStatus::Unknown),
+            ("c".to_string(), PsiPeerStatus::Initialized),
+            ("d".to_string(), PsiPeerStatus::Initialized),
+        ];
+        assert_eq!(psi_owner(&statuses), Some("c"));
+        assert_eq!(psi_owner(&statuses[..2]), Some("a"));
+        assert_eq!(psi_owner(&statuses[1..2]), None);
+        assert_eq!(psi_owner(&[]), None);
+    }
 }
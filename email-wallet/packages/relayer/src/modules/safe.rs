@@ -134,7 +134,7 @@ pub async fn safe_fn() -> Result<()> {
                 let render_data = serde_json::json!({
                     "userEmailAddr": email_addr,
                     "safeTransactionHash": safe_txn_hash,
-                    "chainRpcExplorer": CHAIN_RPC_EXPLORER.get().unwrap(),
+                    "chainRpcExplorer": chain_rpc_explorer(),
                     "walletAddr": wallet_addr,
                 });
                 let body_html = render_html("safe_txn.html", render_data).await?;
@@ -0,0 +1,267 @@
+use crate::*;
+
+use chrono::{DateTime, Datelike, Weekday};
+
+pub const SCHEDULE_MONTHLY: &str = "monthly";
+pub const SCHEDULE_WEEKLY: &str = "weekly";
+pub const MAX_SCHEDULES_PER_USER: usize = 10;
+
+/// A recurring payment. The relayer cannot send without a proof from the sender,
+/// so on each due date it emails the sender a `Send` subject to confirm by reply.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    pub id: i64,
+    pub email_address: String,
+    pub wallet_addr: String,
+    /// Canonical `Send` subject of each payment.
+    pub subject: String,
+    pub frequency: String,
+    /// Day of the month (1-28) for monthly schedules, or day of the week (0 = Monday) for weekly ones.
+    pub day: i32,
+    pub next_due_at: i64,
+}
+
+impl Schedule {
+    pub fn describe(&self) -> String {
+        let when = match self.frequency.as_str() {
+            SCHEDULE_WEEKLY => format!("weekly on {}", weekday_name(self.day).unwrap_or_default()),
+            _ => format!("monthly on day {}", self.day),
+        };
+        format!(
+            "#{}: {}, {}, next on {}",
+            self.id,
+            self.subject,
+            when,
+            format_due_time(self.next_due_at)
+        )
+    }
+}
+
+fn weekday_name(day: i32) -> Option<&'static str> {
+    let names = [
+        "Monday",
+        "Tuesday",
+        "Wednesday",
+        "Thursday",
+        "Friday",
+        "Saturday",
+        "Sunday",
+    ];
+    names.get(usize::try_from(day).ok()?).copied()
+}
+
+pub fn format_due_time(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .map(|t| t.format("%Y-%m-%d").to_string())
+        .unwrap_or(timestamp.to_string())
+}
+
+/// Returns the first due date strictly after `after`, at 00:00 UTC.
+pub fn next_due_time(frequency: &str, day: i32, after: i64) -> Result<i64> {
+    // Later days of the month would skip the months that are too short for them.
+    if frequency == SCHEDULE_MONTHLY && !(1..=28).contains(&day) {
+        bail!("Invalid day {} for a {} schedule", day, frequency);
+    }
+    let mut date = DateTime::from_timestamp(after, 0)
+        .ok_or(anyhow!("Invalid timestamp {}", after))?
+        .date_naive();
+    // Any day of the month up to 28 or any day of the week comes within two months.
+    for _ in 0..62 {
+        date = date.succ_opt().ok_or(anyhow!("Date out of range"))?;
+        let is_due = match frequency {
+            SCHEDULE_MONTHLY => date.day() as i32 == day,
+            SCHEDULE_WEEKLY => date.weekday().num_days_from_monday() as i32 == day,
+            _ => bail!("Unknown schedule frequency {}", frequency),
+        };
+        if is_due {
+            return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp());
+        }
+    }
+    bail!("Invalid day {} for a {} schedule", day, frequency)
+}
+
+/// Handles the `Schedule`, `Schedules` and `Unschedule` commands and returns the message for the reply.
+pub async fn handle_schedule_command(
+    command: &str,
+    subject: &str,
+    email_addr: &str,
+    wallet_addr: Address,
+) -> Result<String> {
+    match command {
+        SCHEDULE_COMMAND => {
+            let schedule = create_schedule(subject, email_addr, wallet_addr).await?;
+            Ok(format!(
+                "Your payment is scheduled as #{}. We will email you to confirm it on {}.",
+                schedule.id,
+                format_due_time(schedule.next_due_at)
+            ))
+        }
+        SCHEDULES_COMMAND => {
+            extract_template_vals_schedules(subject)?;
+            Ok("Here are your scheduled payments.".to_string())
+        }
+        UNSCHEDULE_COMMAND => {
+            let id = match extract_template_vals_unschedule(subject)?.first() {
+                Some(TemplateValue::Uint(id)) if *id <= U256::from(i64::MAX) => id.as_u64() as i64,
+                _ => bail!(WRONG_SUBJECT_FORMAT),
+            };
+            if !DB.cancel_schedule(id, email_addr).await? {
+                bail!("You have no scheduled payment #{}", id);
+            }
+            Ok(format!("Your scheduled payment #{} is cancelled.", id))
+        }
+        _ => bail!(WRONG_SUBJECT_FORMAT),
+    }
+}
+
+async fn create_schedule(
+    subject: &str,
+    email_addr: &str,
+    wallet_addr: Address,
+) -> Result<Schedule> {
+    let (template_idx, vals) = extract_template_vals_schedule(subject)?;
+    let [token_amount_val, recipient_val, day_val] = vals.as_slice() else {
+        bail!(WRONG_SUBJECT_FORMAT);
+    };
+    let TemplateValue::TokenAmount { token_name, amount } = token_amount_val else {
+        bail!(WRONG_SUBJECT_FORMAT);
+    };
+    let decimals = CLIENT.query_decimals_of_erc20(token_name).await?;
+    if TemplateValue::amount_to_uint(amount, decimals)?.is_zero() {
+        bail!("The amount must be greater than zero");
+    }
+    let (frequency, day) = match (template_idx, day_val) {
+        (0, TemplateValue::Uint(day)) => {
+            if *day < U256::one() || *day > U256::from(28) {
+                bail!("The day of the month must be between 1 and 28");
+            }
+            (SCHEDULE_MONTHLY, day.as_u32() as i32)
+        }
+        (1, TemplateValue::String(name)) => {
+            let weekday = name
+                .parse::<Weekday>()
+                .map_err(|_| anyhow!("Unknown day of the week: {}", name))?;
+            (SCHEDULE_WEEKLY, weekday.num_days_from_monday() as i32)
+        }
+        _ => bail!(WRONG_SUBJECT_FORMAT),
+    };
+    let recipient = match recipient_val {
+        TemplateValue::Recipient {
+            email_addr: Some(email_addr),
+            ..
+        } => email_addr.clone(),
+        TemplateValue::Recipient {
+            eth_addr: Some(eth_addr),
+            ..
+        } => format!("{:?}", eth_addr),
+        _ => bail!(WRONG_SUBJECT_FORMAT),
+    };
+    if DB.get_schedules(email_addr).await?.len() >= MAX_SCHEDULES_PER_USER {
+        bail!(
+            "You can have at most {} scheduled payments",
+            MAX_SCHEDULES_PER_USER
+        );
+    }
+    let mut schedule = Schedule {
+        id: 0,
+        email_address: email_addr.to_string(),
+        wallet_addr: format!("{:?}", wallet_addr),
+        subject: format!(
+            "{} {} {} to {}",
+            SEND_COMMAND, amount, token_name, recipient
+        ),
+        frequency: frequency.to_string(),
+        day,
+        next_due_at: next_due_time(frequency, day, now())?,
+    };
+    schedule.id = DB.insert_schedule(&schedule).await?;
+    Ok(schedule)
+}
+
+/// Emails the senders of the schedules that are due and moves them to their next due date.
+#[named]
+pub async fn send_due_schedules(now: i64) -> Result<()> {
+    for mut schedule in DB.get_due_schedules(now).await? {
+        // Missed dates are skipped rather than asking for several payments at once.
+        schedule.next_due_at = next_due_time(&schedule.frequency, schedule.day, now)?;
+        DB.update_schedule_due_time(schedule.id, schedule.next_due_at)
+            .await?;
+        info!(LOG, "scheduled payment {} is due", schedule.id; "func" => function_name!());
+        if let Err(e) = handle_email_event(EmailWalletEvent::ScheduledPaymentDue { schedule }).await
+        {
+            error!(LOG, "Error handling email event: {}", e; "func" => function_name!());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(year: i32, month: u32, day: u32, hour: u32) -> i64 {
+        NaiveDate::from_ymd_opt(year, month, day)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+            .and_utc()
+            .timestamp()
+    }
+
+    #[test]
+    fn recurs_monthly_across_month_ends() {
+        let due = next_due_time(SCHEDULE_MONTHLY, 28, at(2025, 1, 28, 0)).unwrap();
+        assert_eq!(due, at(2025, 2, 28, 0));
+        let due = next_due_time(SCHEDULE_MONTHLY, 28, due).unwrap();
+        assert_eq!(due, at(2025, 3, 28, 0));
+        assert_eq!(
+            next_due_time(SCHEDULE_MONTHLY, 28, at(2024, 2, 27, 12)).unwrap(),
+            at(2024, 2, 28, 0)
+        );
+        assert_eq!(
+            next_due_time(SCHEDULE_MONTHLY, 1, at(2024, 12, 31, 23)).unwrap(),
+            at(2025, 1, 1, 0)
+        );
+        assert_eq!(
+            next_due_time(SCHEDULE_MONTHLY, 15, at(2025, 1, 31, 0)).unwrap(),
+            at(2025, 2, 15, 0)
+        );
+    }
+
+    #[test]
+    fn recurs_weekly() {
+        // 2025-03-02 is a Sunday.
+        assert_eq!(
+            next_due_time(SCHEDULE_WEEKLY, 0, at(2025, 3, 2, 18)).unwrap(),
+            at(2025, 3, 3, 0)
+        );
+        assert_eq!(
+            next_due_time(SCHEDULE_WEEKLY, 0, at(2025, 3, 3, 0)).unwrap(),
+            at(2025, 3, 10, 0)
+        );
+        assert_eq!(
+            next_due_time(SCHEDULE_WEEKLY, 6, at(2025, 12, 29, 0)).unwrap(),
+            at(2026, 1, 4, 0)
+        );
+    }
+
+    #[test]
+    fn catches_up_once_after_downtime() {
+        // Due on 2025-01-10, but the relayer was down until 2025-03-20.
+        let now = at(2025, 3, 20, 9);
+        let due = next_due_time(SCHEDULE_MONTHLY, 10, now).unwrap();
+        assert_eq!(due, at(2025, 4, 10, 0));
+        // The schedule is no longer due at the same time, so a second run does not send it again.
+        assert!(due > now);
+        assert_eq!(next_due_time(SCHEDULE_MONTHLY, 10, now).unwrap(), due);
+    }
+
+    #[test]
+    fn rejects_invalid_schedules() {
+        assert!(next_due_time(SCHEDULE_MONTHLY, 31, at(2025, 1, 1, 0)).is_err());
+        assert!(next_due_time(SCHEDULE_MONTHLY, 0, at(2025, 1, 1, 0)).is_err());
+        assert!(next_due_time(SCHEDULE_WEEKLY, 7, at(2025, 1, 1, 0)).is_err());
+        assert!(next_due_time("daily", 1, at(2025, 1, 1, 0)).is_err());
+    }
+}
@@ -1,10 +1,15 @@
 use anyhow::{anyhow, Result};
 
 use crate::{
-    error, handle_email, handle_email_event, render_html, trace, wallet::EphemeralTx, EmailMessage,
-    EmailWalletEvent, RELAYER_EMAIL_ADDRESS,
+    describe_fee_quote, error, fetch_nft_image, handle_email, handle_email_event,
+    payment_request_subject, record_batch_result, render_html, request_cancellation, trace,
+    wallet::EphemeralTx, warn, EmailMessage, EmailWalletEvent, BATCH_ITEM_FAILED,
+    RELAYER_EMAIL_ADDRESS,
+};
+use crate::{
+    chain_from_subject, chain_ids, chain_rpc_explorer, chain_subject_prefix, current_chain_id,
+    handle_delivery_status, is_delivery_status_notification, with_chain, CLIENT, DB,
 };
-use crate::{CHAIN_RPC_EXPLORER, CLIENT, DB};
 use ethers::{
     types::{Address, Bytes, Signature, U256},
     utils::{hash_message, to_checksum},
@@ -18,7 +23,6 @@ use relayer_utils::{
 };
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
 use serde_json::Number;
 use std::str::FromStr;
 
@@ -45,6 +49,20 @@ pub struct SendRequest {
     pub is_recipient_email: bool,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct PaymentRequestRequest {
+    pub email_addr: String,
+    pub amount: Number,
+    pub token_id: String,
+    pub payer_email_addr: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CancelRequest {
+    pub email_addr: String,
+    pub recipient_email_addr: String,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct IsAccountCreatedRequest {
     pub email_addr: String,
@@ -167,7 +185,7 @@ pub async fn nft_transfer_api_fn(payload: String) -> Result<(u64, EmailMessage)>
         let subject = "Email Wallet Error: Account Not Found".to_string();
         let error_msg =
             "Your wallet is not yet created. Please create your Email Wallet first on https://emailwallet.org.".to_string();
-        let render_data = serde_json::json!({"userEmailAddr": request.email_addr, "errorMsg": error_msg.clone(), "chainRPCExplorer": CHAIN_RPC_EXPLORER.get().unwrap()});
+        let render_data = serde_json::json!({"userEmailAddr": request.email_addr, "errorMsg": error_msg.clone(), "chainRPCExplorer": chain_rpc_explorer()});
         let body_html = render_html("error.html", render_data).await?;
         let email = EmailMessage {
             subject,
@@ -188,29 +206,32 @@ pub async fn nft_transfer_api_fn(payload: String) -> Result<(u64, EmailMessage)>
     let wallet_addr = CLIENT.get_wallet_addr_from_salt(&account_salt.0).await?;
     let body_plain = format!(
         "Hi {}! Please reply to this email to send {} your NFT: ID {} of {}.\nYou don't have to add any message in the reply 😄.\nYour wallet address: {}/address/{}.",
-        request.email_addr,  request.recipient_addr, request.nft_id, nft_name, CHAIN_RPC_EXPLORER.get().unwrap(), wallet_addr,
+        request.email_addr,  request.recipient_addr, request.nft_id, nft_name, chain_rpc_explorer(), wallet_addr,
     );
     let nft_uri = CLIENT
         .query_erc721_token_uri_of_token(nft_addr, U256::from(request.nft_id))
         .await?;
-    let json_uri: Value = serde_json::from_str(
-        &String::from_utf8(
-            base64::decode(nft_uri.split(",").nth(1).expect("Invalid base64 string"))
-                .expect("Failed to decode base64 string"),
-        )
-        .expect("Invalid UTF-8 sequence"),
-    )
-    .expect("Failed to parse JSON");
-    let render_data = serde_json::json!({"userEmailAddr": request.email_addr, "nftName": nft_name, "nftID": request.nft_id, "recipientAddr": request.recipient_addr, "walletAddr": wallet_addr, "img":"cid:0", "chainRPCExplorer": CHAIN_RPC_EXPLORER.get().unwrap(), "img": json_uri["image"].as_str().unwrap_or_default()});
+    let mut attachments = vec![];
+    let img = match fetch_nft_image(&nft_uri, "nft-0").await {
+        Ok(attachment) => {
+            attachments.push(attachment);
+            "cid:nft-0".to_string()
+        }
+        Err(e) => {
+            warn!(LOG, "Failed to fetch the image of NFT {}: {}", request.nft_id, e);
+            String::new()
+        }
+    };
+    let render_data = serde_json::json!({"userEmailAddr": request.email_addr, "nftName": nft_name, "nftID": request.nft_id, "recipient": request.recipient_addr, "walletAddr": wallet_addr, "img": img, "chainRPCExplorer": chain_rpc_explorer()});
     let body_html = render_html("nft_transfer.html", render_data).await?;
     let email = EmailMessage {
-        subject: subject.clone(),
+        subject: chain_subject_prefix() + &subject,
         body_html,
         body_plain,
         to: request.email_addr,
         reference: None,
         reply_to: None,
-        body_attachments: Some(vec![]),
+        body_attachments: Some(attachments),
     };
     Ok((request_id, email))
 }
@@ -232,7 +253,7 @@ pub async fn create_account_api_fn(payload: String) -> Result<(String, EmailMess
             "Email Wallet Account Creation. Code {}",
             invitation_code_hex
         );
-        let render_data = serde_json::json!({"userEmailAddr": email_addr.clone(), "chainRPCExplorer": CHAIN_RPC_EXPLORER.get().unwrap()});
+        let render_data = serde_json::json!({"userEmailAddr": email_addr.clone(), "chainRPCExplorer": chain_rpc_explorer()});
         let body_html = render_html("account_creation.html", render_data).await?;
         let email = EmailMessage {
             subject,
@@ -253,7 +274,7 @@ pub async fn create_account_api_fn(payload: String) -> Result<(String, EmailMess
         let account_salt =
             AccountSalt::new(&PaddedEmailAddr::from_email_addr(&email_addr), account_code)?;
         let wallet_addr = CLIENT.get_wallet_addr_from_salt(&account_salt.0).await?;
-        let render_data = serde_json::json!({"userEmailAddr": email_addr, "errorMsg": error_msg.clone(), "chainRPCExplorer": CHAIN_RPC_EXPLORER.get().unwrap(), "accountCode": account_code_str.unwrap(), "walletAddr": wallet_addr});
+        let render_data = serde_json::json!({"userEmailAddr": email_addr, "errorMsg": error_msg.clone(), "chainRPCExplorer": chain_rpc_explorer(), "accountCode": account_code_str.unwrap(), "walletAddr": wallet_addr});
         let body_html = render_html("account_already_exist.html", render_data).await?;
         let email = EmailMessage {
             subject,
@@ -292,7 +313,7 @@ pub async fn send_api_fn(payload: String) -> Result<(u64, EmailMessage)> {
         let subject = "Email Wallet Error: Account Not Found".to_string();
         let error_msg =
             "Your wallet is not yet created. Please create your Email Wallet first on https://emailwallet.org.".to_string();
-        let render_data = serde_json::json!({"userEmailAddr": request.email_addr, "errorMsg": error_msg.clone(), "chainRPCExplorer": CHAIN_RPC_EXPLORER.get().unwrap()});
+        let render_data = serde_json::json!({"userEmailAddr": request.email_addr, "errorMsg": error_msg.clone(), "chainRPCExplorer": chain_rpc_explorer()});
         let body_html = render_html("error.html", render_data).await?;
         let email = EmailMessage {
             subject,
@@ -311,14 +332,56 @@ pub async fn send_api_fn(payload: String) -> Result<(u64, EmailMessage)> {
         account_code,
     )?;
     let wallet_addr = CLIENT.get_wallet_addr_from_salt(&account_salt.0).await?;
-    let body_plain = format!(
+    let fee_quote = describe_fee_quote(&request.email_addr, &subject).await;
+    let mut body_plain = format!(
         "Hi {}! Please reply to this email to send {} {} to {}.\nYou don't have to add any message in the reply 😄.\nYour wallet address: {}/address/{}.",
-        request.email_addr, request.amount, request.token_id, request.recipient_addr, CHAIN_RPC_EXPLORER.get().unwrap(), wallet_addr,
+        request.email_addr, request.amount, request.token_id, request.recipient_addr, chain_rpc_explorer(), wallet_addr,
     );
-    let render_data = serde_json::json!({"userEmailAddr": request.email_addr, "originalSubject": subject, "walletAddr": wallet_addr, "chainRPCExplorer": CHAIN_RPC_EXPLORER.get().unwrap()});
+    if let Some(fee_quote) = fee_quote.as_ref() {
+        body_plain.push_str(&format!("\nThe estimated fee is {}.", fee_quote));
+    }
+    let render_data = serde_json::json!({"userEmailAddr": request.email_addr, "originalSubject": subject, "feeQuote": fee_quote, "walletAddr": wallet_addr, "chainRPCExplorer": chain_rpc_explorer()});
     let body_html = render_html("send_request.html", render_data).await?;
     let email = EmailMessage {
-        subject: subject.clone(),
+        subject: chain_subject_prefix() + &subject,
+        body_html,
+        body_plain,
+        to: request.email_addr,
+        reference: None,
+        reply_to: None,
+        body_attachments: None,
+    };
+    Ok((request_id, email))
+}
+
+pub async fn payment_request_api_fn(payload: String) -> Result<(u64, EmailMessage)> {
+    let request_id = rand::thread_rng().gen();
+    let request = serde_json::from_str::<PaymentRequestRequest>(&payload)
+        .map_err(|_| anyhow!("Invalid payload json".to_string()))?;
+    let subject = payment_request_subject(
+        &request.amount.to_string(),
+        &request.token_id,
+        &request.payer_email_addr,
+    );
+    let account_code_str = DB
+        .get_account_code(&request.email_addr)
+        .await?
+        .ok_or(anyhow!("Account not found"))?;
+    let account_code = AccountCode(hex2field(&account_code_str)?);
+    let account_salt = AccountSalt::new(
+        &PaddedEmailAddr::from_email_addr(&request.email_addr),
+        account_code,
+    )?;
+    let wallet_addr = CLIENT.get_wallet_addr_from_salt(&account_salt.0).await?;
+    // The payer is emailed only after the requester confirms by replying, so this endpoint cannot be used to spam.
+    let body_plain = format!(
+        "Hi {}! Please reply to this email to request {} {} from {}.\nYou don't have to add any message in the reply 😄.\nYour wallet address: {}/address/{}.",
+        request.email_addr, request.amount, request.token_id, request.payer_email_addr, chain_rpc_explorer(), wallet_addr,
+    );
+    let render_data = serde_json::json!({"userEmailAddr": request.email_addr, "originalSubject": subject, "walletAddr": wallet_addr, "chainRPCExplorer": chain_rpc_explorer()});
+    let body_html = render_html("send_request.html", render_data).await?;
+    let email = EmailMessage {
+        subject: chain_subject_prefix() + &subject,
         body_html,
         body_plain,
         to: request.email_addr,
@@ -329,6 +392,31 @@ pub async fn send_api_fn(payload: String) -> Result<(u64, EmailMessage)> {
     Ok((request_id, email))
 }
 
+pub async fn cancel_api_fn(payload: String) -> Result<u64> {
+    let request_id = rand::thread_rng().gen();
+    let request = serde_json::from_str::<CancelRequest>(&payload)
+        .map_err(|_| anyhow!("Invalid payload json".to_string()))?;
+    let account_code_str = DB
+        .get_account_code(&request.email_addr)
+        .await?
+        .ok_or(anyhow!("Account not found"))?;
+    let account_code = AccountCode(hex2field(&account_code_str)?);
+    let account_salt = AccountSalt::new(
+        &PaddedEmailAddr::from_email_addr(&request.email_addr),
+        account_code,
+    )?;
+    let wallet_addr = CLIENT.get_wallet_addr_from_salt(&account_salt.0).await?;
+    // The cancellation itself happens only when the sender replies to the confirmation email.
+    let event = request_cancellation(
+        request.email_addr,
+        wallet_addr,
+        &request.recipient_email_addr,
+    )
+    .await?;
+    handle_email_event(event).await?;
+    Ok(request_id)
+}
+
 pub async fn get_wallet_address_api_fn(payload: String) -> Result<String> {
     let request = serde_json::from_str::<GetWalletAddress>(&payload)
         .map_err(|_| anyhow!("Invalid payload json".to_string()))?;
@@ -358,7 +446,7 @@ pub async fn recover_account_code_api_fn(payload: String) -> Result<(u64, EmailM
         let subject = "Email Wallet Error: Account Not Found".to_string();
         let error_msg =
             "Your wallet is not yet created. Please create your Email Wallet first.".to_string();
-        let render_data = serde_json::json!({"userEmailAddr": email_addr, "errorMsg": error_msg.clone(), "chainRPCExplorer": CHAIN_RPC_EXPLORER.get().unwrap()});
+        let render_data = serde_json::json!({"userEmailAddr": email_addr, "errorMsg": error_msg.clone(), "chainRPCExplorer": chain_rpc_explorer()});
         let body_html = render_html("error.html", render_data).await?;
         let email = EmailMessage {
             subject,
@@ -377,12 +465,12 @@ pub async fn recover_account_code_api_fn(payload: String) -> Result<(u64, EmailM
         AccountSalt::new(&PaddedEmailAddr::from_email_addr(&email_addr), account_code)?;
     let wallet_addr = CLIENT.get_wallet_addr_from_salt(&account_salt.0).await?;
     let subject = "Email Wallet Account Login".to_string();
-    let render_data = serde_json::json!({"userEmailAddr": email_addr, "accountCode": account_code_hex, "walletAddr": wallet_addr, "chainRPCExplorer": CHAIN_RPC_EXPLORER.get().unwrap()});
+    let render_data = serde_json::json!({"userEmailAddr": email_addr, "accountCode": account_code_hex, "walletAddr": wallet_addr, "chainRPCExplorer": chain_rpc_explorer()});
     let body_html = render_html("account_recovery.html", render_data).await?;
     let email = EmailMessage {
         subject,
         to: email_addr.clone(),
-        body_plain: format!("Hi {}! Your account key is {}, keep it in a safe space.\nYour wallet address: {}/address/{}.", email_addr, account_code_hex, CHAIN_RPC_EXPLORER.get().unwrap(), wallet_addr),
+        body_plain: format!("Hi {}! Your account key is {}, keep it in a safe space.\nYour wallet address: {}/address/{}.", email_addr, account_code_hex, chain_rpc_explorer(), wallet_addr),
         body_html,
         reference: None,
         reply_to: None,
@@ -420,6 +508,33 @@ pub async fn delete_safe_owner_api_fn(payload: String) -> Result<()> {
 }
 
 pub async fn receive_email_api_fn(email: String) -> Result<()> {
+    // Bounces come from the remote MTA, not from a user, so they never go through handle_email.
+    // The invitation may come from any chain; only the chain whose account code matches reacts.
+    if is_delivery_status_notification(&email) {
+        tokio::spawn(async move {
+            for chain_id in chain_ids() {
+                let result = with_chain(chain_id, async {
+                    match handle_delivery_status(&email).await {
+                        Ok(events) => {
+                            for event in events {
+                                if let Err(e) = handle_email_event(event).await {
+                                    error!(LOG, "Error handling email event: {:?}", e);
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            error!(LOG, "Error handling delivery status notification: {:?}", e);
+                        }
+                    }
+                })
+                .await;
+                if let Err(e) = result {
+                    error!(LOG, "Error handling delivery status notification: {:?}", e);
+                }
+            }
+        });
+        return Ok(());
+    }
     let parsed = ParsedEmail::new_from_raw_email(&email).await;
     // Fallback extract From header in case parsing fails (e.g., missing DKIM header)
     let fallback_from = || -> Option<String> {
@@ -439,21 +554,38 @@ pub async fn receive_email_api_fn(email: String) -> Result<()> {
         error!(LOG, "Failed to parse email: {}", err);
         if let Some(addr) = fallback_from() {
             // Notify user with a helpful error instead of panicking the worker
-            tokio::spawn(async move {
+            tokio::spawn(with_chain(current_chain_id(), async move {
                 let _ = handle_email_event(EmailWalletEvent::Error {
                     email_addr: addr,
                     error_subject: "Invalid email".to_string(),
                     error: format!("{}", err),
                 })
                 .await;
-            });
+            }));
         }
         return Ok(());
     }
 
     let parsed_email = parsed.unwrap();
     let from_addr = parsed_email.get_from_addr().unwrap();
-    tokio::spawn(async move {
+    let chain_id = match chain_from_subject(&parsed_email.get_subject_all().unwrap_or_default()) {
+        Ok(chain_id) => chain_id,
+        Err(e) => {
+            tokio::spawn(with_chain(current_chain_id(), async move {
+                if let Err(e) = handle_email_event(EmailWalletEvent::Error {
+                    email_addr: from_addr,
+                    error_subject: parsed_email.get_subject_all().unwrap_or_default(),
+                    error: e.to_string(),
+                })
+                .await
+                {
+                    error!(LOG, "Error handling email event: {:?}", e);
+                }
+            }));
+            return Ok(());
+        }
+    };
+    tokio::spawn(with_chain(chain_id, async move {
         match handle_email_event(EmailWalletEvent::Ack {
             email_addr: from_addr.clone(),
             subject: parsed_email.get_subject_all().unwrap_or_default(),
@@ -498,6 +630,16 @@ pub async fn receive_email_api_fn(email: String) -> Result<()> {
             }
             Err(e) => {
                 error!(LOG, "Error handling email: {:?}", e);
+                if let Err(e) = record_batch_result(
+                    &from_addr,
+                    &parsed_email.get_subject_all().unwrap_or_default(),
+                    BATCH_ITEM_FAILED,
+                    &e.to_string(),
+                )
+                .await
+                {
+                    error!(LOG, "Error recording batch result: {:?}", e);
+                }
                 match handle_email_event(EmailWalletEvent::Error {
                     email_addr: from_addr,
                     error_subject: parsed_email.get_subject_all().unwrap_or_default(),
@@ -512,7 +654,7 @@ pub async fn receive_email_api_fn(email: String) -> Result<()> {
                 }
             }
         }
-    });
+    }));
     Ok(())
 }
 
@@ -552,7 +694,7 @@ pub async fn signup_or_in_api_fn(payload: String) -> Result<(u32, EmailMessage)>
     if is_signup && request.username.is_none() {
         let subject = "Email Wallet Error: No username in the sign-up request".to_string();
         let error_msg = "Please specify a username when you sign-up".to_string();
-        let render_data = serde_json::json!({"userEmailAddr": request.email_addr, "errorMsg": error_msg.clone(), "chainRPCExplorer": CHAIN_RPC_EXPLORER.get().unwrap()});
+        let render_data = serde_json::json!({"userEmailAddr": request.email_addr, "errorMsg": error_msg.clone(), "chainRPCExplorer": chain_rpc_explorer()});
         let body_html = render_html("error.html", render_data).await?;
         let email = EmailMessage {
             subject,
@@ -571,7 +713,7 @@ pub async fn signup_or_in_api_fn(payload: String) -> Result<(u32, EmailMessage)>
             "Please specify an ephemeral address when you sign-in {}",
             &registered_username
         );
-        let render_data = serde_json::json!({"userEmailAddr": request.email_addr, "errorMsg": error_msg.clone(), "chainRPCExplorer": CHAIN_RPC_EXPLORER.get().unwrap()});
+        let render_data = serde_json::json!({"userEmailAddr": request.email_addr, "errorMsg": error_msg.clone(), "chainRPCExplorer": chain_rpc_explorer()});
         let body_html = render_html("error.html", render_data).await?;
         let email = EmailMessage {
             subject,
@@ -632,9 +774,9 @@ pub async fn signup_or_in_api_fn(payload: String) -> Result<(u32, EmailMessage)>
         "Hi {}! Please reply to this email to {} {}.\nYou don't have to add any message in the reply 😄.\nYour wallet address: {}/address/{}.",
         request.email_addr,
         if is_signup { "sign-up" } else { "sign-in" },
-        used_username, CHAIN_RPC_EXPLORER.get().unwrap(), wallet_addr,
+        used_username, chain_rpc_explorer(), wallet_addr,
     );
-    let render_data = serde_json::json!({"userEmailAddr": request.email_addr, "originalSubject": subject, "walletAddr": wallet_addr, "chainRPCExplorer": CHAIN_RPC_EXPLORER.get().unwrap()});
+    let render_data = serde_json::json!({"userEmailAddr": request.email_addr, "originalSubject": subject, "walletAddr": wallet_addr, "chainRPCExplorer": chain_rpc_explorer()});
     let body_html = render_html("send_request.html", render_data).await?;
     let email = EmailMessage {
         subject: subject.clone(),
@@ -664,7 +806,7 @@ pub async fn signup_or_in_api_fn(payload: String) -> Result<(u32, EmailMessage)>
 //         let subject = "Email Wallet Error: Account Not Found".to_string();
 //         let error_msg =
 //             "Your wallet is not yet created. Please create your Email Wallet first on https://emailwallet.org.".to_string();
-//         let render_data = serde_json::json!({"userEmailAddr": request.email_addr, "errorMsg": error_msg.clone(), "chainRPCExplorer": CHAIN_RPC_EXPLORER.get().unwrap()});
+//         let render_data = serde_json::json!({"userEmailAddr": request.email_addr, "errorMsg": error_msg.clone(), "chainRPCExplorer": chain_rpc_explorer()});
 //         let body_html = render_html("error.html", render_data).await?;
 //         let email = EmailMessage {
 //             subject,
@@ -685,9 +827,9 @@ pub async fn signup_or_in_api_fn(payload: String) -> Result<(u32, EmailMessage)>
 //     let wallet_addr = CLIENT.get_wallet_addr_from_salt(&account_salt.0).await?;
 //     let body_plain = format!(
 //         "Hi {}! Please reply to this email to sign-in {}.\nYou don't have to add any message in the reply 😄.\nYour wallet address: {}/address/{}.",
-//         request.email_addr, request.username, CHAIN_RPC_EXPLORER.get().unwrap(), wallet_addr,
+//         request.email_addr, request.username, chain_rpc_explorer(), wallet_addr,
 //     );
-//     let render_data = serde_json::json!({"userEmailAddr": request.email_addr, "originalSubject": subject, "walletAddr": wallet_addr, "chainRPCExplorer": CHAIN_RPC_EXPLORER.get().unwrap()});
+//     let render_data = serde_json::json!({"userEmailAddr": request.email_addr, "originalSubject": subject, "walletAddr": wallet_addr, "chainRPCExplorer": chain_rpc_explorer()});
 //     let body_html = render_html("send_request.html", render_data).await?;
 //     let email = EmailMessage {
 //         subject: subject.clone(),
@@ -19,6 +19,11 @@ async fn unclaim(payload: UnclaimRequest) -> Result<String> {
         .get_unclaim_id_from_tx_hash(&payload.tx_hash, payload.is_fund)
         .await?;
     info!(LOG, "id {:?}", id; "func" => function_name!());
+    let sender = if payload.is_fund {
+        CLIENT.query_unclaimed_fund(id).await?.sender
+    } else {
+        CLIENT.query_unclaimed_state(id).await?.sender
+    };
     let claim = Claim {
         tx_hash: payload.tx_hash.clone(),
         id,
@@ -28,7 +33,9 @@ async fn unclaim(payload: UnclaimRequest) -> Result<String> {
         expiry_time: payload.expiry_time,
         is_fund: payload.is_fund,
         is_announced: false,
-        is_seen: false,
+        status: ClaimStatus::Registered,
+        attempts: 0,
+        sender,
     };
     match claim_unclaims(claim.clone()).await {
         Ok(value) => {
@@ -98,11 +105,29 @@ pub async fn run_server() -> Result<()> {
                 axum::Json(stats)
             }),
         )
+        .route(
+            "/api/claimLink",
+            axum::routing::get(move |query: axum::extract::Query<ClaimLinkQuery>| async move {
+                serve_claim_link(query.0).await.map_err(|err| {
+                    error!(LOG, "Failed to serve claim link: {}", err; "func" => function_name!());
+                    err.to_string()
+                })
+            }),
+        )
+        .route(
+            "/api/claimLinkStats",
+            axum::routing::get(move || async move {
+                DB.get_claim_link_stats()
+                    .await
+                    .map(axum::Json)
+                    .map_err(|err| err.to_string())
+            }),
+        )
         .route(
             "/api/serveCheck/",
             axum::routing::post(move |payload: String| async move {
                 info!(LOG, "/serveCheck Received payload: {}", payload; "func" => function_name!());
-                let json = serde_json::from_str::<CheckRequest>(&payload)
+                let json = serde_json::from_str::<SignedPsiRequest>(&payload)
                     .map_err(|_| "Invalid payload json".to_string())?;
                 serve_check_request(json)
                     .await
@@ -115,8 +140,8 @@ pub async fn run_server() -> Result<()> {
         .route(
             "/api/serveReveal/",
             axum::routing::post(move |payload: String| async move {
-                info!(LOG, "/serveCheck Received payload: {}", payload; "func" => function_name!());
-                let json = serde_json::from_str::<RevealRequest>(&payload)
+                info!(LOG, "/serveReveal Received payload: {}", payload; "func" => function_name!());
+                let json = serde_json::from_str::<SignedPsiRequest>(&payload)
                     .map_err(|_| "Invalid payload json".to_string())?;
                 serve_reveal_request(json)
                     .await
@@ -178,6 +203,35 @@ pub async fn run_server() -> Result<()> {
                 }
             }),
         )
+        .route(
+            "/api/request",
+            axum::routing::post::<_, _, (), _>(move |payload: String| async move {
+                info!(LOG, "Payment request payload: {}", payload);
+                match payment_request_api_fn(payload).await {
+                    Ok((request_id, email)) => {
+                        send_email(email).await.unwrap();
+                        request_id.to_string()
+                    }
+                    Err(err) => {
+                        error!(LOG, "Failed to accept payment request: {}", err);
+                        err.to_string()
+                    }
+                }
+            }),
+        )
+        .route(
+            "/api/cancel",
+            axum::routing::post::<_, _, (), _>(move |payload: String| async move {
+                info!(LOG, "Cancel payload: {}", payload);
+                match cancel_api_fn(payload).await {
+                    Ok(request_id) => request_id.to_string(),
+                    Err(err) => {
+                        error!(LOG, "Failed to accept cancel: {}", err);
+                        err.to_string()
+                    }
+                }
+            }),
+        )
         .route(
             "/api/genAccountCode",
             axum::routing::get::<_, _, (), _>(move || async move {
@@ -338,7 +392,7 @@ pub async fn run_server() -> Result<()> {
         }),
     );
 
-    app = app.layer(
+    app = app.layer(axum::middleware::from_fn(chain_scope)).layer(
         CorsLayer::new()
             .allow_methods(AllowMethods::any())
             .allow_headers(AllowHeaders::any())
@@ -0,0 +1,202 @@
+//! Grumpkin arithmetic for PSI points, matching `point_scalar_mul` of circom-grumpkin.
+//!
+//! Grumpkin is `y^2 = x^3 - 17` over the scalar field of BN254, so coordinates are `Fr` elements
+//! and scalars are reduced modulo the base field of BN254.
+
+use crate::*;
+
+use ff::{Field, PrimeField};
+use num_bigint::BigUint;
+use num_traits::{One, Zero};
+
+/// The order of the Grumpkin group, i.e. the base field modulus of BN254.
+pub const GRUMPKIN_ORDER: &str =
+    "21888242871839275222246405745257275088696311157297823662689037894645226208583";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Affine {
+    x: Fr,
+    y: Fr,
+}
+
+fn curve_b() -> Fr {
+    -Fr::from(17)
+}
+
+fn is_on_curve(p: &Affine) -> bool {
+    p.y.square() == p.x.square() * p.x + curve_b()
+}
+
+// `None` is the point at infinity.
+fn add(p: Option<Affine>, q: Option<Affine>) -> Option<Affine> {
+    let (p, q) = match (p, q) {
+        (None, q) => return q,
+        (p, None) => return p,
+        (Some(p), Some(q)) => (p, q),
+    };
+    let lambda = if p.x == q.x {
+        if p.y != q.y || p.y.is_zero_vartime() {
+            return None;
+        }
+        (p.x.square() * Fr::from(3)) * (p.y.double()).invert().unwrap()
+    } else {
+        (q.y - p.y) * (q.x - p.x).invert().unwrap()
+    };
+    let x = lambda.square() - p.x - q.x;
+    let y = lambda * (p.x - x) - p.y;
+    Some(Affine { x, y })
+}
+
+fn mul(p: Affine, scalar: &BigUint) -> Option<Affine> {
+    let mut acc = None;
+    for i in (0..scalar.bits()).rev() {
+        acc = add(acc, acc);
+        if scalar.bit(i) {
+            acc = add(acc, Some(p));
+        }
+    }
+    acc
+}
+
+fn fr_from_dec(value: &str) -> Result<Fr> {
+    Fr::from_str_vartime(value).ok_or(anyhow!("{} is not a field element", value))
+}
+
+fn fr_to_dec(value: &Fr) -> Result<String> {
+    let hex = field2hex(value);
+    let value = BigUint::parse_bytes(hex.trim_start_matches("0x").as_bytes(), 16)
+        .ok_or(anyhow!("Invalid field element {}", hex))?;
+    Ok(value.to_str_radix(10))
+}
+
+/// Parses a scalar given as `0x`-prefixed hex, like the randomness of the relayer and the client, or as decimal.
+pub fn parse_grumpkin_scalar(value: &str) -> Result<BigUint> {
+    let scalar = match value.strip_prefix("0x") {
+        Some(hex) => BigUint::parse_bytes(hex.as_bytes(), 16),
+        None => BigUint::parse_bytes(value.as_bytes(), 10),
+    }
+    .ok_or(anyhow!("{} is not a scalar", value))?;
+    Ok(scalar % grumpkin_order())
+}
+
+fn grumpkin_order() -> BigUint {
+    BigUint::parse_bytes(GRUMPKIN_ORDER.as_bytes(), 10).unwrap()
+}
+
+/// The inverse of a nonzero scalar modulo the group order.
+pub fn invert_grumpkin_scalar(scalar: &BigUint) -> Result<BigUint> {
+    let order = grumpkin_order();
+    if (scalar % &order).is_zero() {
+        bail!("Zero scalar has no inverse");
+    }
+    Ok(scalar.modpow(&(&order - BigUint::one() - BigUint::one()), &order))
+}
+
+/// Multiplies a point on Grumpkin by a scalar. The point must be on the curve and the result must not be the identity.
+pub fn point_scalar_mul(point: &Point, scalar: &BigUint) -> Result<Point> {
+    let point = Affine {
+        x: fr_from_dec(&point.x)?,
+        y: fr_from_dec(&point.y)?,
+    };
+    if !is_on_curve(&point) {
+        bail!("PSI point is not on the curve");
+    }
+    let result = mul(point, &(scalar % grumpkin_order()))
+        .ok_or(anyhow!("PSI point multiplied to the identity"))?;
+    Ok(Point {
+        x: fr_to_dec(&result.x)?,
+        y: fr_to_dec(&result.y)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: &str, y: &str) -> Point {
+        Point {
+            x: x.to_string(),
+            y: y.to_string(),
+        }
+    }
+
+    fn generator() -> Point {
+        point(
+            "1",
+            "17631683881184975370165255887551781615748388533673675138860",
+        )
+    }
+
+    fn scalar(value: &str) -> BigUint {
+        parse_grumpkin_scalar(value).unwrap()
+    }
+
+    fn assert_point_eq(actual: Point, expected: Point) {
+        assert_eq!((actual.x, actual.y), (expected.x, expected.y));
+    }
+
+    #[test]
+    fn multiplies_the_generator() {
+        assert_point_eq(
+            point_scalar_mul(&generator(), &scalar("2")).unwrap(),
+            point(
+                "3078034153852398078128400807926804309327113743808504829582559963737223069694",
+                "12696890884641142049456609402511852099066095483298083855939691685001536962732",
+            ),
+        );
+        assert_point_eq(
+            point_scalar_mul(&generator(), &scalar("3")).unwrap(),
+            point(
+                "18660890509582237958343981571981920822503400000196279471655180441138020044621",
+                "8902249110305491597038405103722863701255802573786510474664632793109847672620",
+            ),
+        );
+        assert_point_eq(
+            point_scalar_mul(&generator(), &scalar("0x1234567890abcdef")).unwrap(),
+            point(
+                "4024409262038489804121720659899019997073996146226370160220300317557809506442",
+                "516099146112340570002099519841487486933273921012154053906212284473951907042",
+            ),
+        );
+    }
+
+    #[test]
+    fn reduces_scalars_modulo_the_order() {
+        let order = grumpkin_order();
+        assert_point_eq(
+            point_scalar_mul(&generator(), &(&order + BigUint::from(3u8))).unwrap(),
+            point_scalar_mul(&generator(), &scalar("3")).unwrap(),
+        );
+        // (order - 1) * G = -G
+        assert_point_eq(
+            point_scalar_mul(&generator(), &(&order - BigUint::one())).unwrap(),
+            point(
+                "1",
+                "21888242871839275204614721864072299718383108512864252727949815652902133356757",
+            ),
+        );
+        assert!(point_scalar_mul(&generator(), &order).is_err());
+        assert!(point_scalar_mul(&generator(), &BigUint::zero()).is_err());
+    }
+
+    #[test]
+    fn rejects_points_off_the_curve() {
+        assert!(point_scalar_mul(&point("1", "2"), &scalar("2")).is_err());
+        assert!(point_scalar_mul(&point("1", "not a number"), &scalar("2")).is_err());
+    }
+
+    #[test]
+    fn unblinds_with_the_inverse_scalar() {
+        let client_rand = scalar("0x1234567890abcdef");
+        let relayer_rand = scalar("0xabcdef");
+        let blinded = point_scalar_mul(&generator(), &client_rand).unwrap();
+        let response = point_scalar_mul(&blinded, &relayer_rand).unwrap();
+        let unblinded =
+            point_scalar_mul(&response, &invert_grumpkin_scalar(&client_rand).unwrap()).unwrap();
+        assert_point_eq(
+            unblinded,
+            point_scalar_mul(&generator(), &relayer_rand).unwrap(),
+        );
+        assert!(invert_grumpkin_scalar(&grumpkin_order()).is_err());
+    }
+}
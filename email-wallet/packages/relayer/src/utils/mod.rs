@@ -1,7 +1,11 @@
+pub mod grumpkin;
 pub mod strings;
+pub mod subject_aliases;
 pub mod subject_templates;
 pub mod utils;
 
+pub use grumpkin::*;
 pub use strings::*;
+pub use subject_aliases::*;
 pub use subject_templates::*;
 pub use utils::*;
@@ -21,6 +21,33 @@ pub const ONBOARDING_TOKEN_AMOUNT_KEY: &str = "ONBOARDING_TOKEN_AMOUNT";
 pub const ONBOARDING_TOKEN_DISTRIBUTION_LIMIT_KEY: &str = "ONBOARDING_TOKEN_DISTRIBUTION_LIMIT";
 pub const ONBOARDING_REPLY_KEY: &str = "ONBOARDING_REPLY";
 pub const SAFE_API_ENDPOINT_KEY: &str = "SAFE_API_ENDPOINT";
+pub const SUBJECT_ALIASES_PATH_KEY: &str = "SUBJECT_ALIASES_PATH";
+pub const BALANCE_TOKENS_KEY: &str = "BALANCE_TOKENS";
+pub const FEE_TOKENS_KEY: &str = "FEE_TOKENS";
+pub const PRICE_SOURCE_KEY: &str = "PRICE_SOURCE";
+pub const CLAIM_CONCURRENCY_KEY: &str = "CLAIM_CONCURRENCY";
+pub const CLAIM_RETRY_BASE_SECS_KEY: &str = "CLAIM_RETRY_BASE_SECS";
+pub const CLAIM_RETRY_MAX_SECS_KEY: &str = "CLAIM_RETRY_MAX_SECS";
+pub const CLAIM_MAX_RETRIES_KEY: &str = "CLAIM_MAX_RETRIES";
+pub const EXPIRY_REMINDER_OFFSETS_KEY: &str = "EXPIRY_REMINDER_OFFSETS";
+pub const CLAIM_LINK_BASE_URL_KEY: &str = "CLAIM_LINK_BASE_URL";
+pub const TRUSTED_AUTHSERV_ID_KEY: &str = "TRUSTED_AUTHSERV_ID";
+pub const PSI_ENABLED_KEY: &str = "PSI_ENABLED";
+pub const PSI_PEERS_KEY: &str = "PSI_PEERS";
+pub const CHAIN_NAME_KEY: &str = "CHAIN_NAME";
+pub const CHAIN_PROFILES_PATH_KEY: &str = "CHAIN_PROFILES_PATH";
+pub const CHAIN_SUBSCRIPTION_PROVIDER_KEY: &str = "CHAIN_SUBSCRIPTION_PROVIDER";
+pub const EVENT_POLL_INTERVAL_SECS_KEY: &str = "EVENT_POLL_INTERVAL_SECS";
+pub const FEE_STRATEGY_KEY: &str = "FEE_STRATEGY";
+pub const FEE_MARGIN_PERCENT_KEY: &str = "FEE_MARGIN_PERCENT";
+pub const MAX_FEE_PER_GAS_KEY: &str = "MAX_FEE_PER_GAS";
+pub const FEE_HISTORY_BLOCKS_KEY: &str = "FEE_HISTORY_BLOCKS";
+pub const MIN_RELAYER_ETH_BALANCE_KEY: &str = "MIN_RELAYER_ETH_BALANCE";
+pub const MIN_ONBOARDING_TOKEN_BALANCE_KEY: &str = "MIN_ONBOARDING_TOKEN_BALANCE";
+pub const MONITOR_INTERVAL_SECS_KEY: &str = "MONITOR_INTERVAL_SECS";
+pub const ALERT_TASK_FAILURE_THRESHOLD_KEY: &str = "ALERT_TASK_FAILURE_THRESHOLD";
+pub const ALERT_DEDUP_SECS_KEY: &str = "ALERT_DEDUP_SECS";
+pub const ALERT_DIGEST_SECS_KEY: &str = "ALERT_DIGEST_SECS";
 
 // Error strings
 pub const CANNOT_GET_EMAIL_FROM_QUEUE: &str = "Cannot get email from mpsc in handle email task";
@@ -45,6 +72,17 @@ pub const UNINSTALL_COMMAND: &str = "Uninstall";
 pub const EXIT_COMMAND: &str = "Exit";
 pub const DKIM_COMMAND: &str = "DKIM";
 pub const SAFE_COMMAND: &str = "Safe";
+pub const BALANCE_COMMAND: &str = "Balance";
+pub const CANCEL_COMMAND: &str = "Cancel";
+pub const SCHEDULE_COMMAND: &str = "Schedule";
+pub const SCHEDULES_COMMAND: &str = "Schedules";
+pub const UNSCHEDULE_COMMAND: &str = "Unschedule";
+pub const REQUEST_COMMAND: &str = "Request";
+pub const FEE_COMMAND: &str = "Fee";
+pub const MAX_FEE_CLAUSE_WORD: &str = "Max";
+pub const CONTACT_COMMAND: &str = "Contact";
+pub const CONTACTS_COMMAND: &str = "Contacts";
+pub const NOTIFICATIONS_COMMAND: &str = "Notifications";
 
 // DKIM ORACLE ARGS
 pub const CANISTER_ID_KEY: &str = "CANISTER_ID";
@@ -0,0 +1,181 @@
+use crate::*;
+
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// A localized phrasing of a command, e.g. `送金 {tokenAmount} を {recipient} に` for `Send {tokenAmount} to {recipient}`.
+/// Both sides use the placeholders of the subject templates.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SubjectAlias {
+    pub pattern: String,
+    pub canonical: String,
+}
+
+pub fn default_subject_aliases() -> Vec<SubjectAlias> {
+    vec![
+        SubjectAlias {
+            pattern: "送金 {tokenAmount} を {recipient} に".to_string(),
+            canonical: "Send {tokenAmount} to {recipient}".to_string(),
+        },
+        SubjectAlias {
+            pattern: "{recipient} に {tokenAmount} を 送金".to_string(),
+            canonical: "Send {tokenAmount} to {recipient}".to_string(),
+        },
+    ]
+}
+
+/// Reads the alias table from a JSON file, or returns the default table if no path is configured.
+pub fn load_subject_aliases(path: Option<&str>) -> Result<Vec<SubjectAlias>> {
+    let aliases: Vec<SubjectAlias> = match path {
+        Some(path) => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+        None => default_subject_aliases(),
+    };
+    check_subject_aliases(&aliases)?;
+    Ok(aliases)
+}
+
+fn check_subject_aliases(aliases: &[SubjectAlias]) -> Result<()> {
+    for alias in aliases.iter() {
+        let command = alias.canonical.split(' ').next().unwrap_or_default();
+        if builtin_subject_templates(command).is_none() {
+            bail!(
+                "The canonical subject of alias `{}` does not start with a command",
+                alias.pattern
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Rewrites a subject written in one of the configured aliases into the canonical subject
+/// that the `email_sender` circuit and the contract accept.
+pub fn canonicalize_subject_alias(subject: &str) -> Option<String> {
+    SUBJECT_ALIASES
+        .get()?
+        .iter()
+        .find_map(|alias| apply_subject_alias(alias, subject))
+}
+
+fn apply_subject_alias(alias: &SubjectAlias, subject: &str) -> Option<String> {
+    let pattern: Vec<String> = alias.pattern.split_whitespace().map(String::from).collect();
+    // Languages like Japanese do not put spaces between words, so the fixed words are split off first.
+    let mut spaced = subject.to_string();
+    for word in pattern.iter() {
+        if !word.starts_with('{') && !word.is_ascii() {
+            spaced = spaced.replace(word.as_str(), &format!(" {} ", word));
+        }
+    }
+    let tokens = tokenize_subject(&spaced);
+    // The alias may be preceded by arbitrary text, like the skipped prefix of canonical subjects.
+    let first_word = pattern.first()?;
+    let (vals, _) = (0..tokens.len()).find_map(|start| {
+        if !first_word.starts_with('{') && tokens[start].text != *first_word {
+            return None;
+        }
+        parse_template(&tokens[start..], &pattern).ok()
+    })?;
+
+    let mut vals_by_placeholder: HashMap<&str, Vec<TemplateValue>> = HashMap::new();
+    for (placeholder, val) in pattern
+        .iter()
+        .filter(|word| word.starts_with('{'))
+        .zip(vals.into_iter())
+    {
+        vals_by_placeholder
+            .entry(placeholder.as_str())
+            .or_default()
+            .push(val);
+    }
+    let mut canonical_words = vec![];
+    for word in alias.canonical.split(' ') {
+        if word.starts_with('{') {
+            let vals = vals_by_placeholder.get_mut(word)?;
+            if vals.is_empty() {
+                return None;
+            }
+            canonical_words.push(template_value_to_string(&vals.remove(0)));
+        } else {
+            canonical_words.push(word.to_string());
+        }
+    }
+    Some(canonical_words.join(" "))
+}
+
+fn template_value_to_string(val: &TemplateValue) -> String {
+    match val {
+        TemplateValue::TokenAmount { token_name, amount } => format!("{} {}", amount, token_name),
+        TemplateValue::Amount(amount) => amount.clone(),
+        TemplateValue::String(string) => string.clone(),
+        TemplateValue::Uint(uint) => uint.to_string(),
+        TemplateValue::Int(int) => int.to_string(),
+        TemplateValue::Address(address) => ethers::utils::to_checksum(address, None),
+        TemplateValue::Recipient {
+            email_addr,
+            eth_addr,
+            ..
+        } => match (email_addr, eth_addr) {
+            (Some(email_addr), _) => email_addr.clone(),
+            (None, Some(eth_addr)) => ethers::utils::to_checksum(eth_addr, None),
+            (None, None) => String::new(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply_default_aliases(subject: &str) -> Option<String> {
+        default_subject_aliases()
+            .iter()
+            .find_map(|alias| apply_subject_alias(alias, subject))
+    }
+
+    #[test]
+    fn rewrites_japanese_subjects() {
+        let canonical = Some("Send 10 ETH to bob@example.com".to_string());
+        assert_eq!(
+            apply_default_aliases("送金 10 ETH を bob@example.com に"),
+            canonical
+        );
+        assert_eq!(
+            apply_default_aliases("送金10 ETHをbob@example.comに"),
+            canonical
+        );
+        assert_eq!(
+            apply_default_aliases("Re: 送金 10 ETH を bob@example.com に"),
+            canonical
+        );
+        assert_eq!(apply_default_aliases("送金 10 ETH を bob"), None);
+    }
+
+    #[test]
+    fn restores_the_canonical_order_of_placeholders() {
+        assert_eq!(
+            apply_default_aliases("bob@example.com に 1.5 USDC を 送金"),
+            Some("Send 1.5 USDC to bob@example.com".to_string())
+        );
+        assert_eq!(
+            apply_default_aliases("bob@example.comに1.5 USDCを送金"),
+            Some("Send 1.5 USDC to bob@example.com".to_string())
+        );
+    }
+
+    #[test]
+    fn writes_addresses_with_a_checksum() {
+        assert_eq!(
+            apply_default_aliases("送金 1 ETH を 0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed に"),
+            Some("Send 1 ETH to 0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_aliases_of_unknown_commands() {
+        let aliases: Vec<SubjectAlias> = serde_json::from_str(
+            r#"[{ "pattern": "転送 {tokenAmount} を {recipient} に", "canonical": "Forward {tokenAmount} to {recipient}" }]"#,
+        )
+        .unwrap();
+        assert!(check_subject_aliases(&aliases).is_err());
+        assert!(check_subject_aliases(&default_subject_aliases()).is_ok());
+    }
+}
@@ -22,7 +22,6 @@ pub enum TemplateValue {
         email_addr: Option<String>,
         eth_addr: Option<Address>,
     },
-    Fixed(String),
 }
 
 impl TemplateValue {
@@ -30,7 +29,7 @@ impl TemplateValue {
     pub fn abi_encode(&self, amount_decimal_size: Option<u8>) -> Result<Bytes> {
         match self {
             Self::TokenAmount { token_name, amount } => {
-                let amount_u256 = Self::amount_to_uint(amount, amount_decimal_size.unwrap());
+                let amount_u256 = Self::amount_to_uint(amount, amount_decimal_size.unwrap())?;
                 info!(LOG, "amount_u256: {}", amount_u256; "func" => function_name!());
                 Ok(Bytes::from(abi::encode(&[
                     Token::Uint(amount_u256),
@@ -38,7 +37,7 @@ impl TemplateValue {
                 ])))
             }
             Self::Amount(amount) => {
-                let amount_u256 = Self::amount_to_uint(amount, amount_decimal_size.unwrap());
+                let amount_u256 = Self::amount_to_uint(amount, amount_decimal_size.unwrap())?;
                 Ok(Bytes::from(abi::encode(&[Token::Uint(amount_u256)])))
             }
             Self::String(string) => Ok(Bytes::from(abi::encode(&[Token::String(string.clone())]))),
@@ -58,70 +57,334 @@ impl TemplateValue {
                     )])))
                 }
             }
-            Self::Fixed(string) => Err(anyhow!("Fixed value must not be passed to abi_encode")),
         }
     }
 
-    pub fn amount_to_uint(amount_str: &str, decimal_size: u8) -> U256 {
+    pub fn amount_to_uint(amount_str: &str, decimal_size: u8) -> Result<U256> {
+        let amount_str =
+            normalize_amount(amount_str).ok_or(anyhow!("Invalid amount: {}", amount_str))?;
         let decimal_size = decimal_size as usize;
-        let dot = Regex::new("\\.").unwrap().find(amount_str);
-        let (before_dot_str, mut after_dot_str) = match dot {
-            Some(dot_match) => (
-                amount_str[0..dot_match.start()].to_string(),
-                amount_str[dot_match.end()..].to_string(),
+        let (before_dot_str, after_dot_str) =
+            amount_str.split_once('.').unwrap_or((&amount_str, ""));
+        if after_dot_str.len() > decimal_size {
+            bail!(
+                "The amount {} has too many decimal places: the token supports at most {}",
+                amount_str,
+                decimal_size
+            );
+        }
+        let digits = format!(
+            "{}{}{}",
+            before_dot_str,
+            after_dot_str,
+            "0".repeat(decimal_size - after_dot_str.len())
+        );
+        U256::from_dec_str(&digits).map_err(|_| anyhow!("The amount {} is too large", amount_str))
+    }
+}
+
+/// Normalizes an amount into the form the contract renders it in: ASCII digits,
+/// no thousands separators, no leading zeros and no trailing decimal zeros.
+pub fn normalize_amount(amount: &str) -> Option<String> {
+    let ascii: String = amount
+        .chars()
+        .map(|c| match c {
+            '０'..='９' => char::from_digit(c as u32 - '０' as u32, 10).unwrap(),
+            '．' => '.',
+            '，' => ',',
+            c => c,
+        })
+        .collect();
+    if !is_whole_match(AMOUNT_REGEX, &ascii)
+        && !is_whole_match("[0-9]{1,3}(,[0-9]{3})+(\\.[0-9]+)?", &ascii)
+    {
+        return None;
+    }
+    let ascii = ascii.replace(',', "");
+    let (int_part, frac_part) = ascii.split_once('.').unwrap_or((&ascii, ""));
+    let int_part = match int_part.trim_start_matches('0') {
+        "" => "0",
+        int_part => int_part,
+    };
+    match frac_part.trim_end_matches('0') {
+        "" => Some(int_part.to_string()),
+        frac_part => Some(format!("{}.{}", int_part, frac_part)),
+    }
+}
+
+/// Rewrites the amounts of a subject of a built-in command that are written with thousands separators
+/// or full-width digits into the form the contract renders. Returns None if there is nothing to rewrite
+/// or the rewritten subject still does not match a template.
+pub fn canonicalize_amount_notation(command: &str, subject: &str) -> Option<String> {
+    let templates = match command {
+        SCHEDULE_COMMAND => schedule_subject_templates(),
+        FEE_COMMAND => fee_subject_templates(),
+        _ => vec![builtin_subject_templates(command)?],
+    };
+    let is_localized = |word: &str| {
+        word.chars()
+            .any(|c| matches!(c, ',' | '，' | '．' | '０'..='９'))
+    };
+    let rewritten = subject
+        .split(' ')
+        .map(|word| match normalize_amount(word) {
+            Some(amount) if is_localized(word) => amount,
+            _ => word.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    if rewritten == subject {
+        return None;
+    }
+    templates
+        .iter()
+        .any(|template| parse_subject(&rewritten, template).is_ok())
+        .then_some(rewritten)
+}
+
+/// A whitespace separated word of a subject and its byte offset in the subject.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubjectToken {
+    pub text: String,
+    pub start: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubjectParseError {
+    /// The word at `word_idx` (1-based, counted from the command) does not fit the template.
+    Unexpected {
+        word_idx: usize,
+        found: String,
+        expected: String,
+    },
+    /// The subject ended before the template was complete.
+    Missing { word_idx: usize, expected: String },
+    /// The template was complete but the subject has more words.
+    Trailing { word_idx: usize, found: String },
+    /// The subject was understood but is not written in the exact form the contract verifies.
+    NotCanonical { canonical: String },
+}
+
+impl SubjectParseError {
+    // How far the parser got, used to report the most relevant error among several templates.
+    fn progress(&self) -> usize {
+        match self {
+            Self::Unexpected { word_idx, .. }
+            | Self::Missing { word_idx, .. }
+            | Self::Trailing { word_idx, .. } => *word_idx,
+            Self::NotCanonical { .. } => usize::MAX,
+        }
+    }
+}
+
+impl std::fmt::Display for SubjectParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unexpected {
+                word_idx,
+                found,
+                expected,
+            } => write!(
+                f,
+                "{} at word {} `{}`: expected {}",
+                WRONG_SUBJECT_FORMAT, word_idx, found, expected
             ),
-            None => (amount_str.to_string(), "".to_string()),
-        };
-        assert!(after_dot_str.len() <= decimal_size);
-        let num_leading_zeros = decimal_size - after_dot_str.len();
-        after_dot_str.push_str(&"0".repeat(num_leading_zeros));
-        U256::from_dec_str(&(before_dot_str + &after_dot_str))
-            .expect("composed amount string is not valid decimal")
+            Self::Missing { word_idx, expected } => write!(
+                f,
+                "{} at word {}: the subject ended where {} was expected",
+                WRONG_SUBJECT_FORMAT, word_idx, expected
+            ),
+            Self::Trailing { word_idx, found } => write!(
+                f,
+                "{} at word {} `{}`: unexpected words after the end of the command",
+                WRONG_SUBJECT_FORMAT, word_idx, found
+            ),
+            Self::NotCanonical { canonical } => {
+                write!(f, "{}. Did you mean `{}`?", WRONG_SUBJECT_FORMAT, canonical)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SubjectParseError {}
+
+// Punctuation users tend to put around words, e.g. `Send 10 USDC to bob@example.com.`
+const OPENING_PUNCTUATION: &[char] = &['"', '\'', '(', '<', '[', '「', '『'];
+const CLOSING_PUNCTUATION: &[char] = &[
+    ',', '.', ';', ':', '!', '?', '"', '\'', ')', '>', ']', '」', '』', '、', '。',
+];
+
+/// Splits a subject into words, ignoring repeated whitespace and surrounding punctuation.
+pub fn tokenize_subject(input: &str) -> Vec<SubjectToken> {
+    let mut tokens = vec![];
+    let mut word_start = None;
+    for (idx, c) in input.char_indices().chain([(input.len(), ' ')]) {
+        match (c.is_whitespace(), word_start) {
+            (true, Some(start)) => {
+                let word = &input[start..idx];
+                let trimmed = word.trim_start_matches(OPENING_PUNCTUATION);
+                let text = trimmed.trim_end_matches(CLOSING_PUNCTUATION);
+                if !text.is_empty() {
+                    tokens.push(SubjectToken {
+                        text: text.to_string(),
+                        start: start + word.len() - trimmed.len(),
+                    });
+                }
+                word_start = None;
+            }
+            (false, None) => word_start = Some(idx),
+            _ => {}
+        }
     }
+    tokens
 }
 
+const BUILTIN_COMMANDS: [&str; 17] = [
+    SEND_COMMAND,
+    EXECUTE_COMMAND,
+    INSTALL_COMMAND,
+    UNINSTALL_COMMAND,
+    EXIT_COMMAND,
+    DKIM_COMMAND,
+    SAFE_COMMAND,
+    BALANCE_COMMAND,
+    CANCEL_COMMAND,
+    SCHEDULE_COMMAND,
+    SCHEDULES_COMMAND,
+    UNSCHEDULE_COMMAND,
+    REQUEST_COMMAND,
+    FEE_COMMAND,
+    CONTACT_COMMAND,
+    CONTACTS_COMMAND,
+    NOTIFICATIONS_COMMAND,
+];
+
 pub async fn extract_command_from_subject(
     subject: &str,
     account_salt: &AccountSalt,
 ) -> Result<(String, usize)> {
-    for word in subject.split_whitespace() {
-        let position = subject.find(word).unwrap();
-        if word == SEND_COMMAND
-            || word == EXECUTE_COMMAND
-            || word == INSTALL_COMMAND
-            || word == UNINSTALL_COMMAND
-            || word == EXIT_COMMAND
-            || word == DKIM_COMMAND
-            || word == SAFE_COMMAND
+    let tokens = tokenize_subject(subject);
+    for (idx, token) in tokens.iter().enumerate() {
+        // The `fee` of a `Max fee` clause in the prefix is not the `Fee` command.
+        if idx > 0
+            && tokens[idx - 1]
+                .text
+                .eq_ignore_ascii_case(MAX_FEE_CLAUSE_WORD)
         {
-            return Ok((word.to_string(), position));
-        } else if CLIENT
-            .query_user_extension_for_command(account_salt, word)
-            .await?
-            != Address::zero()
+            continue;
+        }
+        if BUILTIN_COMMANDS.contains(&token.text.as_str())
+            || CLIENT
+                .query_user_extension_for_command(account_salt, &token.text)
+                .await?
+                != Address::zero()
         {
-            return Ok((word.to_string(), position));
+            return Ok((token.text.clone(), token.start));
         }
     }
-    Err(anyhow!(
-        "No command found in the subject, subject: {}",
-        subject
-    ))
+    match suggest_subject(&tokens) {
+        Some(suggestion) => Err(anyhow!(
+            "No command found in the subject. Did you mean `{}`?",
+            suggestion
+        )),
+        None => Err(anyhow!(
+            "No command found in the subject, subject: {}",
+            subject
+        )),
+    }
+}
+
+// Finds the word closest to a built-in command and rewrites the subject from there on.
+fn suggest_subject(tokens: &[SubjectToken]) -> Option<String> {
+    let (token_idx, command, _) = tokens
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, token)| {
+            BUILTIN_COMMANDS.iter().map(move |command| {
+                (
+                    idx,
+                    *command,
+                    edit_distance(&token.text.to_lowercase(), &command.to_lowercase()),
+                )
+            })
+        })
+        .filter(|(_, command, distance)| *distance <= (command.len() / 3).clamp(1, 2))
+        .min_by_key(|(_, _, distance)| *distance)?;
+    let mut words = vec![command.to_string()];
+    words.extend(tokens[token_idx + 1..].iter().map(|t| t.text.clone()));
+    let subject = words.join(" ");
+    match builtin_subject_templates(command) {
+        Some(templates) => match parse_template(&tokenize_subject(&subject), &templates) {
+            Ok((_, canonical)) => Some(canonical),
+            Err(_) => Some(subject),
+        },
+        None => Some(subject),
+    }
+}
+
+// Levenshtein distance that also counts a swap of two adjacent characters as one edit.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut dist = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in dist.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in dist[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            dist[i][j] = (dist[i - 1][j - 1] + cost)
+                .min(dist[i - 1][j] + 1)
+                .min(dist[i][j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                dist[i][j] = dist[i][j].min(dist[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    dist[a.len()][b.len()]
+}
+
+/// Returns the subject template of a built-in command, or None for extension commands.
+pub fn builtin_subject_templates(command: &str) -> Option<Vec<String>> {
+    let templates: &[&str] = match command {
+        SEND_COMMAND => &[SEND_COMMAND, "{tokenAmount}", "to", "{recipient}"],
+        EXECUTE_COMMAND => &[EXECUTE_COMMAND, "{string}"],
+        INSTALL_COMMAND => &[INSTALL_COMMAND, "extension", "{string}"],
+        UNINSTALL_COMMAND => &[UNINSTALL_COMMAND, "extension", "{string}"],
+        EXIT_COMMAND => &[
+            EXIT_COMMAND,
+            "Email",
+            "Wallet",
+            "Change",
+            "Ownership",
+            "to",
+            "{address}",
+        ],
+        DKIM_COMMAND => &[DKIM_COMMAND, "registry", "set", "to", "{address}"],
+        BALANCE_COMMAND => &[BALANCE_COMMAND],
+        CANCEL_COMMAND => &[CANCEL_COMMAND, "{recipient}"],
+        SCHEDULE_COMMAND => return schedule_subject_templates().into_iter().next(),
+        SCHEDULES_COMMAND => &[SCHEDULES_COMMAND],
+        UNSCHEDULE_COMMAND => &[UNSCHEDULE_COMMAND, "{uint}"],
+        REQUEST_COMMAND => &[REQUEST_COMMAND, "{tokenAmount}", "from", "{recipient}"],
+        FEE_COMMAND => return fee_subject_templates().into_iter().next(),
+        CONTACT_COMMAND => return contact_subject_templates().into_iter().next(),
+        CONTACTS_COMMAND => &[CONTACTS_COMMAND],
+        NOTIFICATIONS_COMMAND => return notifications_subject_templates().into_iter().next(),
+        _ => return None,
+    };
+    Some(templates.iter().map(|t| t.to_string()).collect())
 }
 
 pub fn extract_template_vals_send(input: &str) -> Result<Vec<TemplateValue>> {
-    let templates = vec![
-        SEND_COMMAND.to_string(),
-        "{tokenAmount}".to_string(),
-        "to".to_string(),
-        "{recipient}".to_string(),
-    ];
-    extract_template_vals(input, templates)
+    extract_template_vals(input, builtin_subject_templates(SEND_COMMAND).unwrap())
 }
 
 pub fn extract_template_vals_execute(input: &str) -> Result<Vec<TemplateValue>> {
-    let templates = vec![EXECUTE_COMMAND.to_string(), "{string}".to_string()];
-    let vals = extract_template_vals(input, templates)?;
+    let vals = extract_template_vals(input, builtin_subject_templates(EXECUTE_COMMAND).unwrap())?;
     if let TemplateValue::String(hex) = &vals[0] {
         let hex_match = Regex::new("0x[0-9a-fA-F]+")
             .unwrap()
@@ -139,276 +402,601 @@ pub fn extract_template_vals_execute(input: &str) -> Result<Vec<TemplateValue>>
 }
 
 pub fn extract_template_vals_install(input: &str) -> Result<Vec<TemplateValue>> {
-    let templates = vec![
-        INSTALL_COMMAND.to_string(),
-        "extension".to_string(),
-        "{string}".to_string(),
-    ];
-    extract_template_vals(input, templates)
+    extract_template_vals(input, builtin_subject_templates(INSTALL_COMMAND).unwrap())
 }
 
 pub fn extract_template_vals_uninstall(input: &str) -> Result<Vec<TemplateValue>> {
-    let templates = vec![
-        UNINSTALL_COMMAND.to_string(),
-        "extension".to_string(),
-        "{string}".to_string(),
-    ];
-    extract_template_vals(input, templates)
+    extract_template_vals(input, builtin_subject_templates(UNINSTALL_COMMAND).unwrap())
 }
 
 pub fn extract_template_vals_exit(input: &str) -> Result<Vec<TemplateValue>> {
-    let templates = vec![
-        EXIT_COMMAND.to_string(),
-        "Email".to_string(),
-        "Wallet".to_string(),
-        "Change".to_string(),
-        "Ownership".to_string(),
-        "to".to_string(),
-        "{address}".to_string(),
-    ];
-    extract_template_vals(input, templates)
+    extract_template_vals(input, builtin_subject_templates(EXIT_COMMAND).unwrap())
 }
 
 pub fn extract_template_vals_dkim(input: &str) -> Result<Vec<TemplateValue>> {
-    let templates = vec![
-        DKIM_COMMAND.to_string(),
-        "registry".to_string(),
-        "set".to_string(),
-        "to".to_string(),
-        "{address}".to_string(),
+    extract_template_vals(input, builtin_subject_templates(DKIM_COMMAND).unwrap())
+}
+
+pub fn extract_template_vals_balance(input: &str) -> Result<Vec<TemplateValue>> {
+    extract_template_vals(input, builtin_subject_templates(BALANCE_COMMAND).unwrap())
+}
+
+pub fn extract_template_vals_cancel(input: &str) -> Result<Vec<TemplateValue>> {
+    extract_template_vals(input, builtin_subject_templates(CANCEL_COMMAND).unwrap())
+}
+
+/// Monthly and weekly forms of the `Schedule` command, in this order.
+pub fn schedule_subject_templates() -> Vec<Vec<String>> {
+    let templates: [&[&str]; 2] = [
+        &[
+            SCHEDULE_COMMAND,
+            "{tokenAmount}",
+            "to",
+            "{recipient}",
+            "monthly",
+            "on",
+            "day",
+            "{uint}",
+        ],
+        &[
+            SCHEDULE_COMMAND,
+            "{tokenAmount}",
+            "to",
+            "{recipient}",
+            "weekly",
+            "on",
+            "{string}",
+        ],
+    ];
+    templates
+        .iter()
+        .map(|template| template.iter().map(|t| t.to_string()).collect())
+        .collect()
+}
+
+pub fn extract_template_vals_schedule(input: &str) -> Result<(usize, Vec<TemplateValue>)> {
+    extract_template_vals_and_idx(input, schedule_subject_templates())
+}
+
+pub fn extract_template_vals_schedules(input: &str) -> Result<Vec<TemplateValue>> {
+    extract_template_vals(input, builtin_subject_templates(SCHEDULES_COMMAND).unwrap())
+}
+
+pub fn extract_template_vals_unschedule(input: &str) -> Result<Vec<TemplateValue>> {
+    extract_template_vals(
+        input,
+        builtin_subject_templates(UNSCHEDULE_COMMAND).unwrap(),
+    )
+}
+
+pub fn extract_template_vals_request(input: &str) -> Result<Vec<TemplateValue>> {
+    extract_template_vals(input, builtin_subject_templates(REQUEST_COMMAND).unwrap())
+}
+
+/// `Fee token <token>`, `Fee cap <amount> <token>` and `Fee cap none`.
+pub fn fee_subject_templates() -> Vec<Vec<String>> {
+    let templates: [&[&str]; 3] = [
+        &[FEE_COMMAND, "token", "{string}"],
+        &[FEE_COMMAND, "cap", "{tokenAmount}"],
+        &[FEE_COMMAND, "cap", "none"],
+    ];
+    templates
+        .iter()
+        .map(|template| template.iter().map(|t| t.to_string()).collect())
+        .collect()
+}
+
+pub fn extract_template_vals_fee(input: &str) -> Result<(usize, Vec<TemplateValue>)> {
+    extract_template_vals_and_idx(input, fee_subject_templates())
+}
+
+/// `Contact add <nickname> <recipient>` and `Contact remove <nickname>`.
+pub fn contact_subject_templates() -> Vec<Vec<String>> {
+    let templates: [&[&str]; 2] = [
+        &[CONTACT_COMMAND, "add", "{string}", "{recipient}"],
+        &[CONTACT_COMMAND, "remove", "{string}"],
+    ];
+    templates
+        .iter()
+        .map(|template| template.iter().map(|t| t.to_string()).collect())
+        .collect()
+}
+
+pub fn extract_template_vals_contact(input: &str) -> Result<(usize, Vec<TemplateValue>)> {
+    extract_template_vals_and_idx(input, contact_subject_templates())
+}
+
+pub fn extract_template_vals_contacts(input: &str) -> Result<Vec<TemplateValue>> {
+    extract_template_vals(input, builtin_subject_templates(CONTACTS_COMMAND).unwrap())
+}
+
+/// `Notifications on` and `Notifications off`.
+pub fn notifications_subject_templates() -> Vec<Vec<String>> {
+    let templates: [&[&str]; 2] = [
+        &[NOTIFICATIONS_COMMAND, "on"],
+        &[NOTIFICATIONS_COMMAND, "off"],
     ];
-    extract_template_vals(input, templates)
+    templates
+        .iter()
+        .map(|template| template.iter().map(|t| t.to_string()).collect())
+        .collect()
 }
 
+pub fn extract_template_vals_notifications(input: &str) -> Result<usize> {
+    let (idx, _) = extract_template_vals_and_idx(input, notifications_subject_templates())?;
+    Ok(idx)
+}
+
+/// Tries each template in order. If none matches, returns the error of the template the subject got furthest into.
 pub fn extract_template_vals_and_idx(
     input: &str,
     templates_array: Vec<Vec<String>>,
-) -> Result<(Option<usize>, Vec<TemplateValue>)> {
+) -> Result<(usize, Vec<TemplateValue>)> {
+    let mut best_error: Option<SubjectParseError> = None;
     for (idx, templates) in templates_array.into_iter().enumerate() {
-        let template_vals = extract_template_vals(input, templates);
-        match template_vals {
+        match parse_subject(input, &templates) {
             Ok(vals) => {
-                return Ok((Some(idx), vals));
+                return Ok((idx, vals));
             }
-            Err(_) => {
-                continue;
+            Err(err) => {
+                if best_error
+                    .as_ref()
+                    .map(|best| err.progress() > best.progress())
+                    .unwrap_or(true)
+                {
+                    best_error = Some(err);
+                }
             }
         }
     }
-    Ok((None, Vec::new()))
+    match best_error {
+        Some(err) => Err(err.into()),
+        None => bail!(WRONG_SUBJECT_FORMAT),
+    }
 }
 
 fn extract_template_vals(input: &str, templates: Vec<String>) -> Result<Vec<TemplateValue>> {
-    let input_decomposed: Vec<&str> = input.split(' ').collect();
+    Ok(parse_subject(input, &templates)?)
+}
+
+fn parse_subject(
+    input: &str,
+    templates: &[String],
+) -> std::result::Result<Vec<TemplateValue>, SubjectParseError> {
+    let (template_vals, canonical) = parse_template(&tokenize_subject(input), templates)?;
+    // The contract rebuilds the subject from the template values, so it must match byte for byte.
+    if canonical != input {
+        return Err(SubjectParseError::NotCanonical { canonical });
+    }
+    Ok(template_vals)
+}
+
+pub fn is_whole_match(pattern: &str, word: &str) -> bool {
+    Regex::new(&format!("^(?:{})$", pattern))
+        .unwrap()
+        .is_match(word)
+}
+
+/// Parses the tokens leniently and returns the values with the canonical form of the subject.
+pub fn parse_template(
+    tokens: &[SubjectToken],
+    templates: &[String],
+) -> std::result::Result<(Vec<TemplateValue>, String), SubjectParseError> {
     let mut template_vals = Vec::new();
+    let mut canonical_words = Vec::new();
     let mut input_idx = 0;
+    let next_word = |input_idx: usize, expected: &str| match tokens.get(input_idx) {
+        Some(token) => Ok(token.text.clone()),
+        None => Err(SubjectParseError::Missing {
+            word_idx: input_idx + 1,
+            expected: expected.to_string(),
+        }),
+    };
+    let unexpected =
+        |input_idx: usize, found: &str, expected: &str| SubjectParseError::Unexpected {
+            word_idx: input_idx + 1,
+            found: found.to_string(),
+            expected: expected.to_string(),
+        };
     for template in templates.iter() {
         match template.as_str() {
             "{tokenAmount}" => {
-                let amount_match = Regex::new(AMOUNT_REGEX)
-                    .unwrap()
-                    .find(input_decomposed[input_idx])
-                    .ok_or(anyhow!("No amount found"))?;
-                if amount_match.start() != 0
-                    || amount_match.end() != input_decomposed[input_idx].len()
-                {
-                    return Err(anyhow!("Amount must be the whole word"));
-                }
-                let amount = amount_match.as_str().to_string();
-                let token_name_match = Regex::new(TOKEN_NAME_REGEX)
-                    .unwrap()
-                    .find(input_decomposed[input_idx + 1])
-                    .ok_or(anyhow!("No token name found"))?;
-                if token_name_match.start() != 0
-                    || token_name_match.end() != input_decomposed[input_idx + 1].len()
-                {
-                    return Err(anyhow!("Token name must be the whole word"));
+                let expected = "an amount such as 10 or 0.5";
+                let word = next_word(input_idx, expected)?;
+                let amount = normalize_amount(&word)
+                    .ok_or_else(|| unexpected(input_idx, &word, expected))?;
+                input_idx += 1;
+                let expected = "a token name such as ETH or USDC";
+                // The name is kept as written, e.g. cbETH, since the contract compares it byte for byte.
+                let token_name = next_word(input_idx, expected)?;
+                if !is_whole_match(&format!("(?i){}", TOKEN_NAME_REGEX), &token_name) {
+                    return Err(unexpected(input_idx, &token_name, expected));
                 }
-                let token_name = token_name_match.as_str().to_string();
+                input_idx += 1;
+                canonical_words.push(format!("{} {}", amount, token_name));
                 template_vals.push(TemplateValue::TokenAmount { token_name, amount });
-                input_idx += 2;
             }
             "{amount}" => {
-                let amount_match = Regex::new(AMOUNT_REGEX)
-                    .unwrap()
-                    .find(input_decomposed[input_idx])
-                    .ok_or(anyhow!("No amount found"))?;
-                if amount_match.start() != 0
-                    || amount_match.end() != input_decomposed[input_idx].len()
-                {
-                    return Err(anyhow!("Amount must be the whole word"));
-                }
-                let amount = amount_match.as_str().to_string();
-                template_vals.push(TemplateValue::Amount(amount));
+                let expected = "an amount such as 10 or 0.5";
+                let word = next_word(input_idx, expected)?;
+                let amount = normalize_amount(&word)
+                    .ok_or_else(|| unexpected(input_idx, &word, expected))?;
                 input_idx += 1;
+                canonical_words.push(amount.clone());
+                template_vals.push(TemplateValue::Amount(amount));
             }
             "{string}" => {
-                let string_match = Regex::new(STRING_RGEX)
-                    .unwrap()
-                    .find(input_decomposed[input_idx])
-                    .ok_or(anyhow!("No string found"))?;
-                if string_match.start() != 0
-                    || string_match.end() != input_decomposed[input_idx].len()
-                {
-                    return Err(anyhow!("String must be the whole word"));
+                let expected = "a word";
+                let string = next_word(input_idx, expected)?;
+                if !is_whole_match(STRING_RGEX, &string) {
+                    return Err(unexpected(input_idx, &string, expected));
                 }
-                let string = string_match.as_str().to_string();
-                template_vals.push(TemplateValue::String(string));
                 input_idx += 1;
+                canonical_words.push(string.clone());
+                template_vals.push(TemplateValue::String(string));
             }
             "{uint}" => {
-                let uint_match = Regex::new(UINT_REGEX)
-                    .unwrap()
-                    .find(input_decomposed[input_idx])
-                    .ok_or(anyhow!("No uint found"))?;
-                if uint_match.start() != 0 || uint_match.end() != input_decomposed[input_idx].len()
-                {
-                    return Err(anyhow!("Uint must be the whole word"));
+                let expected = "a non-negative integer";
+                let word = next_word(input_idx, expected)?;
+                let uint = match is_whole_match(UINT_REGEX, &word) {
+                    true => U256::from_dec_str(&word).ok(),
+                    false => None,
                 }
-                let uint = U256::from_dec_str(uint_match.as_str()).unwrap();
-                template_vals.push(TemplateValue::Uint(uint));
+                .ok_or_else(|| unexpected(input_idx, &word, expected))?;
                 input_idx += 1;
+                canonical_words.push(word);
+                template_vals.push(TemplateValue::Uint(uint));
             }
             "{int}" => {
-                let int_match = Regex::new(INT_REGEX)
-                    .unwrap()
-                    .find(input_decomposed[input_idx])
-                    .ok_or(anyhow!("No int found"))?;
-                if int_match.start() != 0 || int_match.end() != input_decomposed[input_idx].len() {
-                    return Err(anyhow!("Int must be the whole word"));
+                let expected = "an integer";
+                let word = next_word(input_idx, expected)?;
+                let int = match is_whole_match(INT_REGEX, &word) {
+                    true => I256::from_dec_str(&word).ok(),
+                    false => None,
                 }
-                let int_str = int_match.as_str();
-                let int = I256::from_dec_str(int_match.as_str()).unwrap();
-                template_vals.push(TemplateValue::Int(int));
+                .ok_or_else(|| unexpected(input_idx, &word, expected))?;
                 input_idx += 1;
+                canonical_words.push(word);
+                template_vals.push(TemplateValue::Int(int));
             }
             "{address}" => {
-                let address_match = Regex::new(ETH_ADDR_REGEX)
-                    .unwrap()
-                    .find(input_decomposed[input_idx])
-                    .ok_or(anyhow!("No address found"))?;
-                if address_match.start() != 0
-                    || address_match.end() != input_decomposed[input_idx].len()
-                {
-                    return Err(anyhow!("Address must be the whole word"));
+                let expected = "an Ethereum address (0x followed by 40 hex characters)";
+                let word = next_word(input_idx, expected)?;
+                if !is_whole_match(ETH_ADDR_REGEX, &word) {
+                    return Err(unexpected(input_idx, &word, expected));
                 }
-                let address = address_match.as_str().parse::<Address>().unwrap();
-                template_vals.push(TemplateValue::Address(address));
+                let address = word.parse::<Address>().unwrap();
                 input_idx += 1;
+                canonical_words.push(word);
+                template_vals.push(TemplateValue::Address(address));
             }
             "{recipient}" => {
-                let email_addr_match = Regex::new(EMAIL_ADDR_REGEX)
-                    .unwrap()
-                    .find(input_decomposed[input_idx]);
-                let eth_addr_match = Regex::new(ETH_ADDR_REGEX)
-                    .unwrap()
-                    .find(input_decomposed[input_idx]);
-                let is_email = if let Some(email_addr_match) = email_addr_match {
-                    if email_addr_match.start() != 0
-                        || email_addr_match.end() != input_decomposed[input_idx].len()
-                    {
-                        return Err(anyhow!("Email address must be the whole word"));
+                let expected = "an email address or an Ethereum address";
+                let word = next_word(input_idx, expected)?;
+                let recipient = if is_whole_match(EMAIL_ADDR_REGEX, &word) {
+                    TemplateValue::Recipient {
+                        is_email: true,
+                        email_addr: Some(word.clone()),
+                        eth_addr: None,
                     }
-                    true
-                } else if let Some(eth_addr_match) = eth_addr_match {
-                    if eth_addr_match.start() != 0
-                        || eth_addr_match.end() != input_decomposed[input_idx].len()
-                    {
-                        return Err(anyhow!("Eth address must be the whole word"));
+                } else if is_whole_match(ETH_ADDR_REGEX, &word) {
+                    TemplateValue::Recipient {
+                        is_email: false,
+                        email_addr: None,
+                        eth_addr: Some(word.parse::<Address>().unwrap()),
                     }
-                    false
-                } else {
-                    return Err(anyhow!("No recipient found"));
-                };
-                let (email_addr, eth_addr) = if is_email {
-                    (Some(email_addr_match.unwrap().as_str().to_string()), None)
                 } else {
-                    (
-                        None,
-                        Some(eth_addr_match.unwrap().as_str().parse::<Address>().unwrap()),
-                    )
+                    return Err(unexpected(input_idx, &word, expected));
                 };
-                template_vals.push(TemplateValue::Recipient {
-                    is_email,
-                    email_addr,
-                    eth_addr,
-                });
                 input_idx += 1;
+                canonical_words.push(word);
+                template_vals.push(recipient);
             }
-            _ => {
+            fixed => {
+                let expected = format!("`{}`", fixed);
+                let word = next_word(input_idx, &expected)?;
+                // Case differences are accepted here and reported through the canonical subject.
+                if !word.eq_ignore_ascii_case(fixed) {
+                    return Err(unexpected(input_idx, &word, &expected));
+                }
                 input_idx += 1;
+                canonical_words.push(fixed.to_string());
             }
         }
     }
-    if input_idx != input_decomposed.len() {
-        return Err(anyhow!("Input is not fully consumed"));
+    if let Some(token) = tokens.get(input_idx) {
+        return Err(SubjectParseError::Trailing {
+            word_idx: input_idx + 1,
+            found: token.text.clone(),
+        });
+    }
+    Ok((template_vals, canonical_words.join(" ")))
+}
+
+pub fn uint_to_decimal_string(amount: U256, decimal: usize) -> String {
+    let digits = amount.to_string();
+    if decimal == 0 {
+        return digits;
+    }
+    let padded = format!("{:0>width$}", digits, width = decimal + 1);
+    let (int_part, frac_part) = padded.split_at(padded.len() - decimal);
+    match frac_part.trim_end_matches('0') {
+        "" => int_part.to_string(),
+        frac_part => format!("{}.{}", int_part, frac_part),
     }
-    Ok(template_vals)
 }
 
-// Generated by Github Copilot!
-pub fn uint_to_decimal_string(amount: u128, decimal: usize) -> String {
-    // Convert amount to string in wei format (no decimals)
-    let amount_str = amount.to_string();
-    let amount_length = amount_str.len();
-
-    // Create result vector with max length
-    // If less than 18 decimals, then 2 extra for "0.", otherwise one extra for "."
-    let mut result = vec![
-        '0';
-        if amount_length > decimal {
-            amount_length + 1
-        } else {
-            decimal + 2
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn all_templates() -> Vec<Vec<String>> {
+        let mut templates: Vec<Vec<String>> = BUILTIN_COMMANDS
+            .iter()
+            .filter_map(|command| builtin_subject_templates(command))
+            .collect();
+        templates.extend(schedule_subject_templates());
+        templates.extend(fee_subject_templates());
+        templates.extend(contact_subject_templates());
+        templates.extend(notifications_subject_templates());
+        templates
+    }
+
+    fn canonical_amount() -> impl Strategy<Value = String> {
+        (
+            0u64..1_000_000_000_000,
+            proptest::option::of("[0-9]{0,5}[1-9]"),
+        )
+            .prop_map(|(int_part, frac_part)| match frac_part {
+                Some(frac_part) => format!("{}.{}", int_part, frac_part),
+                None => int_part.to_string(),
+            })
+    }
+
+    fn eth_addr() -> impl Strategy<Value = String> {
+        "0x[0-9a-f]{40}"
+    }
+
+    // Renders one template word and the value the parser must return for it.
+    fn slot(template: &str) -> BoxedStrategy<(String, Option<TemplateValue>)> {
+        match template {
+            "{tokenAmount}" => (canonical_amount(), "[A-Z]{1,6}")
+                .prop_map(|(amount, token_name)| {
+                    (
+                        format!("{} {}", amount, token_name),
+                        Some(TemplateValue::TokenAmount { token_name, amount }),
+                    )
+                })
+                .boxed(),
+            "{amount}" => canonical_amount()
+                .prop_map(|amount| (amount.clone(), Some(TemplateValue::Amount(amount))))
+                .boxed(),
+            "{string}" => "[a-zA-Z0-9_]{1,12}"
+                .prop_map(|string| (string.clone(), Some(TemplateValue::String(string))))
+                .boxed(),
+            "{uint}" => any::<u64>()
+                .prop_map(|uint| {
+                    (
+                        uint.to_string(),
+                        Some(TemplateValue::Uint(U256::from(uint))),
+                    )
+                })
+                .boxed(),
+            "{int}" => any::<i64>()
+                .prop_map(|int| (int.to_string(), Some(TemplateValue::Int(I256::from(int)))))
+                .boxed(),
+            "{address}" => eth_addr()
+                .prop_map(|addr| {
+                    let value = TemplateValue::Address(addr.parse().unwrap());
+                    (addr, Some(value))
+                })
+                .boxed(),
+            "{recipient}" => prop_oneof![
+                "[a-z0-9._]{1,10}@[a-z0-9]{1,10}\\.(com|org|io)".prop_map(|email_addr| {
+                    (
+                        email_addr.clone(),
+                        Some(TemplateValue::Recipient {
+                            is_email: true,
+                            email_addr: Some(email_addr),
+                            eth_addr: None,
+                        }),
+                    )
+                }),
+                eth_addr().prop_map(|addr| {
+                    let value = TemplateValue::Recipient {
+                        is_email: false,
+                        email_addr: None,
+                        eth_addr: Some(addr.parse().unwrap()),
+                    };
+                    (addr, Some(value))
+                }),
+            ]
+            .boxed(),
+            fixed => Just((fixed.to_string(), None)).boxed(),
         }
-    ];
-    let result_length = result.len();
+    }
 
-    // Difference between result and amount array index when copying
-    // If more than 18, then 1 index diff for ".", otherwise actual diff in length
-    let mut delta = if amount_length > decimal {
-        1
-    } else {
-        result_length - amount_length
-    };
+    fn rendered_subject() -> impl Strategy<Value = (Vec<String>, String, Vec<TemplateValue>)> {
+        (0..all_templates().len()).prop_flat_map(|idx| {
+            let templates = all_templates().swap_remove(idx);
+            let slots = templates.iter().map(|t| slot(t)).collect::<Vec<_>>();
+            (Just(templates), slots).prop_map(|(templates, slots)| {
+                let subject = slots
+                    .iter()
+                    .map(|(word, _)| word.clone())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let values = slots.into_iter().filter_map(|(_, value)| value).collect();
+                (templates, subject, values)
+            })
+        })
+    }
 
-    // Boolean to indicate if we found a non-zero digit when scanning from last to first index
-    let mut found_non_zero_decimal = false;
+    proptest! {
+        #[test]
+        fn parses_rendered_subjects((templates, subject, values) in rendered_subject()) {
+            let parsed = parse_subject(&subject, &templates).unwrap();
+            prop_assert_eq!(format!("{:?}", parsed), format!("{:?}", values));
+        }
 
-    let mut actual_result_len = 0;
+        #[test]
+        fn tolerates_spacing_and_punctuation((templates, subject, values) in rendered_subject()) {
+            let sloppy = format!("  {}. ", subject.replace(' ', "   "));
+            let (parsed, canonical) = parse_template(&tokenize_subject(&sloppy), &templates).unwrap();
+            prop_assert_eq!(canonical, subject);
+            prop_assert_eq!(format!("{:?}", parsed), format!("{:?}", values));
+        }
 
-    // In each iteration we fill one index of result array (starting from end)
-    for i in (0..result_length).rev() {
-        // Check if we have reached the index where we need to add decimal point
-        if i == result_length - decimal - 1 {
-            // No need to add "." if there was no value in decimal places
-            if found_non_zero_decimal {
-                result[i] = '.';
-                actual_result_len += 1;
+        #[test]
+        fn never_panics_on_arbitrary_subjects(subject in "\\PC{0,80}") {
+            for templates in all_templates() {
+                let _ = parse_subject(&subject, &templates);
             }
-            // Set delta to 0, as we have already added decimal point (only for amount_length > 18)
-            delta = 0;
         }
-        // If amountLength < 18 and we have copied everything, fill zeros
-        else if amount_length <= decimal && i < result_length - amount_length {
-            result[i] = '0';
-            actual_result_len += 1;
+
+        #[test]
+        fn round_trips_amounts(raw in any::<u128>(), decimals in 0u8..=18) {
+            let amount = U256::from(raw);
+            let rendered = uint_to_decimal_string(amount, decimals as usize);
+            prop_assert_eq!(normalize_amount(&rendered), Some(rendered.clone()));
+            prop_assert_eq!(TemplateValue::amount_to_uint(&rendered, decimals).unwrap(), amount);
         }
-        // If non-zero decimal is found, or decimal point inserted (delta == 0), copy from amount array
-        else if found_non_zero_decimal || delta == 0 {
-            result[i] = amount_str.chars().nth(i - delta).unwrap();
-            actual_result_len += 1;
+
+        #[test]
+        fn accepts_localized_amounts(raw in any::<u64>(), decimals in 0u8..=18) {
+            let amount = U256::from(raw);
+            let rendered = uint_to_decimal_string(amount, decimals as usize);
+            let (int_part, frac_part) = rendered.split_once('.').unwrap_or((&rendered, ""));
+            let mut grouped = String::new();
+            for (idx, digit) in int_part.chars().enumerate() {
+                if idx > 0 && (int_part.len() - idx) % 3 == 0 {
+                    grouped.push(',');
+                }
+                grouped.push(digit);
+            }
+            if !frac_part.is_empty() {
+                grouped = format!("{}.{}", grouped, frac_part);
+            }
+            let full_width: String = grouped
+                .chars()
+                .map(|c| match c {
+                    '0'..='9' => char::from_u32('０' as u32 + c.to_digit(10).unwrap()).unwrap(),
+                    '.' => '．',
+                    ',' => '，',
+                    c => c,
+                })
+                .collect();
+            for localized in [&grouped, &full_width] {
+                prop_assert_eq!(normalize_amount(localized), Some(rendered.clone()));
+                prop_assert_eq!(TemplateValue::amount_to_uint(localized, decimals).unwrap(), amount);
+            }
         }
-        // If we find non-zero decimal for the first time (trailing zeros are skipped)
-        else if amount_str.chars().nth(i - delta).unwrap() != '0' {
-            result[i] = amount_str.chars().nth(i - delta).unwrap();
-            actual_result_len += 1;
-            found_non_zero_decimal = true;
+    }
+
+    #[test]
+    fn converts_amounts_at_the_edges() {
+        assert_eq!(
+            TemplateValue::amount_to_uint("0", 18).unwrap(),
+            U256::zero()
+        );
+        assert_eq!(
+            TemplateValue::amount_to_uint("0.000", 0).unwrap(),
+            U256::zero()
+        );
+        assert_eq!(
+            TemplateValue::amount_to_uint("1.000000000000000001", 18).unwrap(),
+            U256::exp10(18) + U256::one()
+        );
+        assert!(TemplateValue::amount_to_uint("1.0000000000000000001", 18).is_err());
+        assert!(TemplateValue::amount_to_uint("0.5", 0).is_err());
+        assert!(TemplateValue::amount_to_uint(&"9".repeat(78), 0).is_err());
+        assert!(TemplateValue::amount_to_uint("1,00", 18).is_err());
+        assert_eq!(uint_to_decimal_string(U256::from(1500), 3), "1.5");
+        assert_eq!(uint_to_decimal_string(U256::from(5), 3), "0.005");
+    }
+
+    #[test]
+    fn canonicalizes_amount_notation() {
+        assert_eq!(
+            canonicalize_amount_notation(SEND_COMMAND, "Send 1,000.50 ETH to bob@example.com")
+                .as_deref(),
+            Some("Send 1000.5 ETH to bob@example.com")
+        );
+        assert_eq!(
+            canonicalize_amount_notation(SEND_COMMAND, "Send １０ ETH to bob@example.com")
+                .as_deref(),
+            Some("Send 10 ETH to bob@example.com")
+        );
+        assert_eq!(
+            canonicalize_amount_notation(SEND_COMMAND, "Send 10 ETH to bob@example.com"),
+            None
+        );
+        assert_eq!(
+            canonicalize_amount_notation(SEND_COMMAND, "Send 1,000 ETH to nobody"),
+            None
+        );
+    }
+
+    #[test]
+    fn reports_the_position_of_errors() {
+        let templates = builtin_subject_templates(SEND_COMMAND).unwrap();
+        match parse_subject("Send ten ETH to bob@example.com", &templates) {
+            Err(SubjectParseError::Unexpected {
+                word_idx, found, ..
+            }) => {
+                assert_eq!(word_idx, 2);
+                assert_eq!(found, "ten");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(matches!(
+            parse_subject("Send 1 ETH to", &templates),
+            Err(SubjectParseError::Missing { word_idx: 5, .. })
+        ));
+        assert!(matches!(
+            parse_subject("Send 1 ETH to bob@example.com now", &templates),
+            Err(SubjectParseError::Trailing { word_idx: 6, .. })
+        ));
+        assert!(matches!(
+            parse_subject("send 1.50 eth to bob@example.com", &templates),
+            Err(SubjectParseError::NotCanonical { canonical })
+                if canonical == "Send 1.5 eth to bob@example.com"
+        ));
+    }
+
+    #[test]
+    fn keeps_the_case_of_token_names() {
+        let templates = builtin_subject_templates(SEND_COMMAND).unwrap();
+        for token in ["cbETH", "ETH", "usdc"] {
+            let subject = format!("Send 1 {} to bob@example.com", token);
+            match parse_subject(&subject, &templates).unwrap().first() {
+                Some(TemplateValue::TokenAmount { token_name, .. }) => {
+                    assert_eq!(token_name, token)
+                }
+                other => panic!("unexpected value {:?}", other),
+            }
         }
+        assert!(parse_subject("Send 1 cb-ETH to bob@example.com", &templates).is_err());
     }
 
-    // Create final result string with correct length
-    let compact_result: String = result.into_iter().take(actual_result_len).collect();
+    #[test]
+    fn rejects_subjects_matching_no_template() {
+        assert!(extract_template_vals_and_idx("Fee cap", fee_subject_templates()).is_err());
+        assert!(extract_template_vals_and_idx("anything", vec![]).is_err());
+        assert_eq!(
+            extract_template_vals_and_idx("Fee cap none", fee_subject_templates())
+                .unwrap()
+                .0,
+            2
+        );
+    }
 
-    compact_result
+    #[test]
+    fn tokenizes_with_offsets() {
+        let tokens = tokenize_subject("  Send \"10\" ETH,  to <bob@example.com>.");
+        let words: Vec<_> = tokens.iter().map(|t| t.text.as_str()).collect();
+        assert_eq!(words, vec!["Send", "10", "ETH", "to", "bob@example.com"]);
+        assert_eq!(tokens[1].start, 8);
+    }
 }
@@ -7,7 +7,9 @@ use ethers::{
     abi::{self, ParamType, Token},
     types::{Address, U256},
 };
+use hmac::{Hmac, Mac};
 use relayer_utils::*;
+use sha2::Sha256;
 use serde_json::Value;
 
 use ::serde::Deserialize;
@@ -72,27 +74,29 @@ impl ProofJson {
     }
 }
 
+/// The PSI point of an email address blinded by `rand`, i.e. `rand * hash_to_curve(email_addr)` on Grumpkin.
 pub async fn compute_psi_point(
     circuits_dir_path: &Path,
     email_addr: &str,
     rand: &str,
 ) -> Result<Point> {
-    info!(LOG, "compute_psi_point, path {:?}", circuits_dir_path);
+    let hashed_point = hash_email_addr_to_curve(circuits_dir_path, email_addr).await?;
+    point_scalar_mul(&hashed_point, &parse_grumpkin_scalar(rand)?)
+}
+
+// Hash-to-curve is still done by circom-grumpkin so that the point matches the account creation circuit.
+async fn hash_email_addr_to_curve(circuits_dir_path: &Path, email_addr: &str) -> Result<Point> {
     let input_file_name = PathBuf::new()
         .join(INPUT_FILES_DIR.get().unwrap())
-        .join(email_addr.to_string() + "psi" + ".json");
-    info!(LOG, "input_file_name: {:?}", input_file_name);
+        .join(calculate_default_hash(email_addr) + "psi" + ".json");
 
     let command_str = format!(
-        "--cwd {} psi-step1 --email-addr {} --client-rand {} --output {}",
+        "--cwd {} psi-hash-to-curve --email-addr {} --output {}",
         circuits_dir_path.to_str().unwrap(),
         email_addr,
-        rand,
         input_file_name.to_str().unwrap()
     );
 
-    info!(LOG, "command_str: {}", command_str);
-
     let proc = tokio::process::Command::new("yarn")
         .args(command_str.split_whitespace())
         .stdout(std::process::Stdio::piped())
@@ -100,12 +104,12 @@ pub async fn compute_psi_point(
         .spawn()?;
 
     let output = proc.wait_with_output().await?;
-    println!("stdout: {}", String::from_utf8_lossy(&output.stdout));
-    println!("stderr: {}", String::from_utf8_lossy(&output.stderr));
-
-    // let status = proc.wait().await?;
-    // info!(LOG, "status: {:?}", status);
-    // assert!(status.success());
+    if !output.status.success() {
+        bail!(
+            "psi-hash-to-curve failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
 
     let result = read_to_string(&input_file_name).await?;
     remove_file(input_file_name).await?;
@@ -160,6 +164,14 @@ pub fn derive_relayer_rand(private_key: &str) -> Result<RelayerRand> {
     Ok(RelayerRand::new_from_seed(&seed)?)
 }
 
+/// The key claim links are signed with. It is kept apart from the relayer rand, which is also the PSI secret.
+pub fn derive_claim_link_key(private_key: &str) -> Result<Vec<u8>> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&hex::decode(&private_key[2..])?)
+        .expect("HMAC takes keys of any size");
+    mac.update(b"EMAIL WALLET CLAIM LINK KEY");
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
 pub async fn search_user_assets(email_addr: &str) -> Result<Vec<Asset>> {
     let claims = DB.get_claims_by_email_addr(email_addr).await?;
     let _is_for_nft_demo = false;
@@ -170,8 +182,7 @@ pub async fn search_user_assets(email_addr: &str) -> Result<Vec<Asset>> {
             let token_decimal = CLIENT
                 .query_decimals_of_erc20_address(unclaim_fund.token_addr)
                 .await?;
-            let amount =
-                uint_to_decimal_string(unclaim_fund.amount.as_u128(), token_decimal as usize);
+            let amount = uint_to_decimal_string(unclaim_fund.amount, token_decimal as usize);
             let name = CLIENT.query_token_name(unclaim_fund.token_addr).await?;
             assets.push(Asset::ERC20 {
                 token_addr: unclaim_fund.token_addr,
@@ -209,17 +220,259 @@ pub async fn get_nft_info(state: &[u8]) -> Result<(Address, U256, String, String
     Ok((nft_addr, nft_id, nft_name, nft_uri))
 }
 
+const NFT_IMAGE_MAX_BYTES: usize = 2 * 1024 * 1024;
+/// Longest side in pixels of an SVG NFT image once rasterized.
+const NFT_SVG_MAX_SIDE: f32 = 512.0;
+const NFT_FETCH_TIMEOUT_SECS: u64 = 10;
+const IPFS_GATEWAY: &str = "https://ipfs.io/ipfs/";
+
+/// Downloads NFT metadata or an image. The URL comes from the token contract, i.e. from anyone, so only
+/// `https` (or `ipfs` through the gateway) to public addresses is fetched, without following redirects.
 pub async fn download_img_from_uri(url: &str) -> Result<Vec<u8>> {
-    let response = reqwest::get(url).await?;
-    let bytes = response.bytes().await.map_err(|e| anyhow!(e))?;
-    Ok(bytes.to_vec())
+    let url = reqwest::Url::parse(&resolve_ipfs_uri(url))?;
+    if url.scheme() != "https" {
+        return Err(anyhow!("Refusing to fetch {}: only https is allowed", url));
+    }
+    let host = url
+        .host_str()
+        .ok_or(anyhow!("Refusing to fetch {}: no host", url))?
+        .to_string();
+    let port = url.port_or_known_default().unwrap_or(443);
+    let addrs = tokio::net::lookup_host((host.trim_matches(|c| c == '[' || c == ']'), port))
+        .await?
+        .collect::<Vec<_>>();
+    if addrs.is_empty() || addrs.iter().any(|addr| !is_public_ip(&addr.ip())) {
+        return Err(anyhow!(
+            "Refusing to fetch {}: it resolves to a non-public address",
+            url
+        ));
+    }
+    // Pin the checked addresses, so that a second DNS lookup cannot point the request elsewhere.
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(NFT_FETCH_TIMEOUT_SECS))
+        .redirect(reqwest::redirect::Policy::none())
+        .resolve(&host, addrs[0])
+        .build()?;
+    let mut response = client.get(url.clone()).send().await?.error_for_status()?;
+    if response.content_length().unwrap_or(0) > NFT_IMAGE_MAX_BYTES as u64 {
+        return Err(anyhow!("Image at {} is too large", url));
+    }
+    // The Content-Length header can be missing or wrong, so the limit is also applied while reading.
+    let mut bytes = vec![];
+    while let Some(chunk) = response.chunk().await? {
+        bytes.extend_from_slice(&chunk);
+        if bytes.len() > NFT_IMAGE_MAX_BYTES {
+            return Err(anyhow!("Image at {} is too large", url));
+        }
+    }
+    Ok(bytes)
+}
+
+/// Whether the address is reachable on the public internet, i.e. not loopback, private, link-local
+/// (including cloud metadata endpoints), shared, multicast or otherwise reserved.
+pub fn is_public_ip(ip: &std::net::IpAddr) -> bool {
+    match ip {
+        std::net::IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 192 && b == 0)
+                || (a == 198 && (18..20).contains(&b))
+                || a >= 240)
+        }
+        std::net::IpAddr::V6(ip) => {
+            if let Some(ip) = embedded_ipv4(ip) {
+                return is_public_ip(&std::net::IpAddr::V4(ip));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80
+                || (first == 0x2001 && ip.segments()[1] == 0x0db8))
+        }
+    }
+}
+
+// The IPv4 address that an IPv4-mapped (::ffff:0:0/96), IPv4-compatible (::/96), 6to4 (2002::/16) or
+// NAT64 (64:ff9b::/96) address reaches, so that it is checked like the IPv4 address itself.
+fn embedded_ipv4(ip: &std::net::Ipv6Addr) -> Option<std::net::Ipv4Addr> {
+    let octets = ip.octets();
+    let segments = ip.segments();
+    let last_four = |from: usize| {
+        std::net::Ipv4Addr::new(
+            octets[from],
+            octets[from + 1],
+            octets[from + 2],
+            octets[from + 3],
+        )
+    };
+    if let Some(ip) = ip.to_ipv4_mapped() {
+        Some(ip)
+    } else if segments[..6] == [0; 6] && !ip.is_loopback() && !ip.is_unspecified() {
+        Some(last_four(12))
+    } else if segments[0] == 0x2002 {
+        Some(last_four(2))
+    } else if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        Some(last_four(12))
+    } else {
+        None
+    }
+}
+
+fn resolve_ipfs_uri(uri: &str) -> String {
+    match uri.strip_prefix("ipfs://") {
+        Some(path) => format!("{}{}", IPFS_GATEWAY, path.trim_start_matches("ipfs/")),
+        None => uri.to_string(),
+    }
+}
+
+// Returns the media type and the decoded contents of a `data:` URI.
+fn decode_data_uri(uri: &str) -> Result<(String, Vec<u8>)> {
+    let (meta, data) = uri
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split_once(','))
+        .ok_or(anyhow!("Invalid data URI"))?;
+    let media_type = meta.split(';').next().unwrap_or_default().to_string();
+    let contents = if meta.ends_with(";base64") {
+        base64::decode(data.trim())?
+    } else {
+        percent_decode(data)
+    };
+    Ok((media_type, contents))
+}
+
+fn percent_decode(data: &str) -> Vec<u8> {
+    let bytes = data.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        if bytes[idx] == b'%' && idx + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[idx + 1..idx + 3]).unwrap_or_default();
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                decoded.push(byte);
+                idx += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[idx]);
+        idx += 1;
+    }
+    decoded
+}
+
+// Detects the image type from its magic bytes rather than trusting the declared one.
+fn sniff_image_content_type(contents: &[u8]) -> Option<&'static str> {
+    if contents.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if contents.starts_with(b"\xff\xd8\xff") {
+        Some("image/jpeg")
+    } else if contents.starts_with(b"GIF87a") || contents.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if contents.len() >= 12 && &contents[0..4] == b"RIFF" && &contents[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        // SVG is not attached as is: mail clients do not render it and it can carry scripts.
+        None
+    }
+}
+
+fn is_svg(contents: &[u8]) -> bool {
+    let head = String::from_utf8_lossy(&contents[..contents.len().min(1024)]).to_ascii_lowercase();
+    let head = head.trim_start_matches('\u{feff}').trim_start();
+    head.starts_with("<svg") || (head.starts_with("<?xml") && head.contains("<svg"))
+}
+
+lazy_static! {
+    static ref SVG_FONTS: std::sync::Arc<resvg::usvg::fontdb::Database> = {
+        let mut fonts = resvg::usvg::fontdb::Database::new();
+        fonts.load_system_fonts();
+        std::sync::Arc::new(fonts)
+    };
+}
+
+/// Rasterizes an SVG image into a PNG of at most NFT_SVG_MAX_SIDE pixels per side.
+/// Images the SVG links to are not loaded, so rendering never touches the network or the file system.
+fn svg_to_png(svg: &[u8]) -> Result<Vec<u8>> {
+    let options = resvg::usvg::Options {
+        image_href_resolver: resvg::usvg::ImageHrefResolver {
+            resolve_data: resvg::usvg::ImageHrefResolver::default_data_resolver(),
+            resolve_string: Box::new(|_, _| None),
+        },
+        fontdb: SVG_FONTS.clone(),
+        ..Default::default()
+    };
+    let tree = resvg::usvg::Tree::from_data(svg, &options)?;
+    let size = tree.size();
+    let scale = (NFT_SVG_MAX_SIDE / size.width().max(size.height())).min(1.0);
+    let size = size
+        .to_int_size()
+        .scale_by(scale)
+        .ok_or(anyhow!("Invalid SVG size"))?;
+    let mut pixmap = resvg::tiny_skia::Pixmap::new(size.width(), size.height())
+        .ok_or(anyhow!("Invalid SVG size"))?;
+    resvg::render(
+        &tree,
+        resvg::tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+    Ok(pixmap.encode_png()?)
+}
+
+/// Parses the metadata JSON behind an ERC721 token URI, either inlined as a `data:` URI or hosted remotely.
+pub async fn get_nft_metadata(token_uri: &str) -> Result<Value> {
+    let contents = if token_uri.starts_with("data:") {
+        decode_data_uri(token_uri)?.1
+    } else {
+        download_img_from_uri(token_uri).await?
+    };
+    Ok(serde_json::from_slice(&contents)?)
+}
+
+/// Fetches the image of an NFT and returns it as an inline attachment referenced by `cid:<inline_id>`.
+pub async fn fetch_nft_image(token_uri: &str, inline_id: &str) -> Result<EmailAttachment> {
+    let metadata = get_nft_metadata(token_uri).await?;
+    let image_uri = metadata["image"]
+        .as_str()
+        .or(metadata["image_data"].as_str())
+        .ok_or(anyhow!("NFT metadata has no image"))?;
+    let mut contents = if is_svg(image_uri.as_bytes()) {
+        // `image_data` may hold the SVG itself.
+        image_uri.as_bytes().to_vec()
+    } else if image_uri.starts_with("data:") {
+        decode_data_uri(image_uri)?.1
+    } else {
+        download_img_from_uri(image_uri).await?
+    };
+    if contents.len() > NFT_IMAGE_MAX_BYTES {
+        return Err(anyhow!("NFT image is too large"));
+    }
+    if is_svg(&contents) {
+        contents = svg_to_png(&contents)?;
+    }
+    let content_type =
+        sniff_image_content_type(&contents).ok_or(anyhow!("Unsupported NFT image format"))?;
+    Ok(EmailAttachment {
+        inline_id: inline_id.to_string(),
+        content_type: content_type.to_string(),
+        contents,
+    })
 }
 
 pub async fn generate_asset_list_body(
     assets: &[Asset],
     mut assets_msgs: Vec<String>,
-) -> Result<(String, Vec<Value>)> {
+) -> Result<(String, Vec<Value>, Vec<EmailAttachment>)> {
     let mut images = vec![None; assets_msgs.len()];
+    let mut attachments = vec![];
     for asset in assets {
         match asset {
             Asset::ERC20 {
@@ -241,19 +494,17 @@ pub async fn generate_asset_list_body(
                 token_id,
                 token_uri,
             } => {
-                let json_uri: Value = serde_json::from_str(
-                    &String::from_utf8(
-                        base64::decode(token_uri.split(",").nth(1).expect("Invalid base64 string"))
-                            .expect("Failed to decode base64 string"),
-                    )
-                    .expect("Invalid UTF-8 sequence"),
-                )
-                .expect("Failed to parse JSON");
-                let img = json_uri.clone()["image"]
-                    .as_str()
-                    .unwrap_or_default()
-                    .to_string();
-                images.push(Some(img));
+                let inline_id = format!("nft-{}", attachments.len());
+                match fetch_nft_image(token_uri, &inline_id).await {
+                    Ok(attachment) => {
+                        images.push(Some(format!("cid:{}", inline_id)));
+                        attachments.push(attachment);
+                    }
+                    Err(e) => {
+                        warn!(LOG, "Failed to fetch the image of NFT {}: {}", token_id, e);
+                        images.push(None);
+                    }
+                }
                 assets_msgs.push(format!("NFT: ID {} of {}", token_id, token_name));
             }
         }
@@ -263,22 +514,146 @@ pub async fn generate_asset_list_body(
         assets_list_plain.push_str(&format!("{}\n", asset_msg));
     }
     let mut assets_list_html = vec![];
-    println!("assets_msgs: {:?}", assets_msgs);
-    for (idx, (asset_msg, image)) in assets_msgs.iter().zip(images.into_iter()).enumerate() {
-        if image.is_some() {
-            let value = serde_json::json!({
+    for (asset_msg, image) in assets_msgs.iter().zip(images.into_iter()) {
+        let value = match image {
+            Some(img) => serde_json::json!({
                 "msg": asset_msg,
-                "img": image.unwrap(),
+                "img": img,
                 "is_img": true,
-            });
-            assets_list_html.push(value);
-        }
-        let value = serde_json::json!({
-            "msg": asset_msg,
-            "img": "",
-            "is_img": false,
-        });
+            }),
+            None => serde_json::json!({
+                "msg": asset_msg,
+                "img": "",
+                "is_img": false,
+            }),
+        };
         assets_list_html.push(value);
     }
-    Ok((assets_list_plain, assets_list_html))
+    Ok((assets_list_plain, assets_list_html, attachments))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_non_public_ips() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::127.0.0.1",
+            "::10.0.0.1",
+            "2002:7f00:1::1",
+            "2002:a9fe:a9fe::1",
+            "64:ff9b::169.254.169.254",
+            "64:ff9b::192.168.0.1",
+        ] {
+            assert!(!is_public_ip(&ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "1.1.1.1",
+            "8.8.8.8",
+            "2606:4700::1111",
+            "2002:0808:0808::1",
+            "64:ff9b::1.1.1.1",
+        ] {
+            assert!(is_public_ip(&ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_unsafe_urls() {
+        for url in [
+            "http://example.com/a.png",
+            "file:///etc/passwd",
+            "https://127.0.0.1/a.png",
+            "https://169.254.169.254/latest/meta-data/",
+            "https://[::1]/a.png",
+        ] {
+            assert!(download_img_from_uri(url).await.is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn resolves_ipfs_uris() {
+        assert_eq!(
+            resolve_ipfs_uri("ipfs://ipfs/Qm123/1.png"),
+            "https://ipfs.io/ipfs/Qm123/1.png"
+        );
+        assert_eq!(
+            resolve_ipfs_uri("ipfs://Qm123"),
+            "https://ipfs.io/ipfs/Qm123"
+        );
+        assert_eq!(
+            resolve_ipfs_uri("https://example.com/1.png"),
+            "https://example.com/1.png"
+        );
+    }
+
+    #[test]
+    fn decodes_data_uris() {
+        let (media_type, contents) =
+            decode_data_uri("data:application/json;base64,eyJhIjoxfQ==").unwrap();
+        assert_eq!(media_type, "application/json");
+        assert_eq!(contents, br#"{"a":1}"#);
+        let (_, contents) = decode_data_uri("data:application/json,%7B%22a%22%3A1%7D").unwrap();
+        assert_eq!(contents, br#"{"a":1}"#);
+    }
+
+    #[test]
+    fn sniffs_raster_images_only() {
+        assert_eq!(
+            sniff_image_content_type(b"\x89PNG\r\n\x1a\n...."),
+            Some("image/png")
+        );
+        assert_eq!(sniff_image_content_type(b"GIF89a..."), Some("image/gif"));
+        assert_eq!(
+            sniff_image_content_type(b"<svg xmlns=\"http://www.w3.org/2000/svg\"></svg>"),
+            None
+        );
+    }
+
+    #[test]
+    fn detects_svg_images() {
+        assert!(is_svg(b"<svg xmlns=\"http://www.w3.org/2000/svg\"></svg>"));
+        assert!(is_svg(
+            b"<?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\"/>"
+        ));
+        assert!(is_svg(b"  <SVG></SVG>"));
+        assert!(!is_svg(b"\x89PNG\r\n\x1a\n...."));
+        assert!(!is_svg(b"<?xml version=\"1.0\"?><html></html>"));
+    }
+
+    #[test]
+    fn converts_svg_images_to_png() {
+        let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" width="2000" height="1000"><rect width="2000" height="1000" fill="red"/></svg>"#;
+        let png = svg_to_png(svg).unwrap();
+        assert_eq!(sniff_image_content_type(&png), Some("image/png"));
+        // Scaled down to NFT_SVG_MAX_SIDE on the longest side.
+        let pixmap = resvg::tiny_skia::Pixmap::decode_png(&png).unwrap();
+        assert_eq!((pixmap.width(), pixmap.height()), (512, 256));
+        assert_eq!(pixmap.pixel(10, 10).unwrap().red(), 255);
+    }
+
+    #[test]
+    fn ignores_images_linked_from_svg() {
+        let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" width="10" height="10"><image href="/etc/passwd" width="10" height="10"/><image xlink:href="https://169.254.169.254/a.png" width="10" height="10"/></svg>"#;
+        let png = svg_to_png(svg).unwrap();
+        let pixmap = resvg::tiny_skia::Pixmap::decode_png(&png).unwrap();
+        assert!(pixmap.pixels().iter().all(|pixel| pixel.alpha() == 0));
+    }
+
+    #[test]
+    fn rejects_invalid_svg() {
+        assert!(svg_to_png(b"<svg").is_err());
+    }
 }